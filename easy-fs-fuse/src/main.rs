@@ -1,6 +1,9 @@
 use easy_fs::{
     BlockDevice,
+    Clock,
     EasyFileSystem,
+    InodeOwner,
+    fsck,
 };
 use std::fs::{File, OpenOptions, read_dir};
 use std::io::{Read, Write, Seek, SeekFrom};
@@ -28,6 +31,83 @@ impl BlockDevice for BlockFile {
     }
 }
 
+// 模拟在第 crash_after 次写入之后断电的块设备
+// 之后的写入仍然对读可见，使得运行过程与不断电时完全相同，但是不会再到达 disk
+#[cfg(test)]
+struct CrashDevice(Mutex<CrashDeviceInner>);
+
+#[cfg(test)]
+struct CrashDeviceInner {
+    blocks: Vec<[u8; BLOCK_SZ]>,
+    disk: Vec<[u8; BLOCK_SZ]>,
+    writes: usize,
+    crash_after: usize,
+    // 记录每次提交完成、提交记录被清空时文件系统部分的状态
+    states: Option<std::collections::HashSet<u64>>,
+}
+
+#[cfg(test)]
+impl CrashDevice {
+    fn new(disk: Vec<[u8; BLOCK_SZ]>, crash_after: usize) -> Self {
+        Self(Mutex::new(CrashDeviceInner {
+            blocks: disk.clone(),
+            disk,
+            writes: 0,
+            crash_after,
+            states: None,
+        }))
+    }
+
+    fn disk(&self) -> Vec<[u8; BLOCK_SZ]> {
+        self.0.lock().unwrap().disk.clone()
+    }
+
+    fn writes(&self) -> usize {
+        self.0.lock().unwrap().writes
+    }
+}
+
+// 文件系统中除去最后的日志区域之外的部分
+#[cfg(test)]
+fn fs_state(blocks: &[[u8; BLOCK_SZ]]) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    blocks[..blocks.len() - easy_fs::JOURNAL_BLOCKS as usize].hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+impl BlockDevice for CrashDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.0.lock().unwrap().blocks[block_id]);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut inner = self.0.lock().unwrap();
+        inner.blocks[block_id].copy_from_slice(buf);
+        if inner.writes < inner.crash_after {
+            inner.disk[block_id].copy_from_slice(buf);
+        }
+        inner.writes += 1;
+        let commit_record = inner.blocks.len() - easy_fs::JOURNAL_BLOCKS as usize;
+        if inner.states.is_some() && block_id == commit_record && buf.iter().all(|&b| b == 0) {
+            let state = fs_state(&inner.blocks);
+            inner.states.as_mut().unwrap().insert(state);
+        }
+    }
+}
+
+// 镜像中文件的时间戳使用打包时主机的时间
+struct HostClock;
+
+impl Clock for HostClock {
+    fn now(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs())
+    }
+}
+
 fn main() {
     easy_fs_pack().expect("Error when packing easy-fs!");
 }
@@ -51,7 +131,26 @@ fn easy_fs_pack() -> std::io::Result<()> {
             .takes_value(true)
             .help("Executable target dir(with backslash)")    
         )
+        .arg(Arg::with_name("etc")
+            .short("e")
+            .long("etc")
+            .takes_value(true)
+            .help("Configuration files copied into /etc (with backslash)")
+        )
+        .arg(Arg::with_name("fsck")
+            .long("fsck")
+            .takes_value(true)
+            .help("Check an easy-fs image instead of packing")
+        )
+        .arg(Arg::with_name("repair")
+            .long("repair")
+            .requires("fsck")
+            .help("Repair the image checked by --fsck")
+        )
         .get_matches();
+    if let Some(image_path) = matches.value_of("fsck") {
+        return easy_fs_check(image_path, matches.is_present("repair"));
+    }
     let src_path = matches.value_of("source").unwrap();
     let target_path = matches.value_of("target").unwrap();
     println!("src_path = {}\ntarget_path = {}", src_path, target_path);
@@ -79,6 +178,7 @@ fn easy_fs_pack() -> std::io::Result<()> {
         BLOCK_NUM,
         1,
     );
+    efs.lock().set_clock(Arc::new(HostClock));
     let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
     // 这个过程相当于将 HostOS 上的文件系统中的一个文件复制到我们的 easy-fs 中
     for app in apps {
@@ -88,10 +188,22 @@ fn easy_fs_pack() -> std::io::Result<()> {
         let mut all_data: Vec<u8> = Vec::new();
         host_file.read_to_end(&mut all_data).unwrap();
         // create a file in easy-fs
-        let inode = root_inode.create(app.as_str()).unwrap();
+        // 应用属于 root，所有用户都可以执行，只有 root 可以修改
+        let inode = root_inode.create_as(app.as_str(), InodeOwner::root(0o755)).unwrap();
         // write data to easy-fs
         inode.write_at(0, all_data.as_slice());
     }
+    // 配置文件 (如 login 使用的 passwd) 放在 /etc 中，所有用户可读
+    if let Some(etc_path) = matches.value_of("etc") {
+        let etc_inode = root_inode.create_dir("etc").unwrap();
+        for dir_entry in read_dir(etc_path)? {
+            let dir_entry = dir_entry?;
+            let name = dir_entry.file_name().into_string().unwrap();
+            let data = std::fs::read(dir_entry.path())?;
+            let inode = etc_inode.create_as(name.as_str(), InodeOwner::root(0o644)).unwrap();
+            inode.write_at(0, data.as_slice());
+        }
+    }
     // list apps
     for app in root_inode.ls() {
         println!("{}", app);
@@ -99,6 +211,33 @@ fn easy_fs_pack() -> std::io::Result<()> {
     Ok(())
 }
 
+// 检查一个 easy-fs 镜像，打开时已经提交的事务会被重做，因此即使不修复也需要写权限
+fn easy_fs_check(image_path: &str, repair: bool) -> std::io::Result<()> {
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new().read(true).write(true).open(image_path)?
+    )));
+    let errors = fsck(block_file.clone(), repair);
+    for error in errors.iter() {
+        println!("{:?}", error);
+    }
+    if errors.is_empty() {
+        println!("{}: clean", image_path);
+        return Ok(());
+    }
+    println!("{}: {} problem(s) found", image_path, errors.len());
+    if repair {
+        let remaining = fsck(block_file, false);
+        for error in remaining.iter() {
+            println!("{:?}", error);
+        }
+        println!("{}: {} problem(s) remain after repair", image_path, remaining.len());
+        if remaining.is_empty() {
+            return Ok(());
+        }
+    }
+    std::process::exit(1);
+}
+
 #[test]
 fn efs_test() -> std::io::Result<()> {
     let block_file = Arc::new(BlockFile(Mutex::new({
@@ -172,4 +311,638 @@ fn efs_test() -> std::io::Result<()> {
     random_str_test(2000 * BLOCK_SZ);
 
     Ok(())
-}
\ No newline at end of file
+}
+
+// 多级目录
+#[test]
+fn efs_dir_test() {
+    const TOTAL_BLOCKS: usize = 4096;
+    let device = Arc::new(CrashDevice::new(vec![[0u8; BLOCK_SZ]; TOTAL_BLOCKS], usize::MAX));
+    let efs = EasyFileSystem::create(device.clone(), TOTAL_BLOCKS as u32, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let greet_str = "Hello, world!";
+    let mut buffer = [0u8; 233];
+    let filea = root_inode.create("filea").unwrap();
+    let dir = root_inode.create_dir("dir").unwrap();
+    assert!(dir.is_dir() && !filea.is_dir());
+    assert!(root_inode.create_dir("dir").is_none());
+    assert!(filea.create("file").is_none());
+    let sub = dir.create_dir("sub").unwrap();
+    sub.create("filec").unwrap().write_at(0, greet_str.as_bytes());
+    assert_eq!(dir.ls(), [".", "..", "sub"]);
+    let filec = root_inode.lookup("/dir//sub/filec").unwrap();
+    let len = filec.read_at(0, &mut buffer);
+    assert_eq!(greet_str.as_bytes(), &buffer[..len]);
+    assert_eq!(root_inode.lookup("dir/sub/..").unwrap().get_inode_id(), dir.get_inode_id());
+    assert_eq!(root_inode.lookup("..").unwrap().get_inode_id(), 0);
+    assert!(root_inode.lookup("dir/sub/filec/x").is_none());
+    assert!(root_inode.lookup("dir/filec").is_none());
+    drop((root_inode, filea, dir, sub, filec));
+    assert_eq!(fsck(device, false), []);
+}
+
+// 硬链接
+#[test]
+fn efs_link_test() {
+    const TOTAL_BLOCKS: usize = 4096;
+    let device = Arc::new(CrashDevice::new(vec![[0u8; BLOCK_SZ]; TOTAL_BLOCKS], usize::MAX));
+    let efs = EasyFileSystem::create(device.clone(), TOTAL_BLOCKS as u32, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let greet_str = "Hello, world!";
+    let mut buffer = [0u8; 233];
+    let dir = root_inode.create_dir("dir").unwrap();
+    let sub = dir.create_dir("sub").unwrap();
+    let filec = sub.create("filec").unwrap();
+    filec.write_at(0, greet_str.as_bytes());
+    assert_eq!(root_inode.get_nlink(), 3);
+    assert_eq!(dir.get_nlink(), 3);
+    assert_eq!(filec.get_nlink(), 1);
+    assert!(dir.link("linkc", &filec));
+    assert!(!dir.link("linkc", &filec));
+    assert!(!dir.link("linksub", &sub));
+    assert_eq!(root_inode.lookup("dir/sub/filec").unwrap().get_nlink(), 2);
+    assert_eq!(root_inode.lookup("dir/linkc").unwrap().get_inode_id(), filec.get_inode_id());
+    assert!(sub.unlink("filec"));
+    assert!(!sub.unlink("filec"));
+    assert!(!dir.unlink("sub"));
+    assert!(!dir.unlink(".."));
+    assert!(root_inode.lookup("dir/sub/filec").is_none());
+    assert_eq!(sub.ls(), [".", ".."]);
+    assert_eq!(filec.get_nlink(), 1);
+    // 空出的目录项被复用
+    assert!(sub.link("linkc", &filec));
+    assert_eq!(sub.ls(), [".", "..", "linkc"]);
+    // 重新打开文件系统后硬链接仍然存在
+    drop((root_inode, dir, sub, filec));
+    let efs = EasyFileSystem::open(device.clone());
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let linkc = root_inode.lookup("dir/sub/linkc").unwrap();
+    assert_eq!(linkc.get_nlink(), 2);
+    let len = linkc.read_at(0, &mut buffer);
+    assert_eq!(greet_str.as_bytes(), &buffer[..len]);
+    drop((root_inode, linkc));
+    assert_eq!(fsck(device, false), []);
+}
+
+// 最后一个硬链接被删除后释放 inode，仍被打开时推迟到最后一次关闭之后
+#[test]
+fn efs_unlink_test() {
+    const TOTAL_BLOCKS: usize = 4096;
+    let device = Arc::new(CrashDevice::new(vec![[0u8; BLOCK_SZ]; TOTAL_BLOCKS], usize::MAX));
+    let efs = EasyFileSystem::create(device.clone(), TOTAL_BLOCKS as u32, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let mut buffer = [0u8; 233];
+    let big = root_inode.create("big").unwrap();
+    big.write_at(0, &[1u8; 100 * BLOCK_SZ]);
+    let big_id = big.get_inode_id();
+    assert!(root_inode.unlink("big"));
+    assert_eq!(big.get_nlink(), 0);
+    assert!(root_inode.find("big").is_none());
+    assert_eq!(big.read_at(99 * BLOCK_SZ, &mut buffer), buffer.len());
+    assert_ne!(root_inode.create("other").unwrap().get_inode_id(), big_id);
+    drop(big);
+    let reused = root_inode.create("reused").unwrap();
+    assert_eq!(reused.get_inode_id(), big_id);
+    assert_eq!(reused.read_at(0, &mut buffer), 0);
+    // 数据块被回收，反复写入再删除不会耗尽只有 2MiB 的文件系统
+    for _ in 0..8 {
+        let file = root_inode.create("tmp").unwrap();
+        file.write_at(0, &[2u8; 1024 * 1024]);
+        drop(file);
+        assert!(root_inode.unlink("tmp"));
+    }
+    drop((root_inode, reused));
+    assert_eq!(fsck(device, false), []);
+}
+
+// 超过原来 8MiB 上限的文件需要三级以上的索引
+#[test]
+fn efs_large_file_test() {
+    const TOTAL_BLOCKS: usize = 24 * 1024;
+    let device = Arc::new(CrashDevice::new(vec![[0u8; BLOCK_SZ]; TOTAL_BLOCKS], usize::MAX));
+    let efs = EasyFileSystem::create(device.clone(), TOTAL_BLOCKS as u32, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let mut buffer = [0u8; 64];
+    let large = root_inode.create("large").unwrap();
+    let offsets = [0, 13 * BLOCK_SZ + 7, 8 * 1024 * 1024 + 100, 9 * 1024 * 1024];
+    for (i, &offset) in offsets.iter().enumerate() {
+        assert_eq!(large.write_at(offset, &[i as u8 + 1; 64]), 64);
+    }
+    for (i, &offset) in offsets.iter().enumerate() {
+        assert_eq!(large.read_at(offset, &mut buffer), 64);
+        assert!(buffer.iter().all(|&b| b == i as u8 + 1));
+    }
+    // size 的高 32 位单独保存，上限超过 4GiB
+    assert!(large.get_max_size() > u32::MAX as usize);
+    assert_eq!(large.write_at(large.get_max_size(), &[0; 64]), 0);
+    drop(large);
+    // 删除之后索引块和数据块都被回收，剩余的空间足够写入一个 10MiB 的文件
+    assert!(root_inode.unlink("large"));
+    let file = root_inode.create("tmp").unwrap();
+    assert_eq!(file.write_at(0, &[3u8; 10 * 1024 * 1024]), 10 * 1024 * 1024);
+    drop((root_inode, file));
+    assert_eq!(fsck(device, false), []);
+}
+
+// 在 Inode 的各种修改操作中间的每一次写入处断电，重新打开后都应当恢复到某个事务提交之后的状态
+#[test]
+fn efs_journal_test() {
+    const TOTAL_BLOCKS: usize = 2048;
+    let formatted = Arc::new(CrashDevice::new(vec![[0u8; BLOCK_SZ]; TOTAL_BLOCKS], usize::MAX));
+    EasyFileSystem::create(formatted.clone(), TOTAL_BLOCKS as u32, 1);
+    let image = formatted.disk();
+
+    let workload = |block_device: Arc<CrashDevice>| {
+        let efs = EasyFileSystem::open(block_device);
+        let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+        let filea = root_inode.create("filea").unwrap();
+        filea.write_at(0, &[1u8; 3 * BLOCK_SZ]);
+        let dir = root_inode.create_dir("dir").unwrap();
+        assert!(dir.link("linka", &filea));
+        assert!(root_inode.unlink("filea"));
+        // 需要多个事务的写入和释放
+        filea.write_at(2 * BLOCK_SZ + 7, &[2u8; 40 * BLOCK_SZ]);
+        dir.create("fileb").unwrap().write_at(0, b"Hello, world!");
+        // 跨目录移动文件和目录，以及交换两个目录项
+        assert!(dir.rename("fileb", &root_inode, "fileb"));
+        assert!(root_inode.create_dir("sub").is_some());
+        assert!(root_inode.rename("sub", &dir, "sub"));
+        assert!(dir.exchange("sub", &root_inode, "fileb"));
+        filea.clear();
+        filea.write_at(0, &[3u8; 100]);
+        // 原子地替换仍被打开的文件，它在关闭之后才被释放
+        assert!(dir.rename("sub", &dir, "linka"));
+        drop(filea);
+        assert!(dir.unlink("linka"));
+    };
+
+    // 不断电地运行一次，记录所有事务边界处的状态以及总的写入次数
+    let reference = Arc::new(CrashDevice::new(image.clone(), usize::MAX));
+    reference.0.lock().unwrap().states = Some(std::iter::once(fs_state(&image)).collect());
+    workload(reference.clone());
+    let writes = reference.writes();
+    let states = reference.0.lock().unwrap().states.take().unwrap();
+    assert!(states.len() > 10);
+
+    for crash_after in 0..=writes {
+        let crashed = Arc::new(CrashDevice::new(image.clone(), crash_after));
+        workload(crashed.clone());
+        assert_eq!(crashed.writes(), writes);
+        // 重新打开时根据日志恢复
+        let recovered = Arc::new(CrashDevice::new(crashed.disk(), usize::MAX));
+        let efs = EasyFileSystem::open(recovered.clone());
+        assert!(EasyFileSystem::root_inode(&efs).ls().contains(&String::from("..")));
+        assert!(
+            states.contains(&fs_state(&recovered.disk())),
+            "inconsistent image after crashing at write {}", crash_after,
+        );
+    }
+}
+
+// 清空超过一个事务容量的大文件时在每一次写入处断电，中间状态只是变短了的文件，不会有块被泄漏
+#[test]
+fn efs_truncate_test() {
+    const TOTAL_BLOCKS: usize = 2048;
+    const FILE_BLOCKS: usize = 300;
+    let formatted = Arc::new(CrashDevice::new(vec![[0u8; BLOCK_SZ]; TOTAL_BLOCKS], usize::MAX));
+    let efs = EasyFileSystem::create(formatted.clone(), TOTAL_BLOCKS as u32, 1);
+    let file = EasyFileSystem::root_inode(&efs).create("file").unwrap();
+    file.write_at(0, &[1u8; FILE_BLOCKS * BLOCK_SZ]);
+    drop((file, efs));
+    let image = formatted.disk();
+
+    let workload = |block_device: Arc<CrashDevice>| {
+        let efs = EasyFileSystem::open(block_device);
+        EasyFileSystem::root_inode(&efs).find("file").unwrap().clear();
+    };
+    let reference = Arc::new(CrashDevice::new(image.clone(), usize::MAX));
+    workload(reference.clone());
+    let writes = reference.writes();
+
+    let mut buffer = [0u8; BLOCK_SZ];
+    for crash_after in 0..=writes {
+        let crashed = Arc::new(CrashDevice::new(image.clone(), crash_after));
+        workload(crashed.clone());
+        let recovered = Arc::new(CrashDevice::new(crashed.disk(), usize::MAX));
+        let efs = EasyFileSystem::open(recovered.clone());
+        let file = EasyFileSystem::root_inode(&efs).find("file").unwrap();
+        let size = file.get_size();
+        assert!(size <= FILE_BLOCKS * BLOCK_SZ);
+        for offset in (0..size).step_by(BLOCK_SZ) {
+            assert_eq!(file.read_at(offset, &mut buffer), BLOCK_SZ);
+            assert!(buffer.iter().all(|&b| b == 1));
+        }
+        drop((file, efs));
+        assert_eq!(fsck(recovered, false), [], "crashing at write {}", crash_after);
+    }
+}
+
+#[test]
+fn efs_fsck_test() {
+    use easy_fs::FsckError;
+    const TOTAL_BLOCKS: usize = 4096;
+    let device = Arc::new(CrashDevice::new(vec![[0u8; BLOCK_SZ]; TOTAL_BLOCKS], usize::MAX));
+    let efs = EasyFileSystem::create(device.clone(), TOTAL_BLOCKS as u32, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let dir = root_inode.create_dir("dir").unwrap();
+    let filea = dir.create("filea").unwrap();
+    filea.write_at(0, &[1u8; 10 * BLOCK_SZ]);
+    assert!(root_inode.link("linka", &filea));
+    let fileb = root_inode.create("fileb").unwrap();
+    fileb.write_at(0, &[2u8; 3 * BLOCK_SZ]);
+    assert_eq!(fsck(device.clone(), false), []);
+    // 文件被删除但仍被打开时断电，留下一个孤儿
+    let orphan = dir.create("orphan").unwrap();
+    orphan.write_at(0, b"lost");
+    assert!(dir.unlink("orphan"));
+    let mut image = device.disk();
+    let (filea_id, fileb_id, orphan_id) = (
+        filea.get_inode_id() as u32,
+        fileb.get_inode_id() as u32,
+        orphan.get_inode_id() as u32,
+    );
+    drop((root_inode, dir, filea, fileb, orphan));
+
+    // 按照超级块中的布局直接破坏镜像
+    let field = |i: usize| u32::from_le_bytes([
+        image[0][4 * i], image[0][4 * i + 1], image[0][4 * i + 2], image[0][4 * i + 3],
+    ]) as usize;
+    let (inode_bitmap_blocks, inode_area_blocks) = (field(2), field(3));
+    let (data_bitmap_blocks, data_area_blocks) = (field(4), field(5));
+    let inode_bitmap_start = 1;
+    let inode_area_start = inode_bitmap_start + inode_bitmap_blocks;
+    let data_bitmap_start = inode_area_start + inode_area_blocks;
+    let data_area_start = data_bitmap_start + data_bitmap_blocks;
+    let flip_bit = |image: &mut Vec<[u8; BLOCK_SZ]>, start: usize, bit: usize| {
+        image[start + bit / (BLOCK_SZ * 8)][bit % (BLOCK_SZ * 8) / 8] ^= 1 << (bit % 8);
+    };
+    let set_nlink = |image: &mut Vec<[u8; BLOCK_SZ]>, inode_id: u32, nlink: u16| {
+        let inode_id = inode_id as usize;
+        let offset = inode_id % 4 * 128 + 126;
+        image[inode_area_start + inode_id / 4][offset..offset + 2]
+            .copy_from_slice(&nlink.to_le_bytes());
+    };
+    // 最后一个数据块被标记为已分配
+    flip_bit(&mut image, data_bitmap_start, data_area_blocks - 1);
+    // fileb 被释放但目录项还在
+    flip_bit(&mut image, inode_bitmap_start, fileb_id as usize);
+    set_nlink(&mut image, fileb_id, 0);
+    set_nlink(&mut image, filea_id, 5);
+
+    let device = Arc::new(CrashDevice::new(image.clone(), usize::MAX));
+    let errors = fsck(device.clone(), false);
+    assert!(errors.contains(&FsckError::OrphanInode(orphan_id)));
+    assert!(errors.contains(&FsckError::LeakedBlock((data_area_start + data_area_blocks - 1) as u32)));
+    assert!(errors.contains(&FsckError::DanglingDirEntry {
+        dir: 0,
+        name: String::from("fileb"),
+        inode_id: fileb_id,
+    }));
+    assert!(errors.contains(&FsckError::WrongLinkCount {
+        inode_id: filea_id,
+        nlink: 5,
+        expected: 2,
+    }));
+    // 还有 fileb 的 3 个数据块被泄漏
+    assert_eq!(errors.len(), 7);
+    assert_eq!(fsck(device.clone(), false), errors);
+
+    // 修复之后再检查没有问题，孤儿出现在 lost+found 中
+    assert_eq!(fsck(device.clone(), true), errors);
+    assert_eq!(fsck(device.clone(), false), []);
+    let efs = EasyFileSystem::open(device.clone());
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    assert!(root_inode.find("fileb").is_none());
+    assert_eq!(root_inode.lookup("dir/filea").unwrap().get_nlink(), 2);
+    let lost_found = root_inode.find("lost+found").unwrap();
+    assert_eq!(lost_found.ls(), [".", "..", &format!("#{}", orphan_id)]);
+    let mut buffer = [0u8; 16];
+    let orphan = root_inode.lookup(&format!("lost+found/#{}", orphan_id)).unwrap();
+    assert_eq!(orphan.read_at(0, &mut buffer), 4);
+    assert_eq!(&buffer[..4], b"lost");
+    assert_eq!(orphan.get_nlink(), 1);
+    drop((root_inode, lost_found, orphan));
+
+    // 超级块损坏时无法继续检查
+    image[0][0] ^= 0xff;
+    assert_eq!(fsck(Arc::new(CrashDevice::new(image, usize::MAX)), false), [FsckError::BadSuperBlock]);
+}
+
+#[cfg(test)]
+struct TestClock(std::sync::atomic::AtomicU64);
+
+#[cfg(test)]
+impl Clock for TestClock {
+    fn now(&self) -> u64 {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+#[test]
+fn efs_timestamp_test() {
+    use std::sync::atomic::Ordering;
+    const TOTAL_BLOCKS: usize = 4096;
+    const DAY: u32 = 24 * 60 * 60;
+    let device = Arc::new(CrashDevice::new(vec![[0u8; BLOCK_SZ]; TOTAL_BLOCKS], usize::MAX));
+    let efs = EasyFileSystem::create(device.clone(), TOTAL_BLOCKS as u32, 1);
+    let clock = Arc::new(TestClock(std::sync::atomic::AtomicU64::new(1000)));
+    efs.lock().set_clock(clock.clone());
+    let set_time = |now: u32| clock.0.store(now as u64, Ordering::SeqCst);
+    let times = |inode: &easy_fs::Inode| {
+        let times = inode.get_times();
+        (times.atime, times.mtime, times.ctime)
+    };
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let file = root_inode.create("file").unwrap();
+    assert_eq!(times(&file), (1000, 1000, 1000));
+    assert_eq!(times(&root_inode).1, 1000);
+
+    set_time(2000);
+    assert_eq!(file.write_at(0, &[1u8; 3 * BLOCK_SZ]), 3 * BLOCK_SZ);
+    assert_eq!(times(&file), (1000, 2000, 2000));
+    assert_eq!(file.get_size(), 3 * BLOCK_SZ);
+    assert_eq!(file.get_blocks(), 3);
+    // 修改之后的第一次读取更新 atime，之后一天之内的读取不再更新
+    set_time(3000);
+    let mut buffer = [0u8; BLOCK_SZ];
+    assert_eq!(file.read_at(0, &mut buffer), BLOCK_SZ);
+    assert_eq!(times(&file), (3000, 2000, 2000));
+    set_time(4000);
+    file.read_at(0, &mut buffer);
+    assert_eq!(times(&file).0, 3000);
+    set_time(3000 + DAY);
+    file.read_at(0, &mut buffer);
+    assert_eq!(times(&file).0, 3000 + DAY);
+
+    // 硬链接只改变 ctime，目录项的增删改变目录的 mtime
+    set_time(5 * DAY);
+    assert!(root_inode.link("link", &file));
+    assert_eq!(times(&file), (3000 + DAY, 2000, 5 * DAY));
+    assert_eq!(times(&root_inode).1, 5 * DAY);
+    set_time(6 * DAY);
+    assert!(root_inode.unlink("link"));
+    assert_eq!(times(&file).2, 6 * DAY);
+    assert_eq!(times(&root_inode).1, 6 * DAY);
+    set_time(7 * DAY);
+    file.clear();
+    assert_eq!(times(&file), (3000 + DAY, 7 * DAY, 7 * DAY));
+    assert_eq!(file.get_blocks(), 0);
+    drop((root_inode, file));
+
+    // 时间戳保存在磁盘上，清空内容不会影响它们
+    let efs = EasyFileSystem::open(Arc::new(CrashDevice::new(device.disk(), usize::MAX)));
+    let file = EasyFileSystem::root_inode(&efs).find("file").unwrap();
+    assert_eq!(times(&file), (3000 + DAY, 7 * DAY, 7 * DAY));
+    assert_eq!(fsck(device, false), []);
+}
+
+#[test]
+fn efs_owner_test() {
+    const TOTAL_BLOCKS: usize = 4096;
+    let device = Arc::new(CrashDevice::new(vec![[0u8; BLOCK_SZ]; TOTAL_BLOCKS], usize::MAX));
+    let efs = EasyFileSystem::create(device.clone(), TOTAL_BLOCKS as u32, 1);
+    let clock = Arc::new(TestClock(std::sync::atomic::AtomicU64::new(1000)));
+    efs.lock().set_clock(clock.clone());
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    assert_eq!(root_inode.get_owner(), InodeOwner::root(0o755));
+    assert_eq!(root_inode.create("file").unwrap().get_owner(), InodeOwner::root(0o644));
+    let user = InodeOwner { uid: 1000, gid: 100, mode: 0o700 };
+    let dir = root_inode.create_dir_as("home", user).unwrap();
+    assert_eq!(dir.get_owner(), user);
+    let file = dir.create_as("notes", InodeOwner { mode: 0o600, ..user }).unwrap();
+    assert_eq!(file.get_owner().mode, 0o600);
+
+    // 修改所有者和访问权限只改变 ctime，多余的位被忽略
+    clock.0.store(2000, std::sync::atomic::Ordering::SeqCst);
+    assert!(file.set_owner(InodeOwner { uid: 0, gid: 0, mode: 0o170644 }));
+    assert_eq!(file.get_owner(), InodeOwner::root(0o644));
+    let times = file.get_times();
+    assert_eq!((times.mtime, times.ctime), (1000, 2000));
+    drop((root_inode, dir, file));
+
+    let efs = EasyFileSystem::open(Arc::new(CrashDevice::new(device.disk(), usize::MAX)));
+    let file = Arc::new(EasyFileSystem::root_inode(&efs)).lookup("home/notes").unwrap();
+    assert_eq!(file.get_owner(), InodeOwner::root(0o644));
+    assert_eq!(fsck(device, false), []);
+}
+
+#[test]
+fn efs_symlink_test() {
+    use easy_fs::{DiskInodeType, SYMLINK_LENGTH_LIMIT};
+    const TOTAL_BLOCKS: usize = 4096;
+    let device = Arc::new(CrashDevice::new(vec![[0u8; BLOCK_SZ]; TOTAL_BLOCKS], usize::MAX));
+    let efs = EasyFileSystem::create(device.clone(), TOTAL_BLOCKS as u32, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let dir = root_inode.create_dir("dir").unwrap();
+    dir.create("file").unwrap().write_at(0, b"hello");
+    let owner = InodeOwner::root(0o777);
+    let link = root_inode.symlink("link", "dir/file", owner).unwrap();
+    assert!(link.is_symlink());
+    assert_eq!(link.get_type(), DiskInodeType::Symlink);
+    assert_eq!(link.read_link().unwrap(), "dir/file");
+    assert_eq!(link.get_size(), 8);
+    assert_eq!(link.get_owner(), owner);
+    // lookup 不展开符号链接，目标可以不存在
+    assert!(root_inode.lookup("link").unwrap().is_symlink());
+    assert!(root_inode.lookup("dir/file").unwrap().read_link().is_none());
+    assert!(dir.symlink("dangling", "/nowhere", owner).is_some());
+    // 目标路径为空、过长或者名字已经存在时失败
+    assert!(root_inode.symlink("empty", "", owner).is_none());
+    let long_target = "a".repeat(SYMLINK_LENGTH_LIMIT);
+    assert!(root_inode.symlink("long", &(long_target.clone() + "a"), owner).is_none());
+    assert!(root_inode.symlink("link", "dir", owner).is_none());
+    let long = root_inode.symlink("long", &long_target, owner).unwrap();
+    assert_eq!(long.read_link().unwrap(), long_target);
+    // 可以为符号链接创建硬链接，删除其中一个不影响另一个
+    assert!(root_inode.link("link2", &link));
+    assert!(root_inode.unlink("link"));
+    assert_eq!(root_inode.find("link2").unwrap().read_link().unwrap(), "dir/file");
+    drop((root_inode, dir, link, long));
+
+    let efs = EasyFileSystem::open(Arc::new(CrashDevice::new(device.disk(), usize::MAX)));
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    assert_eq!(root_inode.lookup("dir/dangling").unwrap().read_link().unwrap(), "/nowhere");
+    assert_eq!(root_inode.find("long").unwrap().get_blocks(), 8);
+    drop(root_inode);
+    assert_eq!(fsck(device, false), []);
+}
+
+#[test]
+fn efs_rename_test() {
+    const TOTAL_BLOCKS: usize = 4096;
+    let device = Arc::new(CrashDevice::new(vec![[0u8; BLOCK_SZ]; TOTAL_BLOCKS], usize::MAX));
+    let efs = EasyFileSystem::create(device.clone(), TOTAL_BLOCKS as u32, 1);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let read = |inode: &easy_fs::Inode| {
+        let mut buffer = [0u8; 16];
+        let len = inode.read_at(0, &mut buffer);
+        String::from_utf8(buffer[..len].to_vec()).unwrap()
+    };
+    let a = root_inode.create_dir("a").unwrap();
+    let b = root_inode.create_dir("b").unwrap();
+    a.create("file").unwrap().write_at(0, b"first");
+    b.create("other").unwrap().write_at(0, b"second");
+
+    // 同一目录中改名，以及移动到另一个目录中
+    assert!(a.rename("file", &a, "renamed"));
+    assert!(a.find("file").is_none());
+    assert!(a.rename("renamed", &b, "moved"));
+    assert_eq!(a.ls(), [".", ".."]);
+    assert_eq!(read(&b.find("moved").unwrap()), "first");
+    assert!(!a.rename("missing", &b, "x"));
+    assert!(!b.rename("moved", &b, "."));
+
+    // 原子地替换已经存在的文件，仍被打开的旧文件在关闭之后才被释放
+    let replaced = b.find("other").unwrap();
+    assert!(!b.rename_noreplace("moved", &b, "other"));
+    assert!(b.rename("moved", &b, "other"));
+    assert_eq!(read(&b.find("other").unwrap()), "first");
+    assert_eq!(replaced.get_nlink(), 0);
+    assert_eq!(read(&replaced), "second");
+    drop(replaced);
+    // 指向同一个 inode 的两个名字之间的改名什么也不做
+    assert!(b.link("hard", &b.find("other").unwrap()));
+    assert!(b.rename("hard", &b, "other"));
+    assert_eq!(b.find("other").unwrap().get_nlink(), 2);
+    assert!(b.unlink("hard"));
+
+    // 移动目录时更新它的 ".." 和两个父目录的硬链接数量
+    let sub = a.create_dir("sub").unwrap();
+    sub.create("inner").unwrap();
+    assert_eq!(a.get_nlink(), 3);
+    assert!(a.rename("sub", &b, "sub"));
+    assert_eq!((a.get_nlink(), b.get_nlink()), (2, 3));
+    assert_eq!(root_inode.lookup("b/sub/../other").map(|inode| read(&inode)).unwrap(), "first");
+    // 目录不能被移动到自身或者它的子目录中，目录和非目录不能互相替换，非空目录不能被替换
+    assert!(!root_inode.rename("b", &sub, "b"));
+    assert!(!root_inode.rename("b", &b, "b2"));
+    assert!(!b.rename("sub", &b, "other"));
+    assert!(!b.rename("other", &b, "sub"));
+    assert!(!root_inode.rename("a", &b, "sub"));
+    // 空目录可以被替换
+    assert!(root_inode.rename("a", &b, "sub2"));
+    let empty = root_inode.create_dir("empty").unwrap();
+    let empty_id = empty.get_inode_id();
+    drop(empty);
+    assert!(b.rename("sub2", &root_inode, "empty"));
+    assert_eq!(root_inode.find("empty").unwrap().get_inode_id(), a.get_inode_id());
+    assert_eq!(root_inode.get_nlink(), 4);
+
+    // 交换两个目录项，包括不同类型的目录项
+    assert!(!b.exchange("other", &b, "missing"));
+    assert!(b.exchange("other", &root_inode, "empty"));
+    assert_eq!(read(&root_inode.find("empty").unwrap()), "first");
+    assert_eq!(b.find("other").unwrap().get_inode_id(), a.get_inode_id());
+    assert_eq!(root_inode.lookup("b/other/..").unwrap().get_inode_id(), b.get_inode_id());
+    assert_eq!((root_inode.get_nlink(), b.get_nlink()), (3, 4));
+    assert!(!root_inode.exchange("b", &sub, "inner"));
+    drop((root_inode, a, b, sub));
+
+    let efs = EasyFileSystem::open(Arc::new(CrashDevice::new(device.disk(), usize::MAX)));
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    // "empty" 复用了 "a" 被移走之后留下的目录项空位
+    assert_eq!(root_inode.ls(), [".", "..", "empty", "b"]);
+    assert_eq!(root_inode.lookup("b/sub/inner").unwrap().get_size(), 0);
+    assert!(root_inode.lookup("b/other/..").unwrap().find("sub").is_some());
+    assert_ne!(root_inode.find("empty").unwrap().get_inode_id(), empty_id);
+    drop(root_inode);
+    assert_eq!(fsck(device, false), []);
+}
+
+// 构造一个早期格式的镜像，根目录中有 files 给出的文件
+// 超级块中 version 和 journal_blocks 为 0，所有 inode 都是原始格式，nlink 所在的填充字节为 0
+#[cfg(test)]
+fn legacy_image(total_blocks: usize, files: &[(&str, &[u8])]) -> Vec<[u8; BLOCK_SZ]> {
+    let device = Arc::new(CrashDevice::new(vec![[0u8; BLOCK_SZ]; total_blocks], usize::MAX));
+    drop(EasyFileSystem::create(device.clone(), total_blocks as u32, 1));
+    let mut image = device.disk();
+    // 原来的日志区域不再属于文件系统
+    let fs_blocks = (total_blocks - easy_fs::JOURNAL_BLOCKS as usize) as u32;
+    image[0][4..8].copy_from_slice(&fs_blocks.to_le_bytes());
+    image[0][24..32].copy_from_slice(&[0u8; 8]);
+    // 根目录的内容只占用第一个直接索引，之后的时间戳和所有者字段清零，format 改为原始格式
+    let inode_area_start = 1 + u32::from_le_bytes([image[0][8], image[0][9], image[0][10], image[0][11]]) as usize;
+    image[inode_area_start][84..124].copy_from_slice(&[0u8; 40]);
+    image[inode_area_start][125] = 0;
+    let device = Arc::new(CrashDevice::new(image, usize::MAX));
+    let efs = EasyFileSystem::open(device.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    for (name, data) in files {
+        root_inode.create(name).unwrap().write_at(0, data);
+    }
+    drop(root_inode);
+    let mut image = device.disk();
+    for inode_id in 0..=files.len() {
+        let offset = inode_id % 4 * 128 + 126;
+        image[inode_area_start + inode_id / 4][offset..offset + 2].copy_from_slice(&[0u8; 2]);
+    }
+    image
+}
+
+#[test]
+fn efs_legacy_link_test() {
+    let device = Arc::new(CrashDevice::new(legacy_image(4096, &[("a", b"data"), ("c", b"")]), usize::MAX));
+    let efs = EasyFileSystem::open(device.clone());
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let a = root_inode.find("a").unwrap();
+    // 早期镜像中的 inode 按照有一个目录项计算
+    assert_eq!((root_inode.get_nlink(), a.get_nlink()), (2, 1));
+    assert!(root_inode.link("b", &a));
+    assert_eq!(a.get_nlink(), 2);
+    assert!(root_inode.unlink("a"));
+    drop(a);
+    let b = root_inode.find("b").unwrap();
+    assert_eq!(b.get_nlink(), 1);
+    let mut buffer = [0u8; 16];
+    assert_eq!(b.read_at(0, &mut buffer), 4);
+    assert_eq!(&buffer[..4], b"data");
+    // 替换只有一个目录项的旧文件时释放它
+    assert!(root_inode.rename("b", &root_inode, "c"));
+    assert_eq!(b.get_nlink(), 1);
+    let sub = root_inode.create_dir("sub").unwrap();
+    assert_eq!((root_inode.get_nlink(), sub.get_nlink()), (3, 2));
+    drop((root_inode, b, sub));
+    // 被修改过的硬链接数量都是准确的值
+    assert_eq!(fsck(device.clone(), false), []);
+}
+
+#[test]
+fn efs_legacy_format_test() {
+    use easy_fs::FsckError;
+    // 超过 28 个直接索引，用到了原始格式的一级索引
+    let data: Vec<u8> = (0..40 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    let device = Arc::new(CrashDevice::new(legacy_image(20480, &[("file", &data)]), usize::MAX));
+    let efs = EasyFileSystem::open(device.clone());
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let file = root_inode.find("file").unwrap();
+    let mut buffer = vec![0u8; data.len() + 16];
+    assert_eq!(file.read_at(0, &mut buffer), data.len());
+    assert_eq!(&buffer[..data.len()], &data[..]);
+    // 原始格式的文件仍然受 8MiB 的上限限制，写入时扩展到二级索引
+    assert_eq!(file.get_max_size(), (28 + 128 + 128 * 128) * BLOCK_SZ);
+    assert_eq!(file.write_at(200 * BLOCK_SZ, b"tail"), 4);
+    assert_eq!(file.write_at(file.get_max_size() - 2, b"end"), 2);
+    assert_eq!(file.get_size(), file.get_max_size());
+    // 新创建的 inode 同样使用原始格式
+    let new_file = root_inode.create("new").unwrap();
+    assert_eq!(new_file.write_at(0, b"new"), 3);
+    assert_eq!(new_file.get_max_size(), file.get_max_size());
+    drop((root_inode, file, new_file));
+
+    let efs = EasyFileSystem::open(Arc::new(CrashDevice::new(device.disk(), usize::MAX)));
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let file = root_inode.find("file").unwrap();
+    assert_eq!(file.read_at(0, &mut buffer), data.len() + 16);
+    assert_eq!(&buffer[..data.len()], &data[..]);
+    assert_eq!(file.read_at(200 * BLOCK_SZ, &mut buffer[..4]), 4);
+    assert_eq!(&buffer[..4], b"tail");
+    assert_eq!(file.read_at(file.get_max_size() - 2, &mut buffer), 2);
+    assert_eq!(&buffer[..2], b"en");
+    assert_eq!(root_inode.lookup("new").unwrap().get_size(), 3);
+    drop((root_inode, file));
+    // 只有没有被修改过的早期 inode 的硬链接数量不对
+    assert_eq!(fsck(device, false), [
+        FsckError::WrongLinkCount { inode_id: 0, nlink: 0, expected: 2 },
+        FsckError::WrongLinkCount { inode_id: 1, nlink: 0, expected: 1 },
+    ]);
+}
@@ -4,6 +4,7 @@ use super::{
 };
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
 use spin::Mutex;
 
@@ -73,7 +74,11 @@ impl BlockCache {
         f(self.get_mut(offset))
     }
 
-    // 在我们简单的实现中，sync 仅会在 BlockCache 被 drop 时才会被调用
+    pub fn block_id(&self) -> usize {
+        self.block_id
+    }
+
+    // 被修改过的块只会在所在的事务提交时由日志层调用 sync 写回
     // 但是linux中，sync 并不是只有在 drop 的时候才会被调用
     pub fn sync(&mut self) {
         if self.modified {
@@ -96,8 +101,15 @@ const BLOCK_CACHE_SIZE: usize = 16;
 // 块缓存全局管理器
 // 为了避免在块缓存上浪费过多内存，我们希望内存中同时只能驻留 有限个磁盘块的缓冲区
 pub struct BlockCacheManager {
-    // 块编号和块缓存的二元组
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    // 块设备和块编号构成的键与块缓存的二元组，不同块设备上编号相同的块互不干扰
+    queue: VecDeque<((usize, usize), Arc<Mutex<BlockCache>>)>,
+    // 为新的块缓存分配 Arc 的函数，默认为 Arc::new，库的使用者可以换成自己的对象缓存
+    alloc: fn(Mutex<BlockCache>) -> Arc<Mutex<BlockCache>>,
+}
+
+// 用块设备对象的地址区分不同的块设备。块缓存持有块设备的引用，因此缓存中的块设备地址不会被复用
+fn device_id(block_device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(block_device) as *const () as usize
 }
 
 // 功能:
@@ -107,7 +119,7 @@ pub struct BlockCacheManager {
 // 这里使用一种类 FIFO 的简单缓存替换算法
 impl BlockCacheManager {
     pub fn new() -> Self {
-        Self { queue: VecDeque::new() }
+        Self { queue: VecDeque::new(), alloc: Arc::new }
     }
 
     // 从块缓存管理器中获取一个编号为 block_id 的块的块缓存，如果找不到的话会从磁盘读取到内存中，还有可能会发生缓存替换
@@ -117,9 +129,10 @@ impl BlockCacheManager {
         block_device: Arc<dyn BlockDevice>,
     ) -> Arc<Mutex<BlockCache>> {
         // 遍历整个队列试图找到一个编号相同的块缓存，如果找到了话会将块缓存管理器中保存的块缓存的引用复制一份并返回
+        let key = (device_id(&block_device), block_id);
         if let Some(pair) = self.queue
             .iter()
-            .find(|pair| pair.0 == block_id) {
+            .find(|pair| pair.0 == key) {
                 Arc::clone(&pair.1)
         } else {
             // 找不到的情况，此时必须将块从磁盘读入内存中的缓冲区
@@ -127,29 +140,43 @@ impl BlockCacheManager {
             // 类 FIFO 算法
             // 此时队头对应的块缓存可能仍在使用：判断的标志是其强引用计数 ≥2 ，即除了块缓存管理器保留的一份副本之外，在外面还有若干份副本正在使用
             // 因此，我们的做法是从队头遍历到队尾找到第一个强引用计数恰好为 1 的块缓存并将其替换出去
-            if self.queue.len() == BLOCK_CACHE_SIZE {
+            // 被修改过的块缓存在事务提交之前不能写回，同样不能被替换
+            // 强引用计数为 1 的块缓存不会被其他人锁住，这里可以放心地获取它的锁
+            while self.queue.len() >= BLOCK_CACHE_SIZE {
                 // from front to tail
                 if let Some((idx, _)) = self.queue
                     .iter()
                     .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1) {
+                    .find(|(_, pair)| {
+                        Arc::strong_count(&pair.1) == 1 && !pair.1.lock().modified
+                    }) {
                     self.queue.drain(idx..=idx);
                 } else {
-                    // 要我们的上限 BLOCK_CACHE_SIZE 设置的足够大，超过所有线程同时访问的块总数上限，
-                    // 那么 队列已满且其中所有的块缓存都正在使用的情形 永远不会发生
-                    // 但是，如果我们的上限设置不足，这里我们就只能 panic
-                    panic!("Run out of BlockCache!");
+                    // 队列中所有的块缓存都正在使用或者等待事务提交，暂时超出 BLOCK_CACHE_SIZE
+                    // 提交之后它们又可以被替换出去，队列会逐渐缩回上限以内
+                    break;
                 }
             }
             // 创建一个新的块缓存（会触发 read_block 进行块读取）并加入到队尾，最后返回给请求者
             // load block into mem and push back
-            let block_cache = Arc::new(Mutex::new(
+            let block_cache = (self.alloc)(Mutex::new(
                 BlockCache::new(block_id, Arc::clone(&block_device))
             ));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
+            self.queue.push_back((key, Arc::clone(&block_cache)));
             block_cache
         }
     }
+
+    // 一个块设备上所有被修改过的块缓存，供日志层在提交事务时使用
+    // 这里只复制引用，调用者需要在释放管理器的锁之后再逐个检查
+    fn block_caches_of(&self, block_device: &Arc<dyn BlockDevice>) -> Vec<Arc<Mutex<BlockCache>>> {
+        let device = device_id(block_device);
+        self.queue
+            .iter()
+            .filter(|pair| pair.0 .0 == device)
+            .map(|pair| Arc::clone(&pair.1))
+            .collect()
+    }
 }
 
 // 创建 BlockCacheManager 的全局实例
@@ -159,6 +186,11 @@ lazy_static! {
     );
 }
 
+// 设置为新的块缓存分配 Arc 的函数，只影响之后创建的块缓存
+pub fn set_block_cache_allocator(alloc: fn(Mutex<BlockCache>) -> Arc<Mutex<BlockCache>>) {
+    BLOCK_CACHE_MANAGER.lock().alloc = alloc;
+}
+
 // 请求块缓存
 // 调用者需要通过 .lock() 获取里层互斥锁 Mutex 才能对最里面的 BlockCache 进行操作
 pub fn get_block_cache(
@@ -167,3 +199,17 @@ pub fn get_block_cache(
 ) -> Arc<Mutex<BlockCache>> {
     BLOCK_CACHE_MANAGER.lock().get_block_cache(block_id, block_device)
 }
+
+// 获取一个块设备上自上次提交以来被修改过的所有块缓存，按照块编号排序
+pub fn get_modified_block_caches(
+    block_device: &Arc<dyn BlockDevice>
+) -> Vec<Arc<Mutex<BlockCache>>> {
+    let mut block_caches: Vec<_> = BLOCK_CACHE_MANAGER
+        .lock()
+        .block_caches_of(block_device)
+        .into_iter()
+        .filter(|block_cache| block_cache.lock().modified)
+        .collect();
+    block_caches.sort_by_key(|block_cache| block_cache.lock().block_id);
+    block_caches
+}
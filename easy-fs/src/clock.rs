@@ -0,0 +1,7 @@
+// 时钟的抽象接口
+
+// inode 中的时间戳来自墙上时钟 (wall clock)，与块设备一样由库的使用者提供并接入到 easy-fs 库
+pub trait Clock : Send + Sync {
+    // 当前时间，自 1970-01-01 00:00:00 UTC 以来的秒数
+    fn now(&self) -> u64;
+}
@@ -1,12 +1,19 @@
 use alloc::sync::Arc;
+use alloc::collections::{BTreeMap, BTreeSet};
 use spin::Mutex;
 use super::{
     BlockDevice,
+    Clock,
     Bitmap,
     SuperBlock,
     DiskInode,
     DiskInodeType,
+    InodeFormat,
+    InodeOwner,
     Inode,
+    Journal,
+    JOURNAL_BLOCKS,
+    TRANSACTION_DATA_BLOCKS,
     get_block_cache,
 };
 use crate::BLOCK_SZ;
@@ -20,6 +27,12 @@ pub struct EasyFileSystem {
     pub data_bitmap: Bitmap,
     inode_area_start_block: u32,
     data_area_start_block: u32,
+    inode_format: InodeFormat, // 新创建的 inode 使用的索引格式，由超级块中的版本决定
+    // 每个 inode 当前在内存中有多少个 Inode，以及其中已经没有目录项指向、等待最后一个 Inode 被回收时释放的 inode
+    open_inodes: BTreeMap<u32, usize>,
+    unlinked_inodes: BTreeSet<u32>,
+    journal: Journal,
+    clock: Option<Arc<dyn Clock>>, // 没有时钟时新的时间戳都是 0
 }
 
 type DataBlock = [u8; BLOCK_SZ];
@@ -37,7 +50,7 @@ impl EasyFileSystem {
         let inode_area_blocks =
             ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32;
         let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
-        let data_total_blocks = total_blocks - 1 - inode_total_blocks;
+        let data_total_blocks = total_blocks - 1 - inode_total_blocks - JOURNAL_BLOCKS;
         // 数据块位图区域最合理的大小是剩余的块数除以 4097 再上取整，因为位图中的每个块能够对应 4096 个数据块。其余的块就都作为数据块使用
         let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
         let data_area_blocks = data_total_blocks - data_bitmap_blocks;
@@ -45,67 +58,92 @@ impl EasyFileSystem {
             (1 + inode_bitmap_blocks + inode_area_blocks) as usize,
             data_bitmap_blocks as usize,
         );
-        let mut efs = Self {
-            block_device: Arc::clone(&block_device),
-            inode_bitmap,
-            data_bitmap,
-            inode_area_start_block: 1 + inode_bitmap_blocks,
-            data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
-        };
         // clear all blocks
         // 首先将块设备的前 total_blocks 个块清零，因为我们的 easy-fs 要用到它们，这也是为初始化做准备
+        // 格式化不需要经过日志，清零之后立即写回
         for i in 0..total_blocks {
-            get_block_cache(
+            let block_cache = get_block_cache(
                 i as usize, 
                 Arc::clone(&block_device)
-            )
-            .lock()
-            .modify(0, |data_block: &mut DataBlock| {
+            );
+            let mut block_cache = block_cache.lock();
+            block_cache.modify(0, |data_block: &mut DataBlock| {
                 for byte in data_block.iter_mut() { *byte = 0; }
             });
+            block_cache.sync();
         }
         // initialize SuperBlock
         // 只需传入之前计算得到的每个区域的块数就行了
-        get_block_cache(0, Arc::clone(&block_device))
-        .lock()
-        .modify(0, |super_block: &mut SuperBlock| {
+        // 超级块之后不会再被修改，同样立即写回，打开文件系统时总能据此找到日志区域
+        let super_block_cache = get_block_cache(0, Arc::clone(&block_device));
+        let mut super_block_cache = super_block_cache.lock();
+        // 新创建的 inode 使用的索引格式与打开文件系统时一样由超级块中的版本决定
+        let inode_format = super_block_cache.modify(0, |super_block: &mut SuperBlock| {
             super_block.initialize(
                 total_blocks,
                 inode_bitmap_blocks,
                 inode_area_blocks,
                 data_bitmap_blocks,
                 data_area_blocks,
+                JOURNAL_BLOCKS,
             );
+            super_block.inode_format()
         });
-        // write back immediately
+        super_block_cache.sync();
+        drop(super_block_cache);
+        let mut efs = Self {
+            block_device: Arc::clone(&block_device),
+            inode_bitmap,
+            data_bitmap,
+            inode_area_start_block: 1 + inode_bitmap_blocks,
+            data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            inode_format,
+            open_inodes: BTreeMap::new(),
+            unlinked_inodes: BTreeSet::new(),
+            journal: Journal::new(
+                (total_blocks - JOURNAL_BLOCKS) as usize,
+                JOURNAL_BLOCKS as usize,
+            ),
+            clock: None,
+        };
+        // 根目录及其目录项的初始化作为第一个事务在 initialize_dir 中提交
         // create a inode for root node "/"
         // 创建根目录 /
-        assert_eq!(efs.alloc_inode(), 0); // 第一次分配，它的编号固定是 0
+        assert_eq!(efs.alloc_inode(), Some(0)); // 第一次分配，它的编号固定是 0
         // 根据 inode 编号获取该 inode 所在的块的编号以及块内偏移
         let (root_inode_block_id, root_inode_offset) = efs.get_disk_inode_pos(0);
-        // easy-fs 中的唯一一个目录
+        // 初始化为一个目录
         get_block_cache(
             root_inode_block_id as usize,
             Arc::clone(&block_device)
         )
         .lock()
         .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
-            disk_inode.initialize(DiskInodeType::Directory);
+            disk_inode.initialize(
+                DiskInodeType::Directory,
+                efs.inode_format,
+                efs.now(),
+                InodeOwner::root(0o755),
+            );
         });
-        Arc::new(Mutex::new(efs))
+        let efs = Arc::new(Mutex::new(efs));
+        // 根目录的 ".." 指向它自己
+        Self::root_inode(&efs).initialize_dir(0);
+        efs
     }
     
     // 从一个已写入了 easy-fs 镜像的块设备上打开我们的 easy-fs 
+    // 上次没有正常写回的已提交事务在这里根据日志重做
     pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
         // read SuperBlock
         // 将块设备编号为 0 的块作为超级块读取进来，就可以从中知道 easy-fs 的磁盘布局，由此可以构造 efs 实例
-        get_block_cache(0, Arc::clone(&block_device))
+        let efs = get_block_cache(0, Arc::clone(&block_device))
             .lock()
             .read(0, |super_block: &SuperBlock| {
                 assert!(super_block.is_valid(), "Error loading EFS!");
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
-                let efs = Self {
+                Self {
                     block_device,
                     inode_bitmap: Bitmap::new(
                         1,
@@ -117,25 +155,33 @@ impl EasyFileSystem {
                     ),
                     inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
                     data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
-                };
-                Arc::new(Mutex::new(efs))
-            })        
+                    inode_format: super_block.inode_format(),
+                    open_inodes: BTreeMap::new(),
+                    unlinked_inodes: BTreeSet::new(),
+                    journal: Journal::new(
+                        (super_block.total_blocks - super_block.journal_blocks) as usize,
+                        super_block.journal_blocks as usize,
+                    ),
+                    clock: None,
+                }
+            });
+        efs.journal.replay(&efs.block_device);
+        Arc::new(Mutex::new(efs))
     }
 
     // 获取根目录的 Inode
     pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
         // 根目录对应于文件系统中第一个分配的 inode ，因此它的 inode_id 总会是 0
-        let block_device = Arc::clone(&efs.lock().block_device);
         // acquire efs lock temporarily
-        let (block_id, block_offset) = efs.lock().get_disk_inode_pos(0);
-        // release efs lock
+        let mut fs = efs.lock();
+        let block_device = Arc::clone(&fs.block_device);
         Inode::new(
             0,
-            block_id,
-            block_offset,
+            &mut fs,
             Arc::clone(efs),
             block_device,
         )
+        // release efs lock
     }
 
     /*
@@ -156,17 +202,37 @@ impl EasyFileSystem {
     }
 
     // inode 和数据块的分配/回收也由它负责
-    pub fn alloc_inode(&mut self) -> u32 {
-        self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
+    pub fn inode_format(&self) -> InodeFormat {
+        self.inode_format
     }
 
-    /// Return a block ID not ID in the data area.
-    pub fn alloc_data(&mut self) -> u32 {
-        self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
+    // inode 用尽时返回 None
+    pub fn alloc_inode(&mut self) -> Option<u32> {
+        self.inode_bitmap.alloc(&self.block_device).map(|inode_id| inode_id as u32)
     }
 
-    // 传入/返回的参数都表示数据块在块设备上的编号，而不是在数据块位图中分配的比特编号
-    pub fn dealloc_data(&mut self, block_id: u32) {
+    // 设置之后 inode 的时间戳从这个时钟获得
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = Some(clock);
+    }
+
+    // 写入 inode 的时间戳，超过 u32 的表示范围时取 u32::MAX
+    pub fn now(&self) -> u32 {
+        self.clock
+            .as_ref()
+            .map_or(0, |clock| clock.now().min(u32::MAX as u64) as u32)
+    }
+
+    // 提交自上次提交以来对块缓存的所有修改，每个修改文件系统的操作在释放 efs 的锁之前调用
+    pub fn commit(&self) {
+        self.journal.commit(&self.block_device);
+    }
+
+    /// Return a block ID not ID in the data area.
+    // 新分配的块被清零，文件中尚未写入的部分读出来总是 0
+    pub fn alloc_data(&mut self) -> u32 {
+        let block_id = self.data_bitmap.alloc(&self.block_device).unwrap() as u32
+            + self.data_area_start_block;
         get_block_cache(
             block_id as usize,
             Arc::clone(&self.block_device)
@@ -175,6 +241,70 @@ impl EasyFileSystem {
         .modify(0, |data_block: &mut DataBlock| {
             data_block.iter_mut().for_each(|p| { *p = 0; })
         });
+        block_id
+    }
+
+    // 回收一个 inode 以及它的所有数据块和索引块
+    // 最后一批块和 inode 本身在同一个事务中释放，之前崩溃时留下一个变短了的孤儿 inode
+    pub fn dealloc_inode(&mut self, inode_id: u32) {
+        self.truncate_inode(inode_id, 0);
+        self.inode_bitmap.dealloc(&self.block_device, inode_id as usize);
+    }
+
+    // 把 inode 缩小到 new_size 字节，从文件末尾开始每次释放至多 TRANSACTION_DATA_BLOCKS 个数据块
+    // 每一批在缩小 size 的同一个事务中回收，崩溃之后文件只是变短了，不会有块被泄漏或重复引用
+    // 除最后一批以外的每一批都会被提交，最后一批留给调用者和其他修改一起提交
+    pub fn truncate_inode(&mut self, inode_id: u32, new_size: usize) {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        let block_cache = get_block_cache(block_id as usize, Arc::clone(&self.block_device));
+        loop {
+            let (size, blocks_dealloc) = block_cache
+                .lock()
+                .modify(block_offset, |disk_inode: &mut DiskInode| {
+                    let data_blocks = disk_inode.data_blocks() as usize;
+                    let size = new_size.max(
+                        data_blocks.saturating_sub(TRANSACTION_DATA_BLOCKS) * BLOCK_SZ
+                    );
+                    (size, disk_inode.decrease_size(size, &self.block_device))
+                });
+            for block_id in blocks_dealloc {
+                self.dealloc_data(block_id);
+            }
+            if size == new_size {
+                break;
+            }
+            self.commit();
+        }
+    }
+
+    // 内存中的 Inode 被创建和回收时维护 open_inodes
+    pub(crate) fn open_inode(&mut self, inode_id: u32) {
+        *self.open_inodes.entry(inode_id).or_insert(0) += 1;
+    }
+
+    pub(crate) fn close_inode(&mut self, inode_id: u32) {
+        let count = self.open_inodes.get_mut(&inode_id).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.open_inodes.remove(&inode_id);
+            // 最后一个 Inode 被回收，延迟的释放在此时进行
+            if self.unlinked_inodes.remove(&inode_id) {
+                self.dealloc_inode(inode_id);
+            }
+        }
+    }
+
+    // 最后一个目录项被删除时调用：inode 仍被打开时推迟到它被关闭之后再释放
+    pub(crate) fn release_inode(&mut self, inode_id: u32) {
+        if self.open_inodes.contains_key(&inode_id) {
+            self.unlinked_inodes.insert(inode_id);
+        } else {
+            self.dealloc_inode(inode_id);
+        }
+    }
+
+    // 传入/返回的参数都表示数据块在块设备上的编号，而不是在数据块位图中分配的比特编号
+    pub fn dealloc_data(&mut self, block_id: u32) {
         self.data_bitmap.dealloc(
             &self.block_device,
             (block_id - self.data_area_start_block) as usize
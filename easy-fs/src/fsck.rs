@@ -0,0 +1,441 @@
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+use spin::Mutex;
+use super::{
+    BLOCK_SZ,
+    BlockDevice,
+    DirEntry,
+    DirentBytes,
+    DiskInode,
+    EasyFileSystem,
+    SuperBlock,
+    DIRENT_SZ,
+    JOURNAL_BLOCKS,
+    get_block_cache,
+};
+
+// 文件系统一致性检查 (fsck)
+// 从根目录出发遍历所有目录，把能够到达的 inode 及其用到的块与两个位图相互核对
+// 修复时重建位图、删除有问题的目录项、修正硬链接数量，并把孤儿 inode 放入根目录下的 lost+found
+
+// 每个位图块中的比特数
+const BLOCK_BITS: usize = BLOCK_SZ * 8;
+
+type DataBlock = [u8; BLOCK_SZ];
+
+#[derive(Debug, PartialEq)]
+pub enum FsckError {
+    // 超级块中的魔数、版本或者各个区域的长度不合法，无法继续检查
+    BadSuperBlock,
+    // 根目录的 inode 不是一个目录
+    BadRoot,
+    // 文件大小超出上限，或者目录的大小不是目录项大小的整数倍
+    BadSize(u32),
+    // inode 索引到了数据块区域之外的块
+    BadBlock { inode_id: u32, block_id: u32 },
+    // 同一个块被两个 inode 使用
+    DoubleAllocated { block_id: u32, inode_ids: (u32, u32) },
+    // 正在使用的 inode 或块在位图中没有被标记
+    UnmarkedInode(u32),
+    UnmarkedBlock(u32),
+    // 目录项的名字不合法
+    BadDirEntry { dir: u32, index: usize },
+    // 目录项指向一个不存在的 inode
+    DanglingDirEntry { dir: u32, name: String, inode_id: u32 },
+    // "." 或 ".." 没有指向目录自身或者父目录
+    BadDotEntry { dir: u32, name: String },
+    // 目录的第二个硬链接
+    DirectoryLink { dir: u32, name: String, inode_id: u32 },
+    WrongLinkCount { inode_id: u32, nlink: u16, expected: u16 },
+    // 在位图中被标记但是从根目录无法到达的 inode
+    OrphanInode(u32),
+    // 在位图中被标记但是没有被任何 inode 使用的块
+    LeakedBlock(u32),
+}
+
+// 检查一个块设备上的 easy-fs，返回发现的所有问题，打开时会先根据日志重做已经提交的事务
+// repair 为 true 时在检查之后进行修复，同一个块被多个 inode 使用以及索引到区域之外的块只报告不修复
+pub fn fsck(block_device: Arc<dyn BlockDevice>, repair: bool) -> Vec<FsckError> {
+    if !check_super_block(&block_device) {
+        return vec![FsckError::BadSuperBlock];
+    }
+    let efs = EasyFileSystem::open(block_device);
+    let (errors, orphan_roots) = {
+        let fs = efs.lock();
+        let checker = Checker::new(&fs).scan();
+        if repair && !checker.errors.is_empty() && !checker.errors.contains(&FsckError::BadRoot) {
+            // 先按照检查结果重建位图并删除有问题的目录项，之后创建 lost+found 时
+            // 不会分配到仍在使用中的 inode 和块，也不会被悬空的目录项指向
+            checker.rebuild_bitmaps(
+                |inode_id| {
+                    checker.reached.contains_key(&inode_id) || checker.orphans.contains(&inode_id)
+                },
+                |block_id| checker.owners.contains_key(&block_id),
+            );
+            checker.fix_dirents();
+        }
+        (checker.errors, checker.orphan_roots)
+    };
+    if !repair || errors.is_empty() || errors.contains(&FsckError::BadRoot) {
+        return errors;
+    }
+    move_to_lost_found(&efs, &orphan_roots);
+    // 孤儿目录的 ".." 在这一次检查中被修正为 lost+found
+    Checker::new(&efs.lock()).scan().fix_dirents();
+    // 最后只保留从根目录能够到达的 inode 和块
+    let fs = efs.lock();
+    let checker = Checker::new(&fs).scan();
+    checker.rebuild_bitmaps(
+        |inode_id| checker.reached.contains_key(&inode_id),
+        |block_id| {
+            checker.owners
+                .get(&block_id)
+                .map_or(false, |owner| checker.reached.contains_key(owner))
+        },
+    );
+    checker.fix_link_counts();
+    errors
+}
+
+// 各个区域的长度之和必须等于总块数，并且位图足以覆盖对应的区域
+fn check_super_block(block_device: &Arc<dyn BlockDevice>) -> bool {
+    get_block_cache(0, Arc::clone(block_device))
+        .lock()
+        .read(0, |super_block: &SuperBlock| {
+            let inodes_per_block = (BLOCK_SZ / core::mem::size_of::<DiskInode>()) as u64;
+            let inode_bitmap_blocks = super_block.inode_bitmap_blocks as u64;
+            let inode_area_blocks = super_block.inode_area_blocks as u64;
+            let data_bitmap_blocks = super_block.data_bitmap_blocks as u64;
+            let data_area_blocks = super_block.data_area_blocks as u64;
+            let journal_blocks = super_block.journal_blocks as u64;
+            super_block.is_valid()
+                && 1 + inode_bitmap_blocks + inode_area_blocks + data_bitmap_blocks
+                    + data_area_blocks + journal_blocks == super_block.total_blocks as u64
+                && inode_bitmap_blocks * BLOCK_BITS as u64 <= inode_area_blocks * inodes_per_block
+                && data_area_blocks <= data_bitmap_blocks * BLOCK_BITS as u64
+                && (journal_blocks == 0 || journal_blocks == JOURNAL_BLOCKS as u64)
+        })
+}
+
+// 把孤儿放入 lost+found，目录项的名字是 "#" 加上 inode 编号
+fn move_to_lost_found(efs: &Arc<Mutex<EasyFileSystem>>, orphans: &[u32]) {
+    if orphans.is_empty() {
+        return;
+    }
+    let root_inode = EasyFileSystem::root_inode(efs);
+    let lost_found = match root_inode
+        .find("lost+found")
+        .or_else(|| root_inode.create_dir("lost+found")) {
+        Some(lost_found) if lost_found.is_dir() => lost_found,
+        _ => return,
+    };
+    for &inode_id in orphans {
+        lost_found.add_dirent(&format!("#{}", inode_id), inode_id);
+    }
+}
+
+struct Checker<'a> {
+    fs: &'a EasyFileSystem,
+    block_device: Arc<dyn BlockDevice>,
+    inode_count: u32,
+    data_area: Range<u32>,
+    // 从根目录可达的 inode 以及指向它的目录项个数
+    reached: BTreeMap<u32, u16>,
+    // 被使用的块以及第一个使用它的 inode
+    owners: BTreeMap<u32, u32>,
+    // 可以恢复的孤儿，以及其中没有被其他孤儿目录引用、需要放入 lost+found 的那些
+    orphans: BTreeSet<u32>,
+    orphan_roots: Vec<u32>,
+    // 需要改写的目录项：所在目录、序号以及新的内容，None 表示删除
+    dirent_fixes: Vec<(u32, usize, Option<(&'static str, u32)>)>,
+    errors: Vec<FsckError>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(fs: &'a EasyFileSystem) -> Self {
+        let block_device = Arc::clone(&fs.block_device);
+        let (inode_count, data_area) = get_block_cache(0, Arc::clone(&block_device))
+            .lock()
+            .read(0, |super_block: &SuperBlock| {
+                let inodes_per_block = (BLOCK_SZ / core::mem::size_of::<DiskInode>()) as u32;
+                let data_area_start = 1 + super_block.inode_bitmap_blocks
+                    + super_block.inode_area_blocks + super_block.data_bitmap_blocks;
+                (
+                    (super_block.inode_bitmap_blocks * BLOCK_BITS as u32)
+                        .min(super_block.inode_area_blocks * inodes_per_block),
+                    data_area_start..data_area_start + super_block.data_area_blocks,
+                )
+            });
+        Self {
+            fs,
+            block_device,
+            inode_count,
+            data_area,
+            reached: BTreeMap::new(),
+            owners: BTreeMap::new(),
+            orphans: BTreeSet::new(),
+            orphan_roots: Vec::new(),
+            dirent_fixes: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    // 编号或内容不合法的 inode 返回 None
+    fn read_inode<V>(&self, inode_id: u32, f: impl FnOnce(&DiskInode) -> V) -> Option<V> {
+        if inode_id >= self.inode_count {
+            return None;
+        }
+        let (block_id, block_offset) = self.fs.get_disk_inode_pos(inode_id);
+        let block_cache = get_block_cache(block_id as usize, Arc::clone(&self.block_device));
+        let block_cache = block_cache.lock();
+        if block_cache.read(0, |data: &DataBlock| DiskInode::is_valid_raw(&data[block_offset..])) {
+            Some(block_cache.read(block_offset, f))
+        } else {
+            None
+        }
+    }
+
+    fn modify_inode(&self, inode_id: u32, f: impl FnOnce(&mut DiskInode)) {
+        let (block_id, block_offset) = self.fs.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(block_offset, f);
+        self.fs.commit();
+    }
+
+    // 目录项指向的 inode 是否存在，存在时返回它是不是目录
+    // 位图中没有标记但硬链接数量不为 0 的 inode 仍被认为存在，由位图的检查报告
+    fn check_target(&self, inode_id: u32) -> Option<bool> {
+        let (is_dir, nlink) = self.read_inode(inode_id, |disk_inode| {
+            (disk_inode.is_dir(), disk_inode.nlink)
+        })?;
+        if nlink == 0 && !self.fs.inode_bitmap.is_allocated(&self.block_device, inode_id as usize) {
+            return None;
+        }
+        Some(is_dir)
+    }
+
+    // 检查 inode 的大小以及用到的块，记录这些块属于它。返回它的内容能否被安全地读取
+    fn claim_blocks(&mut self, inode_id: u32) -> bool {
+        let data_area = self.data_area.clone();
+        let mut blocks = Vec::new();
+        let mut bad_blocks = Vec::new();
+        let block_device = Arc::clone(&self.block_device);
+        let size_ok = self.read_inode(inode_id, |disk_inode| {
+            let size = disk_inode.size();
+            if size > disk_inode.max_size() || disk_inode.is_dir() && size % DIRENT_SZ != 0 {
+                return false;
+            }
+            disk_inode.visit_blocks(&block_device, &mut |block_id| {
+                if data_area.contains(&block_id) {
+                    blocks.push(block_id);
+                    true
+                } else {
+                    bad_blocks.push(block_id);
+                    false
+                }
+            });
+            true
+        }).unwrap();
+        if !size_ok {
+            self.errors.push(FsckError::BadSize(inode_id));
+            return false;
+        }
+        for &block_id in bad_blocks.iter() {
+            self.errors.push(FsckError::BadBlock { inode_id, block_id });
+        }
+        for block_id in blocks {
+            if let Some(&owner) = self.owners.get(&block_id) {
+                self.errors.push(FsckError::DoubleAllocated {
+                    block_id,
+                    inode_ids: (owner, inode_id),
+                });
+                continue;
+            }
+            self.owners.insert(block_id, inode_id);
+            let bit = (block_id - data_area.start) as usize;
+            if !self.fs.data_bitmap.is_allocated(&self.block_device, bit) {
+                self.errors.push(FsckError::UnmarkedBlock(block_id));
+            }
+        }
+        bad_blocks.is_empty()
+    }
+
+    fn check_inode(&mut self, inode_id: u32) -> bool {
+        if !self.fs.inode_bitmap.is_allocated(&self.block_device, inode_id as usize) {
+            self.errors.push(FsckError::UnmarkedInode(inode_id));
+        }
+        self.claim_blocks(inode_id)
+    }
+
+    // 目录中所有非空的目录项，名字不合法的为 None
+    fn read_dirents(&self, dir: u32) -> Vec<(usize, Option<(String, u32)>)> {
+        self.read_inode(dir, |dir_inode| {
+            let mut v = Vec::new();
+            for i in 0..dir_inode.size() / DIRENT_SZ {
+                let mut dirent_bytes: DirentBytes = Default::default();
+                dir_inode.read_at(i * DIRENT_SZ, &mut dirent_bytes, &self.block_device);
+                let dirent = DirEntry::from_bytes(&dirent_bytes);
+                if dirent.is_empty() {
+                    continue;
+                }
+                v.push((i, if dirent.is_valid() {
+                    Some((String::from(dirent.name()), dirent.inode_number()))
+                } else {
+                    None
+                }));
+            }
+            v
+        }).unwrap()
+    }
+
+    fn scan(mut self) -> Self {
+        if self.read_inode(0, |disk_inode| disk_inode.is_dir()) != Some(true) {
+            self.errors.push(FsckError::BadRoot);
+            return self;
+        }
+        // 从根目录出发广度优先遍历，队列中保存目录以及它的父目录
+        let mut queue = VecDeque::new();
+        self.reached.insert(0, 0);
+        if self.check_inode(0) {
+            queue.push_back((0, 0));
+        }
+        while let Some((dir, parent)) = queue.pop_front() {
+            for (index, dirent) in self.read_dirents(dir) {
+                let (name, inode_id) = match dirent {
+                    Some(dirent) => dirent,
+                    None => {
+                        self.errors.push(FsckError::BadDirEntry { dir, index });
+                        self.dirent_fixes.push((dir, index, None));
+                        continue;
+                    }
+                };
+                // "." 和 ".." 按照它们应当指向的目录计数
+                if name == "." || name == ".." {
+                    let (dot, expected) = if name == "." { (".", dir) } else { ("..", parent) };
+                    *self.reached.get_mut(&expected).unwrap() += 1;
+                    if inode_id != expected {
+                        self.errors.push(FsckError::BadDotEntry { dir, name });
+                        self.dirent_fixes.push((dir, index, Some((dot, expected))));
+                    }
+                    continue;
+                }
+                let is_dir = match self.check_target(inode_id) {
+                    Some(is_dir) => is_dir,
+                    None => {
+                        self.errors.push(FsckError::DanglingDirEntry { dir, name, inode_id });
+                        self.dirent_fixes.push((dir, index, None));
+                        continue;
+                    }
+                };
+                if let Some(count) = self.reached.get_mut(&inode_id) {
+                    if is_dir {
+                        self.errors.push(FsckError::DirectoryLink { dir, name, inode_id });
+                        self.dirent_fixes.push((dir, index, None));
+                    } else {
+                        *count += 1;
+                    }
+                    continue;
+                }
+                self.reached.insert(inode_id, 1);
+                if self.check_inode(inode_id) && is_dir {
+                    queue.push_back((inode_id, dir));
+                }
+            }
+        }
+        for (&inode_id, &expected) in self.reached.iter() {
+            let nlink = self.read_inode(inode_id, |disk_inode| disk_inode.nlink).unwrap();
+            if nlink != expected {
+                self.errors.push(FsckError::WrongLinkCount { inode_id, nlink, expected });
+            }
+        }
+        // 孤儿中被其他孤儿目录引用的会随着那个目录一起恢复
+        let mut referenced = BTreeSet::new();
+        for inode_id in 0..self.inode_count {
+            if self.reached.contains_key(&inode_id)
+                || !self.fs.inode_bitmap.is_allocated(&self.block_device, inode_id as usize) {
+                continue;
+            }
+            self.errors.push(FsckError::OrphanInode(inode_id));
+            let is_dir = match self.read_inode(inode_id, |disk_inode| disk_inode.is_dir()) {
+                Some(is_dir) => is_dir,
+                None => continue,
+            };
+            if !self.claim_blocks(inode_id) {
+                continue;
+            }
+            self.orphans.insert(inode_id);
+            if is_dir {
+                for (_, dirent) in self.read_dirents(inode_id) {
+                    if let Some((name, child)) = dirent {
+                        if name != "." && name != ".." {
+                            referenced.insert(child);
+                        }
+                    }
+                }
+            }
+        }
+        self.orphan_roots = self.orphans
+            .iter()
+            .filter(|inode_id| !referenced.contains(*inode_id))
+            .copied()
+            .collect();
+        for block_id in self.data_area.clone() {
+            let bit = (block_id - self.data_area.start) as usize;
+            if !self.owners.contains_key(&block_id)
+                && self.fs.data_bitmap.is_allocated(&self.block_device, bit) {
+                self.errors.push(FsckError::LeakedBlock(block_id));
+            }
+        }
+        self
+    }
+
+    // 每个位图块的修改作为一个事务提交
+    fn rebuild_bitmaps(
+        &self,
+        inode_in_use: impl Fn(u32) -> bool,
+        block_in_use: impl Fn(u32) -> bool,
+    ) {
+        for inode_id in 0..self.inode_count {
+            let bit = inode_id as usize;
+            self.fs.inode_bitmap.set_allocated(&self.block_device, bit, inode_in_use(inode_id));
+            if (bit + 1) % BLOCK_BITS == 0 {
+                self.fs.commit();
+            }
+        }
+        self.fs.commit();
+        for block_id in self.data_area.clone() {
+            let bit = (block_id - self.data_area.start) as usize;
+            self.fs.data_bitmap.set_allocated(&self.block_device, bit, block_in_use(block_id));
+            if (bit + 1) % BLOCK_BITS == 0 {
+                self.fs.commit();
+            }
+        }
+        self.fs.commit();
+    }
+
+    fn fix_dirents(&self) {
+        for &(dir, index, replacement) in self.dirent_fixes.iter() {
+            let dirent = match replacement {
+                Some((name, inode_id)) => DirEntry::new(name, inode_id),
+                None => DirEntry::empty(),
+            };
+            self.modify_inode(dir, |dir_inode| {
+                dir_inode.write_at(index * DIRENT_SZ, dirent.into_bytes(), &self.block_device);
+            });
+        }
+    }
+
+    fn fix_link_counts(&self) {
+        for error in self.errors.iter() {
+            if let FsckError::WrongLinkCount { inode_id, expected, .. } = *error {
+                self.modify_inode(inode_id, |disk_inode| disk_inode.nlink = expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+use super::{
+    BLOCK_SZ,
+    BlockCache,
+    BlockDevice,
+    get_block_cache,
+    get_modified_block_caches,
+};
+
+// 块缓存层之上的预写日志 (write-ahead log)
+
+// 日志区域位于文件系统的最后，第一个块是提交记录，其余的块依次保存一个事务修改过的块的新内容
+// 一次 VFS 操作中对块缓存的所有修改构成一个事务，在操作结束时一起提交：
+// 1. 将所有被修改的块写入日志区域
+// 2. 写入提交记录，其中包含这些块的块编号以及覆盖块编号和块内容的校验和，这是事务生效的时间点
+// 3. 将这些块写回它们原本的位置
+// 4. 清空提交记录
+// 在任何一步崩溃之后，重新打开文件系统时只要提交记录完整且校验和正确，就重新执行第 3 步，否则事务就像从未发生过
+
+const JOURNAL_MAGIC: u32 = 0x6a726e6c;
+// 一个提交记录最多能记录的块编号个数，也就是一个事务最多能修改的块数
+pub const JOURNAL_CAPACITY: usize = BLOCK_SZ / 4 - 3;
+// 日志区域的块数
+pub const JOURNAL_BLOCKS: u32 = 1 + JOURNAL_CAPACITY as u32;
+// 每个事务中最多分配、释放或写入的数据块个数
+// 加上途经的索引块、位图块和 inode 所在的块，修改的块数仍然在 JOURNAL_CAPACITY 以内
+pub const TRANSACTION_DATA_BLOCKS: usize = 32;
+
+type DataBlock = [u8; BLOCK_SZ];
+
+#[repr(C)]
+struct CommitRecord {
+    magic: u32,
+    count: u32, // 事务中修改的块数，0 表示没有待重做的事务
+    checksum: u32,
+    block_ids: [u32; JOURNAL_CAPACITY],
+}
+
+impl CommitRecord {
+    fn empty() -> Self {
+        Self {
+            magic: 0,
+            count: 0,
+            checksum: 0,
+            block_ids: [0; JOURNAL_CAPACITY],
+        }
+    }
+    fn as_bytes(&self) -> &DataBlock {
+        unsafe { &*(self as *const Self as usize as *const DataBlock) }
+    }
+    fn as_bytes_mut(&mut self) -> &mut DataBlock {
+        unsafe { &mut *(self as *mut Self as usize as *mut DataBlock) }
+    }
+    fn block_ids(&self) -> &[u32] {
+        &self.block_ids[..self.count as usize]
+    }
+    // 校验和覆盖块数、块编号以及日志区域中对应的块内容
+    fn compute_checksum<'a>(&self, blocks: impl Iterator<Item = &'a DataBlock>) -> u32 {
+        let mut crc = crc32(!0, &self.count.to_le_bytes());
+        for block_id in self.block_ids() {
+            crc = crc32(crc, &block_id.to_le_bytes());
+        }
+        for block in blocks {
+            crc = crc32(crc, block);
+        }
+        !crc
+    }
+}
+
+// 按位计算的 CRC-32 (IEEE 802.3)，调用者负责初值和最后的取反
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+pub struct Journal {
+    start_block: usize, // 提交记录所在的块编号，之后的块用于保存日志
+    blocks: usize, // 日志区域的块数，早期没有日志的镜像中为 0
+}
+
+impl Journal {
+    pub fn new(start_block: usize, blocks: usize) -> Self {
+        // 提交记录恰好占满一个块
+        assert_eq!(core::mem::size_of::<CommitRecord>(), BLOCK_SZ);
+        assert!(blocks == 0 || blocks == JOURNAL_BLOCKS as usize);
+        Self { start_block, blocks }
+    }
+
+    // 提交当前事务：块设备上所有被修改过的块缓存都属于这个事务
+    // 没有日志区域时直接写回，此时不能保证崩溃一致性
+    // 修改操作都按照 TRANSACTION_DATA_BLOCKS 分批提交，事务不应超过日志的容量
+    // 万一超过，就按照日志的容量分成几次依次提交，这个事务不再是原子的，但不会使内核崩溃
+    pub fn commit(&self, block_device: &Arc<dyn BlockDevice>) {
+        let block_caches = get_modified_block_caches(block_device);
+        for block_caches in block_caches.chunks(JOURNAL_CAPACITY) {
+            self.commit_blocks(block_device, block_caches);
+        }
+    }
+
+    fn commit_blocks(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        block_caches: &[Arc<Mutex<BlockCache>>],
+    ) {
+        if self.blocks > 0 {
+            let mut record = CommitRecord::empty();
+            let mut checksum_blocks = Vec::new();
+            for (i, block_cache) in block_caches.iter().enumerate() {
+                let block_cache = block_cache.lock();
+                let data = block_cache.read(0, |data: &DataBlock| *data);
+                block_device.write_block(self.start_block + 1 + i, &data);
+                record.block_ids[i] = block_cache.block_id() as u32;
+                checksum_blocks.push(data);
+            }
+            record.count = block_caches.len() as u32;
+            record.checksum = record.compute_checksum(checksum_blocks.iter());
+            record.magic = JOURNAL_MAGIC;
+            block_device.write_block(self.start_block, record.as_bytes());
+        }
+        for block_cache in block_caches.iter() {
+            block_cache.lock().sync();
+        }
+        if self.blocks > 0 {
+            block_device.write_block(self.start_block, CommitRecord::empty().as_bytes());
+        }
+    }
+
+    // 打开文件系统时重做已经提交但可能尚未全部写回的事务
+    pub fn replay(&self, block_device: &Arc<dyn BlockDevice>) {
+        if self.blocks == 0 {
+            return;
+        }
+        let mut record = CommitRecord::empty();
+        block_device.read_block(self.start_block, record.as_bytes_mut());
+        if record.magic != JOURNAL_MAGIC || record.count as usize > JOURNAL_CAPACITY {
+            return;
+        }
+        let mut blocks = vec![[0u8; BLOCK_SZ]; record.count as usize];
+        for (i, block) in blocks.iter_mut().enumerate() {
+            block_device.read_block(self.start_block + 1 + i, block);
+        }
+        // 提交记录没有完整写入时校验和不会匹配，这个事务被丢弃
+        if record.compute_checksum(blocks.iter()) != record.checksum {
+            return;
+        }
+        // 通过块缓存写回，使缓存中可能存在的旧内容也得到更新
+        for (&block_id, block) in record.block_ids().iter().zip(blocks.iter()) {
+            let block_cache = get_block_cache(block_id as usize, Arc::clone(block_device));
+            let mut block_cache = block_cache.lock();
+            block_cache.modify(0, |data: &mut DataBlock| *data = *block);
+            block_cache.sync();
+        }
+        block_device.write_block(self.start_block, CommitRecord::empty().as_bytes());
+    }
+}
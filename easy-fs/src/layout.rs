@@ -9,21 +9,32 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 const EFS_MAGIC: u32 = 0x3b800001;
-const INODE_DIRECT_COUNT: usize = 28;
-const NAME_LENGTH_LIMIT: usize = 27;
-const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
-const INODE_INDIRECT2_COUNT: usize = INODE_INDIRECT1_COUNT * INODE_INDIRECT1_COUNT;
-const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
-const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
+// 磁盘格式版本。早期的镜像中超级块没有 version 字段，读出来是 0
 #[allow(unused)]
-const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
+pub const EFS_VERSION_ORIGINAL: u32 = 0;
+pub const EFS_VERSION_LARGE_FILE: u32 = 1; // 新创建的 inode 使用 InodeFormat::Large
+#[allow(unused)]
+pub const EFS_VERSION_JOURNAL: u32 = 2; // 文件系统最后保留一段日志区域
+pub const EFS_VERSION_TIMESTAMPS: u32 = 3; // 新创建的 inode 使用 InodeFormat::Timed
+pub const EFS_VERSION_PERMISSIONS: u32 = 4; // 新创建的 inode 使用 InodeFormat::Owned
+pub const EFS_VERSION_HUGE_FILE: u32 = 5; // 新创建的 inode 使用 InodeFormat::Huge
+pub const EFS_VERSION: u32 = EFS_VERSION_HUGE_FILE; // 新创建的镜像使用的版本
+const INODE_POINTER_COUNT: usize = 30;
+pub const NAME_LENGTH_LIMIT: usize = 27;
+pub const SYMLINK_LENGTH_LIMIT: usize = 4095; // 符号链接保存的目标路径的最大字节数，与 Linux 的 PATH_MAX 相同
+const INDEX_FANOUT: usize = BLOCK_SZ / 4; // 每个索引块中的块编号个数
+const DISK_INODE_TYPE_OFFSET: usize = 4 + 4 * INODE_POINTER_COUNT; // DiskInode 中 type_ 和 format 所在的字节
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+const TIME_SLOTS: usize = 3; // blocks 中块编号之后依次是 3 个时间戳、3 个所有者字段和 size 的高 32 位
+const OWNER_SLOTS: usize = 3;
 
 // 在 easy-fs 磁盘布局中，按照块编号从小到大可以分成 5 个连续区域
 // 最开始的区域长度为一个块，其内容是 easy-fs 超级块 (Super Block)，超级块内以魔数的形式提供了文件系统合法性检查功能，同时还可以定位其他连续区域的位置
 // 接下来的一个区域是一个索引节点位图, 长度为若干个块。它记录了后面的索引节点区域中有哪些索引节点已经被分配出去使用了，而哪些还尚未被分配出去
 // 接下来的一个区域是索引节点区域，长度为若干个块。其中的每个块都存储了若干个索引节点
 // 接下来的一个区域是一个数据块位图，长度为若干个块。它记录了后面的数据块区域中有哪些数据块已经被分配出去使用了，而哪些还尚未被分配出去。
-// 最后的一个区域则是数据块区域，顾名思义，其中的每一个块的职能都是作为一个数据块实际保存文件或目录中的数据。
+// 接下来的一个区域则是数据块区域，顾名思义，其中的每一个块的职能都是作为一个数据块实际保存文件或目录中的数据。
+// 从 EFS_VERSION_JOURNAL 开始，数据块区域之后还有一个日志区域，用于保证元数据修改的崩溃一致性
 
 #[repr(C)]
 pub struct SuperBlock {
@@ -33,6 +44,8 @@ pub struct SuperBlock {
     pub inode_area_blocks: u32,
     pub data_bitmap_blocks: u32,
     pub data_area_blocks: u32,
+    pub version: u32,
+    pub journal_blocks: u32, // 位于数据块区域之后的日志区域的长度，早期镜像中为 0
 }
 
 impl Debug for SuperBlock {
@@ -43,6 +56,8 @@ impl Debug for SuperBlock {
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
             .field("data_area_blocks", &self.data_area_blocks)
+            .field("version", &self.version)
+            .field("journal_blocks", &self.journal_blocks)
             .finish()
     }
 }
@@ -55,6 +70,7 @@ impl SuperBlock {
         inode_area_blocks: u32,
         data_bitmap_blocks: u32,
         data_area_blocks: u32,
+        journal_blocks: u32,
     ) {
         *self = Self {
             magic: EFS_MAGIC,
@@ -63,51 +79,178 @@ impl SuperBlock {
             inode_area_blocks,
             data_bitmap_blocks,
             data_area_blocks,
+            version: EFS_VERSION,
+            journal_blocks,
         }
     }
-    // 通过魔数判断超级块所在的 文件系统 是否合法
+    // 通过魔数判断超级块所在的 文件系统 是否合法，比当前实现更新的格式不能被挂载
     pub fn is_valid(&self) -> bool {
-        self.magic == EFS_MAGIC
+        self.magic == EFS_MAGIC && self.version <= EFS_VERSION
+    }
+    // 在这个文件系统中新创建的 inode 使用的索引格式
+    pub fn inode_format(&self) -> InodeFormat {
+        if self.version >= EFS_VERSION_HUGE_FILE {
+            InodeFormat::Huge
+        } else if self.version >= EFS_VERSION_PERMISSIONS {
+            InodeFormat::Owned
+        } else if self.version >= EFS_VERSION_TIMESTAMPS {
+            InodeFormat::Timed
+        } else if self.version >= EFS_VERSION_LARGE_FILE {
+            InodeFormat::Large
+        } else {
+            InodeFormat::Original
+        }
     }
 }
 
-#[derive(PartialEq)]
+// 符号链接的数据块中保存它指向的路径，不以 '\0' 结尾
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
 pub enum DiskInodeType {
     File,
     Directory,
+    Symlink,
 }
 
 type IndirectBlock = [u32; BLOCK_SZ / 4];
 // 每个保存内容的 数据块 都只是一个字节数组
 type DataBlock = [u8; BLOCK_SZ];
 
+// inode 的索引格式，每个 inode 自己记录，因此新旧格式的 inode 可以在同一个镜像中共存
+// 各种格式都从 DiskInode 的 blocks 开头依次保存块编号，区别在于块编号的个数以及每个位置是几级索引
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum InodeFormat {
+    // 28 个直接索引，一个一级索引和一个二级索引，文件最大约 8MiB
+    Original = 0,
+    // 26 个直接索引，一级到四级索引各一个，文件大小只受 32 位的 size 限制，最大 4GiB - 1
+    Large = 1,
+    // 23 个直接索引，一级到四级索引各一个，文件大小上限与 Large 相同
+    // blocks 中剩下的 3 个位置保存访问、修改和状态改变时间
+    Timed = 2,
+    // 20 个直接索引，一级到四级索引各一个，之后是与 Timed 相同的时间戳以及所有者、所属组和访问权限
+    Owned = 3,
+    // 19 个直接索引，一级到四级索引各一个，之后是与 Owned 相同的字段以及 size 的高 32 位
+    // 文件大小只受四级索引的容量限制，最大约 128GiB
+    Huge = 4,
+}
+
+impl InodeFormat {
+    // 依次给出每组块编号的个数以及它们的索引级数，0 级表示直接指向数据块
+    fn layout(self) -> &'static [(usize, u32)] {
+        match self {
+            InodeFormat::Original => &[(28, 0), (1, 1), (1, 2)],
+            InodeFormat::Large => &[(26, 0), (1, 1), (1, 2), (1, 3), (1, 4)],
+            InodeFormat::Timed => &[(23, 0), (1, 1), (1, 2), (1, 3), (1, 4)],
+            InodeFormat::Owned => &[(20, 0), (1, 1), (1, 2), (1, 3), (1, 4)],
+            InodeFormat::Huge => &[(19, 0), (1, 1), (1, 2), (1, 3), (1, 4)],
+        }
+    }
+    fn has_times(self) -> bool {
+        self != InodeFormat::Original && self != InodeFormat::Large
+    }
+    fn has_owner(self) -> bool {
+        self == InodeFormat::Owned || self == InodeFormat::Huge
+    }
+    fn has_size_high(self) -> bool {
+        self == InodeFormat::Huge
+    }
+    // blocks 中用来保存块编号的位置个数
+    fn pointer_count(self) -> usize {
+        self.layout().iter().map(|&(count, _)| count).sum()
+    }
+    // 每个块编号的索引级数
+    fn depths(self) -> impl Iterator<Item = u32> {
+        self.layout()
+            .iter()
+            .flat_map(|&(count, depth)| core::iter::repeat(depth).take(count))
+    }
+    // 文件大小的上限，没有保存 size 的高 32 位的格式不能超过 u32 的表示范围
+    pub fn max_size(self) -> usize {
+        let capacity: usize = self.depths().map(index_capacity).sum();
+        if self.has_size_high() {
+            capacity * BLOCK_SZ
+        } else {
+            (capacity * BLOCK_SZ).min(u32::MAX as usize)
+        }
+    }
+}
+
+// inode 的三个时间戳，都是自 1970-01-01 00:00:00 UTC 以来的秒数
+// 没有时间戳的旧格式 inode 中都是 0
+#[derive(Clone, Copy, Default, Debug)]
+pub struct InodeTimes {
+    pub atime: u32, // 最后一次读取内容的时间
+    pub mtime: u32, // 最后一次修改内容的时间
+    pub ctime: u32, // 最后一次修改内容或元数据 (如硬链接数量) 的时间
+}
+
+// inode 的所有者、所属组和访问权限
+// mode 只包含 Unix 的 12 个权限位 (0o7777)，文件类型由 DiskInodeType 给出
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct InodeOwner {
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+}
+
+impl InodeOwner {
+    pub const MODE_MASK: u32 = 0o7777;
+    // 没有所有者字段的旧格式 inode 都属于 root，所有人都可以读取和执行，只有 root 可以修改
+    pub const LEGACY: Self = Self { uid: 0, gid: 0, mode: 0o755 };
+    pub fn root(mode: u32) -> Self {
+        Self { uid: 0, gid: 0, mode }
+    }
+}
+
 // Inode: 索引节点
 // 在 inode 中不仅包含了我们通过 stat 工具能够看到的文件/目录的元数据（大小/访问权限/类型等信息），
 // 还包含它到那些实际保存文件/目录数据的数据块（位于最后的数据块区域中）的索引信息，从而能够找到文件/目录的数据被保存在哪里
 #[repr(C)]
-/// Only support level-1 indirect now, **indirect2** field is always 0.
 /// 每个文件/目录在磁盘上均以一个 DiskInode 的形式存储
 /// 将 DiskInode 的大小设置为 128 字节，每个块正好能够容纳 4 个 DiskInode
 pub struct DiskInode {
     // 文件/目录的元数据
-    pub size: u32, // 文件/目录内容的字节数
-    // 当取值为 28 的时候，通过直接索引可以找到 14KiB 的内容
-    pub direct: [u32; INODE_DIRECT_COUNT], // 直接索引, direct 数组中最多可以指向 INODE_DIRECT_COUNT 个数据块
-    pub indirect1: u32, // 一级间接索引. 指向一个位于数据块区域中的一级索引块. 最多能够索引 512/4=128 个数据块, 对应 64KiB 的内容 
-    pub indirect2: u32, // 二级间接索引. 指向一个位于数据块区域中的二级索引块. 每个 u32 指向一个不同的一级索引块，这些一级索引块也位于数据块区域中. 最多能够索引 128×64KiB=8MiB 的内容
-    type_: DiskInodeType, // 索引节点的类型 DiskInodeType ，目前仅支持文件 File 和目录 Directory 两种类型
+    size: u32, // 文件/目录内容的字节数，InodeFormat::Huge 中只是低 32 位，通过 size() 读取完整的值
+    // 直接索引和多级间接索引的根，每个位置的索引级数由 format 决定
+    // k 级索引指向一个索引块，其中的 128 个块编号都是 k-1 级索引，0 级索引就是数据块本身
+    // 原始格式下前 28 个是直接索引，最后两个分别是一级和二级索引
+    // InodeFormat::Timed 只用前 27 个位置，最后 3 个依次是 atime、mtime 和 ctime
+    // InodeFormat::Owned 只用前 24 个位置，之后依次是 atime、mtime、ctime、uid、gid 和 mode
+    // InodeFormat::Huge 只用前 23 个位置，之后是与 Owned 相同的 6 个字段，最后一个位置是 size 的高 32 位
+    blocks: [u32; INODE_POINTER_COUNT],
+    type_: DiskInodeType, // 索引节点的类型 DiskInodeType ，支持文件 File、目录 Directory 和符号链接 Symlink 三种类型
+    format: InodeFormat, // 占用 type_ 之后原本的填充字节，早期镜像中为 0 即原始格式
+    pub nlink: u16, // 指向该 inode 的目录项个数，即硬链接数量。目录的 "." 和子目录的 ".." 也计算在内
 }
 
 impl DiskInode {
-    /// indirect1 and indirect2 block are allocated only when they are needed.
-    pub fn initialize(&mut self, type_: DiskInodeType) {
+    /// Index blocks are allocated only when they are needed.
+    // now 是创建时间，格式支持时间戳时作为三个时间戳的初值；格式不支持所有者时忽略 owner
+    pub fn initialize(
+        &mut self,
+        type_: DiskInodeType,
+        format: InodeFormat,
+        now: u32,
+        owner: InodeOwner,
+    ) {
         // 初始化之后文件/目录的 size 均为 0 ，此时并不会索引到任何数据块
         self.size = 0;
-        self.direct.iter_mut().for_each(|v| *v = 0);
-        // indirect1/2 均被初始化为 0 。因为最开始文件内容的大小为 0 字节，并不会用到一级/二级索引
-        self.indirect1 = 0; // 完全按需分配一级/二级索引块
-        self.indirect2 = 0;
+        // 所有索引均被初始化为 0，完全按需分配索引块
+        self.blocks.iter_mut().for_each(|v| *v = 0);
         self.type_ = type_;
+        self.format = format;
+        self.nlink = 0; // 由创建它的目录项负责增加
+        if let Some(times) = self.times_mut() {
+            times.iter_mut().for_each(|time| *time = now);
+        }
+        self.set_owner(owner);
+    }
+    // 一段磁盘上的数据能否被解释为 DiskInode，type_ 和 format 必须是合法的枚举值
+    // fsck 在读取可能已经损坏的 DiskInode 之前使用
+    pub fn is_valid_raw(raw: &[u8]) -> bool {
+        raw[DISK_INODE_TYPE_OFFSET] <= DiskInodeType::Symlink as u8
+            && raw[DISK_INODE_TYPE_OFFSET + 1] <= InodeFormat::Huge as u8
     }
     // 用来确认 DiskInode 的类型为目录
     pub fn is_dir(&self) -> bool {
@@ -117,244 +260,231 @@ impl DiskInode {
     pub fn is_file(&self) -> bool {
         self.type_ == DiskInodeType::File
     }
+    pub fn is_symlink(&self) -> bool {
+        self.type_ == DiskInodeType::Symlink
+    }
+    pub fn inode_type(&self) -> DiskInodeType {
+        self.type_
+    }
+    pub fn max_size(&self) -> usize {
+        self.format.max_size()
+    }
+    pub fn size(&self) -> usize {
+        if !self.format.has_size_high() {
+            return self.size as usize;
+        }
+        let high = self.blocks[self.format.pointer_count() + TIME_SLOTS + OWNER_SLOTS] as usize;
+        high << 32 | self.size as usize
+    }
+    fn set_size(&mut self, size: usize) {
+        self.size = size as u32;
+        if self.format.has_size_high() {
+            self.blocks[self.format.pointer_count() + TIME_SLOTS + OWNER_SLOTS] = (size >> 32) as u32;
+        }
+    }
+    // 早期镜像中 nlink 所在的位置是填充字节，总是 0，而通过目录项找到的 inode 至少有一个硬链接
+    // 这样的原始格式 inode 按照只有一个目录项计算：文件为 1，目录再加上它自己的 "."，准确的数量由 fsck --repair 重新计算
+    pub fn link_count(&self) -> u16 {
+        match (self.format, self.nlink) {
+            (InodeFormat::Original, 0) if self.is_dir() => 2,
+            (InodeFormat::Original, 0) => 1,
+            (_, nlink) => nlink,
+        }
+    }
+    // 保存在块编号之后的 atime、mtime 和 ctime，格式不支持时间戳时为 None
+    fn times_mut(&mut self) -> Option<&mut [u32]> {
+        if !self.format.has_times() {
+            return None;
+        }
+        let start = self.format.pointer_count();
+        Some(&mut self.blocks[start..start + TIME_SLOTS])
+    }
+    pub fn times(&self) -> InodeTimes {
+        if !self.format.has_times() {
+            return InodeTimes::default();
+        }
+        let times = &self.blocks[self.format.pointer_count()..];
+        InodeTimes { atime: times[0], mtime: times[1], ctime: times[2] }
+    }
+    // 保存在时间戳之后的 uid、gid 和 mode
+    pub fn owner(&self) -> InodeOwner {
+        if !self.format.has_owner() {
+            return InodeOwner::LEGACY;
+        }
+        let owner = &self.blocks[self.format.pointer_count() + TIME_SLOTS..];
+        InodeOwner { uid: owner[0], gid: owner[1], mode: owner[2] }
+    }
+    // 格式不支持所有者时无法修改，返回 false
+    pub fn set_owner(&mut self, owner: InodeOwner) -> bool {
+        if !self.format.has_owner() {
+            return false;
+        }
+        let start = self.format.pointer_count() + TIME_SLOTS;
+        self.blocks[start..start + OWNER_SLOTS]
+            .copy_from_slice(&[owner.uid, owner.gid, owner.mode & InodeOwner::MODE_MASK]);
+        true
+    }
+    // 读取内容之后是否需要更新 atime
+    // 与 Linux 的 relatime 相同，只在 atime 不晚于 mtime 或 ctime、或者已经过去一天以上时才更新
+    // 这样连续的读取不会每次都修改 DiskInode 而产生一个事务
+    pub fn atime_outdated(&self, now: u32) -> bool {
+        let times = self.times();
+        self.format.has_times()
+            && times.atime != now
+            && (times.atime <= times.mtime
+                || times.atime <= times.ctime
+                || now >= times.atime.saturating_add(SECONDS_PER_DAY))
+    }
+    pub fn touch_atime(&mut self, now: u32) {
+        if let Some(times) = self.times_mut() {
+            times[0] = now;
+        }
+    }
+    // 修改内容之后更新 mtime，内容的修改同时也是状态的改变
+    pub fn touch_mtime(&mut self, now: u32) {
+        if let Some(times) = self.times_mut() {
+            times[1] = now;
+            times[2] = now;
+        }
+    }
+    // 只修改元数据时更新 ctime
+    pub fn touch_ctime(&mut self, now: u32) {
+        if let Some(times) = self.times_mut() {
+            times[2] = now;
+        }
+    }
     /// Return block number correspond to size.
     pub fn data_blocks(&self) -> u32 {
-        Self::_data_blocks(self.size)
+        Self::_data_blocks(self.size())
     }
     // 为了容纳自身 size 字节的内容需要多少个数据块
     // 用 size 除以每个块的大小 BLOCK_SZ 并向上取整
-    fn _data_blocks(size: u32) -> u32 {
-        (size + BLOCK_SZ as u32 - 1) / BLOCK_SZ as u32
+    fn _data_blocks(size: usize) -> u32 {
+        ((size + BLOCK_SZ - 1) / BLOCK_SZ) as u32
     }
-    /// Return number of blocks needed include indirect1/2.
+    /// Return number of blocks needed include index blocks.
     // 不仅包含数据块，还需要统计索引块
-    pub fn total_blocks(size: u32) -> u32 {
-        let data_blocks = Self::_data_blocks(size) as usize;
-        let mut total = data_blocks as usize;
-        // indirect1
-        if data_blocks > INODE_DIRECT_COUNT {
-            total += 1;
-        }
-        // indirect2
-        if data_blocks > INDIRECT1_BOUND {
-            total += 1;
-            // sub indirect1
-            total += (data_blocks - INDIRECT1_BOUND + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
+    pub fn total_blocks(&self, size: usize) -> u32 {
+        let mut data_blocks = Self::_data_blocks(size) as usize;
+        let mut total = data_blocks;
+        for depth in self.format.depths() {
+            let blocks = data_blocks.min(index_capacity(depth));
+            total += index_blocks(blocks, depth);
+            data_blocks -= blocks;
         }
         total as u32
     }
     // 将一个 DiskInode 的 size 扩容到 new_size 需要额外多少个数据和索引块
-    pub fn blocks_num_needed(&self, new_size: u32) -> u32 {
-        assert!(new_size >= self.size);
-        Self::total_blocks(new_size) - Self::total_blocks(self.size)
+    pub fn blocks_num_needed(&self, new_size: usize) -> u32 {
+        assert!(new_size >= self.size());
+        self.total_blocks(new_size) - self.total_blocks(self.size())
     }
     // 数据块索引功能
-    // 从索引中查到它自身用于保存文件内容的第 block_id 个数据块的 块编号
+    // 从索引中查到它自身用于保存文件内容的第 inner_id 个数据块的 块编号
     pub fn get_block_id(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
-        let inner_id = inner_id as usize;
-        if inner_id < INODE_DIRECT_COUNT {
-            self.direct[inner_id]
-        } else if inner_id < INDIRECT1_BOUND {
-            get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect_block: &IndirectBlock| {
-                    indirect_block[inner_id - INODE_DIRECT_COUNT]
-                })
-        } else {
-            let last = inner_id - INDIRECT1_BOUND;
-            let indirect1 = get_block_cache(
-                self.indirect2 as usize,
-                Arc::clone(block_device)
-            )
-            .lock()
-            .read(0, |indirect2: &IndirectBlock| {
-                indirect2[last / INODE_INDIRECT1_COUNT]
-            });
-            get_block_cache(
-                indirect1 as usize,
-                Arc::clone(block_device)
-            )
-            .lock()
-            .read(0, |indirect1: &IndirectBlock| {
-                indirect1[last % INODE_INDIRECT1_COUNT]
-            })
+        let mut inner_id = inner_id as usize;
+        for (slot, depth) in self.format.depths().enumerate() {
+            let capacity = index_capacity(depth);
+            if inner_id >= capacity {
+                inner_id -= capacity;
+                continue;
+            }
+            // 逐级向下查找索引块
+            let mut block_id = self.blocks[slot];
+            for level in (0..depth).rev() {
+                let capacity = index_capacity(level);
+                block_id = get_block_cache(block_id as usize, Arc::clone(block_device))
+                    .lock()
+                    .read(0, |index_block: &IndirectBlock| {
+                        index_block[inner_id / capacity]
+                    });
+                inner_id %= capacity;
+            }
+            return block_id;
         }
+        panic!("block index out of range of the inode format");
     }
     // 逐步扩充容量
     pub fn increase_size(
         &mut self,
-        new_size: u32, // 容量扩充之后的文件大小
+        new_size: usize, // 容量扩充之后的文件大小
         new_blocks: Vec<u32>, // 保存了本次容量扩充所需 块编号 的向量, 由上层的磁盘块管理器负责分配的
         block_device: &Arc<dyn BlockDevice>,
     ) {
-        // 按照直接索引、一级索引再到二级索引的顺序进行扩充
-        let mut current_blocks = self.data_blocks();
-        self.size = new_size;
-        let mut total_blocks = self.data_blocks();
+        // 按照直接索引、一级索引再到更多级索引的顺序逐个挂上新的数据块
+        let mut current_blocks = self.data_blocks() as usize;
+        self.set_size(new_size);
+        let total_blocks = self.data_blocks() as usize;
         let mut new_blocks = new_blocks.into_iter();
-        // fill direct
-        while current_blocks < total_blocks.min(INODE_DIRECT_COUNT as u32) {
-            self.direct[current_blocks as usize] = new_blocks.next().unwrap();
-            current_blocks += 1;
-        }
-        // alloc indirect1
-        if total_blocks > INODE_DIRECT_COUNT as u32{
-            if current_blocks == INODE_DIRECT_COUNT as u32 {
-                self.indirect1 = new_blocks.next().unwrap();
+        let mut start = 0usize;
+        for (slot, depth) in self.format.depths().enumerate() {
+            if current_blocks >= total_blocks {
+                break;
             }
-            current_blocks -= INODE_DIRECT_COUNT as u32;
-            total_blocks -= INODE_DIRECT_COUNT as u32;
-        } else {
-            return;
-        }
-        // fill indirect1
-        get_block_cache(
-            self.indirect1 as usize,
-            Arc::clone(block_device)
-        )
-        .lock()
-        .modify(0, |indirect1: &mut IndirectBlock| {
-            while current_blocks < total_blocks.min(INODE_INDIRECT1_COUNT as u32) {
-                indirect1[current_blocks as usize] = new_blocks.next().unwrap();
+            let end = start + index_capacity(depth);
+            while current_blocks < total_blocks.min(end) {
+                insert_block(
+                    &mut self.blocks[slot],
+                    depth,
+                    current_blocks - start,
+                    &mut new_blocks,
+                    block_device,
+                );
                 current_blocks += 1;
             }
-        });
-        // alloc indirect2
-        if total_blocks > INODE_INDIRECT1_COUNT as u32 {
-            if current_blocks == INODE_INDIRECT1_COUNT as u32 {
-                self.indirect2 = new_blocks.next().unwrap();
-            }
-            current_blocks -= INODE_INDIRECT1_COUNT as u32;
-            total_blocks -= INODE_INDIRECT1_COUNT as u32;
-        } else {
-            return;
+            start = end;
         }
-        // fill indirect2 from (a0, b0) -> (a1, b1)
-        let mut a0 = current_blocks as usize / INODE_INDIRECT1_COUNT;
-        let mut b0 = current_blocks as usize % INODE_INDIRECT1_COUNT;
-        let a1 = total_blocks as usize / INODE_INDIRECT1_COUNT;
-        let b1 = total_blocks as usize % INODE_INDIRECT1_COUNT;
-        // alloc low-level indirect1
-        get_block_cache(
-            self.indirect2 as usize,
-            Arc::clone(block_device)
-        )
-        .lock()
-        .modify(0, |indirect2: &mut IndirectBlock| {
-            while (a0 < a1) || (a0 == a1 && b0 < b1) {
-                if b0 == 0 {
-                    indirect2[a0] = new_blocks.next().unwrap();
-                }
-                // fill current
-                get_block_cache(
-                    indirect2[a0] as usize,
-                    Arc::clone(block_device)
-                )
-                .lock()
-                .modify(0, |indirect1: &mut IndirectBlock| {
-                    indirect1[b0] = new_blocks.next().unwrap();
-                });
-                // move to next
-                b0 += 1;
-                if b0 == INODE_INDIRECT1_COUNT {
-                    b0 = 0;
-                    a0 += 1;
-                }
-            } 
-        });
+        assert!(new_blocks.next().is_none());
     }
 
-    // 清空文件的内容并回收所有数据和索引块
-    /// Clear size to zero and return blocks that should be deallocated.
+    // 把文件缩小到 new_size 字节并回收不再用到的数据和索引块
+    /// Decrease size to `new_size` and return blocks that should be deallocated.
     ///
-    /// We will clear the block contents to zero later.
-    pub fn clear_size(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
-        // 回收的所有块的编号保存在一个向量中返回给磁盘块管理器
+    /// Blocks are cleared to zero when they are allocated again.
+    // 回收的所有块的编号保存在一个向量中返回给磁盘块管理器
+    pub fn decrease_size(
+        &mut self,
+        new_size: usize,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        assert!(new_size <= self.size());
+        let dealloc_blocks = self.total_blocks(self.size()) - self.total_blocks(new_size);
+        let data_blocks = self.data_blocks() as usize;
+        let new_blocks = Self::_data_blocks(new_size) as usize;
         let mut v: Vec<u32> = Vec::new();
-        let mut data_blocks = self.data_blocks() as usize;
-        self.size = 0;
-        let mut current_blocks = 0usize;
-        // direct
-        while current_blocks < data_blocks.min(INODE_DIRECT_COUNT) {
-            v.push(self.direct[current_blocks]);
-            self.direct[current_blocks] = 0;
-            current_blocks += 1;
-        }
-        // indirect1 block
-        if data_blocks > INODE_DIRECT_COUNT {
-            v.push(self.indirect1);
-            data_blocks -= INODE_DIRECT_COUNT;
-            current_blocks = 0;
-        } else {
-            return v;
-        }
-        // indirect1
-        get_block_cache(
-            self.indirect1 as usize,
-            Arc::clone(block_device),
-        )
-        .lock()
-        .modify(0, |indirect1: &mut IndirectBlock| {
-            while current_blocks < data_blocks.min(INODE_INDIRECT1_COUNT) {
-                v.push(indirect1[current_blocks]);
-                //indirect1[current_blocks] = 0;
-                current_blocks += 1;
+        let mut start = 0usize;
+        for (slot, depth) in self.format.depths().enumerate() {
+            if start >= data_blocks {
+                break;
             }
-        });
-        self.indirect1 = 0;
-        // indirect2 block
-        if data_blocks > INODE_INDIRECT1_COUNT {
-            v.push(self.indirect2);
-            data_blocks -= INODE_INDIRECT1_COUNT;
-        } else {
-            return v;
+            let capacity = index_capacity(depth);
+            truncate_index(
+                &mut self.blocks[slot],
+                depth,
+                (data_blocks - start).min(capacity),
+                new_blocks.saturating_sub(start).min(capacity),
+                &mut v,
+                block_device,
+            );
+            start += capacity;
         }
-        // indirect2
-        assert!(data_blocks <= INODE_INDIRECT2_COUNT);
-        let a1 = data_blocks / INODE_INDIRECT1_COUNT;
-        let b1 = data_blocks % INODE_INDIRECT1_COUNT;
-        get_block_cache(
-            self.indirect2 as usize,
-            Arc::clone(block_device),
-        )
-        .lock()
-        .modify(0, |indirect2: &mut IndirectBlock| {
-            // full indirect1 blocks
-            for i in 0..a1 {
-                v.push(indirect2[i]);
-                get_block_cache(
-                    indirect2[i] as usize,
-                    Arc::clone(block_device),
-                )
-                .lock()
-                .modify(0, |indirect1: &mut IndirectBlock| {
-                    for j in 0..INODE_INDIRECT1_COUNT {
-                        v.push(indirect1[j]);
-                        //indirect1[j] = 0;
-                    }
-                });
-                //indirect2[i] = 0;
-            }
-            // last indirect1 block
-            if b1 > 0 {
-                v.push(indirect2[a1]);
-                get_block_cache(
-                    indirect2[a1] as usize,
-                    Arc::clone(block_device),
-                )
-                .lock()
-                .modify(0, |indirect1: &mut IndirectBlock| {
-                    for j in 0..b1 {
-                        v.push(indirect1[j]);
-                        //indirect1[j] = 0;
-                    }
-                });
-                //indirect2[a1] = 0;
-            }
-        });
-        self.indirect2 = 0;
+        self.set_size(new_size);
+        assert_eq!(v.len(), dealloc_blocks as usize);
         v
     }
+    // 依次访问文件用到的每个数据块和索引块，visit 返回 false 时不再访问这个索引块下挂的块
+    pub fn visit_blocks(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        visit: &mut impl FnMut(u32) -> bool,
+    ) {
+        let mut data_blocks = self.data_blocks() as usize;
+        for (slot, depth) in self.format.depths().enumerate() {
+            let blocks = data_blocks.min(index_capacity(depth));
+            visit_index(self.blocks[slot], depth, blocks, visit, block_device);
+            data_blocks -= blocks;
+        }
+    }
     // 通过 DiskInode 来读写它索引的那些数据块中的数据
     // 每次我们都是选取其中的一段连续区间进行操作
     // 将文件内容从 offset 字节开始的部分读到内存中的缓冲区 buf 中，并返回实际读到的字节数
@@ -366,7 +496,7 @@ impl DiskInode {
         block_device: &Arc<dyn BlockDevice>,
     ) -> usize {
         let mut start = offset;
-        let end = (offset + buf.len()).min(self.size as usize);
+        let end = (offset + buf.len()).min(self.size());
         // 要读取的内容超出了文件的范围那么直接返回 0 表示读取不到任何内容
         if start >= end {
             return 0;
@@ -407,7 +537,7 @@ impl DiskInode {
         block_device: &Arc<dyn BlockDevice>,
     ) -> usize {
         let mut start = offset;
-        let end = (offset + buf.len()).min(self.size as usize);
+        let end = (offset + buf.len()).min(self.size());
         assert!(start <= end);
         let mut start_block = start / BLOCK_SZ;
         let mut write_size = 0usize;
@@ -437,6 +567,113 @@ impl DiskInode {
     }
 }
 
+// k 级索引最多能索引多少个数据块
+fn index_capacity(depth: u32) -> usize {
+    INDEX_FANOUT.pow(depth)
+}
+
+// k 级索引下挂有 data_blocks 个数据块时用到的索引块个数
+fn index_blocks(data_blocks: usize, depth: u32) -> usize {
+    if data_blocks == 0 || depth == 0 {
+        return 0;
+    }
+    let capacity = index_capacity(depth - 1);
+    1 + data_blocks / capacity * index_blocks(capacity, depth - 1)
+        + index_blocks(data_blocks % capacity, depth - 1)
+}
+
+// 把一个新的数据块挂到以 *root 为根的 depth 级索引的第 inner_id 个位置上
+// 途经的索引块在第一次用到时从 new_blocks 中取出，与 index_blocks 的统计一致
+fn insert_block(
+    root: &mut u32,
+    depth: u32,
+    inner_id: usize,
+    new_blocks: &mut impl Iterator<Item = u32>,
+    block_device: &Arc<dyn BlockDevice>,
+) {
+    if inner_id == 0 {
+        *root = new_blocks.next().unwrap();
+    }
+    if depth == 0 {
+        return;
+    }
+    let capacity = index_capacity(depth - 1);
+    get_block_cache(*root as usize, Arc::clone(block_device))
+        .lock()
+        .modify(0, |index_block: &mut IndirectBlock| {
+            insert_block(
+                &mut index_block[inner_id / capacity],
+                depth - 1,
+                inner_id % capacity,
+                new_blocks,
+                block_device,
+            );
+        });
+}
+
+// 以 *root 为根、挂有 data_blocks 个数据块的 depth 级索引只保留前 new_blocks 个数据块
+// 其余的数据块和不再用到的索引块放入 freed，整个被释放的索引块不再修改，它们重新分配时会被清零
+fn truncate_index(
+    root: &mut u32,
+    depth: u32,
+    data_blocks: usize,
+    new_blocks: usize,
+    freed: &mut Vec<u32>,
+    block_device: &Arc<dyn BlockDevice>,
+) {
+    if new_blocks >= data_blocks {
+        return;
+    }
+    if new_blocks == 0 {
+        visit_index(*root, depth, data_blocks, &mut |block_id| {
+            freed.push(block_id);
+            true
+        }, block_device);
+        *root = 0;
+        return;
+    }
+    let capacity = index_capacity(depth - 1);
+    get_block_cache(*root as usize, Arc::clone(block_device))
+        .lock()
+        .modify(0, |index_block: &mut IndirectBlock| {
+            for i in new_blocks / capacity..(data_blocks + capacity - 1) / capacity {
+                let start = i * capacity;
+                truncate_index(
+                    &mut index_block[i],
+                    depth - 1,
+                    (data_blocks - start).min(capacity),
+                    new_blocks.saturating_sub(start),
+                    freed,
+                    block_device,
+                );
+            }
+        });
+}
+
+// 访问以 root 为根、挂有 data_blocks 个数据块的 depth 级索引用到的所有数据块和索引块
+fn visit_index(
+    root: u32,
+    depth: u32,
+    data_blocks: usize,
+    visit: &mut impl FnMut(u32) -> bool,
+    block_device: &Arc<dyn BlockDevice>,
+) {
+    if data_blocks == 0 || !visit(root) || depth == 0 {
+        return;
+    }
+    let capacity = index_capacity(depth - 1);
+    let index_block = get_block_cache(root as usize, Arc::clone(block_device))
+        .lock()
+        .read(0, |index_block: &IndirectBlock| *index_block);
+    for (i, &child) in index_block.iter().enumerate() {
+        let start = i * capacity;
+        if start >= data_blocks {
+            break;
+        }
+        visit_index(child, depth - 1, (data_blocks - start).min(capacity), visit, block_device);
+    }
+}
+
 // 目录项相当于目录树结构上的孩子指针，我们需要通过它来一级一级的找到实际要访问的文件或目录
 #[repr(C)]
 pub struct DirEntry {
@@ -451,12 +688,23 @@ pub const DIRENT_SZ: usize = 32;
 pub type DirentBytes = [u8; DIRENT_SZ];
 
 impl DirEntry {
+    // 被删除的目录项用空目录项覆盖，之后可以被新的目录项复用
     pub fn empty() -> Self {
         Self {
             name: [0u8; NAME_LENGTH_LIMIT + 1],
             inode_number: 0,
         }
     }
+    pub fn is_empty(&self) -> bool {
+        self.name[0] == 0
+    }
+    // 名字以 '\0' 结尾且是合法的 UTF-8，此时才能调用 name
+    pub fn is_valid(&self) -> bool {
+        match self.name.iter().position(|&b| b == 0) {
+            Some(len) => core::str::from_utf8(&self.name[..len]).is_ok(),
+            None => false,
+        }
+    }
     // 一个合法的目录项
     pub fn new(name: &str, inode_number: u32) -> Self {
         let mut bytes = [0u8; NAME_LENGTH_LIMIT + 1];
@@ -3,16 +3,25 @@
 extern crate alloc;
 
 mod block_dev;
+mod clock;
 mod layout;
 mod efs;
 mod bitmap;
 mod vfs;
 mod block_cache;
+mod journal;
+mod fsck;
 
 pub const BLOCK_SZ: usize = 512; // Byte
 pub use block_dev::BlockDevice;
+pub use clock::Clock;
 pub use efs::EasyFileSystem;
 pub use vfs::Inode;
+pub use block_cache::{BlockCache, set_block_cache_allocator};
+pub use layout::{NAME_LENGTH_LIMIT, SYMLINK_LENGTH_LIMIT, DiskInodeType, InodeTimes, InodeOwner};
+pub use journal::JOURNAL_BLOCKS;
+pub use fsck::{fsck, FsckError};
 use layout::*;
 use bitmap::Bitmap;
-use block_cache::get_block_cache;
\ No newline at end of file
+use block_cache::{get_block_cache, get_modified_block_caches};
+use journal::{Journal, TRANSACTION_DATA_BLOCKS};
\ No newline at end of file
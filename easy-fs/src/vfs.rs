@@ -5,41 +5,47 @@ use super::{
     DirEntry,
     DirentBytes,
     EasyFileSystem,
+    InodeTimes,
+    InodeOwner,
+    BLOCK_SZ,
     DIRENT_SZ,
+    NAME_LENGTH_LIMIT,
+    SYMLINK_LENGTH_LIMIT,
+    TRANSACTION_DATA_BLOCKS,
     get_block_cache,
 };
 use alloc::sync::Arc;
 use alloc::string::String;
 use alloc::vec::Vec;
+use alloc::vec;
 use spin::{Mutex, MutexGuard};
 
-pub struct LinkNumber(pub usize);
-
 // DiskInode 放在磁盘块中比较固定的位置，而 Inode 是放在内存中的
+// 每个修改文件系统的方法都在释放 efs 的锁之前提交一个事务，崩溃之后磁盘上只会留下完整的操作
 pub struct Inode {
     inode_id: usize,
     // block_id 和 block_offset 记录该 Inode 对应的 DiskInode 保存在磁盘上的具体位置
     block_id: usize,
     block_offset: usize,
-    nlink: Arc<Mutex<LinkNumber>>, // 硬链接数量
     fs: Arc<Mutex<EasyFileSystem>>,
     block_device: Arc<dyn BlockDevice>,
 }
 
 impl Inode {
     /// We should not acquire efs lock here.
+    // 调用者已经持有 efs 的锁，通过 efs 登记这个 inode 多了一个内存中的 Inode
     pub fn new(
         inode_id: u32,
-        block_id: u32,
-        block_offset: usize,
+        efs: &mut EasyFileSystem,
         fs: Arc<Mutex<EasyFileSystem>>,
         block_device: Arc<dyn BlockDevice>,
     ) -> Self {
+        let (block_id, block_offset) = efs.get_disk_inode_pos(inode_id);
+        efs.open_inode(inode_id);
         Self {
             inode_id: inode_id as usize,
             block_id: block_id as usize,
             block_offset: block_offset,
-            nlink: Arc::new(Mutex::new(LinkNumber(1))),
             fs: fs,
             block_device: block_device,
         }
@@ -60,21 +66,49 @@ impl Inode {
         ).lock().modify(self.block_offset, f)
     }
 
+    // 访问另一个 inode 对应的 DiskInode，调用者需要持有 efs 的锁
+    // 不能在 read/modify_disk_inode 的闭包中调用，两个 DiskInode 可能位于同一个块中
+    fn read_other_disk_inode<V>(
+        &self,
+        inode_id: u32,
+        fs: &MutexGuard<EasyFileSystem>,
+        f: impl FnOnce(&DiskInode) -> V,
+    ) -> V {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        get_block_cache(
+            block_id as usize,
+            Arc::clone(&self.block_device)
+        ).lock().read(block_offset, f)
+    }
+
+    fn modify_other_disk_inode<V>(
+        &self,
+        inode_id: u32,
+        fs: &MutexGuard<EasyFileSystem>,
+        f: impl FnOnce(&mut DiskInode) -> V,
+    ) -> V {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        get_block_cache(
+            block_id as usize,
+            Arc::clone(&self.block_device)
+        ).lock().modify(block_offset, f)
+    }
+
     /*
     fn get_disk_inode(&self, fs: &mut MutexGuard<EasyFileSystem>) -> Dirty<DiskInode> {
         fs.get_disk_inode(self.inode_id)
     }
     */
 
-    // 尝试从根目录的 DiskInode 上找到要索引的文件名对应的 inode 编号
-    fn find_inode_id(
+    // 在目录的 DiskInode 中查找第一个满足条件的目录项，返回它的序号和指向的 inode 编号
+    fn find_dirent(
         &self,
-        name: &str,
         disk_inode: &DiskInode,
-    ) -> Option<u32> {
+        pred: impl Fn(&DirEntry) -> bool,
+    ) -> Option<(usize, u32)> {
         // assert it is a directory
         assert!(disk_inode.is_dir());
-        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+        let file_count = disk_inode.size() / DIRENT_SZ;
         let mut dirent_space: DirentBytes = Default::default();
         for i in 0..file_count {
             assert_eq!(
@@ -86,24 +120,38 @@ impl Inode {
                 DIRENT_SZ,
             );
             let dirent = DirEntry::from_bytes(&dirent_space);
-            if dirent.name() == name {
-                return Some(dirent.inode_number() as u32);
+            if pred(dirent) {
+                return Some((i, dirent.inode_number()));
             }
         }
         None
     }
 
-    // find 方法只会被根目录 Inode 调用，文件系统中其他文件的 Inode 不会调用这个方法
+    // 尝试从目录的 DiskInode 上找到要索引的文件名对应的 inode 编号
+    fn find_inode_id(
+        &self,
+        name: &str,
+        disk_inode: &DiskInode,
+    ) -> Option<u32> {
+        if name.is_empty() {
+            return None;
+        }
+        self.find_dirent(disk_inode, |dirent| dirent.name() == name)
+            .map(|(_, inode_id)| inode_id)
+    }
+
+    // 在当前目录下查找一个目录项，当前 Inode 不是目录时返回 None
     pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
-        let fs = self.fs.lock();
+        let mut fs = self.fs.lock();
         self.read_disk_inode(|disk_inode| {
+            if !disk_inode.is_dir() {
+                return None;
+            }
             self.find_inode_id(name, disk_inode)
             .map(|inode_id| {
-                let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
                 Arc::new(Self::new(
                     inode_id,
-                    block_id,
-                    block_offset,
+                    &mut fs,
                     self.fs.clone(),
                     self.block_device.clone(),
                 ))
@@ -111,30 +159,91 @@ impl Inode {
         })
     }
 
+    // 从当前目录出发逐级查找一个由 '/' 分隔的相对路径，空的路径分量被忽略
+    // "." 和 ".." 作为普通的目录项查找，由 create_dir 在每个目录中写入；符号链接不会被展开
+    pub fn lookup(self: &Arc<Self>, path: &str) -> Option<Arc<Inode>> {
+        let mut inode = Arc::clone(self);
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            inode = inode.find(name)?;
+        }
+        Some(inode)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.is_symlink())
+    }
+
+    pub fn get_type(&self) -> DiskInodeType {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.inode_type())
+    }
+
     pub fn get_inode_id(&self) -> usize {
         self.inode_id
     }
 
+    // 硬链接数量保存在磁盘上，同一个文件的多个 Inode 看到的总是同一个值
     pub fn get_nlink(&self) -> usize {
-        self.nlink.lock().0
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.link_count() as usize)
+    }
+
+    // 文件内容的字节数
+    pub fn get_size(&self) -> usize {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.size())
+    }
+
+    // 文件大小的上限，由 inode 的索引格式决定
+    pub fn get_max_size(&self) -> usize {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.max_size())
     }
 
-    pub fn create_nlink(&self) {
-        self.nlink.lock().0 += 1;
+    // 文件占用的块数，包括索引块
+    pub fn get_blocks(&self) -> usize {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.total_blocks(disk_inode.size()) as usize)
+    }
+
+    pub fn get_times(&self) -> InodeTimes {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.times())
+    }
+
+    pub fn get_owner(&self) -> InodeOwner {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.owner())
     }
 
-    pub fn destory_nlink(&self) {
-        let mut nlink_inner = self.nlink.lock();
-        if nlink_inner.0 > 1 { nlink_inner.0 -= 1; }
+    // 修改所有者、所属组和访问权限，同时更新 ctime
+    // 旧格式的 inode 没有保存它们的位置，此时返回 false
+    pub fn set_owner(&self, owner: InodeOwner) -> bool {
+        let fs = self.fs.lock();
+        let changed = self.modify_disk_inode(|disk_inode| {
+            let changed = disk_inode.set_owner(owner);
+            if changed {
+                disk_inode.touch_ctime(fs.now());
+            }
+            changed
+        });
+        fs.commit();
+        changed
     }
 
     fn increase_size(
         &self,
-        new_size: u32,
+        new_size: usize,
         disk_inode: &mut DiskInode,
         fs: &mut MutexGuard<EasyFileSystem>,
     ) {
-        if new_size < disk_inode.size {
+        if new_size < disk_inode.size() {
             return;
         }
         let blocks_needed = disk_inode.blocks_num_needed(new_size);
@@ -145,22 +254,368 @@ impl Inode {
         disk_inode.increase_size(new_size, v, &self.block_device);
     }
 
-    // 在根目录下创建一个文件，该方法只有根目录的 Inode 会调用
+    // 在目录中加入一个目录项，优先复用被删除的目录项留下的空位，没有空位时追加到末尾
+    // 目录的内容被修改，同时更新它的 mtime
+    fn append_dirent(
+        &self,
+        name: &str,
+        inode_id: u32,
+        dir_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        let file_count = dir_inode.size() / DIRENT_SZ;
+        let index = match self.find_dirent(dir_inode, |dirent| dirent.is_empty()) {
+            Some((index, _)) => index,
+            None => {
+                let new_size = (file_count + 1) * DIRENT_SZ;
+                // increase size
+                self.increase_size(new_size, dir_inode, fs);
+                file_count
+            }
+        };
+        // write dirent
+        let dirent = DirEntry::new(name, inode_id);
+        dir_inode.write_at(
+            index * DIRENT_SZ,
+            dirent.into_bytes(),
+            &self.block_device,
+        );
+        dir_inode.touch_mtime(fs.now());
+    }
+
+    fn valid_name(name: &str) -> bool {
+        !name.is_empty() && name.len() <= NAME_LENGTH_LIMIT && !name.contains('/')
+    }
+
+    // 在当前目录下创建一个普通文件，属于 root，访问权限为 0o644
     pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::File, InodeOwner::root(0o644), &[])
+    }
+
+    // 在当前目录下创建一个子目录，新目录中包含指向自身的 "." 和指向当前目录的 ".."
+    // 属于 root，访问权限为 0o755
+    pub fn create_dir(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::Directory, InodeOwner::root(0o755), &[])
+    }
+
+    // 以给定的所有者和访问权限创建普通文件或子目录
+    pub fn create_as(&self, name: &str, owner: InodeOwner) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::File, owner, &[])
+    }
+
+    pub fn create_dir_as(&self, name: &str, owner: InodeOwner) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::Directory, owner, &[])
+    }
+
+    // 在当前目录下创建一个指向 target 的符号链接，target 不会被检查，可以指向不存在的路径
+    // target 不能为空，也不能超过 SYMLINK_LENGTH_LIMIT 个字节
+    pub fn symlink(&self, name: &str, target: &str, owner: InodeOwner) -> Option<Arc<Inode>> {
+        if target.is_empty() || target.len() > SYMLINK_LENGTH_LIMIT {
+            return None;
+        }
+        self.create_inode(name, DiskInodeType::Symlink, owner, target.as_bytes())
+    }
+
+    // 读出符号链接指向的路径，当前 Inode 不是符号链接时返回 None
+    pub fn read_link(&self) -> Option<String> {
+        let _fs = self.fs.lock();
+        let target = self.read_disk_inode(|disk_inode| {
+            if !disk_inode.is_symlink() {
+                return None;
+            }
+            let mut target = vec![0u8; disk_inode.size()];
+            disk_inode.read_at(0, &mut target, &self.block_device);
+            Some(target)
+        })?;
+        String::from_utf8(target).ok()
+    }
+
+    // 在当前目录下创建一个指向 target 的目录项，即硬链接，并增加 target 的硬链接数量
+    // 不能为目录创建硬链接，同名的目录项已经存在时失败
+    pub fn link(&self, name: &str, target: &Inode) -> bool {
+        if !Self::valid_name(name) {
+            return false;
+        }
         let mut fs = self.fs.lock();
-        // 检查文件是否已经在根目录下，如果找到的话返回 None
-        if self.modify_disk_inode(|root_inode| {
-            // assert it is a directory
-            assert!(root_inode.is_dir());
-            // has the file been created?
-            self.find_inode_id(name, root_inode)
-        }).is_some() {
+        if target.read_disk_inode(|target_inode| target_inode.is_dir()) {
+            return false;
+        }
+        let linked = self.modify_disk_inode(|dir_inode| {
+            if !dir_inode.is_dir() || self.find_inode_id(name, dir_inode).is_some() {
+                return false;
+            }
+            self.append_dirent(name, target.inode_id as u32, dir_inode, &mut fs);
+            true
+        });
+        if linked {
+            target.modify_disk_inode(|target_inode| {
+                target_inode.nlink = target_inode.link_count() + 1;
+                target_inode.touch_ctime(fs.now());
+            });
+            fs.commit();
+        }
+        linked
+    }
+
+    // 删除当前目录下的一个目录项，并减少它所指向的 inode 的硬链接数量
+    // 目录项被空目录项覆盖；硬链接数量减为 0 时释放 inode 和它的数据块，仍被打开时推迟到最后一次关闭；暂不支持删除目录
+    pub fn unlink(&self, name: &str) -> bool {
+        let mut fs = self.fs.lock();
+        let (index, inode_id) = match self.read_disk_inode(|dir_inode| {
+            if !dir_inode.is_dir() || name.is_empty() {
+                return None;
+            }
+            self.find_dirent(dir_inode, |dirent| dirent.name() == name)
+        }) {
+            Some(dirent) => dirent,
+            None => return false,
+        };
+        if self.read_other_disk_inode(inode_id, &fs, |disk_inode| disk_inode.is_dir()) {
+            return false;
+        }
+        self.modify_disk_inode(|dir_inode| {
+            dir_inode.write_at(
+                index * DIRENT_SZ,
+                DirEntry::empty().into_bytes(),
+                &self.block_device,
+            );
+            dir_inode.touch_mtime(fs.now());
+        });
+        let now = fs.now();
+        let nlink = self.modify_other_disk_inode(inode_id, &fs, |disk_inode| {
+            disk_inode.nlink = disk_inode.link_count().saturating_sub(1);
+            disk_inode.touch_ctime(now);
+            disk_inode.nlink
+        });
+        if nlink == 0 {
+            fs.release_inode(inode_id);
+        }
+        fs.commit();
+        true
+    }
+
+    // 将当前目录下的目录项 old_name 移动到目录 new_dir 中并改名为 new_name，new_dir 可以就是当前目录
+    // new_name 已经存在时被原子地替换：它的目录项被原地改写为指向被移动的 inode，任何时刻这个名字都存在
+    // 目录只能替换空目录，非目录只能替换非目录；目录不能被移动到它自身或者它的子目录中
+    // 两个名字指向同一个 inode 时什么也不做，返回 true
+    pub fn rename(&self, old_name: &str, new_dir: &Inode, new_name: &str) -> bool {
+        self.rename_inner(old_name, new_dir, new_name, RenameMode::Replace)
+    }
+
+    // 与 rename 相同，但 new_name 已经存在时失败
+    pub fn rename_noreplace(&self, old_name: &str, new_dir: &Inode, new_name: &str) -> bool {
+        self.rename_inner(old_name, new_dir, new_name, RenameMode::NoReplace)
+    }
+
+    // 原子地交换两个已经存在的目录项所指向的 inode，两者可以是不同类型
+    pub fn exchange(&self, old_name: &str, new_dir: &Inode, new_name: &str) -> bool {
+        self.rename_inner(old_name, new_dir, new_name, RenameMode::Exchange)
+    }
+
+    // 整个操作是一个事务；在事务内部也总是先写入新的目录项再删除旧的目录项
+    // 被移动的目录的 ".." 和相关目录的硬链接数量在同一个事务中更新
+    fn rename_inner(&self, old_name: &str, new_dir: &Inode, new_name: &str, mode: RenameMode) -> bool {
+        let special = |name: &str| name == "." || name == "..";
+        if !Self::valid_name(new_name) || special(old_name) || special(new_name) {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        if !self.read_disk_inode(|dir_inode| dir_inode.is_dir())
+            || !new_dir.read_disk_inode(|dir_inode| dir_inode.is_dir()) {
+            return false;
+        }
+        let (old_index, old_id) = match self.read_disk_inode(|dir_inode| {
+            self.find_dirent(dir_inode, |dirent| dirent.name() == old_name)
+        }) {
+            Some(dirent) => dirent,
+            None => return false,
+        };
+        let target = new_dir.read_disk_inode(|dir_inode| {
+            new_dir.find_dirent(dir_inode, |dirent| dirent.name() == new_name)
+        });
+        match (mode, target) {
+            (RenameMode::NoReplace, Some(_)) | (RenameMode::Exchange, None) => return false,
+            (_, Some((_, new_id))) if new_id == old_id => return true,
+            _ => {}
+        }
+        let same_dir = self.inode_id == new_dir.inode_id;
+        let old_is_dir = self.read_other_disk_inode(old_id, &fs, |disk_inode| disk_inode.is_dir());
+        if old_is_dir && !same_dir && self.has_ancestor(new_dir.inode_id as u32, old_id, &fs) {
+            return false;
+        }
+        let now = fs.now();
+        if let Some((new_index, new_id)) = target {
+            let new_is_dir = self.read_other_disk_inode(new_id, &fs, |disk_inode| disk_inode.is_dir());
+            if mode == RenameMode::Exchange {
+                if new_is_dir && !same_dir && self.has_ancestor(self.inode_id as u32, new_id, &fs) {
+                    return false;
+                }
+                new_dir.write_dirent(new_index, new_name, old_id, now);
+                self.write_dirent(old_index, old_name, new_id, now);
+                if !same_dir {
+                    self.move_dir(old_id, old_is_dir, new_dir, &fs);
+                    new_dir.move_dir(new_id, new_is_dir, self, &fs);
+                }
+                self.modify_other_disk_inode(new_id, &fs, |disk_inode| disk_inode.touch_ctime(now));
+            } else {
+                if old_is_dir != new_is_dir || new_is_dir && !self.is_empty_dir(new_id, &fs) {
+                    return false;
+                }
+                new_dir.write_dirent(new_index, new_name, old_id, now);
+                self.remove_dirent(old_index, now);
+                if !same_dir {
+                    self.move_dir(old_id, old_is_dir, new_dir, &fs);
+                }
+                // 被替换的 inode 失去一个硬链接，被替换的目录还失去它自己的 "."，它的 ".." 不再指向 new_dir
+                let nlink = self.modify_other_disk_inode(new_id, &fs, |disk_inode| {
+                    disk_inode.nlink = if new_is_dir { 0 } else { disk_inode.link_count().saturating_sub(1) };
+                    disk_inode.touch_ctime(now);
+                    disk_inode.nlink
+                });
+                if new_is_dir {
+                    new_dir.modify_disk_inode(|dir_inode| dir_inode.nlink = dir_inode.link_count() - 1);
+                }
+                if nlink == 0 {
+                    fs.release_inode(new_id);
+                }
+            }
+        } else {
+            new_dir.modify_disk_inode(|dir_inode| {
+                new_dir.append_dirent(new_name, old_id, dir_inode, &mut fs);
+            });
+            self.remove_dirent(old_index, now);
+            if !same_dir {
+                self.move_dir(old_id, old_is_dir, new_dir, &fs);
+            }
+        }
+        self.modify_other_disk_inode(old_id, &fs, |disk_inode| disk_inode.touch_ctime(now));
+        fs.commit();
+        true
+    }
+
+    // 改写目录中第 index 个目录项，同时更新目录的 mtime
+    fn write_dirent(&self, index: usize, name: &str, inode_id: u32, now: u32) {
+        self.modify_disk_inode(|dir_inode| {
+            dir_inode.write_at(
+                index * DIRENT_SZ,
+                DirEntry::new(name, inode_id).into_bytes(),
+                &self.block_device,
+            );
+            dir_inode.touch_mtime(now);
+        });
+    }
+
+    fn remove_dirent(&self, index: usize, now: u32) {
+        self.modify_disk_inode(|dir_inode| {
+            dir_inode.write_at(
+                index * DIRENT_SZ,
+                DirEntry::empty().into_bytes(),
+                &self.block_device,
+            );
+            dir_inode.touch_mtime(now);
+        });
+    }
+
+    // inode_id 从当前目录被移动到了 new_dir 中，如果它是目录则让它的 ".." 指向 new_dir
+    // 它的 ".." 所贡献的硬链接也从当前目录转移到 new_dir
+    fn move_dir(&self, inode_id: u32, is_dir: bool, new_dir: &Inode, fs: &MutexGuard<EasyFileSystem>) {
+        if !is_dir {
+            return;
+        }
+        let parent_index = self.read_other_disk_inode(inode_id, fs, |dir_inode| {
+            self.find_dirent(dir_inode, |dirent| dirent.name() == "..")
+        });
+        if let Some((index, _)) = parent_index {
+            self.modify_other_disk_inode(inode_id, fs, |dir_inode| {
+                dir_inode.write_at(
+                    index * DIRENT_SZ,
+                    DirEntry::new("..", new_dir.inode_id as u32).into_bytes(),
+                    &self.block_device,
+                );
+            });
+        }
+        self.modify_disk_inode(|dir_inode| dir_inode.nlink = dir_inode.link_count() - 1);
+        new_dir.modify_disk_inode(|dir_inode| dir_inode.nlink = dir_inode.link_count() + 1);
+    }
+
+    // 从目录 dir_id 出发沿着 ".." 一直向上走到根目录，判断途中是否经过 ancestor_id
+    fn has_ancestor(&self, dir_id: u32, ancestor_id: u32, fs: &MutexGuard<EasyFileSystem>) -> bool {
+        let mut inode_id = dir_id;
+        loop {
+            if inode_id == ancestor_id {
+                return true;
+            }
+            let parent = self.read_other_disk_inode(inode_id, fs, |dir_inode| {
+                self.find_dirent(dir_inode, |dirent| dirent.name() == "..")
+            });
+            match parent {
+                // 根目录的 ".." 指向它自己
+                Some((_, parent_id)) if parent_id != inode_id => inode_id = parent_id,
+                _ => return false,
+            }
+        }
+    }
+
+    // 目录中除了 "." 和 ".." 之外没有其他目录项
+    fn is_empty_dir(&self, inode_id: u32, fs: &MutexGuard<EasyFileSystem>) -> bool {
+        self.read_other_disk_inode(inode_id, fs, |dir_inode| {
+            self.find_dirent(dir_inode, |dirent| {
+                !dirent.is_empty() && dirent.name() != "." && dirent.name() != ".."
+            }).is_none()
+        })
+    }
+
+    // 供 fsck 把孤儿 inode 放入 lost+found：直接加入一个目录项，不检查重名，也不修改硬链接数量
+    pub(crate) fn add_dirent(&self, name: &str, inode_id: u32) {
+        let mut fs = self.fs.lock();
+        self.modify_disk_inode(|dir_inode| {
+            self.append_dirent(name, inode_id, dir_inode, &mut fs);
+        });
+        fs.commit();
+    }
+
+    // 为一个空目录写入 "." 和 ".." 两个目录项，并增加它们所指向的目录的硬链接数量
+    pub(crate) fn initialize_dir(&self, parent_id: u32) {
+        let mut fs = self.fs.lock();
+        self.initialize_dirents(parent_id, &mut fs);
+        fs.commit();
+    }
+
+    fn initialize_dirents(&self, parent_id: u32, fs: &mut MutexGuard<EasyFileSystem>) {
+        self.modify_disk_inode(|dir_inode| {
+            self.append_dirent(".", self.inode_id as u32, dir_inode, fs);
+            self.append_dirent("..", parent_id, dir_inode, fs);
+            dir_inode.nlink += 1;
+        });
+        self.modify_other_disk_inode(parent_id, fs, |parent_inode| {
+            parent_inode.nlink = parent_inode.link_count() + 1;
+        });
+    }
+
+    fn create_inode(
+        &self,
+        name: &str,
+        type_: DiskInodeType,
+        owner: InodeOwner,
+        data: &[u8],
+    ) -> Option<Arc<Inode>> {
+        if !Self::valid_name(name) {
+            return None;
+        }
+        let mut fs = self.fs.lock();
+        // 当前 Inode 不是目录，或者同名的目录项已经存在时返回 None
+        if self.read_disk_inode(|dir_inode| {
+            !dir_inode.is_dir() || self.find_inode_id(name, dir_inode).is_some()
+        }) {
             return None;
         }
         // 为待创建文件分配一个新的 inode 并进行初始化
         // create a new file
         // alloc a inode with an indirect block
-        let new_inode_id = fs.alloc_inode();
+        let new_inode_id = fs.alloc_inode()?;
+        let inode_format = fs.inode_format();
+        let now = fs.now();
+        let is_dir = type_ == DiskInodeType::Directory;
         // initialize inode
         let (new_inode_block_id, new_inode_block_offset) 
             = fs.get_disk_inode_pos(new_inode_id);
@@ -168,41 +623,41 @@ impl Inode {
             new_inode_block_id as usize,
             Arc::clone(&self.block_device)
         ).lock().modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-            new_inode.initialize(DiskInodeType::File);
+            new_inode.initialize(type_, inode_format, now, owner);
+            new_inode.nlink = 1;
         });
-        // 将待创建文件的目录项插入到根目录的内容中使得之后可以索引过来
-        self.modify_disk_inode(|root_inode| {
-            // append file in the dirent
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            // increase size
-            self.increase_size(new_size as u32, root_inode, &mut fs);
-            // write dirent
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.into_bytes(),
-                &self.block_device,
-            );
+        // 将待创建文件的目录项插入到目录的内容中使得之后可以索引过来
+        self.modify_disk_inode(|dir_inode| {
+            self.append_dirent(name, new_inode_id, dir_inode, &mut fs);
         });
-        let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        // return inode
-        Some(Arc::new(Self::new(
+        let new_inode = Arc::new(Self::new(
             new_inode_id,
-            block_id,
-            block_offset,
+            &mut fs,
             self.fs.clone(),
             self.block_device.clone(),
-        )))
+        ));
+        // 新目录的 "." 和 ".." 与它的目录项在同一个事务中写入
+        if is_dir {
+            new_inode.initialize_dirents(self.inode_id as u32, &mut fs);
+        }
+        // 符号链接的目标路径同样在这个事务中写入，不会出现没有内容的符号链接
+        if !data.is_empty() {
+            new_inode.modify_disk_inode(|disk_inode| {
+                new_inode.increase_size(data.len(), disk_inode, &mut fs);
+                disk_inode.write_at(0, data, &self.block_device);
+            });
+        }
+        fs.commit();
+        // return inode
+        Some(new_inode)
         // release efs lock automatically by compiler
     }
 
-    // 收集根目录下的所有文件的文件名并以向量的形式返回回来
-    // 只有根目录的 Inode 才会调用
+    // 收集当前目录下的所有文件的文件名并以向量的形式返回回来，包括 "." 和 ".."
     pub fn ls(&self) -> Vec<String> {
         let _fs = self.fs.lock();
         self.read_disk_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+            let file_count = disk_inode.size() / DIRENT_SZ;
             let mut v: Vec<String> = Vec::new();
             for i in 0..file_count {
                 let mut dirent_bytes: DirentBytes = Default::default();
@@ -214,7 +669,10 @@ impl Inode {
                     ),
                     DIRENT_SZ,
                 );
-                v.push(String::from(DirEntry::from_bytes(&dirent_bytes).name()));
+                let dirent = DirEntry::from_bytes(&dirent_bytes);
+                if !dirent.is_empty() {
+                    v.push(String::from(dirent.name()));
+                }
             }
             v
         })
@@ -222,32 +680,85 @@ impl Inode {
 
     // 从根目录索引到一个文件之后可以对它进行读写
     // 这里的读写作用在字节序列的一段区间上
+    // 读取也会更新 atime，需要更新时作为一个事务提交
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
-        let _fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            disk_inode.read_at(offset, buf, &self.block_device)
-        })
+        let fs = self.fs.lock();
+        let now = fs.now();
+        let (read_size, atime_outdated) = self.read_disk_inode(|disk_inode| {
+            (disk_inode.read_at(offset, buf, &self.block_device), disk_inode.atime_outdated(now))
+        });
+        if atime_outdated {
+            self.modify_disk_inode(|disk_inode| disk_inode.touch_atime(now));
+            fs.commit();
+        }
+        read_size
     }
 
     // 注意在 DiskInode::write_at 之前先调用 increase_size 对自身进行扩容
+    // 超出文件大小上限的部分不会被写入，返回实际写入的字节数
+    // 扩容和写入都按照 TRANSACTION_DATA_BLOCKS 个块分批进行，每批是一个事务，崩溃之后可能只有前面的一部分被写入
     pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        const BATCH_SIZE: usize = TRANSACTION_DATA_BLOCKS * BLOCK_SZ;
         let mut fs = self.fs.lock();
-        self.modify_disk_inode(|disk_inode| {
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
-            disk_inode.write_at(offset, buf, &self.block_device)
-        })
+        let end = self.read_disk_inode(|disk_inode| {
+            (offset + buf.len()).min(disk_inode.max_size())
+        });
+        if offset >= end {
+            return 0;
+        }
+        loop {
+            let size = self.read_disk_inode(|disk_inode| disk_inode.size());
+            if size >= end {
+                break;
+            }
+            let new_size = end.min(size / BLOCK_SZ * BLOCK_SZ + BATCH_SIZE);
+            self.modify_disk_inode(|disk_inode| {
+                self.increase_size(new_size, disk_inode, &mut fs);
+            });
+            fs.commit();
+        }
+        let mut start = offset;
+        let now = fs.now();
+        while start < end {
+            let batch_end = end.min(start / BLOCK_SZ * BLOCK_SZ + BATCH_SIZE);
+            self.modify_disk_inode(|disk_inode| {
+                disk_inode.write_at(
+                    start,
+                    &buf[start - offset..batch_end - offset],
+                    &self.block_device,
+                );
+                disk_inode.touch_mtime(now);
+            });
+            fs.commit();
+            start = batch_end;
+        }
+        end - offset
     }
 
     // 文件清空。在索引到文件的 Inode 之后可以调用 clear 方法
+    // 和 write_at 一样分批进行，崩溃之后文件可能只被截断了一部分
     pub fn clear(&self) {
         let mut fs = self.fs.lock();
-        self.modify_disk_inode(|disk_inode| {
-            let size = disk_inode.size;
-            let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
-            assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
-            for data_block in data_blocks_dealloc.into_iter() {
-                fs.dealloc_data(data_block);
-            }
-        });
+        let now = fs.now();
+        self.modify_disk_inode(|disk_inode| disk_inode.touch_mtime(now));
+        fs.truncate_inode(self.inode_id as u32, 0);
+        fs.commit();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RenameMode {
+    Replace,
+    NoReplace,
+    Exchange,
+}
+
+// 最后一个 Inode 被回收时，已经被 unlink 的 inode 在这里被真正释放
+// 因此不能在持有 efs 锁的时候回收 Inode
+impl Drop for Inode {
+    fn drop(&mut self) {
+        let mut fs = self.fs.lock();
+        fs.close_inode(self.inode_id as u32);
+        fs.commit();
     }
 }
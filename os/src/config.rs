@@ -1,15 +1,51 @@
-pub const USER_STACK_SIZE: usize = 4096;
+pub const USER_STACK_SIZE: usize = 4096; // 用户栈初始映射的大小
+pub const USER_STACK_LIMIT: usize = 4096 * 16; // 用户栈默认最多可以增长到的大小，可以通过 setrlimit 修改
+pub const USER_STACK_LIMIT_MAX: usize = 0x100_0000; // setrlimit 允许设置的用户栈大小上限
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
 
 // 页表&虚存机制
-pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;
+pub const KERNEL_HEAP_INIT_SIZE: usize = 0x8_0000; // 位于 .bss 段中的初始内核堆大小，之后按需从物理页帧分配器扩展
+pub const KERNEL_HEAP_GROW_SIZE: usize = 0x4_0000; // 内核堆每次扩展时优先取出的大小，连续的页帧不足时可能更小
+pub const KERNEL_HEAP_LOW_WATERMARK: usize = 0x1_0000; // 内核堆剩余空间低于该值时提前扩展
+// 物理内存的终止地址和设备的位置在启动时从设备树中获得，以下硬编码的值仅在设备树不可用时使用
 pub const MEMORY_END: usize = 0x80c0_0000; // 硬编码整块物理内存的终止物理地址为 0x80800000, 可用内存大小设置为 8MiB 
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
+// 分页模式在编译时通过 sv48 特性选择，默认为 SV39
+// 每一级页表都有 512 个页表项，占用虚拟页号中的 9 位
+#[cfg(not(feature = "sv48"))]
+pub const PAGING_LEVELS: usize = 3;
+#[cfg(not(feature = "sv48"))]
+pub const SATP_MODE: usize = 8;
+#[cfg(feature = "sv48")]
+pub const PAGING_LEVELS: usize = 4;
+#[cfg(feature = "sv48")]
+pub const SATP_MODE: usize = 9;
+// 虚拟地址的有效位数，SV39 下为 39，SV48 下为 48
+pub const VA_BITS: usize = PAGE_SIZE_BITS + 9 * PAGING_LEVELS;
 // 可用的物理内存对应的物理页号: [ekernel.ceil(), MEMORY_END.floor())
 
-pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
+// 合法的虚拟地址的高位都是第 VA_BITS - 1 位的符号扩展
+pub const fn canonical_va(va: usize) -> usize {
+    if va & (1 << (VA_BITS - 1)) != 0 {
+        va | !((1usize << VA_BITS) - 1)
+    } else {
+        va & ((1usize << VA_BITS) - 1)
+    }
+}
+// 跳板位于虚拟地址空间的最高页面，Trap 上下文位于它的下面
+pub const TRAMPOLINE: usize = canonical_va((1 << VA_BITS) - PAGE_SIZE);
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
+// shmat 未指定挂载地址时，从这里开始向上寻找空闲的虚拟地址区间
+pub const SHM_AREA_START: usize = 0x4000_0000;
+// 用户地址空间的上界 (虚拟地址空间的低半部分)，应用传入的指针不能超过这里
+pub const USER_SPACE_END: usize = 1 << (VA_BITS - 1);
+// 地址空间布局随机化 (ASLR)，以下区间互不重叠，且都位于 ELF 通常的链接地址和测例使用的固定 mmap 地址之上
+pub const ASLR_STACK_BASE: usize = 0x10_0000_0000; // 启用随机化时用户栈栈顶位于 [ASLR_STACK_BASE, ASLR_STACK_BASE + ASLR_STACK_RANGE)
+pub const ASLR_STACK_RANGE: usize = 0x10_0000_0000;
+pub const ASLR_MMAP_RANGE: usize = 0x8_0000_0000; // shmat 等由内核选择地址时的起点在 SHM_AREA_START 之上的随机偏移范围
+pub const PIE_LOAD_BASE: usize = 0x8_8000_0000; // 位置无关可执行文件 (ET_DYN) 的默认加载地址
+pub const ASLR_PIE_RANGE: usize = 0x4_0000_0000; // 启用随机化时加载地址在 PIE_LOAD_BASE 之上的随机偏移范围
 /// Return (bottom, top) of a kernel stack in kernel space.
 pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
     let top = TRAMPOLINE - app_id * (KERNEL_STACK_SIZE + PAGE_SIZE);
@@ -24,6 +60,14 @@ pub const BIG_STRIDE: isize = 0x7FFFFFFF;
 pub const TASK_INIT_PRIORITY: isize = 16;
 
 // file system
+// 设备树不可用时内核映射的设备寄存器区间 (起始地址, 大小)
 pub const MMIO: &[(usize, usize)] = &[
+    (0x101000, 0x1000),
     (0x10000000, 0x10000),
 ];
+// 设备树不可用时假定存在的 goldfish RTC 的寄存器地址，位于 MMIO 的第一个区间中
+pub const GOLDFISH_RTC: usize = 0x101000;
+// 设备树不可用时假定存在的 virtio-mmio 设备 (起始地址, 大小, 中断号)
+pub const VIRTIO_MMIO: &[(usize, usize, u32)] = &[
+    (0x10001000, 0x1000, 1),
+];
@@ -1,24 +1,20 @@
 
-use virtio_drivers::{VirtIOBlk, VirtIOHeader};
+use virtio_drivers::{VirtIOBlk, VirtIOHeader, DeviceType};
 use crate::mm::{
     PhysAddr,
     VirtAddr,
-    frame_alloc,
-    frame_dealloc,
+    frame_alloc_contiguous,
     PhysPageNum,
     FrameTracker,
-    StepByOne,
     PageTable,
     kernel_token,
 };
+use crate::fdt::machine_info;
 use super::BlockDevice;
 use spin::Mutex;
 use alloc::vec::Vec;
 use lazy_static::*;
 
-#[allow(unused)]
-const VIRTIO0: usize = 0x10001000;
-
 // VirtIO 块设备抽象
 pub struct VirtIOBlock(Mutex<VirtIOBlk<'static>>);
 
@@ -39,10 +35,16 @@ impl VirtIOBlock {
     #[allow(unused)]
     pub fn new() -> Self {
         // VirtIOHeader 实际上就代表以 MMIO 方式访问 VirtIO 设备所需的一组设备寄存器
-        // Virtio MMIO 区间左端 VIRTIO0 开始转化为一个 &mut VirtIOHeader 就可以在该平台上访问这些设备寄存器了
-        Self(Mutex::new(VirtIOBlk::new(
-            unsafe { &mut *(VIRTIO0 as *mut VirtIOHeader) }
-        ).unwrap()))
+        // 设备树中的每个 virtio-mmio 节点都是一个可能挂载了设备的插槽，将 Virtio MMIO 区间左端转化为一个 &mut VirtIOHeader
+        // 就可以读出插槽上设备的类型，使用第一个块设备
+        for dev in machine_info().virtio.iter() {
+            let header = unsafe { &mut *(dev.base as *mut VirtIOHeader) };
+            if header.verify() && header.device_type() == DeviceType::Block {
+                info!("[kernel] virtio-blk at {:#x}, irq {:?}", dev.base, dev.irq);
+                return Self(Mutex::new(VirtIOBlk::new(header).unwrap()));
+            }
+        }
+        panic!("[kernel] No virtio block device found!");
     }
 }
 
@@ -50,27 +52,19 @@ impl VirtIOBlock {
 // 但这并不在 VirtIO 驱动 virtio-drivers 的职责范围之内，因此它声明了数个相关的接口，需要库的使用者自己来实现
 #[no_mangle]
 pub extern "C" fn virtio_dma_alloc(pages: usize) -> PhysAddr {
-    let mut ppn_base = PhysPageNum(0);
-    // 需要分配/回收数个 连续 的物理页帧
-    // 而我们的 frame_alloc 是逐个分配，严格来说并不保证分配的连续性
-    // 幸运的是，这个过程只会发生在内核初始化阶段，因此能够保证连续性
-    for i in 0..pages {
-        let frame = frame_alloc().unwrap();
-        if i == 0 { ppn_base = frame.ppn; }
-        assert_eq!(frame.ppn.0, ppn_base.0 + i);
-        // 通过 frame_alloc 得到的那些物理页帧 FrameTracker 都会被保存在全局的向量 QUEUE_FRAMES 以延长它们的生命周期，避免提前被回收
-        QUEUE_FRAMES.lock().push(frame);
-    }
+    // 需要分配/回收数个 连续 的物理页帧，由 frame_alloc_contiguous 保证分配的连续性
+    let frames = frame_alloc_contiguous(pages, 1).unwrap();
+    let ppn_base = frames[0].ppn;
+    // 得到的那些物理页帧 FrameTracker 都会被保存在全局的向量 QUEUE_FRAMES 以延长它们的生命周期，避免提前被回收
+    QUEUE_FRAMES.lock().extend(frames);
     ppn_base.into()
 }
 
 #[no_mangle]
 pub extern "C" fn virtio_dma_dealloc(pa: PhysAddr, pages: usize) -> i32 {
-    let mut ppn_base: PhysPageNum = pa.into();
-    for _ in 0..pages {
-        frame_dealloc(ppn_base);
-        ppn_base.step();
-    }
+    let ppn_base: PhysPageNum = pa.into();
+    // 从 QUEUE_FRAMES 中移除对应的 FrameTracker, 它们被丢弃时物理页帧随之回收
+    QUEUE_FRAMES.lock().retain(|frame| frame.ppn.0 < ppn_base.0 || frame.ppn.0 >= ppn_base.0 + pages);
     0
 }
 
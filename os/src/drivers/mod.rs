@@ -1,4 +1,6 @@
 mod block;
+mod rtc;
 
 pub use block::BLOCK_DEVICE;
 pub use block::block_device_test;
+pub use rtc::RtcClock;
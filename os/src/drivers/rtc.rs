@@ -0,0 +1,34 @@
+use core::ptr::read_volatile;
+use easy_fs::Clock;
+use crate::fdt::machine_info;
+use crate::timer::get_time_us;
+
+// goldfish RTC: QEMU virt 平台上的实时时钟
+// TIME_LOW 和 TIME_HIGH 两个寄存器合起来是自 1970-01-01 00:00:00 UTC 以来的纳秒数，读取 TIME_LOW 时硬件会锁存对应的 TIME_HIGH
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+const USEC_PER_SEC: usize = 1_000_000;
+
+// 当前的墙上时间，以秒为单位
+// 设备树中没有 RTC 时只能退化为开机以来经过的时间
+pub fn wall_time_sec() -> u64 {
+    match machine_info().rtc {
+        Some(base) => {
+            // 必须先读 TIME_LOW
+            let low = unsafe { read_volatile((base + TIME_LOW) as *const u32) };
+            let high = unsafe { read_volatile((base + TIME_HIGH) as *const u32) };
+            ((high as u64) << 32 | low as u64) / NSEC_PER_SEC
+        }
+        None => (get_time_us() / USEC_PER_SEC) as u64,
+    }
+}
+
+// 文件系统中 inode 的时间戳来自 RTC
+pub struct RtcClock;
+
+impl Clock for RtcClock {
+    fn now(&self) -> u64 {
+        wall_time_sec()
+    }
+}
@@ -3,7 +3,7 @@
     .globl _start
 _start: // _start 是整个程序的入口点
     la sp, boot_stack_top // 将 sp 设置为我们预留的栈空间的栈顶位置
-    call rust_main // 调用 rust_main
+    call rust_main // 调用 rust_main, SBI 传入的 a0 (hartid) 和 a1 (设备树地址) 原样作为它的参数
     // 以上这两条指令单独作为一个名为 .text.entry 的段
 
 // 栈 从 高地址 到 低地址 增长
@@ -0,0 +1,267 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::vec;
+use spin::Once;
+use crate::config::{MEMORY_END, MMIO, VIRTIO_MMIO, GOLDFISH_RTC};
+
+// 扁平设备树 (Flattened Device Tree, FDT) 的解析
+// SBI 跳转到内核时通过 a1 寄存器传入设备树在内存中的物理地址，内核从中获得物理内存的范围、virtio-mmio 设备的位置和中断号以及启动参数
+// 解析的结果都被复制到内核堆上，之后设备树所在的内存就可以作为普通的物理页帧分配出去了
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+// 设备树的大小上限，超过时认为传入的指针不可信
+const FDT_MAX_SIZE: usize = 0x10_0000;
+
+// 一个 virtio-mmio 设备的寄存器区间和中断号
+#[derive(Debug, Clone, Copy)]
+pub struct VirtioMmio {
+    pub base: usize,
+    pub size: usize,
+    pub irq: Option<u32>,
+}
+
+pub struct MachineInfo {
+    pub memory: Vec<(usize, usize)>, // 物理内存区间 [start, end)
+    pub mmio: Vec<(usize, usize)>, // 内核需要恒等映射的设备寄存器区间 (起始地址, 大小)
+    pub virtio: Vec<VirtioMmio>, // 按地址从低到高排列
+    pub rtc: Option<usize>, // goldfish RTC 的寄存器地址
+    pub bootargs: String, // /chosen/bootargs
+    pub rng_seed: usize, // /chosen/rng-seed 和 /chosen/kaslr-seed 折叠成的随机种子，没有时为 0
+}
+
+impl MachineInfo {
+    // 设备树不可用时使用 config.rs 中为 QEMU virt 平台硬编码的默认值
+    fn fallback() -> Self {
+        Self {
+            memory: vec![(0x8000_0000, MEMORY_END)],
+            mmio: MMIO.to_vec(),
+            virtio: VIRTIO_MMIO
+                .iter()
+                .map(|&(base, size, irq)| VirtioMmio { base, size, irq: Some(irq) })
+                .collect(),
+            rtc: Some(GOLDFISH_RTC),
+            bootargs: String::new(),
+            rng_seed: 0,
+        }
+    }
+    // 内核所在的那一块物理内存的终止地址，[ekernel, memory_end) 都可以交给物理页帧分配器
+    pub fn memory_end(&self) -> usize {
+        extern "C" {
+            fn ekernel();
+        }
+        self.memory
+            .iter()
+            .find(|(start, end)| *start <= ekernel as usize && (ekernel as usize) < *end)
+            .map_or(MEMORY_END, |(_, end)| *end)
+    }
+}
+
+static MACHINE_INFO: Once<MachineInfo> = Once::new();
+
+// 解析 dtb_pa 处的设备树，需要在内核堆初始化之后、物理页帧分配器初始化之前调用
+pub fn init(dtb_pa: usize) {
+    MACHINE_INFO.call_once(|| {
+        match unsafe { parse_at(dtb_pa) } {
+            Some(info) => info,
+            None => {
+                warn!("[kernel] No valid device tree at {:#x}, using built-in machine layout", dtb_pa);
+                MachineInfo::fallback()
+            }
+        }
+    });
+    let info = machine_info();
+    for (start, end) in info.memory.iter() {
+        info!("[kernel] memory [{:#x}, {:#x})", start, end);
+    }
+    for dev in info.virtio.iter() {
+        info!("[kernel] virtio-mmio at {:#x}, size {:#x}, irq {:?}", dev.base, dev.size, dev.irq);
+    }
+    if let Some(rtc) = info.rtc {
+        info!("[kernel] goldfish-rtc at {:#x}", rtc);
+    }
+    info!("[kernel] bootargs: {:?}", info.bootargs);
+}
+
+pub fn machine_info() -> &'static MachineInfo {
+    MACHINE_INFO.get().expect("[kernel] device tree is not parsed yet")
+}
+
+// 启动参数由空格分隔，形如 key=value 或者单独的 key (此时值为空串)
+pub fn boot_param(key: &str) -> Option<&'static str> {
+    machine_info().bootargs.split_whitespace().find_map(|arg| {
+        let mut kv = arg.splitn(2, '=');
+        if kv.next() == Some(key) {
+            Some(kv.next().unwrap_or(""))
+        } else {
+            None
+        }
+    })
+}
+
+unsafe fn parse_at(dtb_pa: usize) -> Option<MachineInfo> {
+    if dtb_pa == 0 || dtb_pa % 8 != 0 {
+        return None;
+    }
+    let header = core::slice::from_raw_parts(dtb_pa as *const u8, 8);
+    if be32(header, 0)? != FDT_MAGIC {
+        return None;
+    }
+    let total_size = be32(header, 4)? as usize;
+    if total_size > FDT_MAX_SIZE {
+        return None;
+    }
+    parse(core::slice::from_raw_parts(dtb_pa as *const u8, total_size))
+}
+
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// 由 cells 个 32 位大端序单元拼成的数
+fn read_cells(data: &[u8], cells: usize) -> Option<usize> {
+    let mut value = 0;
+    for i in 0..cells {
+        value = value << 32 | be32(data, i * 4)? as usize;
+    }
+    Some(value)
+}
+
+// offset 处以 \0 结尾的字符串，不包括 \0
+fn c_str(data: &[u8], offset: usize) -> Option<&[u8]> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    Some(&bytes[..len])
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+// 正在解析的节点，节点的属性总是出现在它的子节点之前
+struct Node<'a> {
+    name: &'a [u8],
+    address_cells: usize, // 子节点 reg 属性中地址和大小所占的单元数
+    size_cells: usize,
+    is_memory: bool,
+    is_virtio: bool,
+    is_rtc: bool,
+    reg: &'a [u8],
+    irq: Option<u32>,
+}
+
+impl<'a> Node<'a> {
+    fn new(name: &'a [u8]) -> Self {
+        Self {
+            name,
+            address_cells: 2,
+            size_cells: 1,
+            is_memory: false,
+            is_virtio: false,
+            is_rtc: false,
+            reg: &[],
+            irq: None,
+        }
+    }
+    // 节点结束时按照父节点给出的格式解析 reg 属性
+    fn finish(self, parent: &Node, info: &mut MachineInfo) -> Option<()> {
+        let (address_cells, size_cells) = (parent.address_cells, parent.size_cells);
+        let entry_size = (address_cells + size_cells) * 4;
+        if entry_size == 0 {
+            return Some(());
+        }
+        for entry in self.reg.chunks_exact(entry_size) {
+            let base = read_cells(entry, address_cells)?;
+            let size = read_cells(&entry[address_cells * 4..], size_cells)?;
+            if self.is_memory {
+                info.memory.push((base, base.checked_add(size)?));
+            } else if self.is_virtio {
+                info.virtio.push(VirtioMmio { base, size, irq: self.irq });
+                info.mmio.push((base, size));
+                // 每个 virtio-mmio 设备只有一组寄存器
+                break;
+            } else if self.is_rtc {
+                info.rtc = Some(base);
+                info.mmio.push((base, size));
+                break;
+            }
+        }
+        Some(())
+    }
+}
+
+fn parse(blob: &[u8]) -> Option<MachineInfo> {
+    let struct_offset = be32(blob, 8)? as usize;
+    let strings = blob.get(be32(blob, 12)? as usize..)?;
+    let mut info = MachineInfo {
+        memory: Vec::new(),
+        mmio: Vec::new(),
+        virtio: Vec::new(),
+        rtc: None,
+        bootargs: String::new(),
+        rng_seed: 0,
+    };
+    let mut stack: Vec<Node> = Vec::new();
+    let mut pos = struct_offset;
+    loop {
+        let token = be32(blob, pos)?;
+        pos += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = c_str(blob, pos)?;
+                pos = align4(pos + name.len() + 1);
+                stack.push(Node::new(name));
+            }
+            FDT_PROP => {
+                let len = be32(blob, pos)? as usize;
+                let name = c_str(strings, be32(blob, pos + 4)? as usize)?;
+                let value = blob.get(pos + 8..pos + 8 + len)?;
+                pos = align4(pos + 8 + len);
+                // 根节点位于第 1 层，/chosen 位于第 2 层
+                let depth = stack.len();
+                let node = stack.last_mut()?;
+                match name {
+                    b"#address-cells" => node.address_cells = be32(value, 0)? as usize,
+                    b"#size-cells" => node.size_cells = be32(value, 0)? as usize,
+                    b"device_type" => node.is_memory = c_str(value, 0)? == b"memory",
+                    // compatible 是若干个以 \0 结尾的字符串
+                    b"compatible" => {
+                        node.is_virtio = value.split(|&b| b == 0).any(|s| s == b"virtio,mmio");
+                        node.is_rtc = value.split(|&b| b == 0).any(|s| s == b"google,goldfish-rtc");
+                    }
+                    b"reg" => node.reg = value,
+                    b"interrupts" => node.irq = be32(value, 0),
+                    b"bootargs" if depth == 2 && node.name == b"chosen" => {
+                        info.bootargs = String::from(core::str::from_utf8(c_str(value, 0)?).ok()?);
+                    }
+                    b"rng-seed" | b"kaslr-seed" if depth == 2 && node.name == b"chosen" => {
+                        for (i, byte) in value.iter().enumerate() {
+                            info.rng_seed ^= (*byte as usize) << (i % 8 * 8);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            FDT_END_NODE => {
+                let node = stack.pop()?;
+                if let Some(parent) = stack.last() {
+                    node.finish(parent, &mut info)?;
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => break,
+            _ => return None,
+        }
+    }
+    if info.memory.is_empty() {
+        return None;
+    }
+    info.memory.sort();
+    info.virtio.sort_by_key(|dev| dev.base);
+    Some(info)
+}
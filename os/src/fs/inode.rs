@@ -1,63 +1,298 @@
 // 内核索引节点层
 use easy_fs::{
+    DiskInodeType,
     EasyFileSystem,
     Inode,
+    InodeOwner,
+    InodeTimes,
 };
-use crate::drivers::BLOCK_DEVICE;
+use crate::drivers::{BLOCK_DEVICE, RtcClock};
 use lazy_static::*;
 use bitflags::*;
 use spin::Mutex;
 use super::File;
+use super::path::{split_parent, normalize};
+use crate::task::for_each_cwd;
 use crate::mm::UserBuffer;
 use alloc::vec::Vec;
-use alloc::sync::Arc;
-use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
 use alloc::string::String;
 
-// 硬链接映射表:
-lazy_static! {
-    pub static ref HARD_LINK_MAP: Mutex<BTreeMap<String, Arc<OSInode>>> = Mutex::new(BTreeMap::new());
-    pub static ref RECYCLED_MAP: Mutex<BTreeMap<String, Arc<OSInode>>> = Mutex::new(BTreeMap::new());
+// 进程的身份，fork 时被子进程继承，exec 后保持不变
+#[derive(Clone, Copy, Debug)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
 }
 
-pub fn link(old_path_str: &str, new_path_str: &str) -> isize {
-    if old_path_str == new_path_str {
-        return -1;
+impl Credentials {
+    pub const ROOT: Self = Self { uid: 0, gid: 0 };
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+    // 以这个身份创建的文件或目录的所有者
+    fn owner(&self, mode: u32) -> InodeOwner {
+        InodeOwner { uid: self.uid, gid: self.gid, mode }
+    }
+}
+
+// 访问权限检查时请求的权限，与 mode 中每组三个权限位的含义相同
+pub const MAY_READ: u32 = 0o4;
+pub const MAY_WRITE: u32 = 0o2;
+pub const MAY_EXEC: u32 = 0o1; // 对目录来说是搜索权限
+
+// 按照 Unix 的规则检查 cred 能否以 access 中的全部方式访问 inode
+// 所有者使用 mode 的第 8~6 位，同组用户使用第 5~3 位，其他用户使用第 2~0 位
+// root 可以读写任何文件、搜索任何目录，但只能执行至少有一个执行位的普通文件
+fn permitted(inode: &Inode, cred: &Credentials, access: u32) -> bool {
+    let owner = inode.get_owner();
+    if cred.is_root() {
+        return access & MAY_EXEC == 0 || inode.is_dir() || owner.mode & 0o111 != 0;
     }
-    let old_path = String::from(old_path_str);
-    let new_path = String::from(new_path_str);
-    // 处理创建硬链接的硬链接
-    let mut map_lock = HARD_LINK_MAP.lock();
-    if let Some(mut old_inode) = map_lock.get(&old_path) {
-        old_inode.inner.lock().inode.create_nlink();
-        let new_inode = Arc::clone(old_inode);
-        map_lock.insert(new_path, new_inode);
-        0
+    let bits = if cred.uid == owner.uid {
+        owner.mode >> 6
+    } else if cred.gid == owner.gid {
+        owner.mode >> 3
     } else {
-        -1
+        owner.mode
+    };
+    bits & access == access
+}
+
+// 一次查找中最多展开的符号链接个数，超过时认为符号链接形成了循环
+const MAX_SYMLINKS: usize = 40;
+
+// 内核中的路径都是绝对路径，从根目录开始逐级查找，经过的每个目录都需要搜索权限
+// 路径中间的符号链接总是被展开，最后一个分量是符号链接时只有 follow 为 true 才展开
+// 符号链接中的相对路径相对于它所在的目录，与剩余的路径拼接之后从根目录重新查找
+// ".." 通过当前目录在磁盘上的 ".." 目录项回到展开符号链接之后真正的父目录，根目录的 ".." 仍是根目录
+// 返回找到的 inode 以及不经过任何符号链接的规范化路径
+fn resolve(path: &str, cred: &Credentials, follow: bool) -> Option<(Arc<Inode>, String)> {
+    let mut path = String::from(path);
+    let mut symlinks = 0;
+    'restart: loop {
+        let mut inode = ROOT_INODE.clone();
+        let mut resolved = String::new();
+        let names: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+        for (i, name) in names.iter().enumerate() {
+            if !permitted(&inode, cred, MAY_EXEC) {
+                return None;
+            }
+            if *name == ".." {
+                if let Some(pos) = resolved.rfind('/') {
+                    inode = inode.find("..")?;
+                    resolved.truncate(pos);
+                }
+                continue;
+            }
+            let next = inode.find(name)?;
+            if next.is_symlink() && (i + 1 < names.len() || follow) {
+                symlinks += 1;
+                if symlinks > MAX_SYMLINKS {
+                    return None;
+                }
+                let mut target = next.read_link()?;
+                for name in names[i + 1..].iter() {
+                    target.push('/');
+                    target.push_str(name);
+                }
+                let base = if resolved.is_empty() { "/" } else { resolved.as_str() };
+                path = normalize(base, &target)?;
+                continue 'restart;
+            }
+            resolved.push('/');
+            resolved.push_str(name);
+            inode = next;
+        }
+        if resolved.is_empty() {
+            resolved.push('/');
+        }
+        return Some((inode, resolved));
+    }
+}
+
+fn lookup(path: &str, cred: &Credentials) -> Option<Arc<Inode>> {
+    resolve(path, cred, true).map(|(inode, _)| inode)
+}
+
+// 查找路径所在的目录，在其中增删目录项需要写和搜索权限
+// 最后一个分量不会被展开，返回目录、最后一个分量位于展开之后的目录中的路径以及最后一个分量
+fn lookup_parent<'a>(path: &'a str, cred: &Credentials) -> Option<(Arc<Inode>, String, &'a str)> {
+    let (parent, name) = split_parent(path)?;
+    let (parent, mut path) = resolve(parent, cred, true)?;
+    if !permitted(&parent, cred, MAY_WRITE | MAY_EXEC) {
+        return None;
+    }
+    if path != "/" {
+        path.push('/');
+    }
+    path.push_str(name);
+    Some((parent, path, name))
+}
+
+// 硬链接保存在磁盘上：在新路径所在的目录中写入一个指向同一 inode 的目录项
+// 不能为目录创建硬链接，新路径所在的目录必须存在，新路径已经存在时失败
+// 原有路径是符号链接时链接到符号链接本身
+pub fn link(old_path: &str, new_path: &str, cred: &Credentials) -> isize {
+    let old_inode = match resolve(old_path, cred, false) {
+        Some((old_inode, _)) => old_inode,
+        None => return -1,
+    };
+    match lookup_parent(new_path, cred) {
+        Some((parent, _, name)) if parent.link(name, &old_inode) => 0,
+        _ => -1,
+    }
+}
+
+// 删除路径对应的目录项，暂不支持删除目录，路径是符号链接时删除符号链接本身
+pub fn unlink(path: &str, cred: &Credentials) -> isize {
+    match lookup_parent(path, cred) {
+        Some((parent, _, name)) if parent.unlink(name) => 0,
+        _ => -1,
+    }
+}
+
+// 创建一个空目录，父目录必须已经存在，路径已经存在时失败
+pub fn mkdir(path: &str, mode: u32, cred: &Credentials) -> isize {
+    match lookup_parent(path, cred) {
+        Some((parent, _, name)) if parent.create_dir_as(name, cred.owner(mode)).is_some() => 0,
+        _ => -1,
+    }
+}
+
+// 创建一个指向 target 的符号链接，target 原样保存，不要求它存在
+// 与 Linux 相同，符号链接的访问权限总是 0o777，访问时只检查它指向的文件的权限
+pub fn symlink(target: &str, path: &str, cred: &Credentials) -> isize {
+    match lookup_parent(path, cred) {
+        Some((parent, _, name)) if parent.symlink(name, target, cred.owner(0o777)).is_some() => 0,
+        _ => -1,
+    }
+}
+
+// 读出符号链接中保存的路径，路径不是符号链接时返回 None
+pub fn read_link(path: &str, cred: &Credentials) -> Option<String> {
+    resolve(path, cred, false)?.0.read_link()
+}
+
+// 修改访问权限，只有所有者和 root 可以修改
+pub fn chmod(path: &str, mode: u32, cred: &Credentials) -> isize {
+    let inode = match lookup(path, cred) {
+        Some(inode) => inode,
+        None => return -1,
+    };
+    let owner = inode.get_owner();
+    if !cred.is_root() && cred.uid != owner.uid {
+        return -1;
+    }
+    if inode.set_owner(InodeOwner { mode, ..owner }) { 0 } else { -1 }
+}
+
+// 修改所有者和所属组，为 None 的保持不变，只有 root 可以修改
+pub fn chown(path: &str, uid: Option<u32>, gid: Option<u32>, cred: &Credentials) -> isize {
+    if !cred.is_root() {
+        return -1;
+    }
+    let inode = match lookup(path, cred) {
+        Some(inode) => inode,
+        None => return -1,
+    };
+    let owner = inode.get_owner();
+    let owner = InodeOwner {
+        uid: uid.unwrap_or(owner.uid),
+        gid: gid.unwrap_or(owner.gid),
+        mode: owner.mode,
+    };
+    if inode.set_owner(owner) { 0 } else { -1 }
+}
+
+// 改名的标志，与 Linux 的 renameat2 相同
+bitflags! {
+    pub struct RenameFlags: u32 {
+        const NOREPLACE = 1 << 0; // 新路径已经存在时失败
+        const EXCHANGE = 1 << 1; // 原子地交换两个已经存在的路径，不能与 NOREPLACE 同时使用
     }
 }
 
-pub fn unlink(path_str: &str) -> isize {
-    let path = String::from(path_str);
-    let mut map_lock = HARD_LINK_MAP.lock();
-    if let Some(mut old_inode) = map_lock.get(&path) {
-        old_inode.inner.lock().inode.destory_nlink();
-        RECYCLED_MAP.lock().insert(path.clone(), old_inode.clone());
-        map_lock.remove(&path);
-        0
+// 将 old_path 改名为 new_path，新路径已经存在时被原子地替换，两个路径所在的目录都需要写和搜索权限
+// 两个路径的最后一个分量都不会被展开，改名的是符号链接本身
+// 成功之后位于旧路径之下的已打开文件和进程当前工作目录的路径随之更新，交换时两边的路径互换
+pub fn rename(old_path: &str, new_path: &str, flags: RenameFlags, cred: &Credentials) -> isize {
+    if flags.contains(RenameFlags::NOREPLACE | RenameFlags::EXCHANGE) {
+        return -1;
+    }
+    let (old_parent, old_path, old_name) = match lookup_parent(old_path, cred) {
+        Some(parent) => parent,
+        None => return -1,
+    };
+    let (new_parent, new_path, new_name) = match lookup_parent(new_path, cred) {
+        Some(parent) => parent,
+        None => return -1,
+    };
+    let renamed = if flags.contains(RenameFlags::EXCHANGE) {
+        old_parent.exchange(old_name, &new_parent, new_name)
+    } else if flags.contains(RenameFlags::NOREPLACE) {
+        old_parent.rename_noreplace(old_name, &new_parent, new_name)
     } else {
-        -1
+        old_parent.rename(old_name, &new_parent, new_name)
+    };
+    if !renamed {
+        return -1;
+    }
+    let mut open_inodes = OPEN_INODES.lock();
+    open_inodes.retain(|os_inode| os_inode.strong_count() > 0);
+    for os_inode in open_inodes.iter().filter_map(|os_inode| os_inode.upgrade()) {
+        update_path(&mut os_inode.path.lock(), &old_path, &new_path, flags);
     }
+    drop(open_inodes);
+    for_each_cwd(|cwd| update_path(cwd, &old_path, &new_path, flags));
+    0
 }
 
-pub fn map(path_str: String, inode: Arc<OSInode>) {
-    // insert and update
-    HARD_LINK_MAP.lock().insert(path_str, inode);
+// 改名成功之后更新一个记录下来的路径：位于旧路径之下的路径移到新路径之下，交换时反过来也一样
+// 替换时位于新路径之下的路径指向的是被替换掉的文件或空目录，它已经没有路径了，记为 None
+fn update_path(path: &mut Option<String>, old_path: &str, new_path: &str, flags: RenameFlags) {
+    let current = match path.as_ref() {
+        Some(current) => current,
+        None => return,
+    };
+    if let Some(renamed_path) = replace_prefix(current, old_path, new_path) {
+        *path = Some(renamed_path);
+    } else if flags.contains(RenameFlags::EXCHANGE) {
+        if let Some(renamed_path) = replace_prefix(current, new_path, old_path) {
+            *path = Some(renamed_path);
+        }
+    } else if replace_prefix(current, new_path, "").is_some() {
+        *path = None;
+    }
 }
 
-// 只能控制进程对本次打开的文件的访问
-// 在我们简化版的文件系统中文件不进行权限设置
+// path 是 prefix 本身或者位于 prefix 之下时，将开头的 prefix 替换为 replacement
+fn replace_prefix(path: &str, prefix: &str, replacement: &str) -> Option<String> {
+    let rest = path.strip_prefix(prefix)?;
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+    let mut path = String::from(replacement);
+    path.push_str(rest);
+    Some(path)
+}
+
+// 路径是否指向一个已经存在的目录，内核内部使用，不检查权限
+pub fn is_dir(path: &str) -> bool {
+    ROOT_INODE.lookup(path).map_or(false, |inode| inode.is_dir())
+}
+
+// 查找一个有搜索权限的目录作为当前工作目录，返回展开符号链接之后的路径
+pub fn lookup_dir(path: &str, cred: &Credentials) -> Option<String> {
+    let (inode, path) = resolve(path, cred, true)?;
+    if inode.is_dir() && permitted(&inode, cred, MAY_EXEC) {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+// 只能控制进程对本次打开的文件的访问，文件本身的访问权限由 inode 中的 mode 决定
 // 将一个 u32 的 flags 包装为一个 OpenFlags 结构体更易使用，它的 bits 字段可以将自身转回 u32
 // 打开文件的标志
 bitflags! {
@@ -68,6 +303,7 @@ bitflags! {
         // 在打开文件时 CREATE 标志使得如果 filea 原本不存在，文件系统会自动创建一个同名文件，如果已经存在的话则会清空它的内容
         const CREATE = 1 << 9; // 0x200, 允许创建文件, 在找不到该文件的时候应创建文件; 如果该文件已经存在则应该将该文件的大小归零
         const TRUNC = 1 << 10; // 0x400, 在打开文件的时候应该清空文件的内容并将该文件的大小归零
+        const NOFOLLOW = 1 << 17; // 0x20000, 路径的最后一个分量是符号链接时打开失败
     }
 }
 
@@ -76,6 +312,9 @@ bitflags! {
 pub struct OSInode {
     readable: bool,
     writable: bool,
+    // 展开符号链接之后的规范化路径，作为 *at 系统调用的 dirfd 时以它为起点解析相对路径，改名时被更新
+    // 被改名替换掉之后为 None，不能再作为 dirfd
+    path: Mutex<Option<String>>,
     pub inner: Mutex<OSInodeInner>,
 }
 
@@ -89,16 +328,31 @@ impl OSInode {
         readable: bool,
         writable: bool,
         inode: Arc<Inode>,
+        path: String,
     ) -> Self {
         Self {
             readable,
             writable,
+            path: Mutex::new(Some(path)),
             inner: Mutex::new(OSInodeInner {
                 offset: 0,
                 inode,
             }),
         }
     }
+    // 创建一个被进程打开的 OSInode，并登记它以便改名时更新路径
+    pub fn open(
+        readable: bool,
+        writable: bool,
+        inode: Arc<Inode>,
+        path: String,
+    ) -> Arc<Self> {
+        let os_inode = Arc::new(Self::new(readable, writable, inode, path));
+        let mut open_inodes = OPEN_INODES.lock();
+        open_inodes.retain(|os_inode| os_inode.strong_count() > 0);
+        open_inodes.push(Arc::downgrade(&os_inode));
+        os_inode
+    }
     // 将该文件的数据全部读到一个 u8 向量 中
     pub fn read_all(&self) -> Vec<u8> {
         let mut inner = self.inner.lock();
@@ -121,21 +375,24 @@ lazy_static! {
     pub static ref ROOT_INODE: Arc<Inode> = {
         // 打开块设备BLOCK_DEVICE, 从块设备 BLOCK_DEVICE 上打开文件系统
         let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
+        // inode 的时间戳使用 RTC 给出的墙上时间
+        efs.lock().set_clock(Arc::new(RtcClock));
         // 从文件系统中获取根目录的 inode 
         Arc::new(EasyFileSystem::root_inode(&efs))
     };
+    // 所有被进程打开的 OSInode，已经关闭的在登记和改名时被清理
+    static ref OPEN_INODES: Mutex<Vec<Weak<OSInode>>> = Mutex::new(Vec::new());
 }
 
 pub fn list_apps() {
     println!("/**** APPS ****");
     for app in ROOT_INODE.ls() {
         let inode = ROOT_INODE.find(&app[..]).unwrap();
+        // 跳过 "."、".." 和子目录
+        if inode.is_dir() {
+            continue;
+        }
         println!("{}", app);
-        map(app.clone(), Arc::new(OSInode::new(
-            true,
-            false,
-            inode,
-        )));
     }
     println!("**************/")
 }
@@ -155,54 +412,72 @@ impl OpenFlags {
     }
 }
 
-// 在 内核 中根据文件名打开一个根目录下的文件
-pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+// 在内核中根据规范化的绝对路径打开一个文件或目录，目录只能以只读方式打开
+// 读写和清空文件需要相应的权限；创建文件需要所在目录的写权限，新文件属于 cred，访问权限为 mode
+// 符号链接会被展开，带有 NOFOLLOW 标志时最后一个分量是符号链接则打开失败
+pub fn open_file(path: &str, flags: OpenFlags, mode: u32, cred: &Credentials) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
-    let name_string = String::from(name);
-    let mut locked_map = HARD_LINK_MAP.lock();
-    if flags.contains(OpenFlags::CREATE) { 
-        if let Some(os_inode) = locked_map.get(&name_string) {
+    let follow = !flags.contains(OpenFlags::NOFOLLOW);
+    if let Some((inode, path)) = resolve(path, cred, follow) {
+        if inode.is_symlink() {
+            return None;
+        }
+        let truncate = flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC);
+        let mut access = 0;
+        if readable {
+            access |= MAY_READ;
+        }
+        if writable || truncate {
+            access |= MAY_WRITE;
+        }
+        if !permitted(&inode, cred, access) {
+            return None;
+        }
+        if inode.is_dir() {
+            if writable || truncate {
+                return None;
+            }
+        } else if truncate {
             // clear size
             // 如果文件已经存在则清空文件的内容
-            let inner = os_inode.inner.lock();
-            inner.inode.clear();
-            Some(Arc::new(OSInode::new(
-                readable,
-                writable,
-                Arc::clone(&inner.inode),
-            )))
-        } else {
-            // create file
-            if let Some(inode) = ROOT_INODE.create(name) {
-                let osinode = Arc::new(OSInode::new(
-                    readable,
-                    writable,
-                    inode,
-                ));
-                locked_map.insert(name_string, osinode.clone());
-                Some(osinode)
-            } else {
-                let mut recycled_map = RECYCLED_MAP.lock();
-                let inode = recycled_map.remove(&name_string).unwrap();
-                locked_map.insert(name_string, inode.clone());
-                Some(inode)
-            }
+            inode.clear();
         }
+        Some(OSInode::open(
+            readable,
+            writable,
+            inode,
+            path,
+        ))
+    } else if flags.contains(OpenFlags::CREATE) {
+        // create file
+        // 指向不存在的文件的符号链接不会创建它指向的文件，而是因为名字已经存在而失败
+        let (parent, path, name) = lookup_parent(path, cred)?;
+        let inode = parent.create_as(name, cred.owner(mode))?;
+        Some(OSInode::open(
+            readable,
+            writable,
+            inode,
+            path,
+        ))
     } else {
-        if let Some(os_inode) = locked_map.get(&name_string) {
-            let inner = os_inode.inner.lock();
-            if flags.contains(OpenFlags::TRUNC) {
-                inner.inode.clear();
-            }
-            Some(Arc::new(OSInode::new(
-                readable,
-                writable,
-                Arc::clone(&inner.inode),
-            )))
-        } else {
-            None
-        }
+        None
+    }
+}
+
+// 打开一个可执行文件并读出它的全部内容，需要执行权限，目录不能被执行
+pub fn read_exec_file(path: &str, cred: &Credentials) -> Option<Vec<u8>> {
+    let (inode, path) = resolve(path, cred, true)?;
+    if inode.is_dir() || !permitted(&inode, cred, MAY_EXEC) {
+        return None;
     }
+    Some(OSInode::new(true, false, inode, path).read_all())
+}
+
+// 只查找文件而不读写它，用于获取文件状态，只需要路径上各个目录的搜索权限
+// follow 为 false 时得到的可能是符号链接本身
+pub fn open_path(path: &str, cred: &Credentials, follow: bool) -> Option<Arc<OSInode>> {
+    let (inode, path) = resolve(path, cred, follow)?;
+    Some(OSInode::open(false, false, inode, path))
 }
 
 // 文件描述符层
@@ -211,6 +486,12 @@ impl File for OSInode {
     fn writable(&self) -> bool { self.writable }
     fn nlink(&self) -> usize { self.inner.lock().inode.get_nlink() }
     fn inode_id(&self) -> usize { self.inner.lock().inode.get_inode_id() }
+    fn path(&self) -> Option<String> { self.path.lock().clone() }
+    fn size(&self) -> usize { self.inner.lock().inode.get_size() }
+    fn blocks(&self) -> usize { self.inner.lock().inode.get_blocks() }
+    fn times(&self) -> InodeTimes { self.inner.lock().inode.get_times() }
+    fn owner(&self) -> InodeOwner { self.inner.lock().inode.get_owner() }
+    fn inode_type(&self) -> DiskInodeType { self.inner.lock().inode.get_type() }
     fn read(&self, mut buf: UserBuffer) -> usize {
         let mut inner = self.inner.lock();
         let mut total_read_size = 0usize;
@@ -230,9 +511,12 @@ impl File for OSInode {
         let mut total_write_size = 0usize;
         for slice in buf.buffers.iter() {
             let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
             inner.offset += write_size;
             total_write_size += write_size;
+            // 达到了文件大小的上限
+            if write_size < slice.len() {
+                break;
+            }
         }
         total_write_size
     }
@@ -2,8 +2,12 @@ mod pipe;
 mod stdio;
 mod mail_box;
 mod inode;
+mod path;
 
-use crate::mm::UserBuffer;
+use crate::mm::{UserBuffer, register_arc_cache, slab_arc, slab_zero_ctor};
+use easy_fs::{BlockCache, set_block_cache_allocator, DiskInodeType, InodeOwner, InodeTimes};
+use spin::Mutex;
+use alloc::string::String;
 pub trait File : Send + Sync {
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
@@ -11,10 +15,25 @@ pub trait File : Send + Sync {
     fn write(&self, buf: UserBuffer) -> usize;
     fn inode_id(&self) -> usize;
     fn nlink(&self) -> usize;
+    fn path(&self) -> Option<String>; // 文件系统中的文件返回打开它时的规范化路径
+    fn size(&self) -> usize; // 内容的字节数，以及占用的块数，不在文件系统中的文件都是 0
+    fn blocks(&self) -> usize;
+    fn times(&self) -> InodeTimes;
+    fn owner(&self) -> InodeOwner; // 管道和标准输入输出属于 root，只有所有者可以读写
+    fn inode_type(&self) -> DiskInodeType; // 管道和标准输入输出被视为普通文件
 }
 
 pub use pipe::{Pipe, make_pipe};
 pub use stdio::{Stdin, Stdout};
 pub use mail_box::MailBox;
-pub use inode::{OSInode, open_file, OpenFlags, list_apps};
-pub use inode::{link, unlink, map};
+pub use inode::{OSInode, open_file, open_path, read_exec_file, OpenFlags, list_apps};
+pub use inode::{link, unlink, mkdir, symlink, read_link, rename, RenameFlags, chmod, chown, is_dir, lookup_dir};
+pub use inode::Credentials;
+pub use path::normalize;
+
+// 块缓存的创建和回收非常频繁，为它们注册一个对象缓存，并让 easy-fs 通过它创建块缓存
+// 缓冲区在分配时被清零，不会残留之前其他块的数据
+pub fn init() {
+    register_arc_cache::<Mutex<BlockCache>>("block_cache", Some(slab_zero_ctor));
+    set_block_cache_allocator(slab_arc::<Mutex<BlockCache>>);
+}
@@ -0,0 +1,61 @@
+// 路径处理
+// 内核中的文件统一以规范化的绝对路径来标识：以 '/' 开头，不包含 "."、".." 和连续的 '/'
+// 打开的文件所记录的路径和进程的当前工作目录都以这种形式保存
+// 用户给出的路径展开为绝对路径时保留 ".."：它前面的分量可能是符号链接，只有在查找时才能知道它指向哪个目录
+use alloc::string::String;
+use alloc::vec::Vec;
+use easy_fs::NAME_LENGTH_LIMIT;
+
+// 检查单个路径分量是否是合法的文件名
+pub fn valid_name(name: &str) -> bool {
+    if name.len() == 0 || name.len() > NAME_LENGTH_LIMIT {
+        return false;
+    }
+    // not include ! " #  $ % & ' ( ) * + - , - . /
+    if (name.as_bytes()[0] >= 32 && name.as_bytes()[0] <= 47) || name.as_bytes()[0] > 122 {
+        return false;
+    }
+    for ch in name.bytes() {
+        // 不包含 '/' '\'
+        if ch < 32 || ch > 126 || ch == 47 || ch == 92 {
+            return false;
+        }
+    }
+    true
+}
+
+// 将 path 相对于规范化的绝对路径 base 展开，去掉 "." 和连续的 '/'，".." 原样保留
+// path 为空或者含有非法的路径分量时返回 None
+pub fn normalize(base: &str, path: &str) -> Option<String> {
+    if path.is_empty() {
+        return None;
+    }
+    let base = if path.starts_with('/') { "" } else { base };
+    let mut names: Vec<&str> = Vec::new();
+    for name in base.split('/').chain(path.split('/')) {
+        match name {
+            "" | "." => {}
+            name if name == ".." || valid_name(name) => names.push(name),
+            _ => return None,
+        }
+    }
+    if names.is_empty() {
+        return Some(String::from("/"));
+    }
+    let mut normalized = String::new();
+    for name in names {
+        normalized.push('/');
+        normalized.push_str(name);
+    }
+    Some(normalized)
+}
+
+// 将规范化路径拆分为父目录和最后一个路径分量，根目录没有父目录，返回 None
+pub fn split_parent(path: &str) -> Option<(&str, &str)> {
+    let pos = path.rfind('/')?;
+    if pos + 1 == path.len() {
+        return None;
+    }
+    let parent = if pos == 0 { "/" } else { &path[..pos] };
+    Some((parent, &path[pos + 1..]))
+}
@@ -1,4 +1,6 @@
 use super::File;
+use alloc::string::String;
+use easy_fs::{DiskInodeType, InodeOwner, InodeTimes};
 use alloc::sync::{Arc, Weak};
 use spin::Mutex;
 use crate::mm::{UserBuffer};
@@ -194,4 +196,10 @@ impl File for Pipe {
     fn writable(&self) -> bool { self.writable }
     fn nlink(&self) -> usize { 1 }
     fn inode_id(&self) -> usize { 0 }
+    fn path(&self) -> Option<String> { None }
+    fn size(&self) -> usize { 0 }
+    fn blocks(&self) -> usize { 0 }
+    fn times(&self) -> InodeTimes { InodeTimes::default() }
+    fn owner(&self) -> InodeOwner { InodeOwner::root(0o600) }
+    fn inode_type(&self) -> DiskInodeType { DiskInodeType::File }
 }
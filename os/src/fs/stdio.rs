@@ -1,4 +1,6 @@
 use super::File;
+use alloc::string::String;
+use easy_fs::{DiskInodeType, InodeOwner, InodeTimes};
 use crate::mm::{UserBuffer};
 use crate::sbi::console_getchar;
 use crate::task::suspend_current_and_run_next;
@@ -13,6 +15,12 @@ impl File for Stdin {
     fn writable(&self) -> bool { false }
     fn nlink(&self) -> usize { 1 }
     fn inode_id(&self) -> usize { 0 }
+    fn path(&self) -> Option<String> { None }
+    fn size(&self) -> usize { 0 }
+    fn blocks(&self) -> usize { 0 }
+    fn times(&self) -> InodeTimes { InodeTimes::default() }
+    fn owner(&self) -> InodeOwner { InodeOwner::root(0o600) }
+    fn inode_type(&self) -> DiskInodeType { DiskInodeType::File }
     fn read(&self, mut user_buf: UserBuffer) -> usize {
         // 目前每次仅支持读入一个字符
         assert_eq!(user_buf.len(), 1);
@@ -42,6 +50,12 @@ impl File for Stdout {
     fn writable(&self) -> bool { true }
     fn nlink(&self) -> usize { 1 }
     fn inode_id(&self) -> usize { 0 }
+    fn path(&self) -> Option<String> { None }
+    fn size(&self) -> usize { 0 }
+    fn blocks(&self) -> usize { 0 }
+    fn times(&self) -> InodeTimes { InodeTimes::default() }
+    fn owner(&self) -> InodeOwner { InodeOwner::root(0o600) }
+    fn inode_type(&self) -> DiskInodeType { DiskInodeType::File }
     fn read(&self, _user_buf: UserBuffer) -> usize{
         panic!("Cannot read from stdout!");
     }
@@ -15,16 +15,10 @@ pub fn init() {
     static LOGGER: SimpleLogger = SimpleLogger;
     log::set_logger(&LOGGER).unwrap();
     log::set_max_level(match option_env!("LOG") {
-        Some("ERROR") => LevelFilter::Error,
-        Some("WARN") => LevelFilter::Warn,
-        Some("INFO") => LevelFilter::Info,
-        Some("DEBUG") => LevelFilter::Debug,
-        Some("TRACE") => LevelFilter::Trace,
-        Some("OFF") => LevelFilter::Off,
-        Some(_level) => {
+        Some(level) => level_filter(level).unwrap_or_else(|| {
             // println!("\x1b[93m[LOGGER][0] logging level {:?} is not supported. use default level: `INFO`\x1b[0m", level);
             LevelFilter::Info
-        },
+        }),
         None => {
             // println!("\x1b[93m[LOGGER][0] logging level is not specified. use default level: `INFO`\x1b[0m");
             LevelFilter::Off
@@ -33,6 +27,28 @@ pub fn init() {
     // println!("\x1b[34m[INFO][0] Logging Level: {:?}\x1b[0m", log::max_level());
 }
 
+fn level_filter(level: &str) -> Option<LevelFilter> {
+    match level {
+        "ERROR" => Some(LevelFilter::Error),
+        "WARN" => Some(LevelFilter::Warn),
+        "INFO" => Some(LevelFilter::Info),
+        "DEBUG" => Some(LevelFilter::Debug),
+        "TRACE" => Some(LevelFilter::Trace),
+        "OFF" => Some(LevelFilter::Off),
+        _ => None,
+    }
+}
+
+// 启动参数 log=<LEVEL> 覆盖编译时通过环境变量 LOG 指定的日志等级
+pub fn init_from_bootargs() {
+    if let Some(level) = crate::fdt::boot_param("log") {
+        match level_filter(level) {
+            Some(filter) => log::set_max_level(filter),
+            None => warn!("[kernel] unsupported log level {:?} in bootargs", level),
+        }
+    }
+}
+
 /// Add escape sequence to print with color in Linux console
 macro_rules! with_color {
     ($args: ident, $color_code: ident) => {{
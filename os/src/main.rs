@@ -27,6 +27,8 @@ mod timer;
 mod mm;
 mod fs;
 mod drivers;
+mod fdt;
+mod random;
 
 // 将同目录下的汇编代码 entry.asm 转化为字符串并通过 global_asm! 宏嵌入到代码中
 global_asm!(include_str!("entry.asm"));
@@ -46,16 +48,25 @@ fn clear_bss() {
 // 1. 应用程序不能访问任意的地址空间
 // 2. 应用程序不能执行某些可能破会计算机系统的指令
 
+// SBI 跳转到内核时 a0 为当前硬件线程的编号，a1 为设备树的物理地址
 #[no_mangle] // 避免编译器对 rust_main 的名字进行混淆, 不然会链接失败
-pub fn rust_main() -> ! {
+pub fn rust_main(_hart_id: usize, dtb_pa: usize) -> ! {
     // 在执行环境调用 应用程序的 rust_main 主函数前，把 .bss 段的全局数据清零
     // 在程序内自己进行清零的时候，我们就不用去解析 ELF 了。而是通过链接脚本 linker.ld 中给出的全局符号 sbss 和 ebss 来确定 .bss 段的位置
     clear_bss();
     logging::init();
 
     info!("[kernel] Hello, world!");
+    // 解析设备树需要堆空间，物理页帧分配器又需要从设备树中得到物理内存的范围
+    mm::init_heap();
+    fdt::init(dtb_pa);
+    logging::init_from_bootargs();
+    random::init();
     mm::init();
     mm::remap_test();
+    // 在创建任何进程控制块和块缓存之前注册它们的对象缓存
+    task::init();
+    fs::init();
     info!("[kernel] after initproc!");
 
     trap::init();
@@ -1,4 +1,4 @@
-use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS};
+use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS, PAGING_LEVELS};
 use super::PageTableEntry;
 use core::fmt::{self, Debug, Formatter};
 
@@ -113,13 +113,13 @@ impl From<PhysPageNum> for PhysAddr {
     fn from(v: PhysPageNum) -> Self { Self(v.0 << PAGE_SIZE_BITS) }
 }
 
-// 虚拟页号的 三级页索引，并按照 从高到低 的顺序返回
+// 虚拟页号的 PAGING_LEVELS 级页索引，并按照 从高到低 的顺序返回
 impl VirtPageNum {
-    pub fn indexes(&self) -> [usize; 3] {
+    pub fn indexes(&self) -> [usize; PAGING_LEVELS] {
         let mut vpn = self.0;
-        let mut idx = [0usize; 3];
-        for i in (0..3).rev() {
-            idx[i] = vpn & 511; // 只取出低 27 位
+        let mut idx = [0usize; PAGING_LEVELS];
+        for i in (0..PAGING_LEVELS).rev() {
+            idx[i] = vpn & 511; // 只取出低 9 * PAGING_LEVELS 位
             vpn >>= 9;
         }
         idx
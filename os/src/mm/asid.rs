@@ -0,0 +1,81 @@
+use spin::Mutex;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use riscv::register::satp;
+
+// satp 中 ASID 字段的位置
+const SATP_ASID_SHIFT: usize = 44;
+const SATP_ASID_MASK: usize = 0xffff;
+
+// 地址空间标识符 (ASID)
+// 快表中的键值对都带有 ASID 标记，切换到另一个用户地址空间时不需要清空整个快表
+// ASID 0 保留给内核地址空间; 在不支持 ASID 的硬件上所有地址空间都使用 0，切换时由 trap.S 清空整个快表
+// 用户地址空间分配到的 ASID 只在分配时所处的代中有效，某一代的 ASID 用完之后进入下一代:
+// 清空整个快表，所有用户地址空间在下一次被切换到的时候重新分配 ASID
+#[derive(Copy, Clone)]
+pub struct Asid {
+    pub id: usize,
+    generation: usize,
+}
+
+// 内核地址空间的 ASID 总是有效
+const KERNEL_GENERATION: usize = usize::MAX;
+
+// 当前的代数，从 1 开始，代数为 0 的 ASID 表示还从未被分配过
+static ASID_GENERATION: AtomicUsize = AtomicUsize::new(1);
+
+impl Asid {
+    pub const fn empty() -> Self {
+        Self { id: 0, generation: 0 }
+    }
+    pub const fn kernel() -> Self {
+        Self { id: 0, generation: KERNEL_GENERATION }
+    }
+    // 只有在当前代中有效的 ASID 才可能在快表中留有键值对
+    pub fn is_live(&self) -> bool {
+        self.generation == KERNEL_GENERATION || self.generation == ASID_GENERATION.load(Ordering::Acquire)
+    }
+}
+
+struct AsidAllocator {
+    asids: usize, // 硬件支持的 ASID 个数，即 2^ASIDLEN
+    next: usize, // 当前代中下一个可以分配的 ASID
+}
+
+static ASID_ALLOCATOR: Mutex<AsidAllocator> = Mutex::new(AsidAllocator { asids: 1, next: 1 });
+
+// 需要在开启分页之后调用: 向 satp 的 ASID 字段写入全 1 后读回，得到硬件实际支持的 ASID 个数
+pub fn init_asid() {
+    let old = satp::read().bits();
+    unsafe {
+        satp::write(old | (SATP_ASID_MASK << SATP_ASID_SHIFT));
+    }
+    let asids = ((satp::read().bits() >> SATP_ASID_SHIFT) & SATP_ASID_MASK) + 1;
+    unsafe {
+        satp::write(old);
+        llvm_asm!("sfence.vma" :::: "volatile");
+    }
+    ASID_ALLOCATOR.lock().asids = asids;
+    info!("[kernel] {} ASIDs supported.", asids);
+}
+
+// 切换到一个用户地址空间之前调用，保证它的 ASID 在当前代中有效
+pub fn asid_refresh(asid: &mut Asid) {
+    if asid.is_live() {
+        return;
+    }
+    let mut allocator = ASID_ALLOCATOR.lock();
+    if allocator.asids <= 1 {
+        *asid = Asid { id: 0, generation: ASID_GENERATION.load(Ordering::Acquire) };
+        return;
+    }
+    if allocator.next == allocator.asids {
+        // 这一代的 ASID 已经用完，进入下一代并清空整个快表
+        ASID_GENERATION.fetch_add(1, Ordering::AcqRel);
+        allocator.next = 1;
+        unsafe {
+            llvm_asm!("sfence.vma" :::: "volatile");
+        }
+    }
+    *asid = Asid { id: allocator.next, generation: ASID_GENERATION.load(Ordering::Acquire) };
+    allocator.next += 1;
+}
@@ -1,7 +1,10 @@
 use super::{PhysAddr, PhysPageNum};
+use super::heap_allocator::{heap_can_alloc, reserve_heap};
 use alloc::vec::Vec;
-use spin::Mutex;
-use crate::config::MEMORY_END;
+use alloc::collections::BTreeSet;
+use core::sync::atomic::{AtomicU8, Ordering};
+use spin::{Mutex, MutexGuard, Once};
+use crate::fdt::machine_info;
 use lazy_static::*;
 use core::fmt::{self, Debug, Formatter};
 
@@ -39,18 +42,77 @@ trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
+    // 分配 pages 个物理地址连续的页帧，起始物理页号按 align 个页对齐 (align 为 2 的幂)
+    // 分配出去的页帧仍然逐个通过 dealloc 回收
+    fn alloc_contiguous(&mut self, pages: usize, align: usize) -> Option<PhysPageNum>;
+    fn usable_frames(&self) -> usize;
+    fn stats(&self) -> FrameAllocatorStats;
+    // 下一次 alloc、dealloc、按 align 对齐的 alloc_contiguous 或者连续 align 次 dealloc 中，内部数据结构最多需要从内核堆分配的字节数
+    fn heap_demand(&self, align: usize) -> usize;
+}
+
+// 物理页帧分配器的碎片统计
+pub struct FrameAllocatorStats {
+    pub free_frames: usize, // 空闲页帧总数
+    pub free_blocks: usize, // 空闲页帧组成的极大连续区间的个数
+    pub largest_free_block: usize, // 最大的连续空闲区间包含的页帧数
+}
+
+impl FrameAllocatorStats {
+    // 由若干个 (起始物理页号, 页数) 表示的空闲区间统计碎片情况，相邻的区间会被合并
+    fn from_runs(mut runs: Vec<(usize, usize)>) -> Self {
+        runs.sort_unstable();
+        let mut stats = Self { free_frames: 0, free_blocks: 0, largest_free_block: 0 };
+        let mut current: Option<(usize, usize)> = None;
+        for (start, len) in runs {
+            stats.free_frames += len;
+            current = match current {
+                Some((l, r)) if r == start => Some((l, start + len)),
+                Some((l, r)) => {
+                    stats.free_blocks += 1;
+                    stats.largest_free_block = stats.largest_free_block.max(r - l);
+                    Some((start, start + len))
+                }
+                None => Some((start, start + len)),
+            };
+        }
+        if let Some((l, r)) = current {
+            stats.free_blocks += 1;
+            stats.largest_free_block = stats.largest_free_block.max(r - l);
+        }
+        stats
+    }
+    // 外部碎片率 (百分比): 不在最大连续空闲区间中的空闲页帧所占的比例
+    pub fn fragmentation(&self) -> usize {
+        if self.free_frames == 0 {
+            0
+        } else {
+            100 - self.largest_free_block * 100 / self.free_frames
+        }
+    }
+}
+
+impl Debug for FrameAllocatorStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "FrameAllocatorStats:free={},blocks={},largest={},fragmentation={}%",
+            self.free_frames, self.free_blocks, self.largest_free_block, self.fragmentation()
+        ))
+    }
 }
 
 // 栈式物理页帧管理策略
 // 物理页号区间 [current,end) 此前均 从未 被分配出去过
 // recycled 以 后入先出 的方式保存了被回收的物理页号
+// 两种分配策略只有被 FrameAllocatorImpl 选中的一种会被使用
+#[allow(unused)]
 pub struct StackFrameAllocator {
     current: usize,
     end: usize,
     recycled: Vec<usize>,
 }
 
-
+#[allow(unused)]
 impl StackFrameAllocator {
     // 真正被使用起来之前，需要调用 init 方法将自身的 [current,end) 初始化为可用物理页号区间
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
@@ -58,8 +120,23 @@ impl StackFrameAllocator {
         self.end = r.0;
         info!("[kernel] last {} Physical Frames.", self.end - self.current);
     }
-    fn usable_frames(&self) -> usize {
-        self.end - self.current + self.recycled.len()
+    // 在 recycled 中找到 pages 个物理页号连续、起始物理页号按 align 对齐的页帧并取出，返回起始物理页号
+    // 原地排序之后查找，不需要额外的堆空间
+    fn take_recycled(&mut self, pages: usize, align: usize) -> Option<usize> {
+        if pages == 1 && align == 1 {
+            return self.recycled.pop();
+        }
+        if self.recycled.len() < pages {
+            return None;
+        }
+        self.recycled.sort_unstable();
+        let recycled = &self.recycled;
+        let pos = (0..=recycled.len() - pages).find(|&i| {
+            recycled[i] & (align - 1) == 0 && recycled[i + pages - 1] == recycled[i] + pages - 1
+        })?;
+        let start = self.recycled[pos];
+        self.recycled.drain(pos..pos + pages);
+        Some(start)
     }
 }
 
@@ -105,9 +182,167 @@ impl FrameAllocator for StackFrameAllocator {
         // recycle
         self.recycled.push(ppn);
     }
+    // 优先从 recycled 中取出连续的页帧，找不到时再从从未分配过的区间 [current,end) 中分配
+    // 后者为了对齐而跳过的页帧放入 recycled
+    fn alloc_contiguous(&mut self, pages: usize, align: usize) -> Option<PhysPageNum> {
+        if pages == 0 || !align.is_power_of_two() {
+            return None;
+        }
+        if let Some(start) = self.take_recycled(pages, align) {
+            return Some(start.into());
+        }
+        let start = (self.current + align - 1) & !(align - 1);
+        if start + pages > self.end {
+            return None;
+        }
+        self.recycled.extend(self.current..start);
+        self.current = start + pages;
+        Some(start.into())
+    }
+    fn usable_frames(&self) -> usize {
+        self.end - self.current + self.recycled.len()
+    }
+    fn stats(&self) -> FrameAllocatorStats {
+        let mut runs: Vec<(usize, usize)> = self.recycled.iter().map(|ppn| (*ppn, 1)).collect();
+        runs.push((self.current, self.end - self.current));
+        FrameAllocatorStats::from_runs(runs)
+    }
+    // dealloc 放入一个页帧，alloc_contiguous 为了对齐最多放入 align - 1 个页帧，放不下时 recycled 至少扩容为两倍
+    fn heap_demand(&self, align: usize) -> usize {
+        let len = self.recycled.len() + align;
+        let capacity = self.recycled.capacity();
+        if len <= capacity {
+            0
+        } else {
+            len.max(capacity * 2).max(4) * core::mem::size_of::<usize>()
+        }
+    }
+}
+
+// 伙伴系统物理页帧管理策略
+// 空闲页帧被组织成大小为 2^order 个页、起始物理页号按 2^order 对齐的块，free_lists[order] 保存这些块的起始物理页号
+// 分配时从能满足要求的最小的块开始不断对半拆分，回收时如果与之大小相同的伙伴块也是空闲的就合并成更大的块
+// 因此能够高效地分配物理地址连续的多个页帧
+#[allow(unused)]
+pub struct BuddyFrameAllocator {
+    start: usize,
+    end: usize,
+    free: usize,
+    free_lists: Vec<BTreeSet<usize>>,
+}
+
+// 块的最大阶数，最大的块包含 2^BUDDY_MAX_ORDER 个页帧
+#[allow(unused)]
+const BUDDY_MAX_ORDER: usize = 12;
+// 一次操作中 free_lists 的插入最多需要的堆空间，足够每一阶的 BTreeSet 都分裂出新的节点
+#[allow(unused)]
+const BUDDY_HEAP_DEMAND: usize = 0x2000;
+
+#[allow(unused)]
+impl BuddyFrameAllocator {
+    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
+        self.end = r.0;
+        self.free_range(l.0, r.0);
+        info!("[kernel] last {} Physical Frames.", self.free);
+    }
+    // 将 [start, end) 拆分成尽可能大的对齐块逐个放回空闲链表
+    fn free_range(&mut self, start: usize, end: usize) {
+        let mut ppn = start;
+        while ppn < end {
+            let mut order = (ppn.trailing_zeros() as usize).min(BUDDY_MAX_ORDER);
+            while ppn + (1 << order) > end {
+                order -= 1;
+            }
+            self.free_block(ppn, order);
+            ppn += 1 << order;
+        }
+    }
+    // 放回一个块，并尽可能与它的伙伴块合并
+    fn free_block(&mut self, mut ppn: usize, mut order: usize) {
+        self.free += 1 << order;
+        while order < BUDDY_MAX_ORDER {
+            let buddy = ppn ^ (1 << order);
+            if !self.free_lists[order].remove(&buddy) {
+                break;
+            }
+            ppn = ppn.min(buddy);
+            order += 1;
+        }
+        self.free_lists[order].insert(ppn);
+    }
+    // 取出一个大小为 2^order 的块，没有时拆分更大的块
+    fn alloc_block(&mut self, order: usize) -> Option<usize> {
+        let mut current = (order..=BUDDY_MAX_ORDER).find(|o| !self.free_lists[*o].is_empty())?;
+        // 总是选择物理页号最小的块，让空闲页帧尽量集中在高地址
+        let ppn = *self.free_lists[current].iter().next().unwrap();
+        self.free_lists[current].remove(&ppn);
+        while current > order {
+            current -= 1;
+            self.free_lists[current].insert(ppn + (1 << current));
+        }
+        self.free -= 1 << order;
+        Some(ppn)
+    }
+    // ppn 是否位于某个空闲块中
+    fn is_free(&self, ppn: usize) -> bool {
+        (0..=BUDDY_MAX_ORDER).any(|order| self.free_lists[order].contains(&(ppn & !((1 << order) - 1))))
+    }
 }
 
+impl FrameAllocator for BuddyFrameAllocator {
+    fn new() -> Self {
+        Self {
+            start: 0,
+            end: 0,
+            free: 0,
+            free_lists: (0..=BUDDY_MAX_ORDER).map(|_| BTreeSet::new()).collect(),
+        }
+    }
+    fn alloc(&mut self) -> Option<PhysPageNum> {
+        self.alloc_block(0).map(|ppn| ppn.into())
+    }
+    fn dealloc(&mut self, ppn: PhysPageNum) {
+        let ppn = ppn.0;
+        // validity check
+        if ppn < self.start || ppn >= self.end || self.is_free(ppn) {
+            panic!("Frame ppn={:#x} has not been allocated!", ppn);
+        }
+        self.free_block(ppn, 0);
+    }
+    // 分配一个能容纳 pages 个页帧且满足对齐要求的块，多余的尾部页帧立即放回
+    fn alloc_contiguous(&mut self, pages: usize, align: usize) -> Option<PhysPageNum> {
+        if pages == 0 || !align.is_power_of_two() {
+            return None;
+        }
+        let order = pages.max(align).next_power_of_two().trailing_zeros() as usize;
+        if order > BUDDY_MAX_ORDER {
+            return None;
+        }
+        let ppn = self.alloc_block(order)?;
+        self.free_range(ppn + pages, ppn + (1 << order));
+        Some(ppn.into())
+    }
+    fn usable_frames(&self) -> usize {
+        self.free
+    }
+    fn stats(&self) -> FrameAllocatorStats {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for (order, list) in self.free_lists.iter().enumerate() {
+            runs.extend(list.iter().map(|ppn| (*ppn, 1 << order)));
+        }
+        FrameAllocatorStats::from_runs(runs)
+    }
+    fn heap_demand(&self, _align: usize) -> usize {
+        BUDDY_HEAP_DEMAND
+    }
+}
+
+// 通过 buddy_frame_allocator 特性在两种分配策略之间切换
+#[cfg(not(feature = "buddy_frame_allocator"))]
 type FrameAllocatorImpl = StackFrameAllocator;
+#[cfg(feature = "buddy_frame_allocator")]
+type FrameAllocatorImpl = BuddyFrameAllocator;
 
 // StackFrameAllocator 的全局实例
 lazy_static! {
@@ -115,16 +350,50 @@ lazy_static! {
         Mutex::new(FrameAllocatorImpl::new());
 }
 
-pub fn init_frame_allocator() {
+// 调用物理地址 PhysAddr 的 floor/ceil 方法分别下/上取整获得可用的物理页号区间
+// 可用的物理内存对应的物理页号: [ekernel.ceil(), memory_end.floor())，memory_end 来自设备树
+pub fn frame_range() -> (PhysPageNum, PhysPageNum) {
     extern "C" {
         fn ekernel();
     }
-    // 调用物理地址 PhysAddr 的 floor/ceil 方法分别下/上取整获得可用的物理页号区间
-    // 可用的物理内存对应的物理页号: [ekernel.ceil(), MEMORY_END.floor())
+    let memory_end = machine_info().memory_end();
+    (PhysAddr::from(ekernel as usize).ceil(), PhysAddr::from(memory_end).floor())
+}
+
+// 每个可分配的页帧当前被全局分配器中的哪一部分占用 (内核堆扩展出来的区域或者对象缓存的 slab)，0 表示都不是
+// 由占用者自己写入，全局分配器回收内存时不需要获取任何锁就能据此判断内存来自哪里
+struct FrameOwners {
+    start_ppn: usize,
+    owners: Vec<AtomicU8>,
+}
+
+static FRAME_OWNERS: Once<FrameOwners> = Once::new();
+
+pub fn init_frame_allocator() {
+    let (start, end) = frame_range();
     FRAME_ALLOCATOR
         .lock()
-        .init(PhysAddr::from(ekernel as usize).ceil(), PhysAddr::from(MEMORY_END).floor());
-    info!("[kernel] Frame Total Size [{:#x}, {:#x})", ekernel as usize, MEMORY_END);
+        .init(start, end);
+    let owners = (start.0..end.0).map(|_| AtomicU8::new(0)).collect();
+    FRAME_OWNERS.call_once(|| FrameOwners { start_ppn: start.0, owners });
+    info!("[kernel] Frame Total Size [{:#x}, {:#x})", PhysAddr::from(start).0, machine_info().memory_end());
+}
+
+// 获取 FRAME_ALLOCATOR，并保证接下来按 align 对齐的一次操作中内部数据结构需要的堆空间已经预留好
+// 持有 FRAME_ALLOCATOR 时内核堆无法从中扩展，所以只能在获取锁之前扩展
+fn lock_frame_allocator(align: usize) -> MutexGuard<'static, FrameAllocatorImpl> {
+    loop {
+        let allocator = FRAME_ALLOCATOR.lock();
+        let demand = allocator.heap_demand(align);
+        if demand == 0 || heap_can_alloc(demand) {
+            return allocator;
+        }
+        drop(allocator);
+        if !reserve_heap(demand) {
+            // 已经没有页帧可以用来扩展内核堆，锁内的堆分配失败时由 handle_alloc_error 报告
+            return FRAME_ALLOCATOR.lock();
+        }
+    }
 }
 
 // 包装为一个 FrameTracker
@@ -132,15 +401,75 @@ pub fn init_frame_allocator() {
 // 当一个 FrameTracker 被创建的时候，我们需要从 FRAME_ALLOCATOR 中分配一个 被清零的物理页帧
 pub fn frame_alloc() -> Option<FrameTracker> {
     // println!("[kernel] alloc one frame.");
-    FRAME_ALLOCATOR
-        .lock()
+    lock_frame_allocator(1)
         .alloc()
         .map(|ppn| FrameTracker::new(ppn))
 }
 
+// 分配 pages 个物理地址连续、起始物理页号按 align 个页对齐的页帧
+// 每个页帧仍然由各自的 FrameTracker 管理生命周期
+pub fn frame_alloc_contiguous(pages: usize, align: usize) -> Option<Vec<FrameTracker>> {
+    let base = lock_frame_allocator(align)
+        .alloc_contiguous(pages, align)?;
+    Some((0..pages).map(|i| FrameTracker::new(PhysPageNum(base.0 + i))).collect())
+}
+
+// 为内核堆分配 pages 个物理地址连续、按 pages 对齐的页帧 (pages 为 2 的幂)，这些页帧此后归内核堆所有，不会被清零
+// 持有 FRAME_ALLOCATOR 时需要的堆空间已经预先留好，只有低水位触发的提前扩展可能在这时发生，直接放弃而不是死锁
+// 内核堆正在扩展，无法为这次操作提供内部数据结构需要的堆空间时同样失败
+pub fn frame_alloc_for_heap(pages: usize) -> Option<PhysPageNum> {
+    let mut allocator = FRAME_ALLOCATOR.try_lock()?;
+    let demand = allocator.heap_demand(pages);
+    if demand > 0 && !heap_can_alloc(demand) {
+        return None;
+    }
+    allocator.alloc_contiguous(pages, pages)
+}
+
+// 内核堆或者对象缓存将其中已经完全空闲的页帧 frames 归还给物理页帧分配器，同时清除它们的占用者
+// 与 frame_alloc_for_heap 一样在全局分配器内部调用，只尝试获取锁
+// 无法获取锁或者无法为内部数据结构预留堆空间时不归还任何页帧并返回 false
+pub fn frame_dealloc_for_heap(frames: impl Iterator<Item = PhysPageNum> + Clone) -> bool {
+    let mut allocator = match FRAME_ALLOCATOR.try_lock() {
+        Some(allocator) => allocator,
+        None => return false,
+    };
+    let demand = allocator.heap_demand(frames.clone().count());
+    if demand > 0 && !heap_can_alloc(demand) {
+        return false;
+    }
+    for ppn in frames {
+        set_frame_owner(ppn, 0);
+        allocator.dealloc(ppn);
+    }
+    true
+}
+
+// 物理地址 addr 所在页帧的占用者，不属于可分配的页帧时 (例如 .bss 段中的初始内核堆) 为 0
+pub fn frame_owner(addr: usize) -> u8 {
+    FRAME_OWNERS.get().map_or(0, |frames| {
+        PhysAddr::from(addr).floor().0.checked_sub(frames.start_ppn)
+            .and_then(|i| frames.owners.get(i))
+            .map_or(0, |owner| owner.load(Ordering::Relaxed))
+    })
+}
+
+pub fn set_frame_owner(ppn: PhysPageNum, owner: u8) {
+    let frames = FRAME_OWNERS.get().unwrap();
+    frames.owners[ppn.0 - frames.start_ppn].store(owner, Ordering::Relaxed);
+}
+
+// 被 owner 占用的所有页帧
+pub fn frames_owned_by(owner: u8) -> impl Iterator<Item = PhysPageNum> + Clone {
+    let frames = FRAME_OWNERS.get().unwrap();
+    frames.owners.iter()
+        .enumerate()
+        .filter(move |(_, o)| o.load(Ordering::Relaxed) == owner)
+        .map(move |(i, _)| PhysPageNum(frames.start_ppn + i))
+}
+
 pub fn frame_dealloc(ppn: PhysPageNum) {
-    FRAME_ALLOCATOR
-        .lock()
+    lock_frame_allocator(1)
         .dealloc(ppn);
 }
 
@@ -150,6 +479,12 @@ pub fn usable_frames() -> usize {
         .usable_frames()
 }
 
+pub fn frame_allocator_stats() -> FrameAllocatorStats {
+    FRAME_ALLOCATOR
+        .lock()
+        .stats()
+}
+
 #[allow(unused)]
 pub fn frame_allocator_test() {
     let mut v: Vec<FrameTracker> = Vec::new();
@@ -165,5 +500,13 @@ pub fn frame_allocator_test() {
         v.push(frame);
     }
     drop(v); // 在这里回收
+    // 连续分配的页帧物理页号连续且满足对齐要求
+    let frames = frame_alloc_contiguous(5, 4).unwrap();
+    assert_eq!(frames[0].ppn.0 % 4, 0);
+    for i in 1..frames.len() {
+        assert_eq!(frames[i].ppn.0, frames[0].ppn.0 + i);
+    }
+    drop(frames);
+    println!("{:?}", frame_allocator_stats());
     println!("frame_allocator_test passed!");
 }
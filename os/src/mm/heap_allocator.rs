@@ -1,26 +1,278 @@
-use buddy_system_allocator::LockedHeap;
-use crate::config::KERNEL_HEAP_SIZE;
+use buddy_system_allocator::Heap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+use super::{PhysAddr, PhysPageNum};
+use super::frame_allocator::{
+    frame_alloc_for_heap,
+    frame_dealloc_for_heap,
+    frame_owner,
+    frames_owned_by,
+    set_frame_owner,
+};
+use super::slab::slab_dealloc;
+use crate::config::{KERNEL_HEAP_INIT_SIZE, KERNEL_HEAP_GROW_SIZE, KERNEL_HEAP_LOW_WATERMARK, PAGE_SIZE};
+
+// 可以增长和收缩的内核堆
+// 初始时只有 .bss 段中 KERNEL_HEAP_INIT_SIZE 大小的空间，之后按需从物理页帧分配器中取出页帧组成新的区域加入堆中
+// 每个区域由各自的伙伴系统管理，区域中的内存全部被释放之后，它的页帧可以整体归还给物理页帧分配器
+// 内核地址空间恒等映射了全部可用物理内存，因此页帧的物理地址可以直接作为堆空间使用
+pub struct KernelHeap {
+    inner: Mutex<HeapInner>,
+    growable: AtomicBool, // 物理页帧分配器初始化完成之后才能扩展
+    growing: AtomicBool, // 正在扩展，避免扩展过程中的堆分配再次触发扩展
+    shrinking: AtomicBool, // 正在归还区域，避免归还过程中的堆回收再次触发归还
+    peak: AtomicUsize, // 历史最大的已分配字节数
+    failures: AtomicUsize, // 扩展之后仍然失败的分配次数
+}
+
+// 最多可以扩展出来的区域个数
+// 区域 i 的页帧在物理页帧分配器中的占用者记为 i + 1，不能与对象缓存使用的占用者重叠
+const MAX_HEAP_REGIONS: usize = 64;
+
+const NO_REGION: Option<Heap<32>> = None;
+
+struct HeapInner {
+    base: Heap<32>, // .bss 段中的初始堆
+    regions: [Option<Heap<32>>; MAX_HEAP_REGIONS], // 扩展出来的区域
+}
+
+impl HeapInner {
+    // 依次尝试初始堆和各个区域
+    fn alloc(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        if let Ok(ptr) = self.base.alloc(layout) {
+            return Some(ptr);
+        }
+        self.regions.iter_mut().flatten().find_map(|heap| heap.alloc(layout).ok())
+    }
+    // 根据 ptr 所在页帧的占用者放回初始堆或者对应的区域，返回该区域是否因此完全空闲
+    fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        match frame_owner(ptr.as_ptr() as usize) as usize {
+            0 => {
+                self.base.dealloc(ptr, layout);
+                false
+            }
+            owner => {
+                let heap = self.regions[owner - 1].as_mut().unwrap();
+                heap.dealloc(ptr, layout);
+                heap.stats_alloc_actual() == 0
+            }
+        }
+    }
+    fn total_bytes(&self) -> usize {
+        self.base.stats_total_bytes()
+            + self.regions.iter().flatten().map(|heap| heap.stats_total_bytes()).sum::<usize>()
+    }
+    fn in_use_bytes(&self) -> usize {
+        self.base.stats_alloc_actual()
+            + self.regions.iter().flatten().map(|heap| heap.stats_alloc_actual()).sum::<usize>()
+    }
+}
+
+// 内核堆的使用情况，单位均为字节
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub total: usize, // 堆的总大小
+    pub in_use: usize, // 当前已分配的大小
+    pub peak: usize, // 历史最大的已分配大小
+    pub failures: usize, // 分配失败的次数
+}
+
+impl KernelHeap {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(HeapInner {
+                base: Heap::new(),
+                regions: [NO_REGION; MAX_HEAP_REGIONS],
+            }),
+            growable: AtomicBool::new(false),
+            growing: AtomicBool::new(false),
+            shrinking: AtomicBool::new(false),
+            peak: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+        }
+    }
+    // 从物理页帧分配器中取出页帧组成一个新的区域加入堆中，保证能从中切出一整块 need 字节的空间，返回是否成功
+    fn grow(&self, need: usize) -> bool {
+        if !self.growable.load(Ordering::Acquire) || self.growing.swap(true, Ordering::Acquire) {
+            return false;
+        }
+        let succ = self.add_region(need);
+        self.growing.store(false, Ordering::Release);
+        succ
+    }
+    // 优先取出 KERNEL_HEAP_GROW_SIZE 大小的连续页帧，按页数的 2 的幂分配并对齐，伙伴系统才能从中切出整块的空间
+    // 连续的页帧不足时，超过一页的需求只取出恰好能满足它的连续页帧
+    // 不超过一页的需求则由零散的单个页帧组成区域，这样已经被回收的页帧 (例如栈式分配器的 recycled) 也能用来扩展内核堆
+    fn add_region(&self, need: usize) -> bool {
+        let idx = match self.inner.lock().regions.iter().position(|region| region.is_none()) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        let mut region = Heap::<32>::new();
+        let mut add_frames = |ppn: PhysPageNum, pages: usize| {
+            for i in 0..pages {
+                set_frame_owner(PhysPageNum(ppn.0 + i), idx as u8 + 1);
+            }
+            let start = PhysAddr::from(ppn).0;
+            unsafe {
+                region.add_to_heap(start, start + pages * PAGE_SIZE);
+            }
+        };
+        let need_pages = ((need + PAGE_SIZE - 1) / PAGE_SIZE).max(1).next_power_of_two();
+        let grow_pages = KERNEL_HEAP_GROW_SIZE / PAGE_SIZE;
+        if let Some(ppn) = frame_alloc_for_heap(need_pages.max(grow_pages)) {
+            add_frames(ppn, need_pages.max(grow_pages));
+        } else if need_pages > 1 {
+            if let Some(ppn) = frame_alloc_for_heap(need_pages) {
+                add_frames(ppn, need_pages);
+            }
+        } else {
+            for _ in 0..grow_pages {
+                match frame_alloc_for_heap(1) {
+                    Some(ppn) => add_frames(ppn, 1),
+                    None => break,
+                }
+            }
+        }
+        if region.stats_total_bytes() == 0 {
+            return false;
+        }
+        self.inner.lock().regions[idx] = Some(region);
+        true
+    }
+    // 将完全空闲的区域归还给物理页帧分配器
+    // 堆中其余部分的空闲空间不足 KERNEL_HEAP_GROW_SIZE 时保留该区域，避免反复扩展和归还
+    // 在全局分配器内部调用，物理页帧分配器暂时无法接收时保留该区域，等它下一次完全空闲时再尝试
+    fn shrink(&self) {
+        if self.shrinking.swap(true, Ordering::Acquire) {
+            return;
+        }
+        loop {
+            let mut inner = self.inner.lock();
+            let free = inner.total_bytes() - inner.in_use_bytes();
+            let idx = inner.regions.iter().position(|region| region.as_ref().map_or(false, |heap| {
+                heap.stats_alloc_actual() == 0 && free - heap.stats_total_bytes() >= KERNEL_HEAP_GROW_SIZE
+            }));
+            let idx = match idx {
+                Some(idx) => idx,
+                None => break,
+            };
+            // 先把区域从堆中取出，物理页帧分配器为自身预留的堆空间就不会落在这个区域中
+            let region = inner.regions[idx].take();
+            drop(inner);
+            if !frame_dealloc_for_heap(frames_owned_by(idx as u8 + 1)) {
+                self.inner.lock().regions[idx] = region;
+                break;
+            }
+        }
+        self.shrinking.store(false, Ordering::Release);
+    }
+    // 试分配一次 layout 之后立即释放，判断当前的堆能否满足这样的分配
+    fn can_alloc(&self, layout: Layout) -> bool {
+        let mut inner = self.inner.lock();
+        match inner.alloc(layout) {
+            Some(ptr) => {
+                inner.dealloc(ptr, layout);
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn stats(&self) -> HeapStats {
+        let inner = self.inner.lock();
+        HeapStats {
+            total: inner.total_bytes(),
+            in_use: inner.in_use_bytes(),
+            peak: self.peak.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut grown = false;
+        loop {
+            let mut inner = self.inner.lock();
+            if let Some(ptr) = inner.alloc(layout) {
+                let in_use = inner.in_use_bytes();
+                let free = inner.total_bytes() - in_use;
+                drop(inner);
+                self.peak.fetch_max(in_use, Ordering::Relaxed);
+                // 剩余空间不多时提前扩展，持有 FRAME_ALLOCATOR 时这次扩展会被放弃
+                if free < KERNEL_HEAP_LOW_WATERMARK {
+                    self.grow(0);
+                }
+                return ptr.as_ptr();
+            }
+            drop(inner);
+            if grown || !self.grow(layout.size().max(layout.align())) {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                return core::ptr::null_mut();
+            }
+            grown = true;
+        }
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // 位于对象缓存的 slab 中的对象回到所属的缓存
+        if slab_dealloc(ptr, &layout) {
+            return;
+        }
+        if self.inner.lock().dealloc(NonNull::new_unchecked(ptr), layout) {
+            self.shrink();
+        }
+    }
+}
 
-// LockedHeap 已经实现了 GlobalAlloc 要求的抽象接口了
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap<32> = LockedHeap::empty();
+static HEAP_ALLOCATOR: KernelHeap = KernelHeap::new();
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
-    panic!("Heap allocation error, layout = {:?}", layout);
+    panic!("Heap allocation error, layout = {:?}, {:?}", layout, HEAP_ALLOCATOR.stats());
 }
 
 // static mut 且被零初始化的字节数组，位于内核的 .bss 段中
-static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
+static mut HEAP_SPACE: [u8; KERNEL_HEAP_INIT_SIZE] = [0; KERNEL_HEAP_INIT_SIZE];
 
 pub fn init_heap() {
     unsafe {
         HEAP_ALLOCATOR
+            .inner
             .lock()
-            .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
+            .base
+            .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_INIT_SIZE);
     }
 }
 
+// 物理页帧分配器初始化完成之后，内核堆才可以从中取出页帧进行扩展
+pub fn enable_heap_growth() {
+    HEAP_ALLOCATOR.growable.store(true, Ordering::Release);
+}
+
+fn reserve_layout(size: usize) -> Layout {
+    Layout::from_size_align(size.max(1), core::mem::align_of::<usize>()).unwrap()
+}
+
+// 内核堆当前能否满足一次 size 字节的分配
+pub fn heap_can_alloc(size: usize) -> bool {
+    HEAP_ALLOCATOR.can_alloc(reserve_layout(size))
+}
+
+// 保证内核堆能够满足一次 size 字节的分配，不足时从物理页帧分配器扩展，返回是否成功
+// 持有 FRAME_ALLOCATOR 时内核堆无法扩展，因此物理页帧分配器在获取锁之前通过它预留锁内需要的堆空间
+pub fn reserve_heap(size: usize) -> bool {
+    heap_can_alloc(size)
+        || (HEAP_ALLOCATOR.grow(size) && heap_can_alloc(size))
+}
+
+pub fn heap_stats() -> HeapStats {
+    HEAP_ALLOCATOR.stats()
+}
+
 #[allow(unused)]
 pub fn heap_test() {
     use alloc::boxed::Box;
@@ -45,5 +297,12 @@ pub fn heap_test() {
     }
     assert!(bss_range.contains(&(v.as_ptr() as usize)));
     drop(v);
+    // 超过初始大小的分配会让堆从物理页帧分配器中扩展
+    let total = heap_stats().total;
+    let big: Vec<u8> = Vec::with_capacity(KERNEL_HEAP_INIT_SIZE);
+    assert!(heap_stats().total > total);
+    drop(big);
+    // 完全空闲的区域被归还给物理页帧分配器
+    assert_eq!(heap_stats().total, total);
     println!("heap_test passed!");
 }
@@ -1,21 +1,31 @@
-use super::{PageTable, PageTableEntry, PTEFlags};
+use super::{PageTable, PageTableEntry, PTEFlags, PageSize};
 use super::{VirtPageNum, VirtAddr, PhysPageNum, PhysAddr};
 use super::{FrameTracker, frame_alloc};
 use super::{VPNRange, StepByOne};
+use super::ShmSegment;
+use super::copy_from_user;
+use super::policy::{self, ElfError, MapError};
 use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
+use core::convert::TryInto;
 use riscv::register::satp;
 use alloc::sync::Arc;
 use lazy_static::*;
 use spin::Mutex;
 use crate::config::{
-    MEMORY_END,
     PAGE_SIZE,
     TRAMPOLINE,
     TRAP_CONTEXT,
     USER_STACK_SIZE,
-    MMIO
+    SHM_AREA_START,
+    ASLR_STACK_BASE,
+    ASLR_STACK_RANGE,
+    ASLR_MMAP_RANGE,
+    PIE_LOAD_BASE,
+    ASLR_PIE_RANGE,
 };
+use crate::fdt::machine_info;
+use crate::random::random_usize;
 use crate::task::exit_current_and_run_next;
 
 extern "C" {
@@ -61,15 +71,53 @@ pub fn kernel_token() -> usize {
 pub struct MemorySet {
     page_table: PageTable, // PageTable 下 挂着所有多级页表的节点所在的物理页帧
     areas: Vec<MapArea>, // 对应逻辑段中的数据所在的物理页帧
+    // 用户栈的保留区间 [stack_limit, stack_top)，用户栈逻辑段只映射其中靠上的一部分
+    // 访问保留区间内尚未映射的页面时触发缺页，由 grow_stack 将用户栈向下扩展
+    stack_limit: VirtPageNum,
+    stack_top: VirtPageNum,
+    // 由内核选择挂载地址时 (shmat 的 addr 为 0) 从这里开始向上寻找空闲区间
+    mmap_base: usize,
+}
+
+// 从 ELF 中解析出的、需要通过辅助向量 (auxv) 告诉应用的信息
+#[derive(Clone, Copy)]
+pub struct ElfInfo {
+    pub entry: usize, // 入口点，已经加上了 load_bias
+    pub phdr: usize, // program header 表在应用地址空间中的位置，没有被加载时为 0
+    pub phent: usize, // 每个 program header 的大小
+    pub phnum: usize, // program header 的数目
+}
+
+// program header 的大小和 RISC-V 的机器类型
+const PH_ENTRY_SIZE: usize = 56;
+const EM_RISCV: u16 = 243;
+const PT_GNU_STACK: u32 = 0x6474_e551;
+const PT_GNU_RELRO: u32 = 0x6474_e552;
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const R_RISCV_NONE: u64 = 0;
+const R_RISCV_RELATIVE: u64 = 3;
+
+// [0, range) 内按页对齐的随机偏移
+fn random_page_offset(range: usize) -> usize {
+    random_usize() % (range / PAGE_SIZE) * PAGE_SIZE
 }
 
 impl MemorySet {
-    pub fn new_bare() -> Self {
-        // 新建一个空的地址空间
-        Self {
-            page_table: PageTable::new(),
+    // 新建一个空的地址空间，物理内存不足时返回 None
+    pub fn new_bare() -> Option<Self> {
+        Some(Self {
+            page_table: PageTable::new()?,
             areas: Vec::new(),
-        }
+            stack_limit: VirtPageNum(0),
+            stack_top: VirtPageNum(0),
+            mmap_base: SHM_AREA_START,
+        })
+    }
+    pub fn mmap_base(&self) -> usize {
+        self.mmap_base
     }
     pub fn token(&self) -> usize {
         self.page_table.token()
@@ -82,23 +130,87 @@ impl MemorySet {
         }
         false
     }
+    // vpn 是否位于用户栈的保留区间内，这部分虚拟地址不能再被 mmap/shmat 使用
+    pub fn in_stack_reserve(&self, vpn: &VirtPageNum) -> bool {
+        self.stack_limit <= *vpn && *vpn < self.stack_top
+    }
+    // vpn 是否为用户栈保留区间下方的保护页面，访问它意味着用户栈溢出
+    pub fn is_stack_guard(&self, vpn: &VirtPageNum) -> bool {
+        self.stack_limit.0 > 0 && vpn.0 + 1 == self.stack_limit.0
+    }
+    // vpn 是否位于保留区间内、用户栈当前的底部之下，即可以通过扩展用户栈来映射
+    pub fn is_stack_growth(&self, vpn: &VirtPageNum) -> bool {
+        self.in_stack_reserve(vpn) && self.areas.iter()
+            .filter(|area| self.in_stack_reserve(&area.vpn_range.get_start()))
+            .all(|area| *vpn < area.vpn_range.get_start())
+    }
+    // 将用户栈向下扩展到包含 vpn 的页面
+    // vpn 不满足 is_stack_growth 或者物理内存不足时返回 false
+    pub fn grow_stack(&mut self, vpn: VirtPageNum) -> bool {
+        if !self.is_stack_growth(&vpn) {
+            return false;
+        }
+        // 保留区间内起始位置最低的逻辑段就是用户栈当前的底部 (mprotect 可能将用户栈拆成了多段)
+        let stack_top = self.stack_top;
+        let stack_limit = self.stack_limit;
+        if let Some(area) = self.areas.iter_mut()
+            .filter(|area| {
+                let start = area.vpn_range.get_start();
+                stack_limit <= start && start < stack_top
+            })
+            .min_by_key(|area| area.vpn_range.get_start().0) {
+            area.extend_down(&mut self.page_table, vpn) == 0
+        } else {
+            false
+        }
+    }
+    // vpn 是否已经被映射在某个用户态可以访问的逻辑段中
+    pub fn have_user_mapped(&self, vpn: &VirtPageNum) -> bool {
+        self.areas.iter().any(|area| area.map_perm.contains(MapPermission::U) && area.have_mapped(vpn))
+    }
+    pub fn have_shared(&self, vpn: &VirtPageNum) -> bool {
+        self.areas.iter().any(|area| area.map_type == MapType::Shared && area.have_mapped(vpn))
+    }
+    // 地址空间实际占有的物理页帧数: 页表节点和 Framed 逻辑段中已经映射的页帧
+    // 共享内存段的页帧属于共享内存段本身，不计入其中
     pub fn frames_used(&self) -> usize {
-        let mut frames: usize = 0;
+        let mut frames: usize = self.page_table.frames_used();
         for area in self.areas.iter() {
-            frames += area.vpn_range.get_end().0 - area.vpn_range.get_start().0;
+            if area.map_type != MapType::Shared {
+                frames += area.data_frames.len();
+            }
         }
         frames
     }
     /// Assume that no conflicts.
     /// 在当前地址空间插入一个 Framed 方式映射到 物理内存的逻辑段
     /// 该方法的调用者要保证同一地址空间内的任意两个逻辑段不能存在交集
-    pub fn insert_framed_area(&mut self, start_va: VirtAddr, end_va: VirtAddr, permission: MapPermission) {
+    pub fn insert_framed_area(&mut self, start_va: VirtAddr, end_va: VirtAddr, permission: MapPermission) -> isize {
         self.push(MapArea::new(
             start_va,
             end_va,
             MapType::Framed,
             permission,
-        ), None);
+        ), None)
+    }
+    // 将共享内存段 segment 挂载到从 start_va 开始的虚拟地址区间上
+    pub fn insert_shared_area(&mut self, start_va: VirtAddr, segment: Arc<ShmSegment>, permission: MapPermission) -> isize {
+        self.push(MapArea::new_shared(
+            start_va,
+            segment,
+            permission,
+        ), None)
+    }
+    // 找到以 start_vpn 开始的共享内存逻辑段并解除挂载，返回是否找到
+    pub fn remove_shared_area(&mut self, start_vpn: VirtPageNum) -> bool {
+        if let Some(idx) = self.areas.iter()
+            .position(|area| area.map_type == MapType::Shared && area.vpn_range.get_start() == start_vpn) {
+            self.areas[idx].unmap(&mut self.page_table);
+            self.areas.remove(idx);
+            true
+        } else {
+            false
+        }
     }
     // 只是将地址空间中的逻辑段列表 areas 清空，这将导致应用地址空间的所有数据被存放在的物理页帧被回收，而用来存放页表的那些物理页帧此时则不会被回收
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
@@ -110,17 +222,16 @@ impl MemorySet {
     }
     // 在当前地址空间插入一个新的逻辑段 map_area
     // 如果它是以 Framed 方式映射到 物理内存，还可以可选地在那些被映射到的物理页帧上写入一些初始化数据 data
+    // 物理内存不足时 map_area 不会被插入，已经映射的部分也会被撤销，返回 -1
     pub fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) -> isize {
-        let succ = map_area.map(&mut self.page_table);
+        if map_area.map(&mut self.page_table) < 0 { // 内存分配失败
+            return -1;
+        }
         if let Some(data) = data {
             map_area.copy_data(&mut self.page_table, data);
         }
         self.areas.push(map_area);
-        if succ < 0 { // 内存分配失败
-            -1
-        } else {
-            0
-        }
+        0
     }
     pub fn unmap(&mut self, vpn_range: VPNRange) {
         for vpn in vpn_range {
@@ -130,21 +241,68 @@ impl MemorySet {
                 }
             }
         }
+        // 已经被完全解除映射的逻辑段直接删除，否则 fork 时会为它们重新分配物理页帧
+        self.areas.retain(|area| area.map_type != MapType::Framed || !area.data_frames.is_empty());
+    }
+    // 将虚拟页号区间 vpn_range 内所有页面的访问方式修改为 permission
+    // 与区间部分重叠的逻辑段会先在区间边界处被拆分，保证同一个逻辑段内的访问方式仍然是统一的
+    // 调用者要保证区间内的页面都已经被映射
+    pub fn protect(&mut self, vpn_range: VPNRange, permission: MapPermission) {
+        let start = vpn_range.get_start();
+        let end = vpn_range.get_end();
+        let pte_flags = PTEFlags::from_bits(permission.bits).unwrap();
+        let mut idx = 0;
+        while idx < self.areas.len() {
+            let area_start = self.areas[idx].vpn_range.get_start();
+            let area_end = self.areas[idx].vpn_range.get_end();
+            if area_end <= start || end <= area_start {
+                idx += 1;
+                continue;
+            }
+            // 逻辑段从区间左侧开始: 拆出 [start, area_end) 放到后面，下一轮再处理
+            if area_start < start {
+                let tail = self.areas[idx].split_off(start);
+                self.areas.insert(idx + 1, tail);
+                idx += 1;
+                continue;
+            }
+            // 逻辑段越过区间右侧: 拆出 [end, area_end)，保持原有的访问方式
+            if end < area_end {
+                let tail = self.areas[idx].split_off(end);
+                self.areas.insert(idx + 1, tail);
+            }
+            let area = &mut self.areas[idx];
+            area.map_perm = permission;
+            for vpn in area.vpn_range {
+                if area.have_mapped(&vpn) {
+                    self.page_table.set_flags(vpn, pte_flags);
+                }
+            }
+            idx += 1;
+        }
+        // 快表中过时的键值对已经在 set_flags 中被逐页清空
+        // 页面变为可执行时，还需要保证之前写入的指令对取指可见
+        if permission.contains(MapPermission::X) {
+            unsafe {
+                llvm_asm!("fence.i" :::: "volatile");
+            }
+        }
     }
     /// Mention that trampoline is not collected by areas.
     /// 注意无论是内核还是应用的地址空间，跳板页面均位于同样位置，且它们也将会映射到同一个实际存放这段 汇编代码的物理页帧。
-    fn map_trampoline(&mut self) {
+    fn map_trampoline(&mut self) -> isize {
         // 并没有新增逻辑段 MemoryArea 而是直接在多级页表中插入一个从地址空间的最高虚拟页面映射到 跳板汇编代码所在的物理页帧的键值对，访问方式限制与代码段相同，即 RX 
         self.page_table.map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
             PTEFlags::R | PTEFlags::X,
-        );
+        )
     }
     /// Without kernel stacks.
     /// 创建内核地址空间
     pub fn new_kernel() -> Self {
-        let mut memory_set = Self::new_bare();
+        let mut memory_set = Self::new_bare().unwrap();
+        memory_set.page_table.set_kernel_asid();
         // map trampoline
         // 映射调班
         memory_set.map_trampoline();
@@ -186,14 +344,15 @@ impl MemorySet {
         info!("[kernel] mapping physical memory");
         memory_set.push(MapArea::new(
             (ekernel as usize).into(),
-            MEMORY_END.into(),
+            machine_info().memory_end().into(),
             MapType::Identical,
             MapPermission::R | MapPermission::W,
         ), None);
         // 为了能够在内核中访问 VirtIO 总线，我们就必须在内核地址空间中提前进行映射
         // 进行的是透明的恒等映射从而让内核可以兼容于直接访问物理地址的设备驱动库
+        // 需要映射的设备寄存器区间来自设备树
         info!("[kernel] mapping memory-mapped registers");
-        for pair in MMIO {
+        for pair in machine_info().mmio.iter() {
             memory_set.push(MapArea::new(
                 (*pair).0.into(),
                 ((*pair).0 + (*pair).1).into(),
@@ -207,88 +366,280 @@ impl MemorySet {
     /// also returns user_sp and entry point.
     /// 从应用的 ELF 格式可执行文件 解析出各数据段并对应生成应用的地址空间
     /// 对 get_app_data 得到的 ELF 格式数据进行解析
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
-        let mut memory_set = Self::new_bare();
-        // map trampoline
-        // 将跳板插入到应用地址空间
-        memory_set.map_trampoline();
-        // map program headers of elf, with U flag
+    /// stack_limit 为用户栈最多可以增长到的字节数
+    /// randomize 为真时随机选择用户栈、mmap 基址以及位置无关可执行文件的加载地址
+    /// ELF 损坏、违反映射策略或者物理内存不足时返回错误，已经分配的物理页帧随 memory_set 一起被回收
+    pub fn from_elf(elf_data: &[u8], stack_limit: usize, randomize: bool) -> Result<(Self, usize, ElfInfo), ElfError> {
         // 解析传入的应用 ELF 数据并可以轻松取出各个部分
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+        let elf = xmas_elf::ElfFile::new(elf_data).map_err(ElfError::Malformed)?;
         let elf_header = elf.header;
-        let magic = elf_header.pt1.magic;
         // 取出 ELF 的魔数来判断 它是不是一个合法的 ELF
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
+        if elf_header.pt1.magic != [0x7f, 0x45, 0x4c, 0x46] {
+            return Err(ElfError::Malformed("invalid magic"));
+        }
+        if elf_header.pt1.class() != xmas_elf::header::Class::SixtyFour
+            || elf_data.get(18..20) != Some(&EM_RISCV.to_le_bytes()[..]) {
+            return Err(ElfError::Unsupported("not a RISC-V 64 ELF"));
+        }
+        // 位置无关可执行文件 (ET_DYN) 的链接地址从 0 开始，整体平移 load_bias 之后加载
+        let is_pie = match elf_header.pt2.type_().as_type() {
+            xmas_elf::header::Type::Executable => false,
+            xmas_elf::header::Type::SharedObject => true,
+            _ => return Err(ElfError::Unsupported("not an executable")),
+        };
+        let load_bias = if is_pie {
+            PIE_LOAD_BASE + if randomize { random_page_offset(ASLR_PIE_RANGE) } else { 0 }
+        } else {
+            0
+        };
         let ph_count = elf_header.pt2.ph_count();
-        let mut max_end_vpn = VirtPageNum(0); // 记录目前涉及到的最大的虚拟页号
+        let ph_offset = elf_header.pt2.ph_offset() as usize;
+        let ph_entry_size = elf_header.pt2.ph_entry_size() as usize;
+        if ph_entry_size != PH_ENTRY_SIZE {
+            return Err(ElfError::Malformed("invalid program header size"));
+        }
+        let mut phdr = 0;
+        let mut dynamic = None;
+        let mut relro = None;
+        let mut stack_perm = MapPermission::R | MapPermission::W | MapPermission::U;
+        // 先检查所有的 Load 段，全部合法之后才开始分配物理页帧
+        // (起始虚拟地址, 终止虚拟地址, 访问方式, 从起始虚拟地址所在页面开头处开始的文件数据)
+        let mut segments: Vec<(VirtAddr, VirtAddr, MapPermission, &[u8])> = Vec::new();
         // 我们可以直接得到 program header 的数目，然后遍历所有的 program header 并将合适的区域加入 到应用地址空间中
         for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
-            // Load: 它有被内核加载的必要，此时不必理会其他类型的 program header
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
+            let ph = elf.program_header(i).map_err(ElfError::Malformed)?;
+            let ph_type = ph.get_type().map_err(ElfError::Malformed)?;
+            let (offset, file_size) = (ph.offset() as usize, ph.file_size() as usize);
+            let (vaddr, mem_size) = (ph.virtual_addr() as usize, ph.mem_size() as usize);
+            // Load: 它有被内核加载的必要
+            if ph_type == xmas_elf::program::Type::Load {
+                if mem_size == 0 {
+                    continue;
+                }
+                // 段在文件中的偏移和虚拟地址在页内的偏移必须相同，这样才能整页地拷贝
+                let page_offset = vaddr % PAGE_SIZE;
+                if file_size > mem_size || offset % PAGE_SIZE != page_offset {
+                    return Err(ElfError::Malformed("invalid segment size or alignment"));
+                }
+                let data = offset.checked_add(file_size)
+                    .and_then(|file_end| elf_data.get(offset - page_offset..file_end))
+                    .ok_or(ElfError::Malformed("segment exceeds file"))?;
                 // 计算这一区域在应用地址空间中的位置
-                let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
-                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
+                let start = load_bias.checked_add(vaddr);
+                let end = start.and_then(|start| start.checked_add(mem_size));
+                let (start, end) = match (start, end) {
+                    (Some(start), Some(end)) => (start, end),
+                    _ => return Err(ElfError::Segment(i as usize, MapError::OutOfRange)),
+                };
+                policy::check_user_range(start, end).map_err(|e| ElfError::Segment(i as usize, e))?;
                 let mut map_perm = MapPermission::U; // 默认包含 U 标志
                 // 确认这一区域访问方式的 限制并将其转换为 MapPermission 类型
                 let ph_flags = ph.flags();
                 if ph_flags.is_read() { map_perm |= MapPermission::R; }
-                if ph_flags.is_execute() { map_perm |= MapPermission::X; } 
-                else if ph_flags.is_write() {
-                    map_perm |= MapPermission::W;
+                if ph_flags.is_write() { map_perm |= MapPermission::W; }
+                if ph_flags.is_execute() { map_perm |= MapPermission::X; }
+                policy::check_permission(map_perm).map_err(|e| ElfError::Segment(i as usize, e))?;
+                let (start_va, end_va) = (VirtAddr::from(start), VirtAddr::from(end));
+                // 不同的逻辑段不能包含同一个页面
+                if segments.iter().any(|(s, e, _, _)| start_va.floor() < e.ceil() && s.floor() < end_va.ceil()) {
+                    return Err(ElfError::Segment(i as usize, MapError::Overlap));
+                }
+                segments.push((start_va, end_va, map_perm, data));
+                // 没有 PT_PHDR 时，program header 表位于包含它的文件偏移的那个 Load 段中
+                if phdr == 0 && offset <= ph_offset && ph_offset < offset + file_size {
+                    phdr = start + (ph_offset - offset);
                 }
-                // 创建逻辑段 map_area
-                let map_area = MapArea::new(
-                    start_va,
-                    end_va,
-                    MapType::Framed,
-                    map_perm,
-                );
-                max_end_vpn = map_area.vpn_range.get_end();
-                // push 到应用地址空间
-                // 当前 program header 数据被存放的位置可以通过 ph.offset() 和 ph.file_size() 来找到
-                // 注意: 当 存在一部分零初始化的时候， ph.file_size() 将会小于 ph.mem_size() ，
-                // 因为这些零出于缩减可执行 文件大小的原因不应该实际出现在 ELF 数据中。
-                memory_set.push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize])
-                );
+                continue;
+            }
+            match ph_type {
+                xmas_elf::program::Type::Phdr => phdr = load_bias.wrapping_add(vaddr),
+                xmas_elf::program::Type::Dynamic => dynamic = Some(ph),
+                // 需要动态链接器的程序不受支持，只能加载静态链接的程序
+                xmas_elf::program::Type::Interp => return Err(ElfError::Unsupported("dynamically linked")),
+                _ => {}
+            }
+            // GNU 扩展的段类型直接按照 p_type 的原始值判断
+            let raw_offset = ph_offset + i as usize * ph_entry_size;
+            let raw_type = u32::from_le_bytes(elf_data[raw_offset..raw_offset + 4].try_into().unwrap());
+            if raw_type == PT_GNU_STACK && ph.flags().is_execute() {
+                stack_perm |= MapPermission::X;
+            } else if raw_type == PT_GNU_RELRO {
+                relro = Some(ph);
             }
         }
+        // 入口点必须位于可执行的段中
+        let entry = load_bias.wrapping_add(elf_header.pt2.entry_point() as usize);
+        if !segments.iter().any(|(s, e, perm, _)| perm.contains(MapPermission::X) && s.0 <= entry && entry < e.0) {
+            return Err(ElfError::EntryNotExecutable);
+        }
         // 开始处理用户栈
+        // 为用户栈保留 stack_limit 字节的虚拟地址区间，但一开始只映射最上面的 USER_STACK_SIZE 字节
+        policy::check_permission(stack_perm).map_err(ElfError::Stack)?;
+        let stack_limit = (stack_limit.max(USER_STACK_SIZE) + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        let user_stack_top = if randomize {
+            // 栈顶位于高处的一个随机位置，保留区间下方的页面同样作为保护页面不会被映射
+            ASLR_STACK_BASE + random_page_offset(ASLR_STACK_RANGE)
+        } else {
+            // 紧接着在 ELF 最后一个段上面再放置一个保护页面和用户栈即可
+            // guard page
+            let max_end_va: VirtAddr = segments.iter()
+                .map(|(_, end, _, _)| VirtAddr::from(end.ceil()))
+                .max_by_key(|va| va.0)
+                .unwrap_or_else(|| VirtAddr::from(0));
+            (max_end_va.0 + PAGE_SIZE).saturating_add(stack_limit)
+        };
+        // 保留区间和它下方的保护页面都不能与 ELF 的段重叠
+        let user_stack_bottom = user_stack_top.checked_sub(stack_limit + PAGE_SIZE)
+            .ok_or(ElfError::Stack(MapError::OutOfRange))? + PAGE_SIZE;
+        policy::check_user_range(user_stack_bottom - PAGE_SIZE, user_stack_top).map_err(ElfError::Stack)?;
+        if segments.iter().any(|(s, e, _, _)| s.0 < user_stack_top && user_stack_bottom - PAGE_SIZE < e.0) {
+            return Err(ElfError::Stack(MapError::Overlap));
+        }
+
+        let mut memory_set = Self::new_bare().ok_or(ElfError::OutOfMemory)?;
+        // map trampoline
+        // 将跳板插入到应用地址空间
+        if memory_set.map_trampoline() < 0 {
+            return Err(ElfError::OutOfMemory);
+        }
+        // map program headers of elf, with U flag
+        for (start_va, end_va, map_perm, data) in segments {
+            // 创建逻辑段 map_area
+            let map_area = MapArea::new(
+                start_va,
+                end_va,
+                MapType::Framed,
+                map_perm,
+            );
+            // push 到应用地址空间
+            // 当前 program header 数据被存放的位置可以通过 ph.offset() 和 ph.file_size() 来找到
+            // 注意: 当 存在一部分零初始化的时候， ph.file_size() 将会小于 ph.mem_size() ，
+            // 因为这些零出于缩减可执行 文件大小的原因不应该实际出现在 ELF 数据中。
+            if memory_set.push(map_area, Some(data)) < 0 {
+                return Err(ElfError::OutOfMemory);
+            }
+        }
+        // 静态链接的位置无关可执行文件需要由加载者处理 .rela.dyn 中的重定位
+        if is_pie {
+            if let Some(ph) = dynamic {
+                let data = (ph.offset() as usize).checked_add(ph.file_size() as usize)
+                    .and_then(|end| elf_data.get(ph.offset() as usize..end))
+                    .ok_or(ElfError::Malformed("dynamic segment exceeds file"))?;
+                memory_set.relocate(data, load_bias).ok_or(ElfError::BadRelocation)?;
+            }
+        }
+        // 重定位完成后 PT_GNU_RELRO 描述的区间 (如 .got 和 .data.rel.ro) 变为只读
+        // 区间末尾所在的页面可能还有可写的数据，因此只保护完全位于区间内的页面
+        if let Some(ph) = relro {
+            let start = load_bias.wrapping_add(ph.virtual_addr() as usize);
+            let start_vpn = VirtAddr::from(start).ceil();
+            let end_vpn = VirtAddr::from(start.wrapping_add(ph.mem_size() as usize)).floor();
+            if start_vpn < end_vpn {
+                if (start_vpn.0..end_vpn.0).any(|vpn| !memory_set.have_user_mapped(&VirtPageNum(vpn))) {
+                    return Err(ElfError::Malformed("RELRO outside of loaded segments"));
+                }
+                memory_set.protect(VPNRange::new(start_vpn, end_vpn), MapPermission::R | MapPermission::U);
+            }
+        }
         // map user stack with U flags
-        let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_bottom: usize = max_end_va.into();
-        // 紧接着在它上面再放置一个保护页面和用户栈即可
-        // guard page
-        user_stack_bottom += PAGE_SIZE;
-        let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
-        memory_set.push(MapArea::new(
-            user_stack_bottom.into(),
+        memory_set.stack_limit = VirtAddr::from(user_stack_bottom).floor();
+        memory_set.stack_top = VirtAddr::from(user_stack_top).floor();
+        if memory_set.push(MapArea::new(
+            (user_stack_top - USER_STACK_SIZE).into(),
             user_stack_top.into(),
             MapType::Framed,
-            MapPermission::R | MapPermission::W | MapPermission::U,
-        ), None);
+            stack_perm,
+        ), None) < 0 {
+            return Err(ElfError::OutOfMemory);
+        }
         // map TrapContext, 映射次高页面来存放 Trap 上下文
-        memory_set.push(MapArea::new(
+        if memory_set.push(MapArea::new(
             TRAP_CONTEXT.into(),
             TRAMPOLINE.into(),
             MapType::Framed,
             MapPermission::R | MapPermission::W,
-        ), None);
-        (
+        ), None) < 0 {
+            return Err(ElfError::OutOfMemory);
+        }
+        if randomize {
+            memory_set.mmap_base = SHM_AREA_START + random_page_offset(ASLR_MMAP_RANGE);
+        }
+        Ok((
             memory_set, // 应用地址空间
             user_stack_top, // 用户栈虚拟地址 user_stack_top
-            elf.header.pt2.entry_point() as usize // 从解析 ELF 得到的该应用入口点地址
-        )
+            ElfInfo {
+                entry, // 从解析 ELF 得到的该应用入口点地址
+                phdr,
+                phent: ph_entry_size,
+                phnum: ph_count as usize,
+            },
+        ))
+    }
+    // 按照 dynamic 段 (若干个 (d_tag, d_val) 对) 找到 .rela.dyn，应用其中的 R_RISCV_RELATIVE 重定位
+    // 重定位项从已经加载的段中读出，目标地址不能位于可执行的段中，遇到其他类型的重定位时返回 None
+    fn relocate(&self, dynamic: &[u8], load_bias: usize) -> Option<()> {
+        let (mut rela, mut rela_size, mut rela_ent) = (0, 0, 24);
+        for entry in dynamic.chunks_exact(16) {
+            let tag = u64::from_le_bytes(entry[..8].try_into().unwrap());
+            let value = u64::from_le_bytes(entry[8..].try_into().unwrap()) as usize;
+            match tag {
+                DT_NULL => break,
+                DT_RELA => rela = value,
+                DT_RELASZ => rela_size = value,
+                DT_RELAENT => rela_ent = value,
+                _ => {}
+            }
+        }
+        if rela_size == 0 {
+            return Some(());
+        }
+        if rela == 0 || rela_ent != 24 || rela_size % rela_ent != 0 {
+            return None;
+        }
+        let token = self.token();
+        for i in 0..rela_size / rela_ent {
+            // Elf64_Rela: r_offset, r_info, r_addend
+            let addr = load_bias.checked_add(rela)?.checked_add(i * rela_ent)?;
+            let [offset, info, addend] = copy_from_user(token, addr as *const [u64; 3])?;
+            match info & 0xffff_ffff {
+                R_RISCV_NONE => {}
+                R_RISCV_RELATIVE => {
+                    let target = load_bias.checked_add(offset as usize)?;
+                    let value = load_bias.wrapping_add(addend as usize);
+                    self.write_relocation(target, value)?;
+                }
+                _ => return None,
+            }
+        }
+        Some(())
+    }
+    // 写入一个重定位的结果
+    // 预编译的 core 不是位置无关的，它的虚函数表位于 .rodata 中，链接时使用 -z notext 把对它们的重定位留给加载器
+    // 因此目标可以位于只读的段中，直接写入对应的物理页帧，但不能位于可执行的段中，否则加载之后代码会被修改
+    fn write_relocation(&self, target: usize, value: usize) -> Option<()> {
+        if target % core::mem::size_of::<usize>() != 0 {
+            return None;
+        }
+        let va = VirtAddr::from(target);
+        let pte = self.page_table.translate(va.floor())?;
+        if !pte.is_valid() || !pte.is_user() || pte.executable() {
+            return None;
+        }
+        *PhysAddr::from(PhysAddr::from(pte.ppn()).0 + va.page_offset()).get_mut::<usize>() = value;
+        Some(())
     }
     // 复制一个完全相同的地址空间
     pub fn from_existed_user(user_space: &MemorySet) -> Option<MemorySet> {
         // 新创建一个空的地址空间
-        let mut memory_set = Self::new_bare();
+        let mut memory_set = Self::new_bare()?;
+        memory_set.stack_limit = user_space.stack_limit;
+        memory_set.stack_top = user_space.stack_top;
+        memory_set.mmap_base = user_space.mmap_base;
         // map trampoline
         // 为这个地址空间映射上跳板页面
-        memory_set.map_trampoline();
+        if memory_set.map_trampoline() < 0 {
+            return None;
+        }
         // println!("map tranpolin in exist user");
         // 剩下的逻辑段都包含在 areas 中
         // copy data sections/trap_context/user_stack
@@ -299,7 +650,7 @@ impl MemorySet {
             if memory_set.push(new_area, None) < 0 {
                 succ = false;
                 break;
-            } else {
+            } else if area.map_type != MapType::Shared { // 共享内存段直接映射到同一组物理页帧，不需要复制数据
                 // copy data from another space
                 // 遍历逻辑段中的每个虚拟页面，对应完成数据复制
                 for vpn in area.vpn_range {
@@ -318,8 +669,8 @@ impl MemorySet {
         }        
     }
     pub fn activate(&self) {
-        // 按照 satp CSR 格式要求 构造一个无符号 64 位无符号整数，使得其 分页模式为 SV39, 且将当前多级页表的根节点所在的物理页号填充进去
-        // 从这一刻开始 SV39 分页模式就被启用了
+        // 按照 satp CSR 格式要求 构造一个无符号 64 位无符号整数，使得其 分页模式为 SV39 (或 SV48), 且将当前多级页表的根节点所在的物理页号填充进去
+        // 从这一刻开始分页模式就被启用了
         // 而且 MMU 会使用内核地址空间的多级页表进行地址转换
         let satp = self.page_table.token();
         // 一旦 我们修改了 satp 切换了地址空间，快表中的键值对就会失效，因为它还表示着上个地址空间的映射关系
@@ -328,6 +679,10 @@ impl MemorySet {
             llvm_asm!("sfence.vma" :::: "volatile");
         }
     }
+    // 切换到这个用户地址空间之前调用，保证它的 token 中的 ASID 在当前代中有效
+    pub fn refresh_asid(&mut self) {
+        self.page_table.refresh_asid();
+    }
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
@@ -347,6 +702,7 @@ pub struct MapArea {
     map_type: MapType, // 该逻辑段内的所有虚拟页面映射到物理页帧的同一种方式
     // 仅保留 U/R/W/X 四个标志位
     map_perm: MapPermission, // 控制该逻辑段的访问方式，它是页表项标志位 PTEFlags 的一个子集
+    shm: Option<Arc<ShmSegment>>, // 以 Shared 方式映射时挂载的共享内存段，持有它的一个强引用
 }
 
 impl MapArea {
@@ -366,6 +722,23 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            shm: None,
+        }
+    }
+    // 新建一个挂载共享内存段的逻辑段，长度为该段的页数
+    pub fn new_shared(
+        start_va: VirtAddr,
+        segment: Arc<ShmSegment>,
+        map_perm: MapPermission
+    ) -> Self {
+        let start_vpn: VirtPageNum = start_va.floor();
+        let end_vpn = VirtPageNum(start_vpn.0 + segment.page_count());
+        Self {
+            vpn_range: VPNRange::new(start_vpn, end_vpn),
+            data_frames: BTreeMap::new(),
+            map_type: MapType::Shared,
+            map_perm,
+            shm: Some(segment),
         }
     }
     // 从一个逻辑段 复制得到一个 虚拟地址区间、映射方式和权限控制均相同 的逻辑段
@@ -376,8 +749,37 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            shm: another.shm.clone(), // fork 时子进程挂载同一个共享内存段
+        }
+    }
+    // 在虚拟页号 at 处将逻辑段一分为二，自身保留 [start, at)，返回 [at, end) 的部分
+    // 已经映射的页面连同它们的物理页帧一起转移到新的逻辑段中，页表不需要改动
+    pub fn split_off(&mut self, at: VirtPageNum) -> MapArea {
+        assert_ne!(self.map_type, MapType::Shared, "shared areas can not be split");
+        let end = self.vpn_range.get_end();
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), at);
+        Self {
+            vpn_range: VPNRange::new(at, end),
+            data_frames: self.data_frames.split_off(&at),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            shm: None,
         }
     }
+    // 将逻辑段的起始位置向下扩展到 new_start，并逐页完成映射
+    // 只有映射成功的页面会被计入 vpn_range，物理内存不足时返回 -1
+    pub fn extend_down(&mut self, page_table: &mut PageTable, new_start: VirtPageNum) -> isize {
+        let end = self.vpn_range.get_end();
+        let mut vpn = self.vpn_range.get_start();
+        while vpn > new_start {
+            vpn = VirtPageNum(vpn.0 - 1);
+            if self.map_one(page_table, vpn) < 0 {
+                return -1;
+            }
+            self.vpn_range = VPNRange::new(vpn, end);
+        }
+        0
+    }
     // 单个虚拟页面进行映射/解映射
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> isize {
         // 虚拟页号 vpn 已经确定
@@ -399,10 +801,19 @@ impl MapArea {
                     return -1;
                 }
             }
+            // Shared 方式映射的时候，物理页号来自共享内存段中对应位置的页帧
+            MapType::Shared => {
+                let idx = vpn.0 - self.vpn_range.get_start().0;
+                ppn = self.shm.as_ref().unwrap().frames[idx].ppn;
+            }
         }
         // 页表项的标志位来源于当前逻辑段的类型为 MapPermission 的统一配置
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        // 页表节点分配失败时，刚刚分配的物理页帧也要一并回收
+        if page_table.map(vpn, ppn, pte_flags) < 0 {
+            self.data_frames.remove(&vpn);
+            return -1;
+        }
         return 0;
     }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
@@ -417,21 +828,70 @@ impl MapArea {
         page_table.unmap(vpn); // 删除以传入的虚拟页号为键的 键值对即可
     }
     // 将 当前逻辑段到物理内存的映射 从传入的该逻辑段所属的地址空间的多级页表page_table中 加入或删除
+    // 中途失败时撤销已经完成的映射，保证逻辑段要么整体被映射，要么完全没有被映射
     pub fn map(&mut self, page_table: &mut PageTable) -> isize {
+        if self.map_type == MapType::Identical {
+            return self.map_identical(page_table);
+        }
         for vpn in self.vpn_range {
             if self.map_one(page_table, vpn) < 0 {
+                for mapped in VPNRange::new(self.vpn_range.get_start(), vpn) {
+                    self.unmap_one(page_table, mapped);
+                }
                 return -1;
             }
         }
         return 0;
     }
     pub fn unmap(&mut self, page_table: &mut PageTable) {
+        if self.map_type == MapType::Identical {
+            Self::unmap_identical(page_table, self.vpn_range);
+            return;
+        }
         for vpn in self.vpn_range {
             self.unmap_one(page_table, vpn);
         }
     }
+    // 恒等映射的逻辑段在对齐的位置尽量使用 1GiB/2MiB 的大页，减少页表节点和快表项的数量
+    // 大页只会完全落在逻辑段内部，因此不同逻辑段的访问方式互不影响
+    fn map_identical(&mut self, page_table: &mut PageTable) -> isize {
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        let start = self.vpn_range.get_start();
+        let end = self.vpn_range.get_end();
+        let mut vpn = start;
+        while vpn < end {
+            let size = PageSize::largest_fitting(vpn, end);
+            if page_table.map_huge(vpn, PhysPageNum(vpn.0), size, pte_flags) < 0 {
+                Self::unmap_identical(page_table, VPNRange::new(start, vpn));
+                return -1;
+            }
+            vpn = VirtPageNum(vpn.0 + size.pages());
+        }
+        0
+    }
+    // 完全位于区间内的大页整个删除，只有一部分位于区间内的大页会被拆分
+    fn unmap_identical(page_table: &mut PageTable, vpn_range: VPNRange) {
+        let end = vpn_range.get_end();
+        let mut vpn = vpn_range.get_start();
+        while vpn < end {
+            match page_table.page_size(vpn) {
+                Some(size) if vpn.0 % size.pages() == 0 && vpn.0 + size.pages() <= end.0 => {
+                    page_table.unmap_leaf(vpn);
+                    vpn = VirtPageNum(vpn.0 + size.pages());
+                }
+                _ => {
+                    page_table.unmap(vpn);
+                    vpn.step();
+                }
+            }
+        }
+    }
     pub fn have_mapped(&self, vpn: &VirtPageNum) -> bool {
-        self.data_frames.contains_key(vpn)
+        match self.map_type {
+            // 共享内存的页帧不在 data_frames 中，挂载期间整个区间都是已映射的
+            MapType::Shared => self.vpn_range.get_start() <= *vpn && *vpn < self.vpn_range.get_end(),
+            _ => self.data_frames.contains_key(vpn),
+        }
     }
     // 将切片 data 中的数据 拷贝到 当前逻辑段实际被内核放置在的各物理页帧 上
     // 切片 data 中的数据大小不超过当前逻辑段的 总大小
@@ -469,6 +929,7 @@ impl MapArea {
 pub enum MapType {
     Identical, // 恒等映射, 用于在启用多级页表之后仍能够访问一个特定的物理地址指向的物理内存
     Framed, // 每个虚拟页面都需要映射到一个新分配的物理页帧
+    Shared, // 映射到共享内存段的页帧上，多个地址空间共享同一组物理页帧
 }
 
 // 仅保留 U/R/W/X 四个标志位，因为其他的标志位仅与硬件的地址转换机制细节相关
@@ -501,5 +962,24 @@ pub fn remap_test() {
         kernel_space.page_table.translate(mid_data.floor()).unwrap().executable(),
         false,
     );
+    // 大页不能越过逻辑段的边界: 代码段的最后一页仍然可执行，紧随其后的只读数据段不可执行
+    let last_text = VirtAddr::from(etext as usize - 1).floor();
+    let first_rodata = VirtAddr::from(srodata as usize).floor();
+    assert!(kernel_space.page_table.translate(last_text).unwrap().executable());
+    let pte = kernel_space.page_table.translate(first_rodata).unwrap();
+    assert!(pte.readable() && !pte.writable() && !pte.executable());
+    // 物理内存的恒等映射: 可读写、不可执行，大页中的每个页面仍然映射到相同的物理页号
+    let memory_start = VirtAddr::from(ekernel as usize).ceil();
+    let memory_end = VirtAddr::from(machine_info().memory_end()).floor();
+    for vpn in [memory_start, VirtPageNum((memory_start.0 + memory_end.0) / 2), VirtPageNum(memory_end.0 - 1)].iter() {
+        let pte = kernel_space.page_table.translate(*vpn).unwrap();
+        assert!(pte.readable() && pte.writable() && !pte.executable());
+        assert_eq!(pte.ppn().0, vpn.0);
+    }
+    // 对齐的 2MiB 区间应当被一个大页映射
+    let huge_start = VirtPageNum((memory_start.0 + 511) / 512 * 512);
+    if huge_start.0 + 512 <= memory_end.0 {
+        assert_ne!(kernel_space.page_table.page_size(huge_start), Some(PageSize::Size4K));
+    }
     info!("[kernel] remap_test passed!");
 }
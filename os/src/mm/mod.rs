@@ -3,33 +3,46 @@ mod address;
 mod frame_allocator;
 mod page_table;
 mod memory_set;
+mod shm;
+mod slab;
+mod asid;
+mod policy;
 
 pub use address::{VPNRange, StepByOne, PhysAddr, VirtAddr, PhysPageNum, VirtPageNum};
-pub use frame_allocator::{FrameTracker, frame_alloc, frame_dealloc, usable_frames};
-pub use memory_set::{MemorySet, KERNEL_SPACE, MapPermission, MapArea, MapType, kernel_token, remap_test};
+pub use frame_allocator::{FrameTracker, frame_alloc, frame_alloc_contiguous, usable_frames};
+pub use heap_allocator::{HeapStats, heap_stats};
+pub use slab::{register_arc_cache, slab_arc, slab_stats, slab_zero_ctor, SlabStats};
+pub use memory_set::{MemorySet, ElfInfo, KERNEL_SPACE, MapPermission, MapArea, MapType, kernel_token, remap_test};
+pub use shm::{ShmSegment, SHM_MANAGER, IPC_RMID};
+pub use policy::{check_permission, check_user_range, ElfError};
 pub use page_table::{
     PageTable,
     PTEFlags,
     PageTableEntry,
-    translated_byte_buffer,
-    translated_virtual_ptr,
-    virtual_addr_writable,
-    virtual_addr_printable,
-    virtual_addr_range_printable,
-    virtual_addr_range_writable,
-    translated_str,
-    translated_ref,
+    PageSize,
+    translated_user_buffer,
+    translated_user_buffer_mut,
+    copy_from_user,
+    copy_to_user,
+    copy_str_from_user,
     translated_refmut,
     UserBuffer,
     UserBufferIterator,
 };
 
-pub fn init() {
-    // 全局动态内存分配器的初始化
+// 全局动态内存分配器的初始化，在解析设备树之前进行
+pub fn init_heap() {
     heap_allocator::init_heap();
+}
+
+pub fn init() {
     // 初始化物理页帧 管理器, 内含堆数据结构 Vec<T>
     frame_allocator::init_frame_allocator();
+    // 之后内核堆就可以从物理页帧分配器中取出页帧进行扩展了
+    heap_allocator::enable_heap_growth();
     // 创建内核地址空间并让 CPU 开启分页模式, MMU 在地址转换的时候使用内核的多级页表
     // 这是 KERNEL_SPACE 第一次被使用
     KERNEL_SPACE.lock().activate();
+    // 开启分页之后才能探测硬件支持的 ASID 个数
+    asid::init_asid();
 }
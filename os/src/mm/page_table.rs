@@ -1,10 +1,11 @@
 use super::{frame_alloc, PhysPageNum, FrameTracker, VirtPageNum, VirtAddr, StepByOne};
+use super::asid::{Asid, asid_refresh};
 use alloc::vec::Vec;
 use alloc::vec;
 use alloc::string::String;
 use bitflags::*;
 use crate::mm::{PhysAddr};
-use crate::config::{TRAP_CONTEXT};
+use crate::config::{PAGE_SIZE, PAGING_LEVELS, SATP_MODE, USER_SPACE_END, canonical_va};
 
 // 在我们切换任务的时候， satp 也必须被同时切换
 bitflags! {
@@ -64,6 +65,55 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    pub fn is_user(&self) -> bool {
+        (self.flags() & PTEFlags::U) != PTEFlags::empty()
+    }
+    // 合法且 R/W/X 不全为 0 的页表项是叶节点，可能位于任意一级页表中
+    pub fn is_leaf(&self) -> bool {
+        self.is_valid() && !(self.flags() & (PTEFlags::R | PTEFlags::W | PTEFlags::X)).is_empty()
+    }
+}
+
+// 叶节点映射的页面大小
+// 除了最后一级页表中的 4KiB 页面，倒数第二级和倒数第三级页表中的叶节点分别映射 2MiB 的大页和 1GiB 的巨页
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PageSize {
+    Size4K,
+    Size2M,
+    Size1G,
+}
+
+impl PageSize {
+    // 包含的 4KiB 页面数
+    pub fn pages(&self) -> usize {
+        match self {
+            PageSize::Size4K => 1,
+            PageSize::Size2M => 512,
+            PageSize::Size1G => 512 * 512,
+        }
+    }
+    // 叶节点所在的页表级别，根节点为第 0 级
+    fn level(&self) -> usize {
+        match self {
+            PageSize::Size4K => PAGING_LEVELS - 1,
+            PageSize::Size2M => PAGING_LEVELS - 2,
+            PageSize::Size1G => PAGING_LEVELS - 3,
+        }
+    }
+    fn from_level(level: usize) -> Option<Self> {
+        [PageSize::Size4K, PageSize::Size2M, PageSize::Size1G]
+            .iter()
+            .copied()
+            .find(|size| size.level() == level)
+    }
+    // 能够映射从 vpn 开始、不超过 end 的区间的最大页面，vpn 需要按照页面大小对齐
+    pub fn largest_fitting(vpn: VirtPageNum, end: VirtPageNum) -> Self {
+        [PageSize::Size1G, PageSize::Size2M]
+            .iter()
+            .copied()
+            .find(|size| vpn.0 % size.pages() == 0 && vpn.0 + size.pages() <= end.0)
+            .unwrap_or(PageSize::Size4K)
+    }
 }
 
 // 每个应用的地址空间都对应一个不同的多级页表，这也就意味这不同页表的起始地址（即页表根节点的地址）是不一样的
@@ -72,17 +122,19 @@ impl PageTableEntry {
 pub struct PageTable {
     root_ppn: PhysPageNum,
     frames: Vec<FrameTracker>, // 保存了页表所有的节点（包括根节点）所在的物理页帧
+    asid: Asid, // 快表中属于这个页表的键值对的标记
 }
 
-/// Assume that it won't oom when creating/mapping.
+// 物理内存不足时，创建页表和插入映射都会失败并返回 None/-1，由调用者负责向上传递错误
 impl PageTable {
-    pub fn new() -> Self {
+    pub fn new() -> Option<Self> {
         // 分配一个物理页帧 FrameTracker 并挂在向量 frames 下
-        let frame = frame_alloc().unwrap();
-        PageTable {
+        let frame = frame_alloc()?;
+        Some(PageTable {
             root_ppn: frame.ppn, // 更新根节点的物理页号 root_ppn
             frames: vec![frame],
-        }
+            asid: Asid::empty(), // 在第一次被切换到之前不会分配 ASID
+        })
     }
     /// Temporarily used to get arguments from user space.
     // 临时创建一个专用来手动查页表的 PageTable
@@ -91,41 +143,75 @@ impl PageTable {
         Self {
             root_ppn: PhysPageNum::from(satp & ((1usize << 44) - 1)),
             frames: Vec::new(), // frames 字段为空，也即不实际控制任何资源
+            asid: Asid::empty(), // 仅用来查页表，不会修改页表项，也就不需要刷新快表
         }
     }
-    // 从vpn找ppn, 找不到的时候就创建
-    fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    // 从vpn找ppn, 找不到的时候就创建, 物理内存不足无法创建节点时返回 None
+    // 返回的是映射 size 大小页面的那一级页表中的页表项
+    fn find_pte_create(&mut self, vpn: VirtPageNum, size: PageSize) -> Option<&mut PageTableEntry> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn; // 当前节点的物理页号, 最开始指向多级页表的根节点
         let mut result: Option<&mut PageTableEntry> = None;
         // 通过 get_pte_array 将 取出当前节点的 页表项数组
-        for i in 0..3 {
+        for i in 0..PAGING_LEVELS {
             let pte = &mut ppn.get_pte_array()[idxs[i]]; // 并根据当前级页索引找到对应的页表项
-            if i == 2 { // 如果当前节点是一个叶节点，那么直接返回这个页表项 的可变引用
+            if i == size.level() { // 如果当前节点是一个叶节点，那么直接返回这个页表项 的可变引用
                 result = Some(pte);
                 break;
             }
             // 如果在 遍历的过程中发现有节点尚未创建则会新建一个节点
             if !pte.is_valid() {
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc()?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V); // 更新作为下级节点指针的页表项
                 self.frames.push(frame); // 将新分配的物理页帧移动到 向量 frames 中方便后续的自动回收
             }
+            assert!(!pte.is_leaf(), "vpn {:?} is inside a huge page", vpn);
             ppn = pte.ppn();
         }
         result
     }
     // 从vpn找ppn, 找不到的时候就返回None
-    fn find_pte(&self, vpn: VirtPageNum) -> Option<&PageTableEntry> {
+    // 遇到更高一级页表中的叶节点时提前停下，同时返回叶节点映射的页面大小
+    fn find_pte(&self, vpn: VirtPageNum) -> Option<(&PageTableEntry, PageSize)> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&PageTableEntry> = None;
-        for i in 0..3 {
+        let mut result: Option<(&PageTableEntry, PageSize)> = None;
+        for i in 0..PAGING_LEVELS {
             let pte = &ppn.get_pte_array()[idxs[i]];
-            if i == 2 {
+            if i == PAGING_LEVELS - 1 || pte.is_leaf() {
+                result = PageSize::from_level(i).map(|size| (pte, size));
+                break;
+            }
+            if !pte.is_valid() {
+                return None;
+            }
+            ppn = pte.ppn();
+        }
+        result
+    }
+    // 找到 vpn 在最后一级页表中的页表项，途中遇到的大页会被拆分成下一级页表中的 512 个页面，访问方式不变
+    // 物理内存不足无法拆分时返回 None
+    fn find_pte_split(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        let idxs = vpn.indexes();
+        let mut ppn = self.root_ppn;
+        let mut result: Option<&mut PageTableEntry> = None;
+        for i in 0..PAGING_LEVELS {
+            let pte = &mut ppn.get_pte_array()[idxs[i]];
+            if i == PAGING_LEVELS - 1 {
                 result = Some(pte);
                 break;
             }
+            if pte.is_leaf() {
+                let frame = frame_alloc()?;
+                let step = PageSize::from_level(i + 1)?.pages();
+                for (j, child) in frame.ppn.get_pte_array().iter_mut().enumerate() {
+                    *child = PageTableEntry::new(PhysPageNum(pte.ppn().0 + j * step), pte.flags());
+                }
+                *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+                self.frames.push(frame);
+                // 快表中可能缓存着整个大页的键值对
+                self.flush(vpn);
+            }
             if !pte.is_valid() {
                 return None;
             }
@@ -135,30 +221,91 @@ impl PageTable {
     }
     // 在多级页表中插入一个 <虚拟页号，物理页号> 键值对，
     // 注意这里我们将物理页号 ppn 和页表项标志位 flags 作为 不同的参数传入而不是整合为一个页表项
+    // 中间节点无法分配时返回 -1
     #[allow(unused)]
-    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) -> isize {
+        self.map_huge(vpn, ppn, PageSize::Size4K, flags)
+    }
+    // 插入一个映射 size 大小页面的叶节点，vpn 和 ppn 都需要按照页面大小对齐
+    pub fn map_huge(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, size: PageSize, flags: PTEFlags) -> isize {
+        assert!(vpn.0 % size.pages() == 0 && ppn.0 % size.pages() == 0, "{:?} is not aligned to {:?}", vpn, size);
         // 只需根据虚拟页号找到页表项
-        let pte = self.find_pte_create(vpn).unwrap();
+        let pte = if let Some(pte) = self.find_pte_create(vpn, size) {
+            pte
+        } else {
+            return -1;
+        };
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         // 修改其内容
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        // 硬件可能缓存了不合法的页表项
+        self.flush(vpn);
+        0
     }
     // 删除一个 <虚拟页号，物理页号> 键值对
+    // vpn 位于大页中时，先将大页拆分，只删除 vpn 这一个页面
     #[allow(unused)]
     pub fn unmap(&mut self, vpn: VirtPageNum) {
-        let pte = self.find_pte_create(vpn).unwrap();
+        let pte = self.find_pte_split(vpn).unwrap();
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         // 直接清空页表项内容
         *pte = PageTableEntry::empty();
+        self.flush(vpn);
+    }
+    // 删除 vpn 所在的整个叶节点，返回它映射的页面大小
+    pub fn unmap_leaf(&mut self, vpn: VirtPageNum) -> PageSize {
+        let size = self.page_size(vpn).expect("vpn is invalid before unmapping");
+        let pte = self.find_pte_create(vpn, size).unwrap();
+        *pte = PageTableEntry::empty();
+        self.flush(vpn);
+        size
+    }
+    // vpn 所在的叶节点映射的页面大小，没有被映射时返回 None
+    pub fn page_size(&self, vpn: VirtPageNum) -> Option<PageSize> {
+        self.find_pte(vpn)
+            .filter(|(pte, _)| pte.is_valid())
+            .map(|(_, size)| size)
+    }
+    // 修改一个已经存在的 <虚拟页号，物理页号> 键值对的标志位，物理页号和 A/D 位保持不变
+    pub fn set_flags(&mut self, vpn: VirtPageNum, flags: PTEFlags) {
+        let pte = self.find_pte_split(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before changing flags", vpn);
+        let kept = pte.flags() & (PTEFlags::A | PTEFlags::D);
+        *pte = PageTableEntry::new(pte.ppn(), flags | kept | PTEFlags::V);
+        self.flush(vpn);
+    }
+    // 页表项被修改之后，只清空快表中这个页表的 ASID 下虚拟页面 vpn 对应的键值对
+    // ASID 不在当前代中有效的页表不可能在快表中留有键值对，不需要刷新
+    fn flush(&self, vpn: VirtPageNum) {
+        if !self.asid.is_live() {
+            return;
+        }
+        // sfence.vma 要求合法的虚拟地址，最高的有效位需要符号扩展到高位
+        let va = canonical_va(VirtAddr::from(vpn).0);
+        unsafe {
+            llvm_asm!("sfence.vma $0, $1" :: "r"(va), "r"(self.asid.id) :: "volatile");
+        }
+    }
+    // 内核地址空间使用保留的 ASID 0
+    pub fn set_kernel_asid(&mut self) {
+        self.asid = Asid::kernel();
+    }
+    // 切换到这个页表之前调用，保证它的 ASID 在当前代中有效
+    pub fn refresh_asid(&mut self) {
+        asid_refresh(&mut self.asid);
     }
     // 如果能够找到页表项，那么它会将页表项拷贝一份并返回
     // 否则就 返回一个 None
+    // vpn 位于大页中时，返回的页表项中的物理页号是 vpn 在大页中对应的那个 4KiB 页面
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.find_pte(vpn)
-            .map(|pte| {pte.clone()})
+            .map(|(pte, size)| {
+                let offset = vpn.0 & (size.pages() - 1);
+                PageTableEntry::new(PhysPageNum(pte.ppn().0 + offset), pte.flags())
+            })
     }
     pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
-        self.find_pte(va.clone().floor())
+        self.translate(va.clone().floor())
             .map(|pte| {
                 //println!("translate_va:va = {:?}", va);
                 let aligned_pa: PhysAddr = pte.ppn().into();
@@ -168,13 +315,14 @@ impl PageTable {
                 (aligned_pa_usize + offset).into()
             })
     }
-    pub fn translate_pte(&self, va: VirtAddr) -> Option<PageTableEntry> {
-        let vpn = va.floor();
-        self.translate(vpn)
-    }
     // satp token
+    // MODE 为 8 表示 SV39，为 9 表示 SV48，ASID 位于 [44, 60)，根节点的物理页号位于 [0, 44)
     pub fn token(&self) -> usize {
-        8usize << 60 | self.root_ppn.0
+        SATP_MODE << 60 | self.asid.id << 44 | self.root_ppn.0
+    }
+    // 页表节点占用的物理页帧数
+    pub fn frames_used(&self) -> usize {
+        self.frames.len()
     }
 }
 
@@ -182,101 +330,116 @@ extern "C" {
     fn ekernel(); // 应用不能修改内核地址空间的数据
 }
 
-// 将 应用地址空间中一个缓冲区 转化为在 内核空间中能够直接访问 的形式
-pub fn translated_byte_buffer(
-    token: usize, // 某个应用地址空间的 token 
+// 应用传入的字符串最长的长度 (不包括末尾的 \0)，超过时视为非法地址
+const USER_STR_MAX_LEN: usize = 4096;
+
+// 应用传入的指针只能指向用户态可以访问的页面
+// 页面必须已经映射且设置了 U 标志，读取时需要 R 标志，写入时还需要 W 标志，否则系统调用返回 -1 而不是让内核 panic
+// 同时地址不能超出用户地址空间，否则页号的高位会被截断，转而访问另一个合法页面
+fn user_page(page_table: &PageTable, vpn: VirtPageNum, write: bool) -> Option<PhysPageNum> {
+    let pte = page_table.translate(vpn)?;
+    if pte.is_valid() && pte.is_user() && pte.readable() && (!write || pte.writable()) {
+        Some(pte.ppn())
+    } else {
+        None
+    }
+}
+
+// 检查应用地址空间中的缓冲区 [ptr, ptr + len) 并转化为 内核空间中能够直接访问 的形式
+// write 表示内核是否会写入这段缓冲区，任何一页不满足访问权限时返回 None
+fn user_byte_buffer(
+    token: usize, // 某个应用地址空间的 token
     ptr: *const u8, // 该应用 虚拟地址空间中 的一段缓冲区的起始地址 和长度
-    len: usize
-) -> Vec<&'static mut [u8]> { // 以 向量 的形式返回一组可以在内核空间中直接访问的 字节数组切片
+    len: usize,
+    write: bool,
+) -> Option<Vec<&'static mut [u8]>> { // 以 向量 的形式返回一组可以在内核空间中直接访问的 字节数组切片
     let page_table = PageTable::from_token(token);
     let mut start = ptr as usize;
-    let end = start + len;
+    let end = start.checked_add(len)?;
+    if end > USER_SPACE_END {
+        return None;
+    }
     let mut v = Vec::new();
     while start < end {
         let start_va = VirtAddr::from(start);
         let mut vpn = start_va.floor();
-        let ppn = page_table
-            .translate(vpn)
-            .unwrap()
-            .ppn();
+        let ppn = user_page(&page_table, vpn, write)?;
         vpn.step();
         let mut end_va: VirtAddr = vpn.into();
         end_va = end_va.min(VirtAddr::from(end));
-        v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
+        if end_va.page_offset() == 0 {
+            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
+        } else {
+            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
+        }
         start = end_va.into();
     }
-    v
-}
-
-pub fn virtual_addr_printable(token: usize, va: usize) -> (bool, usize) {
-    let va = VirtAddr::from(va);
-    let page_table = PageTable::from_token(token);
-    if let Some(pte) = page_table.translate_pte(va) {
-        (
-            pte.is_valid() && (pte.readable() && !pte.executable()),
-            page_table.translate_va(va).unwrap().0
-        )
-    } else {
-        (false, 0)
-    }
+    Some(v)
 }
 
-pub fn virtual_addr_writable(token: usize, va: usize) -> bool {
-    if va >= TRAP_CONTEXT {
-        false
-    } else {
-        let va = VirtAddr::from(va);
-        let page_table = PageTable::from_token(token);
-        if let Some(pte) = page_table.translate_pte(va) {
-            pte.is_valid() && pte.readable() && pte.writable()
-        } else {
-            false
-        }
-    }
+// 内核将从中读取数据的应用缓冲区，例如 write 的参数
+pub fn translated_user_buffer(token: usize, ptr: *const u8, len: usize) -> Option<UserBuffer> {
+    user_byte_buffer(token, ptr, len, false).map(UserBuffer::new)
 }
 
-pub fn virtual_addr_range_printable(token: usize, ptr: *const u8, len: usize) -> (bool, usize, usize) {
-    let (start_printable, start_pa) = virtual_addr_printable(token, ptr as usize);
-    let (end_printable, end_pa) = virtual_addr_printable(token, ptr as usize + len);
-    (
-        start_printable && end_printable,
-        start_pa,
-        end_pa
-    )
+// 内核将向其中写入数据的应用缓冲区，例如 read 的参数
+pub fn translated_user_buffer_mut(token: usize, ptr: *mut u8, len: usize) -> Option<UserBuffer> {
+    user_byte_buffer(token, ptr, len, true).map(UserBuffer::new)
 }
 
-pub fn virtual_addr_range_writable(token: usize, ptr: *const u8, len: usize) -> bool {
-    let start_writable = virtual_addr_writable(token, ptr as usize);
-    let end_writable = virtual_addr_writable(token, ptr as usize + len);
-    start_writable && end_writable
+// 从应用地址空间中读出一个 T，它可能跨越页边界，因此逐字节复制
+pub fn copy_from_user<T: Copy>(token: usize, src: *const T) -> Option<T> {
+    let buffers = user_byte_buffer(token, src as *const u8, core::mem::size_of::<T>(), false)?;
+    let mut value = core::mem::MaybeUninit::<T>::uninit();
+    let mut dst = value.as_mut_ptr() as *mut u8;
+    for buffer in buffers {
+        unsafe {
+            core::ptr::copy_nonoverlapping(buffer.as_ptr(), dst, buffer.len());
+            dst = dst.add(buffer.len());
+        }
+    }
+    Some(unsafe { value.assume_init() })
 }
 
-pub fn translated_virtual_ptr<T>(
-    token: usize,
-    v_ptr: *mut T,
-) -> *mut T {
-    let page_table = PageTable::from_token(token);
-    let pa: usize = page_table.translate_va(VirtAddr::from(v_ptr as usize)).unwrap().0;
-    pa as *mut T
+// 将 value 写入应用地址空间，地址非法时不写入任何数据并返回 false
+pub fn copy_to_user<T: Copy>(token: usize, dst: *mut T, value: &T) -> bool {
+    let buffers = match user_byte_buffer(token, dst as *const u8, core::mem::size_of::<T>(), true) {
+        Some(buffers) => buffers,
+        None => return false,
+    };
+    let mut src = value as *const T as *const u8;
+    for buffer in buffers {
+        unsafe {
+            core::ptr::copy_nonoverlapping(src, buffer.as_mut_ptr(), buffer.len());
+            src = src.add(buffer.len());
+        }
+    }
+    true
 }
 
-// 从内核地址空间之外的某个地址空间中拿到一个字符串，其原理就是逐字节查页表直到发现一个 \0 为止
-pub fn translated_str(token: usize, ptr: *const u8) -> String {
+// 从应用地址空间中拿到一个字符串，其原理就是逐字节查页表直到发现一个 \0 为止
+// 每进入一个新的页面都要检查访问权限，字符串过长或者没有 \0 结尾时返回 None
+pub fn copy_str_from_user(token: usize, ptr: *const u8) -> Option<String> {
     let page_table = PageTable::from_token(token);
     let mut string = String::new();
     let mut va = ptr as usize;
     loop {
-        let ch: u8 = *(page_table.translate_va(VirtAddr::from(va)).unwrap().get_mut());
-        if ch == 0 {
-            break;
-        } else {
+        if va >= USER_SPACE_END || string.len() > USER_STR_MAX_LEN {
+            return None;
+        }
+        let start_va = VirtAddr::from(va);
+        let bytes = user_page(&page_table, start_va.floor(), false)?.get_bytes_array();
+        for &ch in bytes[start_va.page_offset()..].iter() {
+            if ch == 0 {
+                return Some(string);
+            }
             string.push(ch as char);
-            va += 1;
         }
+        va += PAGE_SIZE - start_va.page_offset();
     }
-    string
 }
 
+// 只能用于内核自己构建的、确定已经映射的应用地址空间，例如 exec 时在新的用户栈上压入命令行参数
 pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
     //println!("into translated_refmut!");
     let page_table = PageTable::from_token(token);
@@ -285,11 +448,6 @@ pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
     page_table.translate_va(VirtAddr::from(va)).unwrap().get_mut()
 }
 
-pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
-    let page_table = PageTable::from_token(token);
-    page_table.translate_va(VirtAddr::from(ptr as usize)).unwrap().get_ref()
-}
-
 // 应用虚拟地址空间中的一段缓冲区的抽象, 存放的是一些 虚拟地址区间
 // 本质上其实只是一个 &[u8], 给出了缓冲区的起始地址及长度
 // 但是它位于应用地址空间中，在内核中我们无法直接通过这种方式来访问，因此需要进行封装
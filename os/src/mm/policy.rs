@@ -0,0 +1,46 @@
+// 用户地址空间映射的安全策略
+// mmap/mprotect 和 ELF 加载在建立映射之前都要经过这里的检查，违反策略的请求被拒绝而不是让内核 panic
+use super::MapPermission;
+use crate::config::{TRAP_CONTEXT, USER_SPACE_END};
+use crate::fdt::boot_param;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    WritableExecutable, // 同时可写和可执行 (W^X)
+    OutOfRange, // 超出用户地址空间，或者与跳板、Trap 上下文重叠
+    Overlap, // 与已有的映射重叠
+}
+
+// ELF 加载失败的原因，exec 据此返回错误并在日志中说明
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    Malformed(&'static str), // 文件头或 program header 损坏
+    Unsupported(&'static str), // 不是 RISC-V 64 位的可执行文件，或者需要动态链接器
+    Segment(usize, MapError), // 第 i 个 Load 段违反了映射策略
+    Stack(MapError), // 用户栈的保留区间违反了映射策略
+    EntryNotExecutable, // 入口点不在任何可执行的段中
+    BadRelocation, // 重定位项非法或者类型不受支持
+    OutOfMemory,
+}
+
+// 启动参数 allow_wx 关闭 W^X 检查，用于运行依赖可写可执行内存的旧程序
+pub fn wx_enforced() -> bool {
+    boot_param("allow_wx").is_none()
+}
+
+// 检查用户态映射的访问方式
+pub fn check_permission(permission: MapPermission) -> Result<(), MapError> {
+    if wx_enforced() && permission.contains(MapPermission::W | MapPermission::X) {
+        return Err(MapError::WritableExecutable);
+    }
+    Ok(())
+}
+
+// 检查虚拟地址区间 [start, end) 是否完全位于用户地址空间内
+// 跳板和 Trap 上下文位于地址空间的最高处，同样不能被用户态的映射覆盖
+pub fn check_user_range(start: usize, end: usize) -> Result<(), MapError> {
+    if start > end || end > USER_SPACE_END.min(TRAP_CONTEXT) {
+        return Err(MapError::OutOfRange);
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+// System V 风格的共享内存段
+// 同一组物理页帧可以被映射到多个地址空间中，进程之间读写共享内存无需像管道/邮箱那样在内核中逐字节拷贝
+use super::{FrameTracker, frame_alloc};
+use crate::config::PAGE_SIZE;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+// key 为 IPC_PRIVATE 时总是创建一个新的共享内存段
+pub const IPC_PRIVATE: usize = 0;
+// shmctl 的命令: 将共享内存段标记为删除
+pub const IPC_RMID: usize = 0;
+
+// 共享内存段
+// 页帧的生命周期绑定到 ShmSegment 上，每个挂载了该段的逻辑段 MapArea 都持有它的一个强引用
+// 当段已被 IPC_RMID 从全局表中移除且所有挂载都已解除时，引用计数归零，这些页帧随之被回收
+pub struct ShmSegment {
+    pub key: usize,
+    pub size: usize, // shmget 时传入的字节数
+    pub frames: Vec<FrameTracker>,
+}
+
+impl ShmSegment {
+    pub fn page_count(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+// 全局共享内存段管理器
+pub struct ShmManager {
+    next_id: usize,
+    segments: BTreeMap<usize, Arc<ShmSegment>>, // id -> 段
+    keys: BTreeMap<usize, usize>, // key -> id, 不包括 IPC_PRIVATE 创建的段
+}
+
+impl ShmManager {
+    pub fn new() -> Self {
+        Self {
+            next_id: 1,
+            segments: BTreeMap::new(),
+            keys: BTreeMap::new(),
+        }
+    }
+    // 按 key 查找共享内存段，找不到时创建一个至少 size 字节的新段
+    // 返回段的 id，size 为 0、已存在的段比 size 小或物理内存不足时返回 None
+    pub fn get(&mut self, key: usize, size: usize) -> Option<usize> {
+        if key != IPC_PRIVATE {
+            if let Some(id) = self.keys.get(&key) {
+                let segment = self.segments.get(id).unwrap();
+                if size > segment.size {
+                    return None;
+                }
+                return Some(*id);
+            }
+        }
+        if size == 0 {
+            return None;
+        }
+        let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut frames: Vec<FrameTracker> = Vec::new();
+        for _ in 0..pages {
+            // 分配失败时已分配的页帧随 frames 一起被回收
+            frames.push(frame_alloc()?);
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.segments.insert(id, Arc::new(ShmSegment {
+            key,
+            size,
+            frames,
+        }));
+        if key != IPC_PRIVATE {
+            self.keys.insert(key, id);
+        }
+        Some(id)
+    }
+    pub fn segment(&self, id: usize) -> Option<Arc<ShmSegment>> {
+        self.segments.get(&id).map(|segment| Arc::clone(segment))
+    }
+    // 从全局表中移除，之后不能再被 shmget/shmat 找到，已有的挂载不受影响
+    pub fn remove(&mut self, id: usize) -> bool {
+        if let Some(segment) = self.segments.remove(&id) {
+            if segment.key != IPC_PRIVATE {
+                self.keys.remove(&segment.key);
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref SHM_MANAGER: Mutex<ShmManager> = Mutex::new(ShmManager::new());
+}
@@ -0,0 +1,316 @@
+use super::{PhysAddr, PhysPageNum};
+use super::frame_allocator::{frame_alloc_contiguous, frame_dealloc_for_heap, frame_owner, set_frame_owner};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::any::TypeId;
+use core::fmt::{self, Debug, Formatter};
+use core::sync::atomic::AtomicUsize;
+use spin::Mutex;
+use crate::config::PAGE_SIZE;
+use crate::fdt::boot_param;
+
+// Slab 对象缓存
+// 每个缓存只负责一种类型 T 的 Arc<T>，从物理页帧分配器中整块取出若干页 (一个 slab) 切分成等大的对象
+// slab 按自身的大小对齐，开头是记录它的空闲对象链表的 SlabHeader，对象的地址向下对齐就能找到它所在的 slab
+// 空闲对象通过存放在对象自身开头的指针串成单链表，分配和回收都不会在通用堆中留下碎片
+// slab_arc 显式地从缓存中取出对象，在其中就地构造 Arc<T> 的堆上部分之后通过 Arc::from_raw 得到 Arc<T>
+// Arc<T> 释放时仍然经过全局分配器，它根据对象所在页帧的占用者把对象放回所属的缓存
+pub struct SlabCache {
+    name: &'static str,
+    type_id: TypeId, // 缓存负责的类型 T，缓存中的对象都是 Arc<T> 在堆上的部分
+    layout: Layout, // 对象的大小 (已经按对齐要求补齐) 和对齐要求
+    ctor: Option<fn(&mut [u8])>, // 构造钩子，对象每次被分配出去之前以对象所在的内存调用
+    slab_pages: usize, // 每个 slab 包含的页数
+    first_object: usize, // 第一个对象在 slab 中的偏移
+    slab_list: usize, // 缓存的所有 slab 串成的单链表的表头，0 表示没有 slab
+    slabs: usize,
+    in_use: usize,
+    allocs: usize,
+    frees: usize,
+}
+
+// 位于每个 slab 的开头
+#[repr(C)]
+struct SlabHeader {
+    next: usize, // 同一个缓存中的下一个 slab，0 表示没有
+    free_head: usize, // 空闲对象链表的表头，0 表示链表为空
+    in_use: usize, // 正在使用的对象个数
+}
+
+// 与 Arc<T> 在堆上的部分布局相同: 强引用计数、弱引用计数以及 T 本身
+#[repr(C)]
+struct ArcInner<T> {
+    strong: AtomicUsize,
+    weak: AtomicUsize,
+    data: T,
+}
+
+// 每个 slab 至少能容纳的对象个数
+const SLAB_MIN_OBJECTS: usize = 8;
+// 最多可以注册的对象缓存个数
+const MAX_SLAB_CACHES: usize = 8;
+// 统计信息中缓存名字的最大长度
+pub const SLAB_NAME_LEN: usize = 24;
+// 对象缓存 idx 的 slab 页帧在物理页帧分配器中的占用者记为 SLAB_OWNER | idx，与内核堆的区域区分开
+const SLAB_OWNER: u8 = 0x80;
+
+impl SlabCache {
+    fn new(name: &'static str, type_id: TypeId, layout: Layout, ctor: Option<fn(&mut [u8])>) -> Self {
+        // 空闲链表的指针存放在对象开头，对象至少要能放下一个 usize
+        let layout = layout
+            .align_to(core::mem::align_of::<usize>()).unwrap()
+            .pad_to_align();
+        let layout = Layout::from_size_align(
+            layout.size().max(core::mem::size_of::<usize>()),
+            layout.align(),
+        ).unwrap();
+        let first_object = Layout::new::<SlabHeader>()
+            .align_to(layout.align()).unwrap()
+            .pad_to_align()
+            .size();
+        let slab_pages = ((first_object + layout.size() * SLAB_MIN_OBJECTS + PAGE_SIZE - 1) / PAGE_SIZE).next_power_of_two();
+        Self {
+            name,
+            type_id,
+            layout,
+            ctor,
+            slab_pages,
+            first_object,
+            slab_list: 0,
+            slabs: 0,
+            in_use: 0,
+            allocs: 0,
+            frees: 0,
+        }
+    }
+    // 缓存中的对象能否满足这次分配
+    fn fits(&self, layout: &Layout) -> bool {
+        layout.size() <= self.layout.size() && layout.align() <= self.layout.align()
+    }
+    fn objects_per_slab(&self) -> usize {
+        (self.slab_pages * PAGE_SIZE - self.first_object) / self.layout.size()
+    }
+    fn header(slab: usize) -> &'static mut SlabHeader {
+        unsafe { &mut *(slab as *mut SlabHeader) }
+    }
+    // 对象所在的 slab
+    fn slab_of(&self, obj: *mut u8) -> usize {
+        obj as usize & !(self.slab_pages * PAGE_SIZE - 1)
+    }
+    // 将从 start 开始的一个新 slab 切分成对象，挂到 slab 链表的开头
+    fn add_slab(&mut self, start: usize) {
+        let header = Self::header(start);
+        header.free_head = 0;
+        header.in_use = 0;
+        for i in (0..self.objects_per_slab()).rev() {
+            let obj = start + self.first_object + i * self.layout.size();
+            unsafe { *(obj as *mut usize) = header.free_head; }
+            header.free_head = obj;
+        }
+        self.link(start);
+        self.slabs += 1;
+    }
+    fn link(&mut self, slab: usize) {
+        Self::header(slab).next = self.slab_list;
+        self.slab_list = slab;
+    }
+    fn unlink(&mut self, slab: usize) {
+        if self.slab_list == slab {
+            self.slab_list = Self::header(slab).next;
+            return;
+        }
+        let mut prev = self.slab_list;
+        while Self::header(prev).next != slab {
+            prev = Self::header(prev).next;
+        }
+        Self::header(prev).next = Self::header(slab).next;
+    }
+    fn empty_slabs(&self) -> usize {
+        let mut count = 0;
+        let mut slab = self.slab_list;
+        while slab != 0 {
+            let header = Self::header(slab);
+            if header.in_use == 0 {
+                count += 1;
+            }
+            slab = header.next;
+        }
+        count
+    }
+    // 从第一个还有空闲对象的 slab 中取出一个对象
+    fn pop(&mut self) -> Option<*mut u8> {
+        let mut slab = self.slab_list;
+        while slab != 0 && Self::header(slab).free_head == 0 {
+            slab = Self::header(slab).next;
+        }
+        if slab == 0 {
+            return None;
+        }
+        let header = Self::header(slab);
+        let obj = header.free_head;
+        header.free_head = unsafe { *(obj as *const usize) };
+        header.in_use += 1;
+        self.in_use += 1;
+        self.allocs += 1;
+        if let Some(ctor) = self.ctor {
+            ctor(unsafe { core::slice::from_raw_parts_mut(obj as *mut u8, self.layout.size()) });
+        }
+        Some(obj as *mut u8)
+    }
+    // 将对象放回它所在的 slab，返回这个 slab 是否因此完全空闲
+    fn push(&mut self, obj: *mut u8) -> bool {
+        let header = Self::header(self.slab_of(obj));
+        unsafe { *(obj as *mut usize) = header.free_head; }
+        header.free_head = obj as usize;
+        header.in_use -= 1;
+        self.in_use -= 1;
+        self.frees += 1;
+        header.in_use == 0
+    }
+    fn stats(&self) -> SlabStats {
+        let mut name = [0u8; SLAB_NAME_LEN];
+        let len = self.name.len().min(SLAB_NAME_LEN);
+        name[..len].copy_from_slice(&self.name.as_bytes()[..len]);
+        SlabStats {
+            name,
+            object_size: self.layout.size(),
+            slabs: self.slabs,
+            objects: self.slabs * self.objects_per_slab(),
+            in_use: self.in_use,
+            allocs: self.allocs,
+            frees: self.frees,
+        }
+    }
+}
+
+// 单个对象缓存的使用情况，通过系统调用复制给用户程序
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SlabStats {
+    pub name: [u8; SLAB_NAME_LEN], // 以 0 填充
+    pub object_size: usize,
+    pub slabs: usize, // 已经分配的 slab 个数
+    pub objects: usize, // 所有 slab 中的对象总数
+    pub in_use: usize, // 正在使用的对象个数
+    pub allocs: usize, // 累计分配次数
+    pub frees: usize, // 累计回收次数
+}
+
+impl Debug for SlabStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let len = self.name.iter().position(|c| *c == 0).unwrap_or(SLAB_NAME_LEN);
+        f.write_fmt(format_args!(
+            "SlabStats:{}(size={}),slabs={},in_use={}/{},allocs={},frees={}",
+            core::str::from_utf8(&self.name[..len]).unwrap_or("?"),
+            self.object_size, self.slabs, self.in_use, self.objects, self.allocs, self.frees
+        ))
+    }
+}
+
+const NO_CACHE: Option<SlabCache> = None;
+
+// 这里不能使用 Vec: 注册缓存时如果 Vec 扩容就会在持有锁的情况下再次进入全局分配器
+static SLAB_CACHES: Mutex<[Option<SlabCache>; MAX_SLAB_CACHES]> = Mutex::new([NO_CACHE; MAX_SLAB_CACHES]);
+
+// 启动参数 noslab 让 slab_arc 全部退回通用堆，用于对比对象缓存的效果
+fn slab_enabled() -> bool {
+    boot_param("noslab").is_none()
+}
+
+// 为类型 T 注册一个对象缓存，之后通过 slab_arc 创建的 Arc<T> 从中分配
+// 构造钩子在持有锁的情况下调用，不能进行堆分配
+pub fn register_arc_cache<T: 'static>(name: &'static str, ctor: Option<fn(&mut [u8])>) {
+    let mut caches = SLAB_CACHES.lock();
+    let slot = caches.iter_mut()
+        .find(|cache| cache.is_none())
+        .expect("[kernel] Too many slab caches!");
+    *slot = Some(SlabCache::new(name, TypeId::of::<T>(), Layout::new::<ArcInner<T>>(), ctor));
+}
+
+// 创建 Arc<T>，T 注册了对象缓存时从缓存中分配
+// T 没有注册对象缓存、对象缓存被禁用或者无法取得新的 slab 时由通用堆分配
+pub fn slab_arc<T: 'static>(value: T) -> Arc<T> {
+    let obj = if slab_enabled() {
+        slab_alloc(TypeId::of::<T>(), &Layout::new::<ArcInner<T>>())
+    } else {
+        None
+    };
+    match obj {
+        Some(obj) => unsafe {
+            let inner = obj as *mut ArcInner<T>;
+            inner.write(ArcInner {
+                strong: AtomicUsize::new(1),
+                weak: AtomicUsize::new(1),
+                data: value,
+            });
+            Arc::from_raw(&(*inner).data as *const T)
+        },
+        None => Arc::new(value),
+    }
+}
+
+// 从类型 type_id 的对象缓存中分配一个对象，没有空闲对象时取得一个新的 slab
+// 不在全局分配器内部调用，slab 的页帧像其他页帧一样从物理页帧分配器中分配
+fn slab_alloc(type_id: TypeId, layout: &Layout) -> Option<*mut u8> {
+    let mut caches = SLAB_CACHES.lock();
+    let idx = caches.iter()
+        .position(|cache| cache.as_ref().map_or(false, |cache| cache.type_id == type_id))?;
+    let cache = caches[idx].as_mut().unwrap();
+    assert!(cache.fits(layout));
+    if let Some(obj) = cache.pop() {
+        return Some(obj);
+    }
+    // 获取新的 slab 时不能持有锁: 物理页帧分配器自身可能需要进行堆分配
+    let slab_pages = cache.slab_pages;
+    drop(caches);
+    let frames = frame_alloc_contiguous(slab_pages, slab_pages)?;
+    let start = PhysAddr::from(frames[0].ppn).0;
+    // 页帧此后由对象缓存管理，整个 slab 被归还时才回到物理页帧分配器
+    for frame in frames {
+        set_frame_owner(frame.ppn, SLAB_OWNER | idx as u8);
+        core::mem::forget(frame);
+    }
+    let mut caches = SLAB_CACHES.lock();
+    let cache = caches[idx].as_mut().unwrap();
+    cache.add_slab(start);
+    Some(cache.pop().unwrap())
+}
+
+// 如果 ptr 位于某个对象缓存的 slab 中，将它放回该缓存并返回 true
+// 每个缓存最多保留一个完全空闲的 slab，多出来的归还给物理页帧分配器
+pub fn slab_dealloc(ptr: *mut u8, layout: &Layout) -> bool {
+    let owner = frame_owner(ptr as usize);
+    if owner & SLAB_OWNER == 0 {
+        return false;
+    }
+    let mut caches = SLAB_CACHES.lock();
+    let cache = caches[(owner & !SLAB_OWNER) as usize].as_mut().unwrap();
+    debug_assert!(cache.fits(layout));
+    if cache.push(ptr) && cache.empty_slabs() > 1 {
+        let slab = cache.slab_of(ptr);
+        let ppn = PhysAddr::from(slab).floor().0;
+        cache.unlink(slab);
+        // 在全局分配器内部只能尝试归还，物理页帧分配器暂时无法接收时保留这个 slab
+        if frame_dealloc_for_heap((ppn..ppn + cache.slab_pages).map(PhysPageNum)) {
+            cache.slabs -= 1;
+        } else {
+            cache.link(slab);
+        }
+    }
+    true
+}
+
+pub fn slab_stats() -> Vec<SlabStats> {
+    // 先在不持有锁的情况下分配好空间，避免持有锁时进入全局分配器
+    let mut stats = Vec::with_capacity(MAX_SLAB_CACHES);
+    stats.extend(SLAB_CACHES.lock().iter().flatten().map(|cache| cache.stats()));
+    stats
+}
+
+// 构造钩子: 将对象所在的内存清零
+pub fn slab_zero_ctor(obj: &mut [u8]) {
+    for byte in obj.iter_mut() {
+        *byte = 0;
+    }
+}
@@ -0,0 +1,37 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::timer::get_time;
+use crate::fdt::machine_info;
+
+// 内核熵池
+// 启动时混入设备树中由固件或 QEMU 提供的随机种子，之后每次时钟中断都混入此时读到的时间计数器：
+// 中断实际被处理的时刻受到指令执行、缓存和设备的影响而存在抖动，时间计数器的低位因此难以预测
+// 内核目前只有一个处理器核，熵池的读改写不需要是原子的
+static POOL: AtomicUsize = AtomicUsize::new(0x9e37_79b9_7f4a_7c15);
+// 每取出一个随机数加一，保证熵池没有新的输入时连续取出的值也不相同
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// splitmix64 的混合函数，输入的每一位都会影响输出的每一位
+fn mix(mut x: usize) -> usize {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+pub fn add_entropy(value: usize) {
+    let pool = POOL.load(Ordering::Relaxed);
+    POOL.store(mix(pool.rotate_left(7) ^ value), Ordering::Relaxed);
+}
+
+// 在解析设备树之后调用
+pub fn init() {
+    add_entropy(machine_info().rng_seed);
+    add_entropy(get_time());
+}
+
+// 不能用于密码学用途，仅用于地址空间布局随机化等场合
+pub fn random_usize() -> usize {
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let value = mix(POOL.load(Ordering::Relaxed) ^ get_time() ^ n.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    add_entropy(value);
+    value
+}
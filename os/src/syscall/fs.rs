@@ -1,27 +1,39 @@
 use crate::mm::{
     UserBuffer,
-    translated_byte_buffer,
-    translated_refmut,
-    virtual_addr_range_printable,
-    virtual_addr_range_writable,
-    virtual_addr_printable,
-    virtual_addr_writable,
-    translated_str,
-    translated_virtual_ptr
+    translated_user_buffer,
+    translated_user_buffer_mut,
+    copy_to_user,
+    copy_str_from_user,
 };
 use crate::task::{current_user_token, current_task_id, current_task, set_task_mail};
-use crate::fs::{make_pipe, OpenFlags, open_file, link, unlink, OSInode};
+use crate::fs::{make_pipe, File, OpenFlags, open_file, open_path, link, unlink, mkdir, is_dir, normalize};
+use crate::fs::{symlink, read_link, rename, RenameFlags, chmod, chown, lookup_dir, Credentials};
+use easy_fs::{BLOCK_SZ, DiskInodeType};
 use alloc::sync::Arc;
 use alloc::string::String;
 
+// 与 Linux 在 RISC-V 64 上的 struct stat 布局相同
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Stat {
     pub dev: u64, // ID of device containing file, 文件所在磁盘驱动器号, 暂时不考虑
     pub ino: u64, // inode number, inode 文件所在 inode 编号
     pub mode: StatMode, // file type and mode, 文件类型
     pub nlink: u32, // number of hard links, 硬链接数量，初始为1
-    pad: [u64; 7], // unused pad, 无需考虑，为了兼容性设计
+    pub uid: u32, // user ID of owner, 所有者的用户 ID
+    pub gid: u32, // group ID of owner, 所属组的 ID
+    pad0: [u64; 2], // rdev 以及对齐, 暂不支持
+    pub size: u64, // total size in bytes, 文件内容的字节数
+    pub blksize: u32, // block size for filesystem I/O, 文件系统的块大小
+    pad1: u32,
+    pub blocks: u64, // number of 512B blocks allocated, 包括索引块在内占用的 512 字节块数
+    pub atime: u64, // time of last access, 以下都是自 1970-01-01 00:00:00 UTC 以来的秒数和纳秒数
+    pub atime_nsec: u64,
+    pub mtime: u64, // time of last modification
+    pub mtime_nsec: u64,
+    pub ctime: u64, // time of last status change
+    pub ctime_nsec: u64,
+    pad2: [u32; 2],
 }
 
 impl Stat {
@@ -31,9 +43,44 @@ impl Stat {
             ino: 0,
             mode: StatMode::NULL,
             nlink: 1,
-            pad: [0; 7],
+            uid: 0,
+            gid: 0,
+            pad0: [0; 2],
+            size: 0,
+            blksize: BLOCK_SZ as u32,
+            pad1: 0,
+            blocks: 0,
+            atime: 0,
+            atime_nsec: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            ctime: 0,
+            ctime_nsec: 0,
+            pad2: [0; 2],
         }
     }
+    // 根据一个已打开的文件填写，磁盘上的时间戳只精确到秒
+    fn from_file(file: &dyn File) -> Self {
+        let times = file.times();
+        let owner = file.owner();
+        let mut stat = Stat::new();
+        stat.ino = file.inode_id() as u64;
+        let type_ = match file.inode_type() {
+            DiskInodeType::File => StatMode::FILE,
+            DiskInodeType::Directory => StatMode::DIR,
+            DiskInodeType::Symlink => StatMode::LINK,
+        };
+        stat.mode = type_ | StatMode::from_bits_truncate(owner.mode);
+        stat.nlink = file.nlink() as u32;
+        stat.uid = owner.uid;
+        stat.gid = owner.gid;
+        stat.size = file.size() as u64;
+        stat.blocks = (file.blocks() * BLOCK_SZ / 512) as u64;
+        stat.atime = times.atime as u64;
+        stat.mtime = times.mtime as u64;
+        stat.ctime = times.ctime as u64;
+        stat
+    }
 }
 
 bitflags! {
@@ -41,9 +88,17 @@ bitflags! {
         const NULL  = 0;
         const DIR   = 0o040000; // directory
         const FILE  = 0o100000; // ordinary regular file
+        const LINK  = 0o120000; // symbolic link
+        const FMT   = 0o170000; // 文件类型所在的位
+        const PERM  = 0o007777; // 访问权限所在的位
     }
 }
 
+// 当前进程的用户 ID 和组 ID，用于文件访问权限检查
+fn current_cred() -> Credentials {
+    current_task().unwrap().acquire_inner_lock().cred
+}
+
 // 由于内核和应用地址空间的隔离， sys_write 不再能够直接访问位于应用空间中的数据，而需要手动查页表才能知道那些 数据被放置在哪些物理页帧上并进行访问
 // 安全检查：sys_write 仅能输出位于程序本身内存空间内的数据，否则报错
 // write: 将缓冲区中的数据写入文件，最多将缓冲区中的数据全部写入，并返回直接写入的字节数
@@ -64,15 +119,14 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
         let file = file.clone();
         // release Task lock manually to avoid deadlock
         drop(inner);
-        let (printable, start_pa, end_pa) = virtual_addr_range_printable(token, buf, len);
-        if !printable {
-            info!("[kernel] buffer overflow in APP {}, in sys_write! v_addr=[{:#x}, {:#x}), p_addr=[{:#x}, {:#x})", current_task_id(), buf as usize, buf as usize + len, start_pa, end_pa);
-            return -1 as isize;
-        }
-        let buffers = translated_byte_buffer(token, buf, len);
-        file.write(
-            UserBuffer::new(buffers)
-        ) as isize
+        let buffer = match translated_user_buffer(token, buf, len) {
+            Some(buffer) => buffer,
+            None => {
+                info!("[kernel] bad buffer in APP {}, in sys_write! v_addr={:#x}, len={:#x}", current_task_id(), buf as usize, len);
+                return -1 as isize;
+            }
+        };
+        file.write(buffer) as isize
     } else {
         if fd == 1 || fd == 2 { 
             0 
@@ -90,7 +144,7 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     if fd >= inner.fd_table.len() {
         return -1;
     }
-    if virtual_addr_range_writable(token, buf, len) {
+    if let Some(buffer) = translated_user_buffer_mut(token, buf as *mut u8, len) {
         if let Some(file) = &inner.fd_table[fd] {
             if !file.readable() {
                 return -1;
@@ -98,10 +152,7 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
             let file = file.clone();
             // release Task lock manually to avoid deadlock
             drop(inner);
-            let ret = file.read(
-                UserBuffer::new(translated_byte_buffer(token, buf, len))
-            ) as isize;
-            ret
+            file.read(buffer) as isize
         } else {
             -1
         }
@@ -110,47 +161,58 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     }    
 }
 
-pub fn valid_file_name(path: &String) -> bool {
-    if path.len() == 0 {
-        return false;
-    }
-    // not include ! " #  $ % & ' ( ) * + - , - . /
-    if (path.as_bytes()[0] >= 32 && path.as_bytes()[0] <= 47) || path.as_bytes()[0] > 122 {
-        return false;
-    }
-    for ch in path.bytes() { 
-        // 不包含 '/' '\'
-        if ch < 32 || ch > 126 || ch == 47 || ch == 92 {
-            return false;
+pub const AT_FDCWD: isize = -100;
+pub const AT_REMOVEDIR: u32 = 0x200;
+pub const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+
+// 将用户给出的路径解析为规范化的绝对路径
+// 绝对路径直接使用；相对路径在 dirfd 为 AT_FDCWD 时相对于当前工作目录，否则相对于 dirfd 所指向的已打开目录
+// 可能的错误: dirfd 不是一个已打开的目录，路径中含有非法的文件名
+pub fn resolve_path(dirfd: isize, path: &str) -> Option<String> {
+    let task = current_task().unwrap();
+    let base = if path.starts_with('/') {
+        String::from("/")
+    } else if dirfd == AT_FDCWD {
+        task.acquire_inner_lock().cwd.clone()?
+    } else {
+        let inner = task.acquire_inner_lock();
+        let base = inner.fd_table.get(dirfd as usize)?.as_ref()?.path()?;
+        drop(inner);
+        if !is_dir(&base) {
+            return None;
         }
-    }
-    return true;
+        base
+    };
+    normalize(&base, path)
+}
+
+// 从应用地址空间中取出路径并解析
+fn user_path(dirfd: isize, path: *const u8) -> Option<String> {
+    let path = copy_str_from_user(current_user_token(), path)?;
+    resolve_path(dirfd, &path)
 }
 
-/// 功能：打开一个标准文件，并返回可以访问它的文件描述符
-// _dirfd: 仅为了兼容性考虑，本次实验中始终为 AT_FDCWD (-100)。可以忽略。
-// path: 描述要打开的文件的文件名
+/// 功能：打开一个标准文件或目录，并返回可以访问它的文件描述符
+// dirfd: 相对路径的起点，AT_FDCWD (-100) 表示当前工作目录
+// path: 描述要打开的文件的路径
 // flags: 描述打开文件的标志
-// mode: 仅在创建文件时有用，表示传建文件的访问权限，为了简单，本次实验中可以忽略
-pub fn sys_openat(_dirfd: usize, path: *const u8, flags: u32, _mode: u32) -> isize {
+// mode: 仅在创建文件时有用，表示新文件的访问权限，新文件的所有者是当前进程的用户和组
+pub fn sys_openat(dirfd: isize, path: *const u8, flags: u32, mode: u32) -> isize {
     // 有 create 标志但文件存在时，忽略 create 标志，直接打开文件
     // 如果出现了错误则返回 -1，否则返回可以访问给定文件的文件描述符
     // 可能的错误:
     // 1. 文件不存在且无 create 标志
     // 2. 标志非法（低两位为 0x3）
     // 3. 打开文件数量达到上限
+    // 4. 路径非法，或者以可写方式打开目录
+    // 5. 没有相应的访问权限
     let task = current_task().unwrap();
-    let token = current_user_token();
-    let (readable, _path) = virtual_addr_printable(token, path as usize);
-    if readable {
-        let path = translated_str(token, path);
-        if !valid_file_name(&path) {
-            return -1;
-        }
-        if let Some(inode) = open_file(
-            path.as_str(),
-            OpenFlags::from_bits(flags).unwrap()
-        ) {
+    if let Some(path) = user_path(dirfd, path) {
+        let flags = match OpenFlags::from_bits(flags) {
+            Some(flags) => flags,
+            None => return -1,
+        };
+        if let Some(inode) = open_file(path.as_str(), flags, mode & StatMode::PERM.bits(), &current_cred()) {
             let mut inner = task.acquire_inner_lock();
             let fd = inner.alloc_fd();
             inner.fd_table[fd] = Some(inode);
@@ -194,7 +256,8 @@ pub fn sys_close(fd: usize) -> isize {
 pub fn sys_pipe(pipe: *mut usize) -> isize {
     let task = current_task().unwrap();
     let token = current_user_token();
-    if !virtual_addr_writable(token, pipe as usize) {
+    // 先检查整个数组都可以写入，避免创建了管道之后才发现地址非法
+    if translated_user_buffer_mut(token, pipe as *mut u8, 2 * core::mem::size_of::<usize>()).is_none() {
         return -1;
     }
     let mut inner = task.acquire_inner_lock();
@@ -206,8 +269,9 @@ pub fn sys_pipe(pipe: *mut usize) -> isize {
     inner.fd_table[write_fd] = Some(pipe_write);
     drop(inner);
     // 读端和写端的文件描述符 写回到应用地址空间
-    *translated_refmut(token, pipe) = read_fd;
-    *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
+    if !copy_to_user(token, pipe as *mut [usize; 2], &[read_fd, write_fd]) {
+        return -1;
+    }
     0
 }
 
@@ -254,16 +318,15 @@ pub fn sys_mail_read(buffer: *mut u8, len: usize) -> isize {
     // len = 0，则不进行读取. 如果没有报文可读取，返回-1，否则返回0(len=0).
     // 邮箱空 或 buf无效: 返回-1
     // buf无效:
+    // 报文会被写入 buf，因此 buf 必须可写
     let token = current_user_token();
-    let (printable, _start_pa, _end_pa) = virtual_addr_range_printable(token, buffer, len);
-    if !printable {
-        return -1 as isize;
-    }
+    let buffer = match translated_user_buffer_mut(token, buffer, len) {
+        Some(buffer) => buffer,
+        None => return -1 as isize,
+    };
     let task = current_task().unwrap();
     let mut inner = task.acquire_inner_lock();
-    inner.mail_box.read(
-        UserBuffer::new(translated_byte_buffer(token, buffer, len))
-    ) as isize
+    inner.mail_box.read(buffer) as isize
 }
 
 // 向对应进程邮箱插入一条报文
@@ -273,13 +336,13 @@ pub fn sys_mail_write(pid: usize, buffer: *mut u8, len: usize) -> isize {
     // len = 0，则不进行写入，如果邮箱满，返回-1，否则返回0，这是用来测试是否可以发报
     // 可以向自己的邮箱写入报文
     // 邮箱满 或 buf无效: 返回-1
+    // 报文从 buf 中读出，因此 buf 只需要可读
     let token = current_user_token();
-    let writable = virtual_addr_range_writable(token, buffer, len);
-    if !writable {
-        return -1 as isize;
-    }
+    let buffer: UserBuffer = match translated_user_buffer(token, buffer, len) {
+        Some(buffer) => buffer,
+        None => return -1 as isize,
+    };
     // 根据pid查找进程, 得到inner
-    let buffer: UserBuffer = UserBuffer::new(translated_byte_buffer(token, buffer, len));
     if pid != current_task_id() {
         set_task_mail(pid, buffer)
     } else {
@@ -291,45 +354,51 @@ pub fn sys_mail_write(pid: usize, buffer: *mut u8, len: usize) -> isize {
 
 // 创建一个文件的一个硬链接
 // 硬链接的核心: 多个文件名指向同一个inode
-// olddirfd，newdirfd: 仅为了兼容性考虑，本次实验中始终为 AT_FDCWD (-100)，可以忽略
+// olddirfd，newdirfd: 分别是两个相对路径的起点，AT_FDCWD (-100) 表示当前工作目录
 // flags: 仅为了兼容性考虑，本次实验中始终为 0，可以忽略
 // oldpath：原有文件路径
 // newpath: 新的链接文件路径
-// 为了方便，不考虑新文件路径已经存在的情况（属于未定义行为），除非链接同名文件
 // 返回值: 果出现了错误则返回 -1，否则返回 0
-// 可能的错误: 链接同名文件
-pub fn sys_linkat(_olddirfd: i32, oldpath: *const u8, _newdirfd: i32, newpath: *const u8, _flags: u32) -> isize {
-    let token = current_user_token();
-    let (old_path_readable, _old_path) = virtual_addr_printable(token, oldpath as usize);
-    let (new_path_readable, _new_path) = virtual_addr_printable(token, newpath as usize);
-    if (old_path_readable && new_path_readable) {
-        let new_path = translated_str(token, newpath);
-        let old_path = translated_str(token, oldpath);
-        if !valid_file_name(&new_path) || !valid_file_name(&old_path) {
-            return -1;
-        }
-        link(&old_path, &new_path)
+// 可能的错误: 原有文件不存在或者是目录，新路径已经存在（包括链接同名文件），新路径所在的目录不存在
+pub fn sys_linkat(olddirfd: isize, oldpath: *const u8, newdirfd: isize, newpath: *const u8, _flags: u32) -> isize {
+    if let (Some(old_path), Some(new_path)) = (user_path(olddirfd, oldpath), user_path(newdirfd, newpath)) {
+        link(&old_path, &new_path, &current_cred())
+    } else {
+        -1
+    }
+}
+
+// 将一个文件或目录改名，可以移动到另一个目录中
+// olddirfd，newdirfd: 分别是两个相对路径的起点，AT_FDCWD (-100) 表示当前工作目录
+// flags: RENAME_NOREPLACE (1) 时新路径已经存在则失败，RENAME_EXCHANGE (2) 时原子地交换两个已经存在的路径
+// 没有标志时新路径如果已经存在则被原子地替换，目录只能替换空目录
+// 返回值：如果出现了错误则返回 -1，否则返回 0
+// 可能的错误: 原有路径不存在，标志非法，目录被移动到它自身之下，新路径的类型不匹配或者是非空目录，没有所在目录的写权限
+pub fn sys_renameat2(olddirfd: isize, oldpath: *const u8, newdirfd: isize, newpath: *const u8, flags: u32) -> isize {
+    let flags = match RenameFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return -1,
+    };
+    if let (Some(old_path), Some(new_path)) = (user_path(olddirfd, oldpath), user_path(newdirfd, newpath)) {
+        rename(&old_path, &new_path, flags, &current_cred())
     } else {
         -1
     }
 }
 
 // 取消一个文件路径到文件的链接
-// dirfd: 仅为了兼容性考虑，本次实验中始终为 AT_FDCWD (-100)，可以忽略
-// flags: 仅为了兼容性考虑，本次实验中始终为 0，可以忽略
+// dirfd: 相对路径的起点，AT_FDCWD (-100) 表示当前工作目录
+// flags: 暂不支持删除目录，带有 AT_REMOVEDIR 时总是失败
 // path：文件路径
-// 为了方便，不考虑使用 unlink 彻底删除文件的情况
+// 删除路径对应的目录项，文件的最后一个硬链接被删除后释放它的 inode 和数据块，仍被打开时推迟到最后一次关闭之后
 // 返回值：如果出现了错误则返回 -1，否则返回 0。
-// 可能的错误: 文件不存在
-pub fn sys_unlinkat(_dirfd: i32, path: *const u8, _flags: u32) -> isize {
-    let token = current_user_token();
-    let (path_readable, _path) = virtual_addr_printable(token, path as usize);
-    if path_readable {
-        let path_str = translated_str(token, path);
-        if !valid_file_name(&path_str) {
-            return -1;
-        }
-        unlink(&path_str)
+// 可能的错误: 文件不存在，路径指向一个目录
+pub fn sys_unlinkat(dirfd: isize, path: *const u8, flags: u32) -> isize {
+    if flags & AT_REMOVEDIR != 0 {
+        return -1;
+    }
+    if let Some(path) = user_path(dirfd, path) {
+        unlink(&path, &current_cred())
     } else {
         -1
     }    
@@ -344,26 +413,136 @@ pub fn sys_unlinkat(_dirfd: i32, path: *const u8, _flags: u32) -> isize {
 //  2. st 地址非法
 pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
     let token = current_user_token();
-    // check st address
-    if !virtual_addr_writable(token, st as usize) {
-        return -1 as isize;
-    }
     let task = current_task().unwrap();
     let inner = task.acquire_inner_lock();
     if fd >= inner.fd_table.len() {
         return -1;
     }
     if let Some(file) = &inner.fd_table[fd] {
-        unsafe {
-            let st_ptr = translated_virtual_ptr(token, st);
-            if let Some(pa_st) = st_ptr.as_mut() {
-                (*pa_st).ino = file.inode_id() as u64;
-                (*pa_st).mode = StatMode::FILE;
-                (*pa_st).nlink = file.nlink() as u32;
-            }
-        }
-        0
+        let stat = Stat::from_file(file.as_ref());
+        // st 地址非法时返回 -1
+        if copy_to_user(token, st, &stat) { 0 } else { -1 }
     } else {
         -1
     }
 }
+
+// 根据路径获取文件状态，不需要先打开文件
+// dirfd: 相对路径的起点，AT_FDCWD (-100) 表示当前工作目录
+// flags: 带有 AT_SYMLINK_NOFOLLOW 时路径的最后一个分量是符号链接则获取符号链接本身的状态
+// 不需要文件本身的读权限，只需要路径上各个目录的搜索权限
+// 如果出现了错误则返回 -1，否则返回 0
+// 可能的错误: 路径不存在或者非法，st 地址非法
+pub fn sys_fstatat(dirfd: isize, path: *const u8, st: *mut Stat, flags: u32) -> isize {
+    let follow = flags & AT_SYMLINK_NOFOLLOW == 0;
+    let file = match user_path(dirfd, path).and_then(|path| open_path(&path, &current_cred(), follow)) {
+        Some(file) => file,
+        None => return -1,
+    };
+    let stat = Stat::from_file(file.as_ref());
+    if copy_to_user(current_user_token(), st, &stat) { 0 } else { -1 }
+}
+
+// 创建一个空目录
+// dirfd: 相对路径的起点，AT_FDCWD (-100) 表示当前工作目录
+// mode: 新目录的访问权限，所有者是当前进程的用户和组
+// 返回值：如果出现了错误则返回 -1，否则返回 0
+// 可能的错误: 路径已经存在，父目录不存在，路径非法，没有父目录的写权限
+pub fn sys_mkdirat(dirfd: isize, path: *const u8, mode: u32) -> isize {
+    match user_path(dirfd, path) {
+        Some(path) => mkdir(&path, mode & StatMode::PERM.bits(), &current_cred()),
+        None => -1,
+    }
+}
+
+// 创建一个指向 target 的符号链接
+// target: 原样保存在符号链接中，可以是相对于符号链接所在目录的相对路径，不要求它存在
+// newdirfd: linkpath 为相对路径时的起点，AT_FDCWD (-100) 表示当前工作目录
+// 返回值：如果出现了错误则返回 -1，否则返回 0
+// 可能的错误: target 为空或者过长，linkpath 已经存在，父目录不存在或者没有写权限
+pub fn sys_symlinkat(target: *const u8, newdirfd: isize, linkpath: *const u8) -> isize {
+    let target = match copy_str_from_user(current_user_token(), target) {
+        Some(target) => target,
+        None => return -1,
+    };
+    match user_path(newdirfd, linkpath) {
+        Some(path) => symlink(&target, &path, &current_cred()),
+        None => -1,
+    }
+}
+
+// 读出符号链接中保存的路径，写入 buf 的内容不以 '\0' 结尾，超出 len 的部分被截断
+// 返回值：成功时返回写入 buf 的字节数，出现错误时返回 -1
+// 可能的错误: 路径不存在或者不是符号链接，buf 地址非法
+pub fn sys_readlinkat(dirfd: isize, path: *const u8, buf: *mut u8, len: usize) -> isize {
+    let target = match user_path(dirfd, path).and_then(|path| read_link(&path, &current_cred())) {
+        Some(target) => target,
+        None => return -1,
+    };
+    let len = len.min(target.len());
+    let buffer = match translated_user_buffer_mut(current_user_token(), buf, len) {
+        Some(buffer) => buffer,
+        None => return -1,
+    };
+    for (dst, &byte) in buffer.into_iter().zip(target.as_bytes()) {
+        unsafe { *dst = byte; }
+    }
+    len as isize
+}
+
+// 修改文件或目录的访问权限
+// dirfd: 相对路径的起点，AT_FDCWD (-100) 表示当前工作目录
+// 可能的错误: 路径不存在或者非法，当前进程既不是所有者也不是 root
+pub fn sys_fchmodat(dirfd: isize, path: *const u8, mode: u32) -> isize {
+    match user_path(dirfd, path) {
+        Some(path) => chmod(&path, mode & StatMode::PERM.bits(), &current_cred()),
+        None => -1,
+    }
+}
+
+// 修改文件或目录的所有者和所属组
+// uid, gid: 为 u32::MAX (也就是 -1) 时保持不变
+// flags: 暂不支持，忽略
+// 可能的错误: 路径不存在或者非法，当前进程不是 root
+pub fn sys_fchownat(dirfd: isize, path: *const u8, uid: u32, gid: u32, _flags: u32) -> isize {
+    let uid = if uid == u32::MAX { None } else { Some(uid) };
+    let gid = if gid == u32::MAX { None } else { Some(gid) };
+    match user_path(dirfd, path) {
+        Some(path) => chown(&path, uid, gid, &current_cred()),
+        None => -1,
+    }
+}
+
+// 切换当前工作目录
+// 新的当前工作目录是展开符号链接之后的路径
+// 可能的错误: 路径不存在或者不是目录，没有这个目录的搜索权限
+pub fn sys_chdir(path: *const u8) -> isize {
+    let path = match user_path(AT_FDCWD, path).and_then(|path| lookup_dir(&path, &current_cred())) {
+        Some(path) => path,
+        None => return -1,
+    };
+    current_task().unwrap().acquire_inner_lock().cwd = Some(path);
+    0
+}
+
+// 将当前工作目录的绝对路径连同结尾的 '\0' 写入 buf
+// 返回值：成功时返回写入的字节数，缓冲区长度不够或者地址非法时返回 -1
+pub fn sys_getcwd(buf: *mut u8, len: usize) -> isize {
+    let token = current_user_token();
+    let mut cwd = match current_task().unwrap().acquire_inner_lock().cwd.clone() {
+        Some(cwd) => cwd,
+        None => return -1,
+    };
+    cwd.push('\0');
+    if cwd.len() > len {
+        return -1;
+    }
+    let buffer = match translated_user_buffer_mut(token, buf, cwd.len()) {
+        Some(buffer) => buffer,
+        None => return -1,
+    };
+    for (dst, &byte) in buffer.into_iter().zip(cwd.as_bytes()) {
+        unsafe { *dst = byte; }
+    }
+    cwd.len() as isize
+}
@@ -5,23 +5,33 @@ use crate::task::{
     set_task_priority,
     map_virtual_pages,
     unmap_virtual_pages,
+    protect_virtual_pages,
+    shm_get,
+    shm_attach,
+    shm_detach,
+    shm_control,
     current_task,
     current_user_token,
     add_task,
-    INITPROC,
+    reserve_frames,
+    oom_reclaim,
+    ADDR_NO_RANDOMIZE,
 };
 use crate::timer::{get_time_sys, TimeVal};
+use crate::config::{USER_STACK_SIZE, USER_STACK_LIMIT_MAX};
 use crate::mm::{
-    translated_str,
-    translated_refmut,
-    translated_ref,
-    virtual_addr_writable,
-    usable_frames
+    copy_from_user,
+    copy_to_user,
+    copy_str_from_user,
+    heap_stats,
+    slab_stats,
+    HeapStats,
+    SlabStats,
 };
 use crate::fs::{
-    open_file,
-    OpenFlags,
+    read_exec_file,
 };
+use super::fs::{resolve_path, AT_FDCWD};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use alloc::string::String;
@@ -50,6 +60,84 @@ pub fn sys_set_priority(priority: isize) -> isize {
     set_task_priority(priority)
 }
 
+// 调试用: 将内核堆的使用情况 (总大小、已分配、峰值、失败次数) 写入 stats 指向的结构体
+// 错误返回 -1: stats 不可写
+pub fn sys_heap_stats(stats: *mut HeapStats) -> isize {
+    if copy_to_user(current_user_token(), stats, &heap_stats()) { 0 } else { -1 }
+}
+
+// 调试用: 将至多 len 个对象缓存的使用情况写入 stats 指向的数组，返回对象缓存的总数
+// 错误返回 -1: stats 不可写
+pub fn sys_slab_stats(stats: *mut SlabStats, len: usize) -> isize {
+    let token = current_user_token();
+    let all = slab_stats();
+    for (i, cache) in all.iter().take(len).enumerate() {
+        if !copy_to_user(token, stats.wrapping_add(i), cache) {
+            return -1;
+        }
+    }
+    all.len() as isize
+}
+
+// 资源限制，目前只支持 RLIMIT_STACK: 用户栈最多可以增长到的字节数
+pub const RLIMIT_STACK: usize = 3;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RLimit {
+    pub rlim_cur: usize, // 软限制，即当前生效的值
+    pub rlim_max: usize, // 硬限制，软限制不能超过它
+}
+
+// 获取资源 resource 的限制并写入 rlim 指向的结构体
+// 错误返回 -1: 不支持的 resource、rlim 不可写
+pub fn sys_getrlimit(resource: usize, rlim: *mut RLimit) -> isize {
+    if resource != RLIMIT_STACK {
+        return -1;
+    }
+    let stack_limit = current_task().unwrap().acquire_inner_lock().stack_limit;
+    let value = RLimit {
+        rlim_cur: stack_limit,
+        rlim_max: USER_STACK_LIMIT_MAX,
+    };
+    if copy_to_user(current_user_token(), rlim, &value) { 0 } else { -1 }
+}
+
+// 设置资源 resource 的限制，新的用户栈大小在下一次 exec 时生效，并会被 fork 出的子进程继承
+// 错误返回 -1: 不支持的 resource、rlim 不可访问、rlim_cur > rlim_max、
+// rlim_max 超过 USER_STACK_LIMIT_MAX 或 rlim_cur 小于初始映射的用户栈大小
+pub fn sys_setrlimit(resource: usize, rlim: *const RLimit) -> isize {
+    if resource != RLIMIT_STACK {
+        return -1;
+    }
+    let rlim = match copy_from_user(current_user_token(), rlim) {
+        Some(rlim) => rlim,
+        None => return -1,
+    };
+    if rlim.rlim_cur > rlim.rlim_max || rlim.rlim_max > USER_STACK_LIMIT_MAX || rlim.rlim_cur < USER_STACK_SIZE {
+        return -1;
+    }
+    current_task().unwrap().acquire_inner_lock().stack_limit = rlim.rlim_cur;
+    0
+}
+
+// 设置进程的执行域标志并返回原来的标志，persona 为 0xffffffff 时只查询不修改
+// 目前只支持 ADDR_NO_RANDOMIZE，在下一次 exec 时生效，并会被 fork 出的子进程继承
+// 错误返回 -1: 包含不支持的标志
+pub fn sys_personality(persona: usize) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    let old = inner.personality;
+    if persona == 0xffff_ffff {
+        return old as isize;
+    }
+    if persona & !ADDR_NO_RANDOMIZE != 0 {
+        return -1;
+    }
+    inner.personality = persona;
+    old as isize
+}
+
 // 申请长度为 len 字节的物理内存
 // 并映射到 addr 开始的虚存，内存页属性为 port
 // addr 要求按页对齐(否则报错)，len 可直接按页上取整
@@ -79,21 +167,97 @@ pub fn sys_munmap(
     unmap_virtual_pages(start, len)
 }
 
+// 修改一块已映射虚存的访问权限，port 的含义与 sys_mmap 相同
+// 与区间部分重叠的逻辑段会在区间边界处被拆分
+// 正确时返回 0，错误返回 -1
+pub fn sys_mprotect(
+    start: usize,
+    len: usize,
+    port: usize,
+) -> isize {
+    // 失败的情况:
+    // 1. start 未按页对齐或 len 过大
+    // 2. port & !0x7 != 0 或 port & 0x7 = 0
+    // 3. [start, start + len) 中存在未被映射的虚存、用户态不可访问的页或共享内存页
+    protect_virtual_pages(start, len, port)
+}
+
+// 共享内存: 同一组物理页帧被映射到多个进程的地址空间中，读写时无需经过内核拷贝
+// 获取 key 对应的共享内存段 id，不存在时创建一个至少 size 字节的段
+// key 为 IPC_PRIVATE (0) 时总是创建新段
+// 错误返回 -1: size 为 0 或过大、已存在的段小于 size、物理内存不足
+pub fn sys_shmget(key: usize, size: usize) -> isize {
+    shm_get(key, size)
+}
+
+// 将共享内存段 id 挂载到 addr 处(按页对齐)，addr 为 0 时由内核选择地址
+// 成功返回挂载的起始地址，fork 出的子进程会继承所有挂载
+// 错误返回 -1: id 不存在、addr 未对齐、区间内存在已经被映射的页
+pub fn sys_shmat(id: usize, addr: usize) -> isize {
+    shm_attach(id, addr)
+}
+
+// 解除挂载在 addr 处的共享内存段，错误返回 -1
+pub fn sys_shmdt(addr: usize) -> isize {
+    shm_detach(addr)
+}
+
+// cmd 目前只支持 IPC_RMID (0)，移除之后段不能再被挂载，所有挂载解除之后物理页帧被回收
+pub fn sys_shmctl(id: usize, cmd: usize) -> isize {
+    shm_control(id, cmd)
+}
+
 // 返回当前进程的进程 ID。
 pub fn sys_getpid() -> isize {
     current_task().unwrap().pid.0 as isize
 }
 
+// 返回当前进程的用户 ID
+pub fn sys_getuid() -> isize {
+    current_task().unwrap().acquire_inner_lock().cred.uid as isize
+}
+
+// 返回当前进程的组 ID
+pub fn sys_getgid() -> isize {
+    current_task().unwrap().acquire_inner_lock().cred.gid as isize
+}
+
+// 设置当前进程的用户 ID，只有 root 可以切换到其他用户，切换之后无法再回到 root
+pub fn sys_setuid(uid: u32) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    if !inner.cred.is_root() && inner.cred.uid != uid {
+        return -1;
+    }
+    inner.cred.uid = uid;
+    0
+}
+
+// 设置当前进程的组 ID，同样只有 root 可以设置为其他的组
+pub fn sys_setgid(gid: u32) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    if !inner.cred.is_root() && inner.cred.gid != gid {
+        return -1;
+    }
+    inner.cred.gid = gid;
+    0
+}
+
 // 父进程的返回值为新创建进程的 PID ，而新创建进程的返回值为 0 
 // 每个进程可能有多个子进程，但最多只能有一个父进程
 pub fn sys_fork() -> isize {
     let current_task = current_task().unwrap();
     // println!("before alloc {}, remain {}", current_task.frames_used(), usable_frames());
-    if (usable_frames() < current_task.frames_used() + 100 + INITPROC.frames_used()) {
-        // return -1;
-        exit_current_and_run_next(-1);
+    // 为初始进程保留足够的物理页帧，物理内存不足时先通过 OOM killer 回收内存，仍然不足时 fork 失败返回 -1
+    if !reserve_frames(current_task.frames_used()) {
+        return -1;
+    }
+    let mut new_task = current_task.fork();
+    while new_task.is_none() && oom_reclaim() {
+        new_task = current_task.fork();
     }
-    if let Some(new_task) = current_task.fork() {
+    if let Some(new_task) = new_task {
         let new_pid = new_task.pid.0;
         // modify trap context of new_task, because it returns immediately after switching
         let trap_cx = new_task.acquire_inner_lock().get_trap_cx();
@@ -112,23 +276,36 @@ pub fn sys_fork() -> isize {
 // 我们在 sys_exec 所需的应用 ELF 数据就不再需要通过应用加载器从内核的数据段获取，而是从文件系统中获取即可
 pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
     let token = current_user_token();
-    let path = translated_str(token, path);
+    // 路径、参数数组以及其中的任何一个参数地址非法时返回 -1
+    // 相对路径相对于当前工作目录
+    let path = match copy_str_from_user(token, path).and_then(|path| resolve_path(AT_FDCWD, &path)) {
+        Some(path) => path,
+        None => return -1,
+    };
     let mut args_vec: Vec<String> = Vec::new();
     loop {
-        let arg_str_ptr = *translated_ref(token, args);
+        let arg_str_ptr = match copy_from_user(token, args) {
+            Some(arg_str_ptr) => arg_str_ptr,
+            None => return -1,
+        };
         if arg_str_ptr == 0 {
             break;
         }
-        args_vec.push(translated_str(token, arg_str_ptr as *const u8));
+        match copy_str_from_user(token, arg_str_ptr as *const u8) {
+            Some(arg) => args_vec.push(arg),
+            None => return -1,
+        }
         unsafe { args = args.add(1); }
     }
-    //  以只读的方式在内核中打开应用可执行文件并获取它的对应的 OSInode
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
-        // 通过 OSInode::read_all 将该文件的数据全部读到一个向量 all_data 中
-        let all_data = app_inode.read_all();
+    // 在内核中读出应用可执行文件的全部数据，当前进程需要对它有执行权限
+    let cred = current_task().unwrap().acquire_inner_lock().cred;
+    if let Some(all_data) = read_exec_file(path.as_str(), &cred) {
         let task = current_task().unwrap();
         let argc = args_vec.len();
-        task.exec(all_data.as_slice(), args_vec);
+        // 物理内存不足时 exec 失败，原来的地址空间保持不变
+        if task.exec(all_data.as_slice(), args_vec) < 0 {
+            return -1;
+        }
         // return argc because cx.x[10] will be covered with it later
         argc as isize
     } else {
@@ -136,6 +313,19 @@ pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
     }
 }
 
+// 将子进程的返回值写回 exit_code_ptr，地址为 0 时不必保存，地址非法时返回 false
+fn write_exit_code(token: usize, exit_code_ptr: *mut i32, exit_code: i32) -> bool {
+    if exit_code_ptr.is_null() {
+        return true;
+    }
+    if copy_to_user(token, exit_code_ptr, &exit_code) {
+        true
+    } else {
+        info!("[kernel] bad address in APP {}, in sys_waitpid! v_addr={:#x}", current_task_id(), exit_code_ptr as usize);
+        false
+    }
+}
+
 // 当前进程等待一个子进程结束，并获取其返回值
 /// If there is not a child process whose pid is same as given, return -1.
 /// Else if there is a child process but it is still running, return -2.
@@ -171,25 +361,20 @@ pub fn sys_waitpid_non_blocking(
             // ++++ release child PCB lock
         });
     if let Some((idx, _)) = pair {
+        // ++++ temporarily hold child lock
+        let exit_code = inner.children[idx].acquire_inner_lock().exit_code;
+        // ++++ release child PCB lock
+        // 先写回返回值，exit_code_ptr 不合法时子进程留在向量中，之后仍然可以被回收
+        if !write_exit_code(inner.memory_set.token(), exit_code_ptr, exit_code) {
+            return -1 as isize;
+        }
         // 将子进程从向量中移除并置于当前上下文中
         // 这是对于该子进程控制块的唯一一次强引用, 即它不会出现在某个进程的子进程向量中
         let child = inner.children.remove(idx);
         // confirm that child will be deallocated after removing from children list
         assert!(Arc::strong_count(&child) == 1 || Arc::strong_count(&child) == 2);
         // 收集的子进程信息返回回去
-        let found_pid = child.getpid();
-        // ++++ temporarily hold child lock
-        let exit_code = child.acquire_inner_lock().exit_code;
-        // ++++ release child PCB lock
-        // 判断 exit_code_ptr 是否合法
-        if virtual_addr_writable(inner.memory_set.token(), exit_code_ptr as usize) {
-            // 手动查页表找到应该写入到物理内存中的哪个位置
-            *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
-            return found_pid as isize;
-        } else {
-            info!("[kernel] buffer overflow in APP {}, in sys_waitpid! v_addr={:#x}", current_task_id(), exit_code_ptr as usize);
-            return -1 as isize;
-        }
+        child.getpid() as isize
     } else {
         -2
     }
@@ -232,21 +417,16 @@ pub fn sys_waitpid_blocking(
                 // ++++ release child PCB lock
             });
         if let Some((idx, _)) = pair {
-            let child = inner.children.remove(idx);
-            // confirm that child will be deallocated after removing from children list
-            assert_eq!(Arc::strong_count(&child), 1);
-            let found_pid = child.getpid();
             // ++++ temporarily hold child lock
-            let exit_code = child.acquire_inner_lock().exit_code;
+            let exit_code = inner.children[idx].acquire_inner_lock().exit_code;
             // ++++ release child PCB lock
-            // 判断 exit_code_ptr 是否合法
-            if virtual_addr_writable(inner.memory_set.token(), exit_code_ptr as usize) {
-                *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
-                return found_pid as isize;
-            } else {
-                info!("[kernel] buffer overflow in APP {}, in sys_waitpid! v_addr={:#x}", current_task_id(), exit_code_ptr as usize);
+            if !write_exit_code(inner.memory_set.token(), exit_code_ptr, exit_code) {
                 return -1 as isize;
             }
+            let child = inner.children.remove(idx);
+            // confirm that child will be deallocated after removing from children list
+            assert_eq!(Arc::strong_count(&child), 1);
+            return child.getpid() as isize;
         } else {
             // 阻塞方式实现
             drop(inner); // 注意释放互斥锁
@@ -266,10 +446,14 @@ pub fn sys_waitpid_blocking(
 //  2. 进程池满/内存不足等资源错误。(暂不考虑)
 pub fn sys_spawn(path: *const u8) -> isize {
     let token = current_user_token();
-    let path = translated_str(token, path);
+    // 相对路径相对于当前工作目录
+    let path = match copy_str_from_user(token, path).and_then(|path| resolve_path(AT_FDCWD, &path)) {
+        Some(path) => path,
+        None => return -1,
+    };
     let args_vec: Vec<String> = Vec::new();
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
-        let all_data = app_inode.read_all();
+    let cred = current_task().unwrap().acquire_inner_lock().cred;
+    if let Some(all_data) = read_exec_file(path.as_str(), &cred) {
         let current_task = current_task().unwrap();
         if let Some(new_task) = current_task.fork() {
             let new_pid = new_task.pid.0;
@@ -278,7 +462,11 @@ pub fn sys_spawn(path: *const u8) -> isize {
             // we do not have to move to next instruction since we have done it before
             // for child process, fork returns 0
             trap_cx.x[10] = 0;
-            new_task.exec(all_data.as_slice(), args_vec);
+            // exec 失败时子进程还没有被调度过，直接从父进程的孩子向量中移除即可回收
+            if new_task.exec(all_data.as_slice(), args_vec) < 0 {
+                current_task.acquire_inner_lock().children.retain(|child| !Arc::ptr_eq(child, &new_task));
+                return -1;
+            }
             add_task(new_task);
             new_pid as isize
         } else {
@@ -1,4 +1,4 @@
-use super::TaskControlBlock;
+use super::{TaskControlBlock, INITPROC};
 use alloc::collections::{VecDeque, BinaryHeap};
 use alloc::sync::Arc;
 use spin::Mutex;
@@ -28,6 +28,18 @@ impl TaskManager {
     pub fn running_num(&self) -> usize {
         self.ready_queue.len()
     }
+    // 就绪队列中占用物理页帧最多的进程，初始进程除外
+    pub fn largest(&self) -> Option<Arc<TaskControlBlock>> {
+        self.ready_queue.iter()
+            .filter(|task| task.getpid() != INITPROC.getpid())
+            .max_by_key(|task| task.frames_used())
+            .cloned()
+    }
+    // 将进程 pid 从就绪队列中移除
+    pub fn remove(&mut self, pid: usize) -> Option<Arc<TaskControlBlock>> {
+        let idx = self.ready_queue.iter().position(|task| task.getpid() == pid)?;
+        self.ready_queue.remove(idx)
+    }
     pub fn set_task_mail(&mut self, pid: usize, buffer: UserBuffer) -> isize {
         let pair = self.ready_queue.iter_mut().enumerate().find(
             |(_, p)| {
@@ -64,6 +76,22 @@ impl StrideTaskManager {
     pub fn running_num(&self) -> usize {
         self.ready_queue.len()
     }
+    pub fn largest(&self) -> Option<Arc<TaskControlBlock>> {
+        self.ready_queue.iter()
+            .map(|task| &task.0)
+            .filter(|task| task.getpid() != INITPROC.getpid())
+            .max_by_key(|task| task.frames_used())
+            .cloned()
+    }
+    // BinaryHeap 不支持删除任意元素，只能取出全部元素之后重新建堆
+    pub fn remove(&mut self, pid: usize) -> Option<Arc<TaskControlBlock>> {
+        let mut tasks = core::mem::take(&mut self.ready_queue).into_vec();
+        let removed = tasks.iter()
+            .position(|task| task.0.getpid() == pid)
+            .map(|idx| tasks.swap_remove(idx).0);
+        self.ready_queue = BinaryHeap::from(tasks);
+        removed
+    }
     pub fn set_task_mail(&mut self, pid: usize, buffer: UserBuffer) -> isize {
         let pair = self.ready_queue.iter().enumerate().find(
             |(_, p)| {
@@ -98,3 +126,11 @@ pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
 pub fn running_task_num() -> usize {
     TASK_MANAGER.lock().running_num()
 }
+
+pub fn largest_task() -> Option<Arc<TaskControlBlock>> {
+    TASK_MANAGER.lock().largest()
+}
+
+pub fn remove_task(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    TASK_MANAGER.lock().remove(pid)
+}
@@ -5,13 +5,17 @@ mod manager;
 mod processor;
 mod pid;
 
-use crate::fs::{open_file, OpenFlags};
+use crate::fs::{read_exec_file, Credentials};
 use switch::__switch;
 use task::{TaskControlBlock, TaskStatus};
 use alloc::sync::Arc;
-use manager::fetch_task;
+use alloc::string::String;
+use alloc::vec::Vec;
+use manager::{fetch_task, largest_task, remove_task};
 use lazy_static::*;
-use crate::mm::{MapPermission, MapType, MapArea, VPNRange, VirtAddr, usable_frames};
+use crate::mm::{MapPermission, MapType, MapArea, VPNRange, VirtAddr, VirtPageNum, usable_frames};
+use crate::mm::{SHM_MANAGER, IPC_RMID, register_arc_cache, slab_arc};
+use crate::mm::{check_permission, check_user_range};
 use crate::config::PAGE_SIZE;
 
 pub use context::TaskContext;
@@ -19,6 +23,7 @@ pub use processor::{
     run_tasks,
     current_task,
     current_user_token,
+    current_user_satp,
     current_trap_cx,
     take_current_task,
     current_task_id,
@@ -27,6 +32,12 @@ pub use processor::{
 };
 pub use manager::{add_task, running_task_num, set_task_mail};
 pub use pid::{PidHandle, pid_alloc, KernelStack};
+pub use task::ADDR_NO_RANDOMIZE;
+
+// fork 频繁地创建和回收进程控制块，为它们注册一个对象缓存
+pub fn init() {
+    register_arc_cache::<TaskControlBlock>("task_control_block", None);
+}
 
 // 暂停当前任务并切换到下一个任务
 // 注意，当仅有一个任务的时候， suspend_current_and_run_next 的效果是会继续执行这个任务
@@ -53,6 +64,19 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     // 将当前进程控制块从处理器监控 PROCESSOR 中取出而不是得到一份拷贝
     // 为了正确维护进程控制块的引用计数
     let task = take_current_task().unwrap();
+    exit_task(&task, exit_code);
+    // drop task manually to maintain rc correctly
+    drop(task);
+    // we do not have to save task context
+    let _unused: usize = 0;
+    // println!("unused physical frames: {}", usable_frames());
+    // 我们再也不会回到该进程的执行过程中，因此无需关心任务上下文的保存
+    schedule(&_unused as *const _);
+}
+
+// 将 task 标记为僵尸进程，把它的子进程交给初始进程并回收它的地址空间
+// 调用者需要保证 task 已经不在处理器和就绪队列中
+fn exit_task(task: &Arc<TaskControlBlock>, exit_code: i32) {
     // **** hold current PCB lock
     let mut inner = task.acquire_inner_lock();
     // Change status to Zombie
@@ -79,20 +103,74 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     inner.memory_set.recycle_data_pages();
     drop(inner);
     // **** release current PCB lock
-    // drop task manually to maintain rc correctly
-    drop(task);
-    // we do not have to save task context
-    let _unused: usize = 0;
-    // println!("unused physical frames: {}", usable_frames());
-    // 我们再也不会回到该进程的执行过程中，因此无需关心任务上下文的保存
-    schedule(&_unused as *const _);
+}
+
+// 在当前进程和就绪队列中选出占用物理页帧最多的进程，初始进程不会被选中
+fn oom_victim(current: &Arc<TaskControlBlock>) -> Option<Arc<TaskControlBlock>> {
+    let victim = largest_task();
+    if current.getpid() != INITPROC.getpid()
+        && victim.as_ref().map_or(true, |task| task.frames_used() <= current.frames_used()) {
+        return Some(current.clone());
+    }
+    victim
+}
+
+// OOM killer: 无法通过返回错误来处理物理内存不足时 (例如缺页时扩展用户栈)
+// 选出占用物理页帧最多的进程并杀死它，如果被杀死的是当前进程则不会返回
+pub fn oom_kill() {
+    let current = current_task().unwrap();
+    let victim = oom_victim(&current).expect("[kernel] Out of memory and no application can be killed!");
+    println!(
+        "[kernel] Out of memory: killed Application {} ({} frames used).",
+        victim.getpid(),
+        victim.frames_used(),
+    );
+    if Arc::ptr_eq(&victim, &current) {
+        drop(victim);
+        drop(current);
+        exit_current_and_run_next(-4);
+    } else if let Some(task) = remove_task(victim.getpid()) {
+        exit_task(&task, -4);
+    }
+}
+
+// 系统调用分配物理页帧失败时调用的 OOM killer: 占用物理页帧最多的是其他进程时杀死它并返回 true，调用者随后重试分配
+// 占用最多的是当前进程时不杀死任何进程并返回 false，由系统调用向当前进程返回错误
+// 调用者不能持有任何进程控制块的锁
+pub fn oom_reclaim() -> bool {
+    let current = current_task().unwrap();
+    let victim = match oom_victim(&current) {
+        Some(victim) if !Arc::ptr_eq(&victim, &current) => victim,
+        _ => return false,
+    };
+    let task = match remove_task(victim.getpid()) {
+        Some(task) => task,
+        None => return false,
+    };
+    println!(
+        "[kernel] Out of memory: killed Application {} ({} frames used).",
+        task.getpid(),
+        task.frames_used(),
+    );
+    exit_task(&task, -4);
+    true
+}
+
+// 保证至少还有 frames 个可用物理页帧，同时为初始进程保留足够的物理页帧
+// 不足时通过 OOM killer 回收，仍然不足时返回 false
+pub fn reserve_frames(frames: usize) -> bool {
+    while usable_frames() < frames + 100 + INITPROC.frames_used() {
+        if !oom_reclaim() {
+            return false;
+        }
+    }
+    true
 }
 
 // 将初始进程 initproc 加入任务管理器
 lazy_static! {
-    pub static ref INITPROC: Arc<TaskControlBlock> = Arc::new({
-        let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
-        let v = inode.read_all();
+    pub static ref INITPROC: Arc<TaskControlBlock> = slab_arc({
+        let v = read_exec_file("/initproc", &Credentials::ROOT).unwrap();
         TaskControlBlock::new(v.as_slice())
     });
 }
@@ -101,6 +179,18 @@ pub fn add_initproc() {
     add_task(INITPROC.clone());
 }
 
+// 依次修改每个进程的当前工作目录，用于在改名之后更新它们
+// 所有进程都是 initproc 的后代，从 initproc 出发沿着 children 遍历即可找到全部进程
+pub fn for_each_cwd(mut f: impl FnMut(&mut Option<String>)) {
+    let mut tasks: Vec<Arc<TaskControlBlock>> = Vec::new();
+    tasks.push(INITPROC.clone());
+    while let Some(task) = tasks.pop() {
+        let mut inner = task.acquire_inner_lock();
+        f(&mut inner.cwd);
+        tasks.extend(inner.children.iter().cloned());
+    }
+}
+
 pub fn map_virtual_pages(addr: usize, len: usize, port: usize) -> isize {
     // addr 按页 (4096 Byte) 对齐, len \in [0, 1GB = 0x4000_0000) 
     // port 其余位必须为0, port & 0x7 = 0
@@ -108,35 +198,41 @@ pub fn map_virtual_pages(addr: usize, len: usize, port: usize) -> isize {
         return -1;
     }
     if len == 0 { return 0; }
-    if (usable_frames() < len / 4096 + 100 + INITPROC.frames_used()) {
+    // 可写可执行的映射和超出用户地址空间的区间由映射策略拒绝
+    let map_perm = port_to_permission(port);
+    if check_permission(map_perm).is_err() || check_user_range(addr, addr.saturating_add(len)).is_err() {
+        return -1;
+    }
+    if !reserve_frames(len / PAGE_SIZE) {
         return -1;
     }
     let task = current_task().unwrap();
     let mut inner = task.acquire_inner_lock();
-    let map_perm = port_to_permission(port);
-    let map_area: MapArea = MapArea::new(
+    let new_area = || MapArea::new(
         addr.into(),
         (addr+len).into(),
         MapType::Framed,
         map_perm
     );
-    let vpn_range: VPNRange = map_area.vpn_range;
+    let vpn_range: VPNRange = new_area().vpn_range;
     // 处理 虚拟地址区间 [addr, addr+len) 存在已经被映射的页的错误
+    // 用户栈的保留区间同样不能被占用
     for vpn in vpn_range {
-        if inner.memory_set.have_mapped(&vpn) {
+        if inner.memory_set.have_mapped(&vpn) || inner.memory_set.in_stack_reserve(&vpn) {
             return -1;
         }
     }
     let va_start: VirtAddr = vpn_range.get_start().into();
     let va_end: VirtAddr = vpn_range.get_end().into();
-    // 处理物理内存不足的错误, 目前直接panic
-    let succ = inner.memory_set.push(map_area, None);
-    drop(inner);
-    if succ < 0 {
-        -1
-    } else {
-        (va_end.0 - va_start.0) as isize
+    // 处理物理内存不足的错误: 通过 OOM killer 回收内存之后重试，期间不能持有当前进程的锁
+    while inner.memory_set.push(new_area(), None) < 0 {
+        drop(inner);
+        if !oom_reclaim() {
+            return -1;
+        }
+        inner = task.acquire_inner_lock();
     }
+    (va_end.0 - va_start.0) as isize
 }
 
 pub fn unmap_virtual_pages(addr: usize, len: usize) -> isize {
@@ -154,8 +250,9 @@ pub fn unmap_virtual_pages(addr: usize, len: usize) -> isize {
     let va_end: VirtAddr = vpn_range.get_end().into();
 
     // 处理 虚拟地址区间 [addr, addr+len) 存在未被映射的页的错误
+    // 共享内存段只能通过 shmdt 整体解除挂载
     for vpn in vpn_range {
-        if !inner.memory_set.have_mapped(&vpn) {
+        if !inner.memory_set.have_mapped(&vpn) || inner.memory_set.have_shared(&vpn) {
             return -1;
         }
     }
@@ -165,6 +262,160 @@ pub fn unmap_virtual_pages(addr: usize, len: usize) -> isize {
     (va_end.0 - va_start.0) as isize
 }
 
+// 访问用户栈保留区间内尚未映射的页面时，将用户栈向下扩展到包含 addr 的页面
+// 返回是否成功处理了这次缺页
+pub fn grow_user_stack(addr: usize) -> bool {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    inner.memory_set.grow_stack(VirtAddr::from(addr).floor())
+}
+
+// addr 所在的页面是否可以通过扩展用户栈来映射
+pub fn is_user_stack_growth(addr: usize) -> bool {
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    inner.memory_set.is_stack_growth(&VirtAddr::from(addr).floor())
+}
+
+// addr 是否位于用户栈保留区间下方的保护页面中
+pub fn is_user_stack_guard(addr: usize) -> bool {
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    inner.memory_set.is_stack_guard(&VirtAddr::from(addr).floor())
+}
+
+// 修改 [addr, addr+len) 内已映射页面的访问权限为 port，参数的检查与 map_virtual_pages 相同
+pub fn protect_virtual_pages(addr: usize, len: usize, port: usize) -> isize {
+    if addr & (PAGE_SIZE-1) != 0 || len > 0x4000_0000 || (port & !0x7) != 0 || port & 0x7 == 0 {
+        return -1;
+    }
+    if len == 0 { return 0; }
+    let permission = port_to_permission(port);
+    if check_permission(permission).is_err() {
+        return -1;
+    }
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+
+    let start_va: VirtAddr = addr.into();
+    let end_va: VirtAddr = (addr+len).into();
+    let vpn_range: VPNRange = VPNRange::new(start_va.floor(), end_va.ceil());
+
+    // 处理 虚拟地址区间 [addr, addr+len) 存在未被映射的页的错误
+    // 用户态不可访问的页 (如 Trap 上下文) 和共享内存段的权限不能被修改
+    for vpn in vpn_range {
+        if !inner.memory_set.have_user_mapped(&vpn) || inner.memory_set.have_shared(&vpn) {
+            return -1;
+        }
+    }
+    inner.memory_set.protect(vpn_range, permission);
+    0
+}
+
+// 获取 key 对应的共享内存段，不存在时创建一个至少 size 字节的新段，返回段的 id
+pub fn shm_get(key: usize, size: usize) -> isize {
+    if size > 0x4000_0000 {
+        return -1;
+    }
+    if !reserve_frames(size / PAGE_SIZE) {
+        return -1;
+    }
+    match SHM_MANAGER.lock().get(key, size) {
+        Some(id) => id as isize,
+        None => -1,
+    }
+}
+
+// 将共享内存段挂载到当前进程地址空间的 addr 处，addr 为 0 时由内核选择地址
+// 成功返回挂载的起始地址
+pub fn shm_attach(id: usize, addr: usize) -> isize {
+    if addr & (PAGE_SIZE-1) != 0 {
+        return -1;
+    }
+    let segment = if let Some(segment) = SHM_MANAGER.lock().segment(id) {
+        segment
+    } else {
+        return -1;
+    };
+    let pages = segment.page_count();
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    let range_free = |start_vpn: usize| {
+        (start_vpn..start_vpn + pages).all(|vpn| {
+            !inner.memory_set.have_mapped(&VirtPageNum(vpn)) && !inner.memory_set.in_stack_reserve(&VirtPageNum(vpn))
+        })
+    };
+    // 区间 [start, start + pages * PAGE_SIZE) 必须完全位于用户地址空间内，地址溢出同样视为越界
+    let in_user_space = |start: usize| {
+        start.checked_add(pages * PAGE_SIZE)
+            .map_or(false, |end| check_user_range(start, end).is_ok())
+    };
+    let start_vpn = if addr != 0 {
+        if !in_user_space(addr) {
+            return -1;
+        }
+        let start_vpn = VirtAddr::from(addr).floor().0;
+        // 处理 虚拟地址区间 存在已经被映射的页的错误
+        if !range_free(start_vpn) {
+            return -1;
+        }
+        start_vpn
+    } else {
+        // 从地址空间的 mmap 基址 (启用地址空间布局随机化时为 SHM_AREA_START 之上的一个随机位置) 开始逐页寻找第一个足够大的空闲区间
+        // 搜索到 Trap 上下文为止，找不到时失败
+        let mut start_vpn = VirtAddr::from(inner.memory_set.mmap_base()).floor().0;
+        loop {
+            if !in_user_space(start_vpn * PAGE_SIZE) {
+                return -1;
+            }
+            if range_free(start_vpn) {
+                break start_vpn;
+            }
+            start_vpn += 1;
+        }
+    };
+    let va_start: VirtAddr = VirtPageNum(start_vpn).into();
+    // 页表节点分配失败时通过 OOM killer 回收内存之后重试
+    while inner.memory_set.insert_shared_area(
+        va_start,
+        segment.clone(),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    ) < 0 {
+        drop(inner);
+        if !oom_reclaim() {
+            return -1;
+        }
+        inner = task.acquire_inner_lock();
+    }
+    va_start.0 as isize
+}
+
+// 解除挂载在 addr 处的共享内存段
+pub fn shm_detach(addr: usize) -> isize {
+    if addr & (PAGE_SIZE-1) != 0 {
+        return -1;
+    }
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    if inner.memory_set.remove_shared_area(VirtAddr::from(addr).floor()) {
+        0
+    } else {
+        -1
+    }
+}
+
+// 目前只支持 IPC_RMID: 移除共享内存段，等所有挂载都解除之后页帧才会被真正回收
+pub fn shm_control(id: usize, cmd: usize) -> isize {
+    if cmd != IPC_RMID {
+        return -1;
+    }
+    if SHM_MANAGER.lock().remove(id) {
+        0
+    } else {
+        -1
+    }
+}
+
 pub fn port_to_permission(port: usize) -> MapPermission {
     let mut map_perm = MapPermission::U;
     if port & 0x01 != 0 { map_perm |= MapPermission::R; }
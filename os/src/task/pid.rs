@@ -77,19 +77,22 @@ pub struct KernelStack {
 
 impl KernelStack {
     // 从一个 PidHandle ，也就是一个已分配的进程标识符中对应生成一个内核栈 KernelStack
-    pub fn new(pid_handle: &PidHandle) -> Self {
+    // 物理内存不足时返回 None
+    pub fn new(pid_handle: &PidHandle) -> Option<Self> {
         let pid = pid_handle.0;
         let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(pid);
-        KERNEL_SPACE
+        if KERNEL_SPACE
             .lock()
             .insert_framed_area(
                 kernel_stack_bottom.into(),
                 kernel_stack_top.into(),
                 MapPermission::R | MapPermission::W,
-            );
-        KernelStack {
-            pid: pid_handle.0,
+            ) < 0 {
+            return None;
         }
+        Some(KernelStack {
+            pid: pid_handle.0,
+        })
     }
     // 将一个类型为 T 的变量压入内核栈顶并返回其裸指针，这也是一个泛型函数
     pub fn push_on_top<T>(&self, value: T) -> *mut T where
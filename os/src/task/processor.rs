@@ -109,6 +109,14 @@ pub fn current_user_token() -> usize {
     token
 }
 
+// 返回用户态之前调用: 保证当前应用地址空间的 ASID 在当前代中有效，并返回它的 token
+pub fn current_user_satp() -> usize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    inner.memory_set.refresh_asid();
+    inner.get_user_token()
+}
+
 pub fn current_trap_cx() -> &'static mut TrapContext {
     current_task().unwrap().acquire_inner_lock().get_trap_cx()
 }
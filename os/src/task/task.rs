@@ -4,19 +4,84 @@ use crate::mm::{
     PhysPageNum,
     KERNEL_SPACE,
     VirtAddr,
-    translated_refmut
+    ElfInfo,
+    translated_refmut,
+    slab_arc,
+    ElfError,
 };
 use crate::trap::{TrapContext, trap_handler};
-use crate::config::{BIG_STRIDE, TASK_INIT_PRIORITY, TRAP_CONTEXT};
+use crate::config::{BIG_STRIDE, PAGE_SIZE, TASK_INIT_PRIORITY, TRAP_CONTEXT, USER_STACK_LIMIT};
 use super::TaskContext;
-use super::{PidHandle, pid_alloc, KernelStack};
+use super::{PidHandle, pid_alloc, KernelStack, oom_reclaim};
 use alloc::sync::{Weak, Arc};
 use alloc::vec;
 use alloc::vec::Vec;
 use alloc::string::String;
 use spin::{Mutex, MutexGuard};
 use core::cmp::{Ordering};
-use crate::fs::{File, Stdin, Stdout, MailBox};
+use crate::fs::{File, Stdin, Stdout, MailBox, Credentials};
+use crate::fdt::boot_param;
+use crate::random::random_usize;
+
+// personality 标志: 之后 exec 的程序不进行地址空间布局随机化，便于复现问题和调试
+pub const ADDR_NO_RANDOMIZE: usize = 0x0040000;
+
+// 辅助向量 (auxv) 的类型
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_ENTRY: usize = 9;
+const AT_RANDOM: usize = 25;
+
+// 在新地址空间的用户栈上放置程序启动时需要的信息，返回新的栈顶 user_sp 和 argv 数组的起始地址
+// 用户栈从高到低依次为: AT_RANDOM 指向的 16 字节随机数、命令行参数字符串、
+// 以及从 user_sp 开始的 argc、argv 数组、空的 envp 数组和辅助向量，与 Linux 的初始栈布局相同
+fn push_init_stack(memory_set: &MemorySet, mut user_sp: usize, args: &[String], elf_info: &ElfInfo) -> (usize, usize) {
+    let token = memory_set.token();
+    let word = core::mem::size_of::<usize>();
+    user_sp -= 2 * word;
+    let random_bytes = user_sp;
+    *translated_refmut(token, random_bytes as *mut usize) = random_usize();
+    *translated_refmut(token, (random_bytes + word) as *mut usize) = random_usize();
+    // 字符串是通过 copy_str_from_user 从应用地址空间取出的，它的末尾不包含 \0 。为了应用能知道每个字符串的长度，我们需要手动在末尾加入 \0
+    let mut argv: Vec<usize> = Vec::new();
+    for arg in args.iter() {
+        user_sp -= arg.len() + 1;
+        argv.push(user_sp);
+        let mut p = user_sp;
+        for c in arg.as_bytes() {
+            *translated_refmut(token, p as *mut u8) = *c;
+            p += 1;
+        }
+        *translated_refmut(token, p as *mut u8) = 0;
+    }
+    argv.push(0);
+    let auxv = [
+        (AT_PHDR, elf_info.phdr),
+        (AT_PHENT, elf_info.phent),
+        (AT_PHNUM, elf_info.phnum),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_ENTRY, elf_info.entry),
+        (AT_RANDOM, random_bytes),
+        (AT_NULL, 0),
+    ];
+    let mut words = vec![args.len()];
+    words.extend_from_slice(&argv);
+    words.push(0); // envp
+    for (key, value) in auxv.iter() {
+        words.push(*key);
+        words.push(*value);
+    }
+    // 将 user_sp 以 16 字节对齐，这是 RISC-V 调用约定的要求
+    user_sp -= words.len() * word;
+    user_sp &= !0xf;
+    for (i, value) in words.iter().enumerate() {
+        *translated_refmut(token, (user_sp + i * word) as *mut usize) = *value;
+    }
+    (user_sp, user_sp + word)
+}
 
 // 进程控制块
 // 线程成为CPU（也称处理器）调度（scheduling）和分派（switch）的对象
@@ -43,6 +108,8 @@ pub struct TaskControlBlockInner {
     pub memory_set: MemorySet, // 应用的地址空间 
     pub trap_cx_ppn: PhysPageNum, // 位于应用地址空间次高页的 Trap 上下文被实际存放在物理页帧的物理页号
     pub base_size: usize, // 应用数据的大小，也就是 在应用地址空间中从 0x0 开始到用户栈结束一共包含多少字节
+    pub stack_limit: usize, // RLIMIT_STACK: 用户栈最多可以增长到的字节数，在下一次 exec 时生效
+    pub personality: usize, // 进程的执行域标志，目前只支持 ADDR_NO_RANDOMIZE，在下一次 exec 时生效并被子进程继承
 
     pub parent: Option<Weak<TaskControlBlock>>, // 使用 Weak 而非 Arc 来包裹另一个任务控制块，因此这个智能指针将不会影响父进程的引用计数
     pub children: Vec<Arc<TaskControlBlock>>,
@@ -54,6 +121,8 @@ pub struct TaskControlBlockInner {
     // Arc 首先提供了共享引用能力, 可能会有多个进程共享同一个文件对它进行读写
     // dyn 关键字表明 Arc 里面的类型实现了 File/Send/Sync 三个 Trait, 等到运行时才能知道它的具体类型 (Rust 多态)
     pub mail_box: MailBox,
+    pub cwd: Option<String>, // 当前工作目录，是一个规范化的绝对路径，fork 时被子进程继承，exec 后保持不变；被改名替换掉之后为 None
+    pub cred: Credentials, // 进程的用户和组，访问文件时据此检查权限，initproc 以 root 身份运行
 }
 // 子进程的进程控制块并不会被直接放到父进程控制块下面，因为子进程完全有可能在父进程退出后仍然存在
 // 因此进程控制块的本体是被放到内核堆上面的，对于它的一切访问都是通过智能指针 Arc/Weak 来进行的
@@ -95,6 +164,10 @@ impl TaskControlBlockInner {
     pub fn is_zombie(&self) -> bool {
         self.get_status() == TaskStatus::Zombie
     }
+    // 下一次 exec 时是否随机化地址空间布局，启动参数 norandmaps 可以对所有进程关闭随机化
+    pub fn randomize_layout(&self) -> bool {
+        self.personality & ADDR_NO_RANDOMIZE == 0 && boot_param("norandmaps").is_none()
+    }
     // 最先匹配
     // 在进程控制块中分配一个最小的空闲文件描述符来访问一个新打开的文件
     pub fn alloc_fd(&mut self) -> usize {
@@ -121,7 +194,10 @@ impl TaskControlBlock {
         // memory_set with elf program headers/trampoline/trap context/user stack
         // 解析传入的 ELF 格式数据构造应用的地址空间 memory_set 并获得其他信息
         // 用户栈在应用地址空间中的位置 user_sp 以及应用的入口点 entry_point
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let randomize = boot_param("norandmaps").is_none();
+        let (memory_set, user_sp, elf_info) = MemorySet::from_elf(elf_data, USER_STACK_LIMIT, randomize).unwrap();
+        // initproc 没有命令行参数，但同样需要 argv、envp 和辅助向量
+        let (user_sp, argv_base) = push_init_stack(&memory_set, user_sp, &[], &elf_info);
         // 地址空间 memory_set 中查多级页表找到应用地址空间中的 Trap 上下文实际被放在哪个物理页帧
         // 手动查页表找到应用地址空间中的 Trap 上下文被实际放在哪个物理页帧上，用来做后续的初始化
         let trap_cx_ppn = memory_set
@@ -133,7 +209,7 @@ impl TaskControlBlock {
         // map a kernel-stack in kernel space
         // 我们根据传入的应用 ID app_id 调用在 config 子模块中定义的 kernel_stack_position 找到 应用的内核栈预计放在内核地址空间 KERNEL_SPACE 中的哪个位置，
         // 并通过 insert_framed_area 实际将这个逻辑段 加入到内核地址空间中
-        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack = KernelStack::new(&pid_handle).unwrap();
         // 记录下内核栈在内核地址空间的位置 kernel_stack_top
         let kernel_stack_top = kernel_stack.get_top();
         // push a task context which goes to trap_return to the top of kernel stack
@@ -153,6 +229,8 @@ impl TaskControlBlock {
                 memory_set,
                 trap_cx_ppn,
                 base_size: user_sp,
+                stack_limit: USER_STACK_LIMIT,
+                personality: 0,
 
                 parent: None,
                 children: Vec::new(),
@@ -167,6 +245,8 @@ impl TaskControlBlock {
                     Some(Arc::new(Stdout)), // 文件描述符为 2 的标准错误输出
                 ],
                 mail_box: MailBox::new(),
+                cwd: Some(String::from("/")),
+                cred: Credentials::ROOT,
                 // 在我们的实现中并不区分标准输出和标准错误输出
                 // 进程打开一个文件的时候，内核总是会将文件分配到该进程文件描述符表中 最小的 空闲位置 (最先匹配算法)
             }),
@@ -179,49 +259,41 @@ impl TaskControlBlock {
         // prepare TrapContext in user space
         let trap_cx = task_control_block.acquire_inner_lock().get_trap_cx();
         *trap_cx = TrapContext::app_init_context(
-            entry_point,
+            elf_info.entry,
             user_sp,
             KERNEL_SPACE.lock().token(),
             kernel_stack_top,
             trap_handler as usize,
         );
+        trap_cx.x[10] = 0;
+        trap_cx.x[11] = argv_base;
         task_control_block
     }
     // 用来实现 exec 系统调用，即当前进程加载并执行另一个 ELF 格式可执行文件
-    pub fn exec(&self, elf_data: &[u8], args: Vec<String>) {
+    // 物理内存不足时返回 -1，此时原有的地址空间保持不变
+    pub fn exec(&self, elf_data: &[u8], args: Vec<String>) -> isize {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, mut user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let (stack_limit, randomize) = {
+            let inner = self.acquire_inner_lock();
+            (inner.stack_limit, inner.randomize_layout())
+        };
+        // 物理内存不足时通过 OOM killer 回收内存之后重试
+        let (memory_set, user_sp, elf_info) = loop {
+            match MemorySet::from_elf(elf_data, stack_limit, randomize) {
+                Ok(result) => break result,
+                Err(ElfError::OutOfMemory) if oom_reclaim() => continue,
+                Err(err) => {
+                    warn!("[kernel] exec: failed to load ELF: {:?}", err);
+                    return -1;
+                }
+            }
+        };
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
         // push arguments on user stack
-        // 将命令行参数压入用户栈
-        user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
-        let argv_base = user_sp;
-        let mut argv: Vec<_> = (0..=args.len())
-            .map(|arg| {
-                translated_refmut(
-                    memory_set.token(),
-                    (argv_base + arg * core::mem::size_of::<usize>()) as *mut usize
-                )
-            })
-            .collect();
-        // 字符串是通过 translated_str 从应用地址空间取出的，它的末尾不包含 \0 。为了应用能知道每个字符串的长度，我们需要手动在末尾加入 \0
-        *argv[args.len()] = 0;
-        for i in 0..args.len() {
-            user_sp -= args[i].len() + 1;
-            *argv[i] = user_sp;
-            let mut p = user_sp;
-            for c in args[i].as_bytes() {
-                *translated_refmut(memory_set.token(), p as *mut u8) = *c;
-                p += 1;
-            }
-            *translated_refmut(memory_set.token(), p as *mut u8) = 0;
-        }
-        // 将 user_sp 以 8 字节对齐
-        // make the user_sp aligned to 8B for k210 platform
-        user_sp -= user_sp % core::mem::size_of::<usize>();
+        let (user_sp, argv_base) = push_init_stack(&memory_set, user_sp, &args, &elf_info);
 
         // **** hold current PCB lock
         let mut inner = self.acquire_inner_lock();
@@ -234,7 +306,7 @@ impl TaskControlBlock {
         // initialize trap_cx
         // 修改新的地址空间中的 Trap 上下文，将解析得到的应用入口点、用户栈位置以及一些内核的信息进行初始化，这样才能正常实现 Trap 机制
         let mut trap_cx = TrapContext::app_init_context(
-            entry_point,
+            elf_info.entry,
             user_sp,
             KERNEL_SPACE.lock().token(),
             self.kernel_stack.get_top(),
@@ -247,6 +319,7 @@ impl TaskControlBlock {
         trap_cx.x[11] = argv_base;
         *inner.get_trap_cx() = trap_cx;
         // **** release current PCB lock
+        0
     }
     // 实现 fork 系统调用，即当前进程 fork 出来一个与之几乎相同的子进程
     pub fn fork(self: &Arc<TaskControlBlock>) -> Option<Arc<TaskControlBlock>> {
@@ -262,7 +335,7 @@ impl TaskControlBlock {
                 .ppn();
             // alloc a pid and a kernel stack in kernel space
             let pid_handle = pid_alloc();
-            let kernel_stack = KernelStack::new(&pid_handle);
+            let kernel_stack = KernelStack::new(&pid_handle)?;
             let kernel_stack_top = kernel_stack.get_top();
             // push a goto_trap_return task_cx on the top of kernel stack
             // 子进程内核栈上压入一个初始化的任务上下文，使得内核一旦通过任务切换到该进程，就会跳转到 trap_return 来进入用户态
@@ -281,7 +354,7 @@ impl TaskControlBlock {
             for mail in parent_inner.mail_box.packets.iter() {
                 new_mail_box.push(*mail);
             }
-            let task_control_block = Arc::new(TaskControlBlock {
+            let task_control_block = slab_arc(TaskControlBlock {
                 pid: pid_handle,
                 kernel_stack,
                 inner: Mutex::new(TaskControlBlockInner {
@@ -294,6 +367,8 @@ impl TaskControlBlock {
                     memory_set,
                     trap_cx_ppn,
                     base_size: parent_inner.base_size, // 让子进程和父进程的 base_size ，也即应用数据的大小保持一致
+                    stack_limit: parent_inner.stack_limit,
+                    personality: parent_inner.personality,
 
                     parent: Some(Arc::downgrade(self)), // 将父进程的弱引用计数放到子进程的进程控制块中
                     children: Vec::new(),
@@ -302,6 +377,8 @@ impl TaskControlBlock {
                     fd_table: new_fd_table,
 
                     mail_box: new_mail_box,
+                    cwd: parent_inner.cwd.clone(),
+                    cred: parent_inner.cred,
                 }),
             });
             // 注意父子进程关系的维护
@@ -2,7 +2,7 @@
 use riscv::register::time;
 use crate::sbi::set_timer; // 由 SEE 提供的标准 SBI 接口函数，它可以用来设置 mtimecmp 的值
 use crate::config::CLOCK_FREQ;
-use crate::mm::translated_virtual_ptr;
+use crate::mm::copy_to_user;
 use crate::task::current_user_token;
 
 const TICKS_PER_SEC: usize = 100;
@@ -35,16 +35,13 @@ pub fn get_time_us() -> usize {
 // ts 为当前时间结构体
 // 正确返回 0，错误返回 -1
 pub fn get_time_sys(ts: *mut TimeVal, _tz: usize) -> isize {
-    unsafe {
-        let pa_ts = translated_virtual_ptr(current_user_token(), ts);
-        // println!("in get_time_sys(), va={:#x}, pa={:#x}", ts as usize, pa_ts as usize);
-        if let Some(pa_ts) = pa_ts.as_mut() {
-            (*pa_ts).usec = get_time_us() % USEC_PER_SEC;
-            (*pa_ts).sec = get_time_us() / USEC_PER_SEC;
-            return 0
-        }
-    }
-    -1
+    let us = get_time_us();
+    let time = TimeVal {
+        sec: us / USEC_PER_SEC,
+        usec: us % USEC_PER_SEC,
+    };
+    // ts 不可写时返回 -1
+    if copy_to_user(current_user_token(), ts, &time) { 0 } else { -1 }
 }
 
 // 设置 10ms 的计时器
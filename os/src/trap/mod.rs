@@ -17,10 +17,15 @@ use crate::task::{
     exit_current_and_run_next,
     suspend_current_and_run_next,
     current_task_id,
-    current_user_token,
+    current_user_satp,
     current_trap_cx,
+    grow_user_stack,
+    is_user_stack_growth,
+    is_user_stack_guard,
+    oom_kill,
 };
-use crate::timer::set_next_trigger;
+use crate::timer::{set_next_trigger, get_time};
+use crate::random::add_entropy;
 use crate::config::{TRAP_CONTEXT, TRAMPOLINE};
 
 global_asm!(include_str!("trap.S"));
@@ -77,8 +82,19 @@ pub fn trap_handler() -> ! {
             cx = current_trap_cx();
             cx.x[10] = result as usize;
         }
+        // 访问的是用户栈保留区间内尚未映射的页面: 扩展用户栈之后回到用户态重新执行这条指令即可
+        // 物理内存不足时交给 OOM killer 处理，如果当前进程没有被杀死就会再次触发缺页并重试
+        Trap::Exception(Exception::StorePageFault) |
+        Trap::Exception(Exception::LoadPageFault) if is_user_stack_growth(stval) => {
+            if !grow_user_stack(stval) {
+                oom_kill();
+            }
+        }
         Trap::Exception(Exception::StoreFault) |
         Trap::Exception(Exception::StorePageFault) => {
+            if is_user_stack_guard(stval) {
+                println!("[kernel] Stack overflow in Application {}, bad addr = {:#x}, killed.", current_task_id(), stval);
+            }
             info!(
                 "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.",
                 scause.cause(),
@@ -90,6 +106,9 @@ pub fn trap_handler() -> ! {
         },
         Trap::Exception(Exception::LoadFault) |
         Trap::Exception(Exception::LoadPageFault) => {
+            if is_user_stack_guard(stval) {
+                println!("[kernel] Stack overflow in Application {}, bad addr = {:#x}, killed.", current_task_id(), stval);
+            }
             info!(
                 "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.",
                 scause.cause(),
@@ -118,6 +137,7 @@ pub fn trap_handler() -> ! {
         // 中断不会被屏蔽，而是 Trap 到 S 特权级内的我们的 trap_handler 里面进行处理，并顺利切换到下一个应用
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             set_next_trigger(); // 重新设置一个 10ms 的计时器
+            add_entropy(get_time()); // 时钟中断被处理的时刻存在抖动
             suspend_current_and_run_next(); // 暂停当前应用并切换到下一个
         },
         _ => {
@@ -132,7 +152,7 @@ pub fn trap_handler() -> ! {
 pub fn trap_return() -> ! {
     set_user_trap_entry(); // 让应用 Trap 到 S 的时候可以跳转到 __alltraps
     let trap_cx_ptr = TRAP_CONTEXT; // Trap 上下文在应用地址空间中的虚拟地址
-    let user_satp = current_user_token(); // 要继续执行的应用 地址空间的 token 
+    let user_satp = current_user_satp(); // 要继续执行的应用 地址空间的 token 
     extern "C" {
         fn __alltraps();
         fn __restore();
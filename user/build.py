@@ -1,5 +1,23 @@
 import os
 
+# 以静态位置无关可执行文件 (static-PIE) 的形式构建的应用，用来测试内核对 ET_DYN 的平移加载和重定位
+PIE_APPS = ['ch5_pie0']
+PIE_RUSTFLAGS = ' '.join([
+    '-Clink-args=-Tsrc/linker-pie.ld',
+    '-Crelocation-model=pie',
+    # rust-lld 不认识 -static-pie，使用与之等价的 -pie --no-dynamic-linker
+    '-Clink-arg=-pie',
+    '-Clink-arg=--no-dynamic-linker',
+    # 预编译的 core 不是位置无关的，对只读段的重定位留给内核在加载时完成
+    '-Clink-arg=-znotext',
+])
+TARGET_DIR = 'target/riscv64gc-unknown-none-elf/release'
+PIE_TARGET_DIR = 'target/pie'
+
 if __name__ == '__main__':
     os.system('cargo build --release')
     # os.system('cargo build')
+    # RUSTFLAGS 会覆盖 .cargo/config 中的 rustflags，使用单独的目标目录避免与上面的构建互相干扰
+    for app in PIE_APPS:
+        os.system('RUSTFLAGS="%s" cargo build --release --bin %s --target-dir %s' % (PIE_RUSTFLAGS, app, PIE_TARGET_DIR))
+        os.system('cp %s/riscv64gc-unknown-none-elf/release/%s %s/%s' % (PIE_TARGET_DIR, app, TARGET_DIR, app))
@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap, mprotect};
+
+/*
+理想结果：对于错误的 mprotect 返回 -1，页面变为可执行后能够运行写入的指令，最终输出 Test mprotect0 OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len: usize = 4096;
+    assert_eq!(mmap(start, len * 3, 3), (len * 3) as isize);
+    for i in start..(start + len * 3) {
+        unsafe { *(i as *mut u8) = i as u8; }
+    }
+    // 参数错误
+    assert_eq!(mprotect(start + 1, len, 1), -1);
+    assert_eq!(mprotect(start, len, 0), -1);
+    assert_eq!(mprotect(start, len, 1 | 8), -1);
+    assert_eq!(mprotect(start, len * 4, 1), -1);
+    assert_eq!(mprotect(start - len, len, 1), -1);
+    assert_eq!(mprotect(start, 0, 1), 0);
+    // 将中间一页改为只读，逻辑段被拆成三段，数据保持不变
+    assert_eq!(mprotect(start + len, len, 1), 0);
+    for i in start..(start + len * 3) {
+        assert_eq!(unsafe { *(i as *const u8) }, i as u8);
+    }
+    // 两侧的页仍然可写
+    unsafe {
+        *(start as *mut u8) = 0xaa;
+        *((start + len * 2) as *mut u8) = 0xbb;
+    }
+    // 改回可写之后可以再次写入
+    assert_eq!(mprotect(start + len, len, 3), 0);
+    unsafe { *((start + len) as *mut u8) = 0xcc; }
+    assert_eq!(unsafe { *((start + len) as *const u8) }, 0xcc);
+    // W -> X: 写入 `li a0, 42; ret` 之后改为可读可执行并调用
+    let code = (start + len * 2) as *mut u32;
+    unsafe {
+        code.write_volatile(0x02a0_0513);
+        code.add(1).write_volatile(0x0000_8067);
+    }
+    assert_eq!(mprotect(start + len * 2, len, 5), 0);
+    let f: extern "C" fn() -> usize = unsafe { core::mem::transmute(code) };
+    assert_eq!(f(), 42);
+    // 拆分之后的逻辑段仍然可以被整体解除映射
+    assert_eq!(munmap(start, len * 3), (len * 3) as isize);
+    assert_eq!(mprotect(start, len, 3), -1);
+    println!("Test mprotect0 OK!");
+    0
+}
@@ -0,0 +1,26 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, mprotect};
+
+/*
+理想结果：程序写只读页触发访存异常，被杀死。不输出 error 就算过。
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len: usize = 4096;
+    assert_eq!(mmap(start, len * 2, 3), (len * 2) as isize);
+    unsafe { *((start + len) as *mut u8) = 1; }
+    assert_eq!(mprotect(start + len, len, 1), 0);
+    let addr: *mut u8 = (start + len) as *mut u8;
+    unsafe {
+        *addr = 2;
+    }
+    println!("Should cause error, Test mprotect1 fail!");
+    0
+}
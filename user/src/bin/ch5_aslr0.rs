@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{personality, spawn, waitpid, ADDR_NO_RANDOMIZE};
+
+/*
+理想结果：默认情况下两次运行 ch5_aslr1 得到不同的地址空间布局，设置 ADDR_NO_RANDOMIZE 之后布局相同，
+输出 aslr0 test OK! (内核以 norandmaps 启动参数启动时不适用)
+*/
+
+fn run(path: &str) -> i32 {
+    let pid = spawn(path);
+    assert!(pid > 0);
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(personality(0xffff_ffff), 0);
+    // 不支持的标志
+    assert_eq!(personality(1), -1);
+    let a = run("ch5_aslr1\0");
+    let b = run("ch5_aslr1\0");
+    assert_ne!(a, b);
+    // 关闭随机化，标志被 spawn 出的子进程继承并在加载程序时生效
+    assert_eq!(personality(ADDR_NO_RANDOMIZE), 0);
+    assert_eq!(personality(0xffff_ffff), ADDR_NO_RANDOMIZE as isize);
+    let c = run("ch5_aslr1\0");
+    let d = run("ch5_aslr1\0");
+    assert_eq!(c, d);
+    assert_eq!(personality(0), ADDR_NO_RANDOMIZE as isize);
+    println!("aslr0 test OK!");
+    0
+}
@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use user_lib::{shmget, shmat, shmdt, shmctl, IPC_PRIVATE, IPC_RMID};
+
+/*
+辅助测例：由 ch5_aslr0 运行，将用户栈的位置和由内核选择的共享内存挂载地址折叠成返回值，
+地址空间布局相同时返回值相同。
+*/
+
+const LEN: usize = 4096;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let local = 0usize;
+    let sp = &local as *const usize as usize;
+    let id = shmget(IPC_PRIVATE, LEN);
+    assert!(id > 0);
+    let addr = shmat(id as usize, 0);
+    assert!(addr > 0);
+    assert_eq!(shmdt(addr as usize), 0);
+    assert_eq!(shmctl(id as usize, IPC_RMID), 0);
+    let layout = (sp >> 12).wrapping_mul(0x9e37_79b9) ^ (addr as usize >> 12);
+    (layout ^ (layout >> 31)) as i32 & 0x7fff_ffff
+}
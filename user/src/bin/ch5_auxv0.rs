@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    getauxval, AT_ENTRY, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_RANDOM,
+};
+
+/*
+理想结果：用户栈上的辅助向量给出正确的页面大小、入口点和 program header 信息，
+AT_RANDOM 指向 16 字节可读的随机数，输出 Test auxv0 OK!
+*/
+
+extern "C" {
+    fn _start();
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    assert_eq!(argc, argv.len());
+    assert_eq!(getauxval(AT_PAGESZ), Some(4096));
+    assert_eq!(getauxval(AT_ENTRY), Some(_start as unsafe extern "C" fn() as usize));
+    assert_eq!(getauxval(AT_PHENT), Some(56));
+    assert!(getauxval(AT_PHNUM).unwrap() > 0);
+    assert!(getauxval(AT_PHDR).is_some());
+    assert_eq!(getauxval(0x7fff_ffff), None);
+    let random = getauxval(AT_RANDOM).unwrap();
+    let bytes = unsafe { core::slice::from_raw_parts(random as *const u8, 16) };
+    assert!(bytes.iter().any(|&b| b != 0));
+    println!("Test auxv0 OK!");
+    0
+}
@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap, fork, waitpid, exit};
+
+/*
+理想结果：物理内存耗尽时 mmap 返回 -1 而内核不会 panic，释放之后可以再次分配并正常 fork，最终输出 Test oom0 OK!
+*/
+
+const START: usize = 0x10000000;
+const CHUNK: usize = 0x10_0000;
+
+#[no_mangle]
+fn main() -> i32 {
+    // 每次映射 1MiB，直到物理内存不足
+    let mut chunks = 0;
+    loop {
+        let addr = START + chunks * CHUNK;
+        let ret = mmap(addr, CHUNK, 3);
+        if ret < 0 {
+            break;
+        }
+        assert_eq!(ret, CHUNK as isize);
+        unsafe { *(addr as *mut usize) = chunks; }
+        chunks += 1;
+    }
+    assert!(chunks > 0);
+    println!("mapped {} MiB before running out of memory", chunks);
+    for i in 0..chunks {
+        assert_eq!(unsafe { *((START + i * CHUNK) as *const usize) }, i);
+    }
+    // 内存不足时 fork 要么失败返回 -1，要么成功
+    let pid = fork();
+    if pid == 0 {
+        exit(0);
+    }
+    if pid > 0 {
+        let mut exit_code: i32 = 0;
+        assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+        assert_eq!(exit_code, 0);
+    }
+    // 释放之后可以再次分配
+    assert_eq!(munmap(START, chunks * CHUNK), (chunks * CHUNK) as isize);
+    assert_eq!(mmap(START, CHUNK, 3), CHUNK as isize);
+    assert_eq!(munmap(START, CHUNK), CHUNK as isize);
+    let pid = fork();
+    assert!(pid >= 0);
+    if pid == 0 {
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    println!("Test oom0 OK!");
+    0
+}
@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, getauxval, waitpid, AT_ENTRY};
+
+/*
+这个程序以 static-PIE 的形式构建 (见 build.py)，内核将它平移到链接地址之外的位置加载并完成重定位
+理想结果：静态数据中保存的函数指针和数据指针都指向实际加载的位置，
+PT_GNU_RELRO 中的页面在重定位之后变为只读，写入时进程被杀死，输出 Test pie0 OK!
+*/
+
+extern "C" {
+    fn _start();
+}
+
+fn one() -> usize {
+    1
+}
+
+fn two() -> usize {
+    2
+}
+
+static VALUE: usize = 42;
+// 以下指针在文件中保存的是相对于链接地址 0 的值，需要内核按照 R_RISCV_RELATIVE 重定位
+// 不可变的静态数据位于 PT_GNU_RELRO 描述的 .data.rel.ro 中
+static FUNCS: [fn() -> usize; 2] = [one, two];
+static VALUE_REF: &usize = &VALUE;
+// 可变的静态数据位于普通的 .data 中，重定位之后仍然可写
+static mut VALUE_MUT_REF: &usize = &VALUE;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // 链接地址从 0 开始，入口点被平移到了别处
+    let entry = _start as unsafe extern "C" fn() as usize;
+    assert_ne!(entry, 0);
+    assert_eq!(getauxval(AT_ENTRY), Some(entry));
+    // 通过 volatile 读取保证使用的是内存中重定位之后的值，而不是编译器算出的地址
+    let funcs = unsafe { core::ptr::read_volatile(&FUNCS) };
+    assert_eq!(funcs[0] as usize, one as fn() -> usize as usize);
+    assert_eq!(funcs[1] as usize, two as fn() -> usize as usize);
+    assert_eq!(funcs[0]() + funcs[1](), 3);
+    let value_ref = unsafe { core::ptr::read_volatile(&VALUE_REF) };
+    assert_eq!(value_ref as *const usize, &VALUE as *const usize);
+    assert_eq!(*value_ref, 42);
+    unsafe {
+        let value_mut_ref = core::ptr::read_volatile(&VALUE_MUT_REF);
+        assert_eq!(value_mut_ref as *const usize, &VALUE as *const usize);
+        core::ptr::write_volatile(&mut VALUE_MUT_REF, &VALUE);
+    }
+    // .data.rel.ro 在重定位之后是只读的，子进程写入它时因为页错误被杀死
+    let pid = fork();
+    if pid == 0 {
+        let addr = FUNCS.as_ptr() as usize;
+        unsafe {
+            core::ptr::write_volatile(addr as *mut usize, 0);
+        }
+        println!("RELRO is still writable, Test pie0 fail!");
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -2);
+    println!("Test pie0 OK!");
+    0
+}
@@ -0,0 +1,33 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::ptr::{read_volatile, write_volatile};
+
+/*
+理想结果：用户栈从初始的一页自动增长到约 32KiB，输出 stack grow0 test OK!
+*/
+
+const FRAME_SIZE: usize = 1024;
+const DEPTH: usize = 32;
+
+// 每一层递归在栈上占用至少 FRAME_SIZE 字节，递归返回后仍然读取局部数组防止被优化为循环
+fn touch(depth: usize) -> usize {
+    let mut buf = [0u8; FRAME_SIZE];
+    for byte in buf.iter_mut() {
+        unsafe { write_volatile(byte, depth as u8); }
+    }
+    let below = if depth == 0 { 0 } else { touch(depth - 1) };
+    below + unsafe { read_volatile(&buf[FRAME_SIZE - 1]) } as usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(touch(DEPTH), DEPTH * (DEPTH + 1) / 2);
+    // 已经增长出来的栈页面可以被再次使用
+    assert_eq!(touch(DEPTH), DEPTH * (DEPTH + 1) / 2);
+    println!("stack grow0 test OK!");
+    0
+}
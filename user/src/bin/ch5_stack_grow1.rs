@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::ptr::{read_volatile, write_volatile};
+
+/*
+辅助测例：需要约 128KiB 的用户栈，超过默认的用户栈上限，由 ch5_stack_rlimit 调大上限之后运行。
+单独运行时应当因为栈溢出被杀死。
+*/
+
+const FRAME_SIZE: usize = 1024;
+const DEPTH: usize = 128;
+
+fn touch(depth: usize) -> usize {
+    let mut buf = [0u8; FRAME_SIZE];
+    for byte in buf.iter_mut() {
+        unsafe { write_volatile(byte, depth as u8); }
+    }
+    let below = if depth == 0 { 0 } else { touch(depth - 1) };
+    below + unsafe { read_volatile(&buf[FRAME_SIZE - 1]) } as usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(touch(DEPTH), DEPTH * (DEPTH + 1) / 2);
+    println!("stack grow1 test OK!");
+    0
+}
@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{getrlimit, setrlimit, spawn, waitpid, RLimit, RLIMIT_STACK};
+
+/*
+理想结果：默认上限下 ch5_stack_grow1 因为栈溢出被杀死，调大 RLIMIT_STACK 之后正常退出，输出 stack rlimit test OK!
+*/
+
+fn run(path: &str) -> i32 {
+    let pid = spawn(path);
+    assert!(pid > 0);
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut rlim = RLimit::new();
+    assert_eq!(getrlimit(RLIMIT_STACK, &mut rlim), 0);
+    assert!(rlim.rlim_cur <= rlim.rlim_max);
+    assert_eq!(getrlimit(0, &mut rlim), -1);
+    // 参数错误
+    let max = rlim.rlim_max;
+    assert_eq!(setrlimit(RLIMIT_STACK, &RLimit { rlim_cur: max + 4096, rlim_max: max + 4096 }), -1);
+    assert_eq!(setrlimit(RLIMIT_STACK, &RLimit { rlim_cur: 8192, rlim_max: 4096 }), -1);
+    assert_eq!(setrlimit(RLIMIT_STACK, &RLimit { rlim_cur: 0, rlim_max: max }), -1);
+    // 默认上限不足，子进程被杀死
+    assert_ne!(run("ch5_stack_grow1\0"), 0);
+    // 新的上限被 spawn 出的子进程继承并在加载程序时生效
+    assert_eq!(setrlimit(RLIMIT_STACK, &RLimit { rlim_cur: 256 * 1024, rlim_max: max }), 0);
+    assert_eq!(getrlimit(RLIMIT_STACK, &mut rlim), 0);
+    assert_eq!(rlim.rlim_cur, 256 * 1024);
+    assert_eq!(run("ch5_stack_grow1\0"), 0);
+    println!("stack rlimit test OK!");
+    0
+}
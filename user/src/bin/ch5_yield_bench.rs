@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, exit, wait, get_time, yield_};
+
+/*
+上下文切换密集型负载的性能测试：若干进程轮流访问各自的一组页面并立即让出处理器
+每次切换回来之后这些页面的地址转换是否还在快表中决定了耗时，用于对比 ASID 的效果
+理想结果：输出耗时以及 yield_bench passed!
+*/
+
+const PROCS: usize = 4;
+const ROUNDS: usize = 2000;
+const PAGES: usize = 16;
+const PAGE_SIZE: usize = 4096;
+
+static mut DATA: [u8; PAGES * PAGE_SIZE] = [0; PAGES * PAGE_SIZE];
+
+fn work(id: usize) -> i32 {
+    for round in 0..ROUNDS {
+        for page in 0..PAGES {
+            unsafe { DATA[page * PAGE_SIZE + id] = round as u8; }
+        }
+        yield_();
+    }
+    for page in 0..PAGES {
+        assert_eq!(unsafe { DATA[page * PAGE_SIZE + id] }, (ROUNDS - 1) as u8);
+    }
+    0
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = get_time();
+    for id in 0..PROCS {
+        if fork() == 0 {
+            exit(work(id));
+        }
+    }
+    let mut exit_code: i32 = 0;
+    for _ in 0..PROCS {
+        assert!(wait(&mut exit_code) > 0);
+        assert_eq!(exit_code, 0);
+    }
+    let elapsed = get_time() - start;
+    println!(
+        "{} processes x {} yields, touching {} pages each time: {} ms",
+        PROCS, ROUNDS, PAGES, elapsed
+    );
+    println!("yield_bench passed!");
+    0
+}
@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{pipe, close, heap_stats, HeapStats};
+
+/*
+理想结果：创建大量管道时内核堆的已分配大小增加，关闭之后回落，峰值不小于任何时刻的已分配大小，最终输出 Test heap0 OK!
+*/
+
+const PIPES: usize = 200;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut before = HeapStats::new();
+    assert_eq!(heap_stats(&mut before), 0);
+    assert!(before.in_use <= before.total);
+    assert!(before.in_use <= before.peak);
+    let mut fds = [[0usize; 2]; PIPES];
+    for i in 0..PIPES {
+        assert_eq!(pipe(&mut fds[i]), 0);
+    }
+    let mut during = HeapStats::new();
+    assert_eq!(heap_stats(&mut during), 0);
+    println!("{:?}", during);
+    assert!(during.in_use > before.in_use);
+    assert!(during.in_use <= during.total);
+    assert!(during.peak >= during.in_use);
+    for i in 0..PIPES {
+        assert_eq!(close(fds[i][0]), 0);
+        assert_eq!(close(fds[i][1]), 0);
+    }
+    let mut after = HeapStats::new();
+    assert_eq!(heap_stats(&mut after), 0);
+    assert!(after.in_use < during.in_use);
+    assert!(after.peak >= during.peak);
+    println!("Test heap0 OK!");
+    0
+}
@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, wait, shmget, shmat, shmdt, shmctl, IPC_PRIVATE, IPC_RMID};
+
+/*
+理想结果：输出 shm0 test OK!
+*/
+
+const KEY: usize = 0x5348;
+const LEN: usize = 4096 * 2;
+
+#[no_mangle]
+fn main() -> i32 {
+    // 参数错误
+    assert_eq!(shmget(IPC_PRIVATE, 0), -1);
+    assert_eq!(shmat(12345, 0), -1);
+    assert_eq!(shmdt(0x10000000), -1);
+    // 同一个 key 得到同一个段，要求的大小超出已有段时失败
+    let id = shmget(KEY, LEN);
+    assert!(id > 0);
+    assert_eq!(shmget(KEY, LEN), id);
+    assert_eq!(shmget(KEY, LEN * 2), -1);
+    let addr = shmat(id as usize, 0);
+    assert!(addr > 0);
+    let buf = addr as usize as *mut u8;
+    // fork 之后父子进程共享同一组物理页帧
+    let pid = fork();
+    if pid == 0 {
+        for i in 0..LEN {
+            unsafe { buf.add(i).write_volatile(i as u8); }
+        }
+        // 挂载在同一个段上的另一个地址看到相同的内容
+        let addr2 = shmat(id as usize, 0);
+        assert!(addr2 > 0 && addr2 != addr);
+        let buf2 = addr2 as usize as *const u8;
+        for i in 0..LEN {
+            assert_eq!(unsafe { buf2.add(i).read_volatile() }, i as u8);
+        }
+        assert_eq!(shmdt(addr2 as usize), 0);
+        assert_eq!(shmdt(addr2 as usize), -1);
+        return 0;
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    for i in 0..LEN {
+        assert_eq!(unsafe { buf.add(i).read_volatile() }, i as u8);
+    }
+    // 移除后无法再通过 key 或 id 找到该段，但已有的挂载仍然可用
+    assert_eq!(shmctl(id as usize, IPC_RMID), 0);
+    assert_eq!(shmctl(id as usize, IPC_RMID), -1);
+    assert_eq!(shmat(id as usize, 0), -1);
+    unsafe { buf.write_volatile(0xff); }
+    assert_eq!(unsafe { buf.read_volatile() }, 0xff);
+    assert_eq!(shmdt(addr as usize), 0);
+    // 相同的 key 现在会创建一个新段
+    let new_id = shmget(KEY, LEN);
+    assert!(new_id > 0 && new_id != id);
+    assert_eq!(shmctl(new_id as usize, IPC_RMID), 0);
+    println!("shm0 test OK!");
+    0
+}
@@ -0,0 +1,110 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    fork, exit, close, pipe, read, write, wait, get_time, yield_,
+    shmget, shmat, shmdt, shmctl, IPC_PRIVATE, IPC_RMID,
+};
+
+/*
+对比通过管道和共享内存在父子进程之间传输相同数据量的耗时
+理想结果：输出 shm_large_test passed!
+*/
+
+const LENGTH: usize = 3000;
+const ROUNDS: usize = 16;
+
+fn fill(buf: &mut [u8], round: usize) {
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte = (i + round) as u8;
+    }
+}
+
+fn checksum(buf: &[u8]) -> usize {
+    buf.iter().map(|v| *v as usize).sum::<usize>()
+}
+
+fn pipe_transfer() -> usize {
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let mut buf = [0u8; LENGTH];
+    if fork() == 0 {
+        close(pipe_fd[1]);
+        let mut sum = 0;
+        for _ in 0..ROUNDS {
+            let mut got = 0;
+            while got < LENGTH {
+                got += read(pipe_fd[0], &mut buf[got..]) as usize;
+            }
+            sum += checksum(&buf);
+        }
+        close(pipe_fd[0]);
+        exit((sum & 0xffff) as i32);
+    }
+    close(pipe_fd[0]);
+    let mut sum = 0;
+    for round in 0..ROUNDS {
+        fill(&mut buf, round);
+        sum += checksum(&buf);
+        assert_eq!(write(pipe_fd[1], &buf) as usize, LENGTH);
+    }
+    close(pipe_fd[1]);
+    let mut exit_code: i32 = 0;
+    wait(&mut exit_code);
+    assert_eq!(exit_code as usize, sum & 0xffff);
+    sum
+}
+
+fn shm_transfer() -> usize {
+    // 段的第一个字节作为标志: 0 表示缓冲区空，1 表示缓冲区已写满
+    let id = shmget(IPC_PRIVATE, LENGTH + 1);
+    assert!(id > 0);
+    let addr = shmat(id as usize, 0);
+    assert!(addr > 0);
+    let flag = addr as usize as *mut u8;
+    let data = unsafe { core::slice::from_raw_parts_mut(flag.add(1), LENGTH) };
+    unsafe { flag.write_volatile(0); }
+    if fork() == 0 {
+        let mut sum = 0;
+        for _ in 0..ROUNDS {
+            while unsafe { flag.read_volatile() } == 0 {
+                yield_();
+            }
+            sum += checksum(data);
+            unsafe { flag.write_volatile(0); }
+        }
+        exit((sum & 0xffff) as i32);
+    }
+    let mut sum = 0;
+    for round in 0..ROUNDS {
+        while unsafe { flag.read_volatile() } == 1 {
+            yield_();
+        }
+        fill(data, round);
+        sum += checksum(data);
+        unsafe { flag.write_volatile(1); }
+    }
+    let mut exit_code: i32 = 0;
+    wait(&mut exit_code);
+    assert_eq!(exit_code as usize, sum & 0xffff);
+    assert_eq!(shmdt(addr as usize), 0);
+    assert_eq!(shmctl(id as usize, IPC_RMID), 0);
+    sum
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = get_time();
+    let pipe_sum = pipe_transfer();
+    let pipe_time = get_time() - start;
+    let start = get_time();
+    let shm_sum = shm_transfer();
+    let shm_time = get_time() - start;
+    assert_eq!(pipe_sum, shm_sum);
+    println!("{} bytes, pipe: {}ms, shm: {}ms", LENGTH * ROUNDS, pipe_time, shm_time);
+    println!("shm_large_test passed!");
+    0
+}
@@ -19,13 +19,23 @@ static TESTS: &[&str] = &[
     "ch4_mmap3\0",
     "ch4_unmap\0",
     "ch4_unmap2\0",
+    "ch4_mprotect0\0",
+    "ch4_mprotect1\0",
     "ch5_getpid\0",
     "ch5_spawn0\0",
     "ch5_spawn1\0",
+    "ch5_oom0\0",
+    "ch5_stack_grow0\0",
+    "ch5_stack_rlimit\0",
+    "ch5_aslr0\0",
+    "ch5_auxv0\0",
+    "ch5_pie0\0",
     "ch6_mail0\0",
     "ch6_mail1\0",
     "ch6_mail2\0",
     "ch6_mail3\0",
+    "ch6_shm0\0",
+    "ch6_heap0\0",
 ];
 
 use user_lib::{spawn, waitpid};
@@ -0,0 +1,108 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fork, mmap, open, syscall, waitpid, yield_, OpenFlags,
+    SYSCALL_EXEC, SYSCALL_FSTAT, SYSCALL_GETTIMEOFDAY, SYSCALL_OPENAT, SYSCALL_PIPE,
+    SYSCALL_READ, SYSCALL_WAITPID, SYSCALL_WRITE,
+};
+
+/*
+理想结果：系统调用收到未映射、权限不足、跨越到未映射页面或者超出用户地址空间的指针时返回 -1，
+内核不会 panic，进程也不会被杀死，最终输出 Test badptr0 OK!
+*/
+
+const PAGE_SIZE: usize = 0x1000;
+const RW_PAGE: usize = 0x10000000; // 可读写，下一页未映射
+const RO_PAGE: usize = 0x10002000; // 只读
+const UNMAPPED: usize = 0x10004000;
+const KERNEL_ADDR: usize = 0x80200000;
+const TRAP_CONTEXT: usize = usize::MAX - 2 * PAGE_SIZE + 1;
+
+fn write_raw(fd: usize, buf: usize, len: usize) -> isize {
+    syscall(SYSCALL_WRITE, [fd, buf, len])
+}
+
+fn read_raw(fd: usize, buf: usize, len: usize) -> isize {
+    syscall(SYSCALL_READ, [fd, buf, len])
+}
+
+fn open_raw(path: usize, flags: u32) -> isize {
+    syscall(SYSCALL_OPENAT, [-100isize as usize, path, flags as usize])
+}
+
+fn waitpid_raw(pid: usize, exit_code: usize) -> isize {
+    loop {
+        match syscall(SYSCALL_WAITPID, [pid, exit_code, 0]) {
+            -2 => { yield_(); }
+            ret => return ret,
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mmap(RW_PAGE, PAGE_SIZE, 3), PAGE_SIZE as isize);
+    assert_eq!(mmap(RO_PAGE, PAGE_SIZE, 1), PAGE_SIZE as isize);
+    let fd = open("badptr0\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    // 合法的缓冲区
+    assert_eq!(write_raw(fd, RW_PAGE, 10), 10);
+    assert_eq!(write_raw(fd, RO_PAGE, 10), 10);
+    assert_eq!(write_raw(fd, RW_PAGE, 0), 0);
+    // 非法的缓冲区
+    assert_eq!(write_raw(fd, UNMAPPED, 10), -1);
+    assert_eq!(write_raw(fd, RW_PAGE + PAGE_SIZE - 5, 10), -1);
+    assert_eq!(write_raw(fd, RW_PAGE, usize::MAX), -1);
+    assert_eq!(write_raw(fd, KERNEL_ADDR, 10), -1);
+    assert_eq!(write_raw(fd, TRAP_CONTEXT, 10), -1);
+    assert_eq!(write_raw(fd, (1 << 39) + RW_PAGE, 10), -1);
+    close(fd);
+    let fd = open("badptr0\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    // 内核不能写入只读页面和代码段
+    assert_eq!(read_raw(fd, RO_PAGE, 10), -1);
+    assert_eq!(read_raw(fd, (main as fn() -> i32) as usize, 10), -1);
+    assert_eq!(read_raw(fd, RW_PAGE + PAGE_SIZE - 5, 10), -1);
+    assert_eq!(syscall(SYSCALL_FSTAT, [fd, RO_PAGE, 0]), -1);
+    assert_eq!(syscall(SYSCALL_FSTAT, [fd, RW_PAGE + PAGE_SIZE - 8, 0]), -1);
+    assert_eq!(syscall(SYSCALL_FSTAT, [fd, RW_PAGE, 0]), 0);
+    assert_eq!(read_raw(fd, RW_PAGE, 20), 20);
+    close(fd);
+    assert_eq!(syscall(SYSCALL_PIPE, [RO_PAGE, 0, 0]), -1);
+    assert_eq!(syscall(SYSCALL_PIPE, [RW_PAGE + PAGE_SIZE - 8, 0, 0]), -1);
+    assert_eq!(syscall(SYSCALL_GETTIMEOFDAY, [RO_PAGE, 0, 0]), -1);
+    assert_eq!(syscall(SYSCALL_GETTIMEOFDAY, [KERNEL_ADDR, 0, 0]), -1);
+    // 路径没有以 \0 结尾并跨越到未映射的页面
+    let tail = RW_PAGE + PAGE_SIZE - 3;
+    for i in 0..3 {
+        unsafe { *((tail + i) as *mut u8) = b'a'; }
+    }
+    assert_eq!(open_raw(tail, OpenFlags::RDONLY.bits()), -1);
+    assert_eq!(open_raw(UNMAPPED, OpenFlags::RDONLY.bits()), -1);
+    // 非法的打开标志
+    let name = "badptr0\0";
+    assert_eq!(open_raw(name.as_ptr() as usize, 1 << 20), -1);
+    let path = "ch2_exit\0";
+    assert_eq!(syscall(SYSCALL_EXEC, [UNMAPPED, 0, 0]), -1);
+    assert_eq!(syscall(SYSCALL_EXEC, [path.as_ptr() as usize, UNMAPPED, 0]), -1);
+    let bad_args = [UNMAPPED, 0];
+    assert_eq!(syscall(SYSCALL_EXEC, [path.as_ptr() as usize, bad_args.as_ptr() as usize, 0]), -1);
+    // exit_code 地址非法时 waitpid 返回 -1，子进程之后仍然可以被回收
+    let pid = fork();
+    if pid == 0 {
+        exit(7);
+    }
+    assert!(pid > 0);
+    assert_eq!(waitpid_raw(pid as usize, RO_PAGE), -1);
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 7);
+    println!("Test badptr0 OK!");
+    0
+}
@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+use user_lib::{
+    chdir, close, exit, fork, fstat, getcwd, linkat, mkdir, open, openat, read, unlink, unlinkat,
+    waitpid, write, OpenFlags, Stat, StatMode, AT_FDCWD,
+};
+
+/// 测试多级目录、当前工作目录和 *at 系统调用的 dirfd，输出 Test dir OK! 就算正确。
+
+fn assert_cwd(expected: &str) {
+    let mut buf = [0u8; 64];
+    let len = getcwd(&mut buf);
+    assert_eq!(len as usize, expected.len() + 1);
+    assert_eq!(core::str::from_utf8(&buf[..len as usize - 1]).unwrap(), expected);
+}
+
+fn read_str(fd: isize, expected: &str) {
+    assert!(fd >= 0);
+    let mut buf = [0u8; 32];
+    let len = read(fd as usize, &mut buf) as usize;
+    assert_eq!(core::str::from_utf8(&buf[..len]).unwrap(), expected);
+    close(fd as usize);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let test_str = "Hello, dir!";
+    assert_cwd("/");
+    assert_eq!(mkdir("dir0\0"), 0);
+    assert_eq!(mkdir("dir0\0"), -1);
+    assert_eq!(mkdir("nodir/sub\0"), -1);
+    assert_eq!(mkdir("dir0/sub\0"), 0);
+    let fd = open("dir0/file\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd >= 0);
+    write(fd as usize, test_str.as_bytes());
+    close(fd as usize);
+    // 子目录中的文件不会出现在根目录中
+    assert_eq!(open("file\0", OpenFlags::RDONLY), -1);
+
+    // 相对路径相对于当前工作目录解析
+    assert_eq!(chdir("dir0\0"), 0);
+    assert_cwd("/dir0");
+    read_str(open("file\0", OpenFlags::RDONLY), test_str);
+    read_str(open("../dir0/./file\0", OpenFlags::RDONLY), test_str);
+    assert_eq!(chdir("sub\0"), 0);
+    assert_cwd("/dir0/sub");
+    assert_eq!(chdir("../..\0"), 0);
+    assert_cwd("/");
+    assert_eq!(chdir("..\0"), 0);
+    assert_cwd("/");
+    assert_eq!(chdir("dir0/file\0"), -1);
+    assert_eq!(chdir("dir0/none\0"), -1);
+    assert_cwd("/");
+
+    // 目录只能以只读方式打开，并可以作为 dirfd 使用
+    assert_eq!(open("dir0\0", OpenFlags::WRONLY), -1);
+    let dirfd = open("dir0\0", OpenFlags::RDONLY);
+    assert!(dirfd >= 0);
+    let stat = Stat::new();
+    assert_eq!(fstat(dirfd as usize, &stat), 0);
+    assert_eq!(stat.mode & StatMode::FMT, StatMode::DIR);
+    read_str(openat(dirfd, "file\0", OpenFlags::RDONLY), test_str);
+    read_str(openat(AT_FDCWD, "/dir0/file\0", OpenFlags::RDONLY), test_str);
+    assert_eq!(linkat(dirfd, "file\0", AT_FDCWD, "dir0/sub/link\0"), 0);
+    read_str(open("/dir0/sub/link\0", OpenFlags::RDONLY), test_str);
+    assert_eq!(unlinkat(dirfd, "sub/link\0"), 0);
+    assert_eq!(open("/dir0/sub/link\0", OpenFlags::RDONLY), -1);
+    // 普通文件不能作为 dirfd，目录不能被 unlink
+    let fd = open("dir0/file\0", OpenFlags::RDONLY);
+    assert_eq!(openat(fd, "file\0", OpenFlags::RDONLY), -1);
+    close(fd as usize);
+    close(dirfd as usize);
+    assert_eq!(unlink("dir0\0"), -1);
+
+    // 子进程继承当前工作目录
+    assert_eq!(chdir("/dir0/sub\0"), 0);
+    let pid = fork();
+    if pid == 0 {
+        assert_cwd("/dir0/sub");
+        read_str(open("../file\0", OpenFlags::RDONLY), test_str);
+        exit(0);
+    }
+    let mut exit_code: i32 = -1;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    println!("Test dir OK!");
+    0
+}
@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{chmod, close, exec, mmap, mprotect, open, write, OpenFlags};
+
+/*
+理想结果：损坏的 ELF、可写可执行的段、相互重叠或超出用户地址空间的段、入口点不可执行的程序都无法被 exec，
+exec 返回 -1 且进程继续运行；可写可执行的 mmap/mprotect 被拒绝，最终输出 Test elf0 OK!
+(内核以 allow_wx 启动参数启动时不适用)
+*/
+
+const PAGE_SIZE: usize = 0x1000;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+// 构造一个只有 Load 段的 RISC-V 64 位 ELF，每个段为 (虚拟地址, 内存大小, 标志)，段在文件中没有数据
+fn build_elf(segments: &[(u64, u64, u32)], entry: u64) -> Vec<u8> {
+    let mut elf = Vec::new();
+    elf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    elf.extend_from_slice(&[0; 8]);
+    elf.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+    elf.extend_from_slice(&243u16.to_le_bytes()); // EM_RISCV
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&entry.to_le_bytes());
+    elf.extend_from_slice(&64u64.to_le_bytes()); // e_phoff
+    elf.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    elf.extend_from_slice(&64u16.to_le_bytes()); // e_ehsize
+    elf.extend_from_slice(&56u16.to_le_bytes()); // e_phentsize
+    elf.extend_from_slice(&(segments.len() as u16).to_le_bytes());
+    elf.extend_from_slice(&64u16.to_le_bytes());
+    elf.extend_from_slice(&0u16.to_le_bytes());
+    elf.extend_from_slice(&0u16.to_le_bytes());
+    for &(vaddr, mem_size, flags) in segments {
+        elf.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+        elf.extend_from_slice(&flags.to_le_bytes());
+        elf.extend_from_slice(&(vaddr % PAGE_SIZE as u64).to_le_bytes()); // p_offset
+        elf.extend_from_slice(&vaddr.to_le_bytes());
+        elf.extend_from_slice(&vaddr.to_le_bytes());
+        elf.extend_from_slice(&0u64.to_le_bytes()); // p_filesz
+        elf.extend_from_slice(&mem_size.to_le_bytes());
+        elf.extend_from_slice(&(PAGE_SIZE as u64).to_le_bytes());
+    }
+    elf.resize(PAGE_SIZE, 0);
+    elf
+}
+
+fn exec_image(image: &[u8]) -> isize {
+    let fd = open("elf0\0", OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, image), image.len() as isize);
+    close(fd as usize);
+    // 新创建的文件没有执行权限
+    assert_eq!(chmod("elf0\0", 0o755), 0);
+    exec("elf0\0", &[core::ptr::null::<u8>()])
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let rx = PF_R | PF_X;
+    // 损坏的文件
+    assert_eq!(exec_image(b"not an elf"), -1);
+    let mut bad_magic = build_elf(&[(0x10000, 0x1000, rx)], 0x10000);
+    bad_magic[1] = b'X';
+    assert_eq!(exec_image(&bad_magic), -1);
+    let mut bad_machine = build_elf(&[(0x10000, 0x1000, rx)], 0x10000);
+    bad_machine[18] = 62; // EM_X86_64
+    assert_eq!(exec_image(&bad_machine), -1);
+    // 可写可执行的段
+    assert_eq!(exec_image(&build_elf(&[(0x10000, 0x1000, rx | PF_W)], 0x10000)), -1);
+    // 相互重叠的段，包括只在同一个页面内重叠的情况
+    assert_eq!(exec_image(&build_elf(&[(0x10000, 0x2000, rx), (0x11000, 0x1000, PF_R)], 0x10000)), -1);
+    assert_eq!(exec_image(&build_elf(&[(0x10000, 0x800, rx), (0x10800, 0x800, PF_R | PF_W)], 0x10000)), -1);
+    // 超出用户地址空间、覆盖 Trap 上下文和跳板、地址回绕的段
+    assert_eq!(exec_image(&build_elf(&[(1 << 38, 0x1000, rx)], 1 << 38)), -1);
+    assert_eq!(exec_image(&build_elf(&[(0x10000, 0x1000, rx), (u64::MAX - 0x1fff, 0x2000, PF_R)], 0x10000)), -1);
+    assert_eq!(exec_image(&build_elf(&[(0x10000, u64::MAX, rx)], 0x10000)), -1);
+    // 入口点不在可执行的段中
+    assert_eq!(exec_image(&build_elf(&[(0x10000, 0x1000, rx), (0x20000, 0x1000, PF_R)], 0x20000)), -1);
+    assert_eq!(exec_image(&build_elf(&[(0x10000, 0x1000, rx)], 0x30000)), -1);
+    // 可写可执行的内存映射
+    let start: usize = 0x10000000;
+    assert_eq!(mmap(start, PAGE_SIZE, 7), -1);
+    assert_eq!(mmap(start, PAGE_SIZE, 3), PAGE_SIZE as isize);
+    assert_eq!(mprotect(start, PAGE_SIZE, 7), -1);
+    assert_eq!(mprotect(start, PAGE_SIZE, 5), 0);
+    assert_eq!(mmap((1 << 38) - PAGE_SIZE, 2 * PAGE_SIZE, 3), -1);
+    println!("Test elf0 OK!");
+    0
+}
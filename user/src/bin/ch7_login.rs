@@ -0,0 +1,144 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate user_lib;
+
+const LF: u8 = 0x0au8;
+const CR: u8 = 0x0du8;
+const DL: u8 = 0x7fu8;
+const BS: u8 = 0x08u8;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{chdir, close, exec, flush, open, read, setgid, setuid, OpenFlags};
+use user_lib::console::getchar;
+
+/// 根据 /etc/passwd 验证用户名和密码，切换到这个用户之后在它的主目录中运行它的 shell
+/// /etc/passwd 的每一行为 name:hash:uid:gid:gecos:home:shell，
+/// hash 是密码的 64 位 FNV-1a 散列值的十六进制表示，为空时不需要密码
+
+struct Account<'a> {
+    name: &'a str,
+    hash: &'a str,
+    uid: u32,
+    gid: u32,
+    home: &'a str,
+    shell: &'a str,
+}
+
+fn parse_account<'a>(line: &'a str) -> Option<Account<'a>> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() != 7 {
+        return None;
+    }
+    Some(Account {
+        name: fields[0],
+        hash: fields[1],
+        uid: fields[2].parse().ok()?,
+        gid: fields[3].parse().ok()?,
+        home: fields[5],
+        shell: fields[6],
+    })
+}
+
+fn read_file(path: &str) -> Option<String> {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let mut data = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let len = read(fd as usize, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..len as usize]);
+    }
+    close(fd as usize);
+    String::from_utf8(data).ok()
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// 读入一行，输入密码时不回显
+fn read_line(echo: bool) -> String {
+    let mut line = String::new();
+    flush();
+    loop {
+        let c = getchar();
+        match c {
+            LF | CR => {
+                println!("");
+                return line;
+            }
+            BS | DL => {
+                if line.pop().is_some() && echo {
+                    print!("{} {}", BS as char, BS as char);
+                }
+            }
+            _ => {
+                if echo {
+                    print!("{}", c as char);
+                }
+                line.push(c as char);
+            }
+        }
+        flush();
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let passwd = match read_file("/etc/passwd\0") {
+        Some(passwd) => passwd,
+        None => {
+            println!("login: cannot read /etc/passwd");
+            return -1;
+        }
+    };
+    loop {
+        print!("login: ");
+        let name = read_line(true);
+        let account = passwd.lines().filter_map(parse_account).find(|account| account.name == name);
+        let password = match account {
+            Some(ref account) if account.hash.is_empty() => String::new(),
+            _ => {
+                print!("Password: ");
+                read_line(false)
+            }
+        };
+        let account = match account {
+            Some(account) if account.hash.is_empty()
+                || format!("{:016x}", fnv1a(password.as_bytes())) == account.hash => account,
+            _ => {
+                println!("Login incorrect");
+                continue;
+            }
+        };
+        // 先切换组，切换用户之后就没有权限再修改组了
+        if setgid(account.gid) != 0 || setuid(account.uid) != 0 {
+            println!("login: cannot switch to user {}", account.name);
+            return -1;
+        }
+        let home = format!("{}\0", account.home);
+        if chdir(home.as_str()) != 0 {
+            println!("login: cannot enter home directory {}", account.home);
+        }
+        let shell = format!("{}\0", account.shell);
+        exec(shell.as_str(), &[shell.as_ptr(), core::ptr::null::<u8>()]);
+        println!("login: cannot execute {}", account.shell);
+        return -1;
+    }
+}
@@ -0,0 +1,83 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{lstat, readlink, Stat, StatMode};
+
+/// 以 ls -ld 的格式输出每个参数的类型和访问权限、硬链接数量、所有者、所属组、大小、占用的块数和修改时间 (UTC)
+/// 暂时没有读取目录内容的系统调用，目录也只输出它自身的信息；符号链接输出它本身的信息以及它指向的路径
+
+// 把自 1970-01-01 以来的天数转换为公历的年月日
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// 按照 rwxrwxrwx 的格式输出访问权限
+fn print_mode(mode: StatMode) {
+    let fmt = mode & StatMode::FMT;
+    let type_ = if fmt == StatMode::DIR {
+        'd'
+    } else if fmt == StatMode::LINK {
+        'l'
+    } else {
+        '-'
+    };
+    print!("{}", type_);
+    let perm = (mode & StatMode::PERM).bits();
+    for shift in [6, 3, 0].iter() {
+        let bits = perm >> shift;
+        print!(
+            "{}{}{}",
+            if bits & 0o4 != 0 { 'r' } else { '-' },
+            if bits & 0o2 != 0 { 'w' } else { '-' },
+            if bits & 0o1 != 0 { 'x' } else { '-' },
+        );
+    }
+}
+
+fn print_time(time: u64) {
+    let (year, month, day) = civil_from_days(time / 86400);
+    let secs = time % 86400;
+    print!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year, month, day, secs / 3600, secs / 60 % 60, secs % 60
+    );
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let mut exit_code = 0;
+    for path in argv[1..argc].iter() {
+        let st = Stat::new();
+        // argv 中的字符串之后紧跟着 '\0'
+        if lstat(path, &st) != 0 {
+            println!("ls: cannot access '{}'", path);
+            exit_code = 1;
+            continue;
+        }
+        print_mode(st.mode);
+        print!(" {:>3} {:>5} {:>5} {:>8} {:>6} ", st.nlink, st.uid, st.gid, st.size, st.blocks);
+        print_time(st.mtime);
+        print!(" {}", path);
+        if st.mode & StatMode::FMT == StatMode::LINK {
+            let mut target = [0u8; 256];
+            let len = readlink(path, &mut target);
+            if len >= 0 {
+                print!(" -> {}", core::str::from_utf8(&target[..len as usize]).unwrap_or("?"));
+            }
+        }
+        println!("");
+    }
+    exit_code
+}
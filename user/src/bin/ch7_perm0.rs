@@ -0,0 +1,141 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+use user_lib::{
+    chmod, chown, close, exec, exit, fork, getgid, getuid, mkdir, open, read, setgid, setuid,
+    stat, unlink, waitpid, write, OpenFlags, Stat, StatMode,
+};
+
+/// 测试文件的所有者、访问权限以及切换到普通用户之后的权限检查，输出 Test perm OK! 就算正确。
+
+const USER: u32 = 1000;
+
+fn mode_of(path: &str) -> u32 {
+    let st = Stat::new();
+    assert_eq!(stat(path, &st), 0);
+    (st.mode & StatMode::PERM).bits()
+}
+
+// 复制一个应用作为测试用的可执行文件
+fn copy_file(from: &str, to: &str) {
+    let src = open(from, OpenFlags::RDONLY);
+    assert!(src > 0);
+    let dst = open(to, OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC);
+    assert!(dst > 0);
+    let mut buf = [0u8; 512];
+    loop {
+        let len = read(src as usize, &mut buf);
+        assert!(len >= 0);
+        if len == 0 {
+            break;
+        }
+        assert_eq!(write(dst as usize, &buf[..len as usize]), len);
+    }
+    close(src as usize);
+    close(dst as usize);
+}
+
+// 在子进程中以普通用户的身份运行 f，返回子进程的退出码
+fn run_as_user(f: fn()) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(setgid(USER), 0);
+        assert_eq!(setuid(USER), 0);
+        f();
+        exit(0);
+    }
+    let mut exit_code = -1;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit_code
+}
+
+fn user_checks() {
+    assert_eq!(getuid(), USER as isize);
+    assert_eq!(getgid(), USER as isize);
+    // 切换之后无法再回到 root
+    assert_eq!(setuid(0), -1);
+    assert_eq!(setgid(0), -1);
+    // 属于 root 且只有所有者可以读写的文件
+    assert_eq!(open("perm0a\0", OpenFlags::RDONLY), -1);
+    assert_eq!(open("perm0a\0", OpenFlags::WRONLY), -1);
+    assert_eq!(chmod("perm0a\0", 0o666), -1);
+    assert_eq!(chown("perm0a\0", USER, USER), -1);
+    // 根目录不允许普通用户写入
+    assert_eq!(unlink("perm0a\0"), -1);
+    assert_eq!(open("perm0b\0", OpenFlags::CREATE | OpenFlags::WRONLY), -1);
+    // 没有执行权限
+    assert_eq!(exec("perm0x\0", &[core::ptr::null::<u8>()]), -1);
+    // 属于自己的目录中可以创建和删除文件，新文件属于当前用户
+    let fd = open("perm0d/user\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    close(fd as usize);
+    let st = Stat::new();
+    assert_eq!(stat("perm0d/user\0", &st), 0);
+    assert_eq!((st.uid, st.gid), (USER, USER));
+    assert_eq!(mode_of("perm0d/user\0"), 0o644);
+    assert_eq!(chmod("perm0d/user\0", 0o400), 0);
+    assert_eq!(open("perm0d/user\0", OpenFlags::WRONLY), -1);
+    // 去掉目录的搜索权限之后无法访问其中的文件
+    assert_eq!(chmod("perm0d\0", 0o600), 0);
+    assert_eq!(stat("perm0d/user\0", &st), -1);
+    assert_eq!(chmod("perm0d\0", 0o755), 0);
+    assert_eq!(unlink("perm0d/user\0"), 0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(getuid(), 0);
+    unlink("perm0a\0");
+    unlink("perm0b\0");
+    unlink("perm0x\0");
+    unlink("perm0d/user\0");
+
+    // 新文件属于当前用户，默认的访问权限是 0o644
+    let fd = open("perm0a\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    close(fd as usize);
+    let st = Stat::new();
+    assert_eq!(stat("perm0a\0", &st), 0);
+    assert_eq!(st.mode & StatMode::FMT, StatMode::FILE);
+    assert_eq!((st.uid, st.gid), (0, 0));
+    assert_eq!(mode_of("perm0a\0"), 0o644);
+    assert_eq!(chmod("perm0a\0", 0o600), 0);
+    assert_eq!(mode_of("perm0a\0"), 0o600);
+
+    // 即使是 root 也不能执行没有执行位的文件
+    copy_file("/ch2_exit\0", "perm0x\0");
+    assert_eq!(exec("perm0x\0", &[core::ptr::null::<u8>()]), -1);
+    assert_eq!(chmod("perm0x\0", 0o700), 0);
+    let pid = fork();
+    if pid == 0 {
+        exec("perm0x\0", &[core::ptr::null::<u8>()]);
+        panic!("exec perm0x failed");
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 1234);
+
+    // 没有删除目录的系统调用，目录可能在之前的运行中已经创建
+    mkdir("perm0d\0");
+    assert_eq!(chown("perm0d\0", USER, USER), 0);
+    assert_eq!(chmod("perm0d\0", 0o755), 0);
+    assert_eq!(stat("perm0d\0", &st), 0);
+    assert_eq!(st.mode, StatMode::DIR | StatMode::from_bits_truncate(0o755));
+    assert_eq!((st.uid, st.gid), (USER, USER));
+    // u32::MAX 表示保持不变
+    assert_eq!(chown("perm0d\0", u32::MAX, 0), 0);
+    assert_eq!(stat("perm0d\0", &st), 0);
+    assert_eq!((st.uid, st.gid), (USER, 0));
+    assert_eq!(chown("perm0d\0", u32::MAX, USER), 0);
+
+    assert_eq!(run_as_user(user_checks), 0);
+    // 普通用户的操作都没有生效
+    assert_eq!(mode_of("perm0a\0"), 0o600);
+    assert_eq!(stat("perm0b\0", &st), -1);
+    assert_eq!(unlink("perm0a\0"), 0);
+    assert_eq!(unlink("perm0x\0"), 0);
+    println!("Test perm OK!");
+    0
+}
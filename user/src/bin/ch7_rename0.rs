@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+use user_lib::{
+    chdir, close, getcwd, mkdir, open, openat, read, rename, renameat2, stat, unlink, write,
+    OpenFlags, Stat, AT_FDCWD, RENAME_EXCHANGE, RENAME_NOREPLACE,
+};
+
+/// 测试改名、移动、原子替换和交换，以及已打开的目录和当前工作目录在改名之后的路径，输出 Test rename OK! 就算正确。
+
+fn create(path: &str, content: &str) {
+    let fd = open(path, OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, content.as_bytes()), content.len() as isize);
+    close(fd as usize);
+}
+
+fn read_str(path: &str, expected: &str) {
+    let fd = open(path, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buf = [0u8; 32];
+    let len = read(fd as usize, &mut buf) as usize;
+    assert_eq!(core::str::from_utf8(&buf[..len]).unwrap(), expected);
+    close(fd as usize);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // 没有删除目录的系统调用，目录可能在之前的运行中已经创建，上一次运行移走的 sub 被移回原处
+    mkdir("/rename0\0");
+    mkdir("/rename0/a\0");
+    mkdir("/rename0/b\0");
+    rename("/rename0/b/sub\0", "/rename0/a/sub\0");
+    mkdir("/rename0/a/sub\0");
+    mkdir("/rename0/d\0");
+    for path in ["/rename0/a/file\0", "/rename0/a/x\0", "/rename0/b/other\0"].iter() {
+        unlink(path);
+    }
+    let st = Stat::new();
+
+    // 同一目录中改名，以及通过 dirfd 移动到另一个目录中
+    create("/rename0/a/file\0", "first");
+    let file_fd = open("/rename0/a/file\0", OpenFlags::RDONLY);
+    assert!(file_fd > 0);
+    assert_eq!(rename("/rename0/a/file\0", "/rename0/a/renamed\0"), 0);
+    assert_eq!(stat("/rename0/a/file\0", &st), -1);
+    let dir_fd = open("/rename0/a\0", OpenFlags::RDONLY);
+    assert!(dir_fd > 0);
+    assert_eq!(renameat2(dir_fd, "renamed\0", AT_FDCWD, "/rename0/b/moved\0", 0), 0);
+    close(dir_fd as usize);
+    read_str("/rename0/b/moved\0", "first");
+    assert_eq!(rename("/rename0/a/missing\0", "/rename0/b/missing\0"), -1);
+
+    // 新路径已经存在时被原子地替换，带有 RENAME_NOREPLACE 时失败
+    create("/rename0/b/other\0", "second");
+    assert_eq!(renameat2(AT_FDCWD, "/rename0/b/moved\0", AT_FDCWD, "/rename0/b/other\0", RENAME_NOREPLACE), -1);
+    assert_eq!(rename("/rename0/b/moved\0", "/rename0/b/other\0"), 0);
+    assert_eq!(stat("/rename0/b/moved\0", &st), -1);
+    read_str("/rename0/b/other\0", "first");
+
+    // 交换两个已经存在的路径
+    create("/rename0/a/x\0", "xx");
+    assert_eq!(renameat2(AT_FDCWD, "/rename0/a/x\0", AT_FDCWD, "/rename0/b/none\0", RENAME_EXCHANGE), -1);
+    assert_eq!(renameat2(AT_FDCWD, "/rename0/a/x\0", AT_FDCWD, "/rename0/b/other\0", RENAME_EXCHANGE | RENAME_NOREPLACE), -1);
+    assert_eq!(renameat2(AT_FDCWD, "/rename0/a/x\0", AT_FDCWD, "/rename0/b/other\0", RENAME_EXCHANGE), 0);
+    read_str("/rename0/a/x\0", "first");
+    read_str("/rename0/b/other\0", "xx");
+    // 已经打开的文件不受改名影响
+    let mut buf = [0u8; 32];
+    assert_eq!(read(file_fd as usize, &mut buf), 5);
+    close(file_fd as usize);
+
+    // 移动目录之后，已经打开的目录作为 dirfd 时使用新的路径
+    let sub_fd = open("/rename0/a/sub\0", OpenFlags::RDONLY);
+    assert!(sub_fd > 0);
+    assert_eq!(rename("/rename0/a/sub\0", "/rename0/b/sub\0"), 0);
+    let fd = openat(sub_fd, "inner\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    close(fd as usize);
+    close(sub_fd as usize);
+    assert_eq!(stat("/rename0/b/sub/inner\0", &st), 0);
+    assert_eq!(stat("/rename0/b/sub/../other\0", &st), 0);
+    // 位于被移动的目录之下的当前工作目录随之更新
+    assert_eq!(chdir("/rename0/b/sub\0"), 0);
+    assert_eq!(rename("/rename0/b\0", "/rename0/c\0"), 0);
+    let mut cwd = [0u8; 32];
+    assert_eq!(getcwd(&mut cwd), 15);
+    assert_eq!(&cwd[..15], b"/rename0/c/sub\0");
+    assert_eq!(stat("inner\0", &st), 0);
+    assert_eq!(rename("/rename0/c\0", "/rename0/b\0"), 0);
+    assert_eq!(stat("inner\0", &st), 0);
+    assert_eq!(chdir("/\0"), 0);
+    // 被替换掉的空目录已经没有路径，打开它的文件描述符不能再作为 dirfd，位于其中的进程也没有当前工作目录
+    mkdir("/rename0/c\0");
+    let d_fd = open("/rename0/d\0", OpenFlags::RDONLY);
+    assert!(d_fd > 0);
+    assert_eq!(chdir("/rename0/d\0"), 0);
+    assert_eq!(rename("/rename0/c\0", "/rename0/d\0"), 0);
+    assert_eq!(openat(d_fd, "new\0", OpenFlags::CREATE | OpenFlags::WRONLY), -1);
+    close(d_fd as usize);
+    assert_eq!(getcwd(&mut cwd), -1);
+    assert_eq!(stat("inner\0", &st), -1);
+    assert_eq!(chdir("/\0"), 0);
+    // 目录不能被移动到自身之下，文件不能替换目录，非空目录不能被替换
+    assert_eq!(rename("/rename0/b\0", "/rename0/b/sub/b\0"), -1);
+    assert_eq!(rename("/rename0/a/x\0", "/rename0/b/sub\0"), -1);
+    assert_eq!(rename("/rename0/a\0", "/rename0/b/sub\0"), -1);
+    assert_eq!(unlink("/rename0/b/sub/inner\0"), 0);
+
+    assert_eq!(unlink("/rename0/a/x\0"), 0);
+    assert_eq!(unlink("/rename0/b/other\0"), 0);
+    println!("Test rename OK!");
+    0
+}
@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fork, get_time, open, read, slab_stats, unlink, waitpid, write, OpenFlags, SlabStats,
+};
+
+/*
+fork 密集型和文件 I/O 密集型负载的性能测试，并检查进程控制块和块缓存确实从内核的对象缓存中分配
+以默认参数和 noslab 启动参数各运行一次，对比两次输出的耗时就能看出对象缓存的效果
+理想结果：输出两项耗时、各个对象缓存的使用情况以及 slab_bench passed!
+*/
+
+const FORKS: usize = 200;
+const FILE_BLOCKS: usize = 256;
+const READ_ROUNDS: usize = 8;
+const BLOCK_SIZE: usize = 512;
+
+fn fork_bench() -> isize {
+    let start = get_time();
+    for i in 0..FORKS {
+        let pid = fork();
+        if pid == 0 {
+            exit(i as i32);
+        }
+        let mut exit_code: i32 = 0;
+        assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+        assert_eq!(exit_code, i as i32);
+    }
+    get_time() - start
+}
+
+fn io_bench() -> isize {
+    let fname = "slab_bench\0";
+    let start = get_time();
+    let fd = open(fname, OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC);
+    assert!(fd > 0);
+    let mut buf = [0u8; BLOCK_SIZE];
+    for i in 0..FILE_BLOCKS {
+        buf.iter_mut().for_each(|b| *b = i as u8);
+        assert_eq!(write(fd as usize, &buf), BLOCK_SIZE as isize);
+    }
+    close(fd as usize);
+    // 文件远大于块缓存的容量，每一轮读取都会替换掉大部分块缓存
+    for _ in 0..READ_ROUNDS {
+        let fd = open(fname, OpenFlags::RDONLY);
+        assert!(fd > 0);
+        for i in 0..FILE_BLOCKS {
+            assert_eq!(read(fd as usize, &mut buf), BLOCK_SIZE as isize);
+            assert!(buf.iter().all(|b| *b == i as u8));
+        }
+        close(fd as usize);
+    }
+    assert_eq!(unlink(fname), 0);
+    get_time() - start
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fork_ms = fork_bench();
+    let io_ms = io_bench();
+    println!("{} x fork/exit/waitpid: {} ms", FORKS, fork_ms);
+    println!("write {} blocks and read them {} times: {} ms", FILE_BLOCKS, READ_ROUNDS, io_ms);
+    let mut stats = [SlabStats::new(); 8];
+    let count = slab_stats(&mut stats);
+    assert!(count >= 0 && count as usize <= stats.len());
+    let stats = &stats[..count as usize];
+    for cache in stats.iter() {
+        println!("{:?}", cache);
+        assert!(cache.in_use <= cache.objects);
+        assert_eq!(cache.allocs - cache.frees, cache.in_use);
+        // 完全空闲的 slab 已经被归还，每个缓存最多保留一个
+        assert!(cache.slabs <= cache.in_use + 1);
+    }
+    let cache = |name: &str| stats.iter().find(|cache| cache.name() == name);
+    let (tcb, block_cache) = (cache("task_control_block"), cache("block_cache"));
+    assert!(tcb.is_some() && block_cache.is_some());
+    // 以 noslab 启动时对象缓存不会被使用
+    if tcb.unwrap().allocs == 0 {
+        println!("slab caches are disabled");
+    } else {
+        assert!(tcb.unwrap().allocs >= FORKS);
+        assert!(block_cache.unwrap().allocs >= FILE_BLOCKS);
+    }
+    println!("slab_bench passed!");
+    0
+}
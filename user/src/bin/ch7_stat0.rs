@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+use user_lib::{
+    close, fstat, fstatat, link, open, read, stat, unlink, write, OpenFlags, Stat, StatMode,
+    AT_FDCWD,
+};
+
+/// 测试 fstat 和 fstatat 给出的大小、块数和时间戳，输出 Test stat OK! 就算正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fname = "stat0\0";
+    unlink(fname);
+    let fd = open(fname, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let st = Stat::new();
+    assert_eq!(fstat(fd, &st), 0);
+    assert_eq!(st.size, 0);
+    assert_eq!(st.blocks, 0);
+    assert_eq!(st.blksize, 512);
+    // 新文件的三个时间戳都是创建时间
+    assert_eq!(st.atime, st.mtime);
+    assert_eq!(st.ctime, st.mtime);
+    let created = st.mtime;
+
+    // 1000 字节占用两个 512 字节的块，写入更新 mtime 和 ctime
+    let buf = [0x33u8; 1000];
+    assert_eq!(write(fd, &buf), 1000);
+    assert_eq!(fstat(fd, &st), 0);
+    assert_eq!(st.size, 1000);
+    assert_eq!(st.blocks, 2);
+    assert!(st.mtime >= created);
+    assert_eq!(st.ctime, st.mtime);
+    close(fd);
+
+    // 不打开文件，通过路径获取同样的信息
+    let st2 = Stat::new();
+    assert_eq!(fstatat(AT_FDCWD, fname, &st2), 0);
+    assert_eq!(st2.ino, st.ino);
+    assert_eq!(st2.mode & StatMode::FMT, StatMode::FILE);
+    assert_eq!(st2.size, 1000);
+    assert_eq!(st2.mtime, st.mtime);
+
+    // 修改之后的读取更新 atime
+    let fd = open(fname, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut read_buf = [0u8; 16];
+    assert_eq!(read(fd as usize, &mut read_buf), 16);
+    assert_eq!(fstat(fd as usize, &st2), 0);
+    assert!(st2.atime >= st.mtime);
+    close(fd as usize);
+
+    // 硬链接只改变 ctime
+    assert_eq!(link(fname, "stat1\0"), 0);
+    assert_eq!(stat("stat1\0", &st2), 0);
+    assert_eq!(st2.nlink, 2);
+    assert_eq!(st2.mtime, st.mtime);
+    assert!(st2.ctime >= st.mtime);
+    assert_eq!(unlink("stat1\0"), 0);
+    assert_eq!(unlink(fname), 0);
+
+    assert_eq!(stat(fname, &st2), -1);
+    assert_eq!(stat("/\0", &st2), 0);
+    assert_eq!(st2.mode & StatMode::FMT, StatMode::DIR);
+    println!("Test stat OK!");
+    0
+}
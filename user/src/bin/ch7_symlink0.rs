@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+use user_lib::{
+    chdir, close, getcwd, link, lstat, mkdir, open, read, readlink, stat, symlink, unlink, write,
+    OpenFlags, Stat, StatMode,
+};
+
+/// 测试符号链接的创建、读取、展开以及 O_NOFOLLOW，输出 Test symlink OK! 就算正确。
+
+const FILES: [&str; 10] = [
+    "/symlink0/file\0",
+    "/symlink0/rel\0",
+    "/symlink0/abs\0",
+    "/symlink0/dirlink\0",
+    "/symlink0/loop1\0",
+    "/symlink0/loop2\0",
+    "/symlink0/dangling\0",
+    "/symlink0/hard\0",
+    "/symlink0/sub/lnk\0",
+    "/symlink0/uplink\0",
+];
+
+fn read_all(path: &str, buf: &mut [u8]) -> isize {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return fd;
+    }
+    let len = read(fd as usize, buf);
+    close(fd as usize);
+    len
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // 没有删除目录的系统调用，目录可能在之前的运行中已经创建
+    mkdir("/symlink0\0");
+    mkdir("/symlink0/sub\0");
+    for path in FILES.iter() {
+        unlink(path);
+    }
+    let fd = open("/symlink0/file\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, b"symlink0"), 8);
+    close(fd as usize);
+
+    // 相对路径相对于符号链接所在的目录，绝对路径从根目录开始
+    assert_eq!(symlink("file\0", "/symlink0/rel\0"), 0);
+    assert_eq!(symlink("/symlink0/rel\0", "/symlink0/abs\0"), 0);
+    assert_eq!(symlink("file\0", "/symlink0/rel\0"), -1);
+    assert_eq!(symlink("\0", "/symlink0/empty\0"), -1);
+    let mut buf = [0u8; 32];
+    assert_eq!(readlink("/symlink0/abs\0", &mut buf), 13);
+    assert_eq!(&buf[..13], b"/symlink0/rel");
+    // 缓冲区不够时被截断
+    assert_eq!(readlink("/symlink0/abs\0", &mut buf[..4]), 4);
+    assert_eq!(readlink("/symlink0/file\0", &mut buf), -1);
+    assert_eq!(read_all("/symlink0/abs\0", &mut buf), 8);
+    assert_eq!(&buf[..8], b"symlink0");
+
+    // stat 展开符号链接，lstat 不展开
+    let st = Stat::new();
+    let lst = Stat::new();
+    assert_eq!(stat("/symlink0/abs\0", &st), 0);
+    assert_eq!(st.mode & StatMode::FMT, StatMode::FILE);
+    assert_eq!(st.size, 8);
+    assert_eq!(lstat("/symlink0/abs\0", &lst), 0);
+    assert_eq!(lst.mode, StatMode::LINK | StatMode::from_bits_truncate(0o777));
+    assert_eq!(lst.size, 13);
+    assert_ne!(lst.ino, st.ino);
+
+    // O_NOFOLLOW 只影响最后一个路径分量
+    assert_eq!(open("/symlink0/rel\0", OpenFlags::RDONLY | OpenFlags::NOFOLLOW), -1);
+    let fd = open("/symlink0/file\0", OpenFlags::RDONLY | OpenFlags::NOFOLLOW);
+    assert!(fd > 0);
+    close(fd as usize);
+    assert_eq!(symlink("/symlink0\0", "/symlink0/dirlink\0"), 0);
+    let fd = open("/symlink0/dirlink/file\0", OpenFlags::RDONLY | OpenFlags::NOFOLLOW);
+    assert!(fd > 0);
+    close(fd as usize);
+
+    // 通过指向目录的符号链接创建文件，切换工作目录之后得到的是展开之后的路径
+    let fd = open("/symlink0/dirlink/dirlink/new\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    close(fd as usize);
+    assert_eq!(stat("/symlink0/new\0", &st), 0);
+    assert_eq!(unlink("/symlink0/dirlink/new\0"), 0);
+    assert_eq!(chdir("/symlink0/dirlink\0"), 0);
+    let mut cwd = [0u8; 32];
+    assert_eq!(getcwd(&mut cwd), 10);
+    assert_eq!(&cwd[..10], b"/symlink0\0");
+    assert_eq!(chdir("/\0"), 0);
+
+    // 符号链接之后的 ".." 是它指向的目录的父目录，而不是符号链接所在的目录
+    assert_eq!(symlink("/symlink0\0", "/symlink0/sub/lnk\0"), 0);
+    assert_eq!(read_all("/symlink0/sub/lnk/../symlink0/file\0", &mut buf), 8);
+    assert_eq!(symlink("sub/lnk/..\0", "/symlink0/uplink\0"), 0);
+    assert_eq!(read_all("/symlink0/uplink/symlink0/file\0", &mut buf), 8);
+    assert_eq!(chdir("/symlink0/uplink/symlink0/sub/lnk/..\0"), 0);
+    assert_eq!(getcwd(&mut cwd), 2);
+    assert_eq!(&cwd[..2], b"/\0");
+
+    // 循环的符号链接在展开一定次数之后失败，指向不存在的路径的符号链接无法打开
+    assert_eq!(symlink("loop2\0", "/symlink0/loop1\0"), 0);
+    assert_eq!(symlink("loop1\0", "/symlink0/loop2\0"), 0);
+    assert_eq!(open("/symlink0/loop1\0", OpenFlags::RDONLY), -1);
+    assert_eq!(stat("/symlink0/loop1/file\0", &st), -1);
+    assert_eq!(lstat("/symlink0/loop1\0", &lst), 0);
+    assert_eq!(symlink("nowhere\0", "/symlink0/dangling\0"), 0);
+    assert_eq!(open("/symlink0/dangling\0", OpenFlags::RDONLY), -1);
+    assert_eq!(open("/symlink0/dangling\0", OpenFlags::CREATE | OpenFlags::WRONLY), -1);
+
+    // 硬链接指向符号链接本身，删除符号链接不影响它指向的文件
+    assert_eq!(link("/symlink0/rel\0", "/symlink0/hard\0"), 0);
+    assert_eq!(lstat("/symlink0/hard\0", &lst), 0);
+    assert_eq!(lst.mode & StatMode::FMT, StatMode::LINK);
+    assert_eq!(lst.nlink, 2);
+    assert_eq!(unlink("/symlink0/rel\0"), 0);
+    assert_eq!(read_all("/symlink0/hard\0", &mut buf), 8);
+    assert_eq!(read_all("/symlink0/abs\0", &mut buf), -1);
+    assert_eq!(stat("/symlink0/file\0", &st), 0);
+    assert_eq!(st.nlink, 1);
+
+    for path in FILES.iter().filter(|&&path| path != "/symlink0/rel\0") {
+        assert_eq!(unlink(path), 0);
+    }
+    println!("Test symlink OK!");
+    0
+}
@@ -0,0 +1,47 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+use user_lib::{close, fstat, open, read, unlink, write, OpenFlags, Stat};
+
+/// 测试 unlink 释放 inode 和数据块，输出 Test unlink OK! 就算正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let test_str = "Hello, unlink!";
+    let fname = "unlink0\0";
+    let fd = open(fname, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, test_str.as_bytes());
+    close(fd as usize);
+
+    // 仍被打开的文件在 unlink 之后依然可以读写，直到最后一次关闭
+    let fd = open(fname, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(unlink(fname), 0);
+    assert_eq!(unlink(fname), -1);
+    assert_eq!(open(fname, OpenFlags::RDONLY), -1);
+    let stat = Stat::new();
+    assert_eq!(fstat(fd, &stat), 0);
+    assert_eq!(stat.nlink, 0);
+    let mut buf = [0u8; 32];
+    let len = read(fd, &mut buf) as usize;
+    assert_eq!(core::str::from_utf8(&buf[..len]).unwrap(), test_str);
+    close(fd);
+
+    // 反复写入 2MiB 再删除，总量超过 fs.img 的大小，数据块泄漏时会耗尽磁盘
+    let chunk = [0x5au8; 4096];
+    for _ in 0..16 {
+        let fd = open("unlink1\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+        assert!(fd > 0);
+        for _ in 0..512 {
+            assert_eq!(write(fd as usize, &chunk), chunk.len() as isize);
+        }
+        close(fd as usize);
+        assert_eq!(unlink("unlink1\0"), 0);
+    }
+    println!("Test unlink OK!");
+    0
+}
@@ -29,6 +29,9 @@ static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
 #[global_allocator]
 static HEAP: LockedHeap<32> = LockedHeap::empty();
 
+// 内核放在用户栈上的辅助向量的起始地址，由 _start 根据 argv 找到
+static mut AUXV: usize = 0;
+
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
     panic!("Heap allocation error, layout = {:?}", layout);
@@ -41,6 +44,14 @@ pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
         HEAP.lock()
             .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
     }
+    // argv 数组以 0 结尾，之后是同样以 0 结尾的 envp 数组，再之后就是辅助向量
+    unsafe {
+        let mut p = argv + (argc + 1) * core::mem::size_of::<usize>();
+        while (p as *const usize).read_volatile() != 0 {
+            p += core::mem::size_of::<usize>();
+        }
+        AUXV = p + core::mem::size_of::<usize>();
+    }
     let mut v: Vec<&'static str> = Vec::new();
     // 分别取出 argc 个字符串的起始地址 (基于字符串数组的 base 地址 argv)
     for i in 0..argc {
@@ -75,6 +86,7 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        const NOFOLLOW = 1 << 17;
     }
 }
 
@@ -102,8 +114,29 @@ pub struct Stat {
     pub mode: StatMode,
     /// number of hard links
     pub nlink: u32,
-    /// unused pad
-    pad: [u64; 7],
+    /// user ID of owner
+    pub uid: u32,
+    /// group ID of owner
+    pub gid: u32,
+    /// rdev, unsupported
+    pad0: [u64; 2],
+    /// total size in bytes
+    pub size: u64,
+    /// block size for filesystem I/O
+    pub blksize: u32,
+    pad1: u32,
+    /// number of 512B blocks allocated
+    pub blocks: u64,
+    /// time of last access, in seconds since the epoch
+    pub atime: u64,
+    pub atime_nsec: u64,
+    /// time of last modification
+    pub mtime: u64,
+    pub mtime_nsec: u64,
+    /// time of last status change
+    pub ctime: u64,
+    pub ctime_nsec: u64,
+    pad2: [u32; 2],
 }
 
 impl Stat {
@@ -113,7 +146,20 @@ impl Stat {
             ino: 0,
             mode: StatMode::NULL,
             nlink: 0,
-            pad: [0; 7],
+            uid: 0,
+            gid: 0,
+            pad0: [0; 2],
+            size: 0,
+            blksize: 0,
+            pad1: 0,
+            blocks: 0,
+            atime: 0,
+            atime_nsec: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            ctime: 0,
+            ctime_nsec: 0,
+            pad2: [0; 2],
         }
     }
 }
@@ -125,10 +171,101 @@ bitflags! {
         const DIR   = 0o040000;
         /// ordinary regular file
         const FILE  = 0o100000;
+        /// symbolic link
+        const LINK  = 0o120000;
+        /// bit mask for the file type
+        const FMT   = 0o170000;
+        /// bit mask for the permission bits
+        const PERM  = 0o007777;
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct RLimit {
+    /// soft limit
+    pub rlim_cur: usize,
+    /// hard limit
+    pub rlim_max: usize,
+}
+
+impl RLimit {
+    pub fn new() -> Self {
+        RLimit { rlim_cur: 0, rlim_max: 0 }
     }
 }
 
-const AT_FDCWD: isize = -100;
+pub const RLIMIT_STACK: usize = 3;
+
+/// personality 标志：之后 exec 的程序不进行地址空间布局随机化
+pub const ADDR_NO_RANDOMIZE: usize = 0x0040000;
+
+/// 辅助向量的类型
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3;
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_ENTRY: usize = 9;
+pub const AT_RANDOM: usize = 25;
+
+/// 内核堆的使用情况，单位均为字节
+#[repr(C)]
+#[derive(Debug)]
+pub struct HeapStats {
+    pub total: usize,
+    pub in_use: usize,
+    pub peak: usize,
+    pub failures: usize,
+}
+
+impl HeapStats {
+    pub fn new() -> Self {
+        HeapStats { total: 0, in_use: 0, peak: 0, failures: 0 }
+    }
+}
+
+pub const SLAB_NAME_LEN: usize = 24;
+
+/// 内核对象缓存的使用情况
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SlabStats {
+    pub name: [u8; SLAB_NAME_LEN],
+    pub object_size: usize,
+    pub slabs: usize,
+    pub objects: usize,
+    pub in_use: usize,
+    pub allocs: usize,
+    pub frees: usize,
+}
+
+impl SlabStats {
+    pub fn new() -> Self {
+        SlabStats { name: [0; SLAB_NAME_LEN], object_size: 0, slabs: 0, objects: 0, in_use: 0, allocs: 0, frees: 0 }
+    }
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|c| *c == 0).unwrap_or(SLAB_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+}
+
+impl core::fmt::Debug for SlabStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f, "SlabStats {{ name: {:?}, object_size: {}, slabs: {}, in_use: {}/{}, allocs: {}, frees: {} }}",
+            self.name(), self.object_size, self.slabs, self.in_use, self.objects, self.allocs, self.frees
+        )
+    }
+}
+
+pub const AT_FDCWD: isize = -100;
+pub const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+pub const RENAME_NOREPLACE: u32 = 1;
+pub const RENAME_EXCHANGE: u32 = 2;
+
+pub const IPC_PRIVATE: usize = 0;
+pub const IPC_RMID: usize = 0;
 
 pub fn write(fd: usize, buf: &[u8]) -> isize { sys_write(fd, buf) }
 pub fn read(fd: usize, buf: &mut [u8]) -> isize { sys_read(fd, buf) }
@@ -159,6 +296,9 @@ pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
 pub fn munmap(start: usize, len: usize) -> isize {
     sys_munmap(start, len)
 }
+pub fn mprotect(start: usize, len: usize, prot: usize) -> isize {
+    sys_mprotect(start, len, prot)
+}
 pub fn getpid() -> isize {
     sys_getpid()
 }
@@ -194,6 +334,35 @@ pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
     }
 }
 
+pub fn getrlimit(resource: usize, rlim: &mut RLimit) -> isize {
+    sys_getrlimit(resource, rlim)
+}
+pub fn setrlimit(resource: usize, rlim: &RLimit) -> isize {
+    sys_setrlimit(resource, rlim)
+}
+pub fn personality(persona: usize) -> isize {
+    sys_personality(persona)
+}
+/// 在辅助向量中查找类型为 key 的项
+pub fn getauxval(key: usize) -> Option<usize> {
+    let mut p = unsafe { AUXV };
+    loop {
+        let (k, v) = unsafe { ((p as *const usize).read_volatile(), ((p + 8) as *const usize).read_volatile()) };
+        if k == AT_NULL {
+            return None;
+        }
+        if k == key {
+            return Some(v);
+        }
+        p += 2 * core::mem::size_of::<usize>();
+    }
+}
+pub fn heap_stats(stats: &mut HeapStats) -> isize {
+    sys_heap_stats(stats)
+}
+pub fn slab_stats(stats: &mut [SlabStats]) -> isize {
+    sys_slab_stats(stats)
+}
 pub fn spawn(path: &str) -> isize {
     sys_spawn(path)
 }
@@ -210,9 +379,14 @@ pub fn mail_read(buf: &mut [u8]) -> isize {
 pub fn mail_write(pid: usize, buf: &[u8]) -> isize {
     sys_mail_write(pid, buf)
 }
+pub fn shmget(key: usize, size: usize) -> isize { sys_shmget(key, size) }
+pub fn shmat(id: usize, addr: usize) -> isize { sys_shmat(id, addr) }
+pub fn shmdt(addr: usize) -> isize { sys_shmdt(addr) }
+pub fn shmctl(id: usize, cmd: usize) -> isize { sys_shmctl(id, cmd) }
 pub fn dup(fd: usize) -> isize { sys_dup(fd) }
+// 新创建的文件的访问权限为 0o644，新创建的目录为 0o755
 pub fn open(path: &str, flags: OpenFlags) -> isize {
-    sys_openat(AT_FDCWD as usize, path, flags.bits, OpenFlags::RDWR.bits)
+    sys_openat(AT_FDCWD as usize, path, flags.bits, 0o644)
 }
 pub fn link(old_path: &str, new_path: &str) -> isize {
     sys_linkat(AT_FDCWD as usize, old_path, AT_FDCWD as usize, new_path, 0)
@@ -223,3 +397,56 @@ pub fn unlink(path: &str) -> isize {
 pub fn fstat(fd: usize, st: &Stat) -> isize {
     sys_fstat(fd, st)
 }
+pub fn fstatat(dirfd: isize, path: &str, st: &Stat) -> isize {
+    sys_fstatat(dirfd as usize, path, st, 0)
+}
+pub fn stat(path: &str, st: &Stat) -> isize {
+    sys_fstatat(AT_FDCWD as usize, path, st, 0)
+}
+// 路径是符号链接时获取符号链接本身的状态
+pub fn lstat(path: &str, st: &Stat) -> isize {
+    sys_fstatat(AT_FDCWD as usize, path, st, AT_SYMLINK_NOFOLLOW)
+}
+pub fn symlink(target: &str, link_path: &str) -> isize {
+    sys_symlinkat(target, AT_FDCWD as usize, link_path)
+}
+// 成功时返回写入 buf 的字节数，写入的路径不以 '\0' 结尾，超出 buf 长度的部分被截断
+pub fn readlink(path: &str, buf: &mut [u8]) -> isize {
+    sys_readlinkat(AT_FDCWD as usize, path, buf)
+}
+pub fn openat(dirfd: isize, path: &str, flags: OpenFlags) -> isize {
+    sys_openat(dirfd as usize, path, flags.bits, 0o644)
+}
+pub fn linkat(old_dirfd: isize, old_path: &str, new_dirfd: isize, new_path: &str) -> isize {
+    sys_linkat(old_dirfd as usize, old_path, new_dirfd as usize, new_path, 0)
+}
+// 新路径已经存在时被原子地替换
+pub fn rename(old_path: &str, new_path: &str) -> isize {
+    sys_renameat2(AT_FDCWD as usize, old_path, AT_FDCWD as usize, new_path, 0)
+}
+pub fn renameat2(old_dirfd: isize, old_path: &str, new_dirfd: isize, new_path: &str, flags: u32) -> isize {
+    sys_renameat2(old_dirfd as usize, old_path, new_dirfd as usize, new_path, flags)
+}
+pub fn unlinkat(dirfd: isize, path: &str) -> isize {
+    sys_unlinkat(dirfd as usize, path, 0)
+}
+pub fn mkdir(path: &str) -> isize {
+    sys_mkdirat(AT_FDCWD as usize, path, 0o755)
+}
+pub fn mkdirat(dirfd: isize, path: &str) -> isize {
+    sys_mkdirat(dirfd as usize, path, 0o755)
+}
+pub fn chmod(path: &str, mode: u32) -> isize {
+    sys_fchmodat(AT_FDCWD as usize, path, mode)
+}
+// uid 或 gid 为 u32::MAX 时保持不变
+pub fn chown(path: &str, uid: u32, gid: u32) -> isize {
+    sys_fchownat(AT_FDCWD as usize, path, uid, gid, 0)
+}
+pub fn getuid() -> isize { sys_getuid() }
+pub fn getgid() -> isize { sys_getgid() }
+pub fn setuid(uid: u32) -> isize { sys_setuid(uid) }
+pub fn setgid(gid: u32) -> isize { sys_setgid(gid) }
+pub fn chdir(path: &str) -> isize { sys_chdir(path) }
+// 成功时返回写入 buf 的字节数，包括结尾的 '\0'
+pub fn getcwd(buf: &mut [u8]) -> isize { sys_getcwd(buf) }
@@ -1,27 +1,50 @@
-use super::{Stat, TimeVal};
+use super::{Stat, TimeVal, RLimit, HeapStats, SlabStats};
 
+pub const SYSCALL_GETCWD: usize = 17;
 pub const SYSCALL_DUP: usize = 24;
+pub const SYSCALL_MKDIRAT: usize = 34;
 pub const SYSCALL_OPENAT: usize = 56;
 pub const SYSCALL_CLOSE: usize = 57;
 pub const SYSCALL_PIPE: usize = 59;
 pub const SYSCALL_READ: usize = 63;
 pub const SYSCALL_WRITE: usize = 64;
 pub const SYSCALL_UNLINKAT: usize = 35;
+pub const SYSCALL_SYMLINKAT: usize = 36;
 pub const SYSCALL_LINKAT: usize = 37;
+pub const SYSCALL_CHDIR: usize = 49;
+pub const SYSCALL_FCHMODAT: usize = 53;
+pub const SYSCALL_FCHOWNAT: usize = 54;
+pub const SYSCALL_READLINKAT: usize = 78;
+pub const SYSCALL_FSTATAT: usize = 79;
 pub const SYSCALL_FSTAT: usize = 80;
 pub const SYSCALL_EXIT: usize = 93;
 pub const SYSCALL_YIELD: usize = 124;
+pub const SYSCALL_GETRLIMIT: usize = 163;
+pub const SYSCALL_SETRLIMIT: usize = 164;
+pub const SYSCALL_PERSONALITY: usize = 92;
+pub const SYSCALL_SETGID: usize = 144;
+pub const SYSCALL_SETUID: usize = 146;
 pub const SYSCALL_GETTIMEOFDAY: usize = 169;
 pub const SYSCALL_GETPID: usize = 172;
+pub const SYSCALL_GETUID: usize = 174;
+pub const SYSCALL_GETGID: usize = 176;
+pub const SYSCALL_SHMGET: usize = 194;
+pub const SYSCALL_SHMCTL: usize = 195;
+pub const SYSCALL_SHMAT: usize = 196;
+pub const SYSCALL_SHMDT: usize = 197;
 pub const SYSCALL_FORK: usize = 220;
 pub const SYSCALL_EXEC: usize = 221;
 pub const SYSCALL_WAITPID: usize = 260;
+pub const SYSCALL_RENAMEAT2: usize = 276;
 pub const SYSCALL_SET_PRIORITY: usize = 140;
 pub const SYSCALL_MUNMAP: usize = 215;
 pub const SYSCALL_MMAP: usize = 222;
+pub const SYSCALL_MPROTECT: usize = 226;
 pub const SYSCALL_SPAWN: usize = 400;
 pub const SYSCALL_MAIL_READ: usize = 401;
 pub const SYSCALL_MAIL_WRITE: usize = 402;
+pub const SYSCALL_HEAP_STATS: usize = 403;
+pub const SYSCALL_SLAB_STATS: usize = 404;
 
 pub fn syscall(id: usize, args: [usize; 3]) -> isize {
     let mut ret: isize; // 变量 ret 必须为可变 绑定，否则无法通过编译, 这也说明在 unsafe 块内编译器还是会进行力所能及的安全检查。
@@ -111,6 +134,10 @@ pub fn sys_munmap(start: usize, len: usize) -> isize {
     syscall(SYSCALL_MUNMAP, [start, len, 0])
 }
 
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
+    syscall(SYSCALL_MPROTECT, [start, len, prot])
+}
+
 pub fn sys_getpid() -> isize {
     syscall(SYSCALL_GETPID, [0, 0, 0])
 }
@@ -140,6 +167,42 @@ pub fn sys_waitpid(pid: isize, xstatus: *mut i32) -> isize {
     syscall(SYSCALL_WAITPID, [pid as usize, xstatus as usize, 0])
 }
 
+/// 功能：获取资源限制，目前只支持 RLIMIT_STACK (3)。
+/// 返回值：如果出现了错误则返回 -1，否则返回 0。
+/// syscall ID：163
+pub fn sys_getrlimit(resource: usize, rlim: &mut RLimit) -> isize {
+    syscall(SYSCALL_GETRLIMIT, [resource, rlim as *mut _ as usize, 0])
+}
+
+/// 功能：设置资源限制，RLIMIT_STACK 设置的用户栈大小在下一次 exec 时生效。
+/// 返回值：如果出现了错误则返回 -1，否则返回 0。
+/// syscall ID：164
+pub fn sys_setrlimit(resource: usize, rlim: &RLimit) -> isize {
+    syscall(SYSCALL_SETRLIMIT, [resource, rlim as *const _ as usize, 0])
+}
+
+/// 功能：设置进程的执行域标志，目前只支持 ADDR_NO_RANDOMIZE，在下一次 exec 时生效。
+/// 参数：persona 为 0xffffffff 时只查询不修改。
+/// 返回值：如果出现了错误则返回 -1，否则返回原来的标志。
+/// syscall ID：92
+pub fn sys_personality(persona: usize) -> isize {
+    syscall(SYSCALL_PERSONALITY, [persona, 0, 0])
+}
+
+/// 功能：调试用，获取内核堆的总大小、已分配大小、峰值以及分配失败的次数。
+/// 返回值：如果出现了错误则返回 -1，否则返回 0。
+/// syscall ID：403
+pub fn sys_heap_stats(stats: &mut HeapStats) -> isize {
+    syscall(SYSCALL_HEAP_STATS, [stats as *mut _ as usize, 0, 0])
+}
+
+/// 功能：调试用，将至多 stats.len() 个内核对象缓存的使用情况写入 stats。
+/// 返回值：如果出现了错误则返回 -1，否则返回内核对象缓存的总数。
+/// syscall ID：404
+pub fn sys_slab_stats(stats: &mut [SlabStats]) -> isize {
+    syscall(SYSCALL_SLAB_STATS, [stats.as_mut_ptr() as usize, stats.len(), 0])
+}
+
 pub fn sys_spawn(path: &str) -> isize {
     syscall(SYSCALL_SPAWN, [path.as_ptr() as usize, 0, 0])
 }
@@ -166,6 +229,36 @@ pub fn sys_mail_write(pid: usize, buffer: &[u8]) -> isize {
     )
 }
 
+/// 功能：获取 key 对应的共享内存段，不存在时创建一个至少 size 字节的新段。
+/// 参数：key 为 IPC_PRIVATE (0) 时总是创建新段；size 为段的字节数。
+/// 返回值：如果出现了错误则返回 -1，否则返回共享内存段的 id。
+/// syscall ID：194
+pub fn sys_shmget(key: usize, size: usize) -> isize {
+    syscall(SYSCALL_SHMGET, [key, size, 0])
+}
+
+/// 功能：将共享内存段挂载到当前进程的地址空间中。
+/// 参数：id 为 shmget 返回的段 id；addr 为按页对齐的挂载地址，为 0 时由内核选择。
+/// 返回值：如果出现了错误则返回 -1，否则返回挂载的起始地址。
+/// syscall ID：196
+pub fn sys_shmat(id: usize, addr: usize) -> isize {
+    syscall(SYSCALL_SHMAT, [id, addr, 0])
+}
+
+/// 功能：解除挂载在 addr 处的共享内存段。
+/// 返回值：如果出现了错误则返回 -1，否则返回 0。
+/// syscall ID：197
+pub fn sys_shmdt(addr: usize) -> isize {
+    syscall(SYSCALL_SHMDT, [addr, 0, 0])
+}
+
+/// 功能：控制共享内存段，目前只支持 IPC_RMID (0) 移除共享内存段。
+/// 返回值：如果出现了错误则返回 -1，否则返回 0。
+/// syscall ID：195
+pub fn sys_shmctl(id: usize, cmd: usize) -> isize {
+    syscall(SYSCALL_SHMCTL, [id, cmd, 0])
+}
+
 pub fn sys_dup(fd: usize) -> isize {
     syscall(SYSCALL_DUP, [fd, 0, 0])
 }
@@ -204,6 +297,26 @@ pub fn sys_linkat(
     )
 }
 
+pub fn sys_renameat2(
+    old_dirfd: usize,
+    old_path: &str,
+    new_dirfd: usize,
+    new_path: &str,
+    flags: u32,
+) -> isize {
+    syscall6(
+        SYSCALL_RENAMEAT2,
+        [
+            old_dirfd,
+            old_path.as_ptr() as usize,
+            new_dirfd,
+            new_path.as_ptr() as usize,
+            flags as usize,
+            0,
+        ],
+    )
+}
+
 pub fn sys_unlinkat(dirfd: usize, path: &str, flags: usize) -> isize {
     syscall(SYSCALL_UNLINKAT, [dirfd, path.as_ptr() as usize, flags])
 }
@@ -211,3 +324,84 @@ pub fn sys_unlinkat(dirfd: usize, path: &str, flags: usize) -> isize {
 pub fn sys_fstat(fd: usize, st: &Stat) -> isize {
     syscall(SYSCALL_FSTAT, [fd, st as *const _ as usize, 0])
 }
+
+pub fn sys_fstatat(dirfd: usize, path: &str, st: &Stat, flags: u32) -> isize {
+    syscall6(
+        SYSCALL_FSTATAT,
+        [
+            dirfd,
+            path.as_ptr() as usize,
+            st as *const _ as usize,
+            flags as usize,
+            0,
+            0,
+        ],
+    )
+}
+
+pub fn sys_symlinkat(target: &str, new_dirfd: usize, link_path: &str) -> isize {
+    syscall(
+        SYSCALL_SYMLINKAT,
+        [target.as_ptr() as usize, new_dirfd, link_path.as_ptr() as usize],
+    )
+}
+
+pub fn sys_readlinkat(dirfd: usize, path: &str, buf: &mut [u8]) -> isize {
+    syscall6(
+        SYSCALL_READLINKAT,
+        [
+            dirfd,
+            path.as_ptr() as usize,
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+            0,
+            0,
+        ],
+    )
+}
+
+pub fn sys_mkdirat(dirfd: usize, path: &str, mode: u32) -> isize {
+    syscall(SYSCALL_MKDIRAT, [dirfd, path.as_ptr() as usize, mode as usize])
+}
+
+pub fn sys_fchmodat(dirfd: usize, path: &str, mode: u32) -> isize {
+    syscall(SYSCALL_FCHMODAT, [dirfd, path.as_ptr() as usize, mode as usize])
+}
+
+pub fn sys_fchownat(dirfd: usize, path: &str, uid: u32, gid: u32, flags: u32) -> isize {
+    syscall6(
+        SYSCALL_FCHOWNAT,
+        [
+            dirfd,
+            path.as_ptr() as usize,
+            uid as usize,
+            gid as usize,
+            flags as usize,
+            0,
+        ],
+    )
+}
+
+pub fn sys_getuid() -> isize {
+    syscall(SYSCALL_GETUID, [0, 0, 0])
+}
+
+pub fn sys_getgid() -> isize {
+    syscall(SYSCALL_GETGID, [0, 0, 0])
+}
+
+pub fn sys_setuid(uid: u32) -> isize {
+    syscall(SYSCALL_SETUID, [uid as usize, 0, 0])
+}
+
+pub fn sys_setgid(gid: u32) -> isize {
+    syscall(SYSCALL_SETGID, [gid as usize, 0, 0])
+}
+
+pub fn sys_chdir(path: &str) -> isize {
+    syscall(SYSCALL_CHDIR, [path.as_ptr() as usize, 0, 0])
+}
+
+pub fn sys_getcwd(buf: &mut [u8]) -> isize {
+    syscall(SYSCALL_GETCWD, [buf.as_mut_ptr() as usize, buf.len(), 0])
+}